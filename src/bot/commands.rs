@@ -1,6 +1,38 @@
+use crate::bot::dispatcher::Command;
 use crate::bot::handler::MessageHandler;
-use log::info;
-use teloxide::{requests::Requester, types::Message, Bot, RequestError};
+use crate::bot::traits::BotApi;
+use crate::calculator::report::{ReportGenerator, ReportPeriod};
+use crate::database::models::{
+    ChatSettings, Language, OutputMode, Role, SummarySubscription, TransactionFilter,
+    TransactionPage,
+};
+use crate::export::{CsvExporter, ExportKind, ExportQuery};
+use crate::import::{ImportFormat, Importer};
+use crate::money::Money;
+use crate::utils::{Formatter, PeriodArg};
+use log::{debug, error, info};
+use std::ops::RangeInclusive;
+use teloxide::{
+    types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, Message},
+    RequestError,
+};
+
+/// /history 每页显示的交易数
+const HISTORY_PAGE_SIZE: usize = 10;
+/// /history 翻页按钮的回调数据前缀
+const HISTORY_CALLBACK_PREFIX: &str = "history";
+/// /import 接受的最大文件大小（字节）
+const MAX_IMPORT_FILE_SIZE: u32 = 5 * 1024 * 1024;
+/// 自动删除的确认消息最多保留的秒数
+const MAX_CONFIRMATION_TTL: u32 = 24 * 60 * 60;
+/// /settings 按钮的回调数据前缀
+const SETTINGS_CALLBACK_PREFIX: &str = "settings";
+/// /settings 中轮换的货币单位
+const CURRENCIES: [&str; 6] = ["元", "¥", "$", "€", "£", "HK$"];
+/// 可以设置的时区（相对 UTC 的小时数）
+const UTC_OFFSETS: RangeInclusive<i32> = -12..=14;
+/// /threshold 可以设置的倍数
+const CONFIRM_RATIOS: RangeInclusive<u32> = 2..=100;
 
 #[derive(Clone)]
 pub struct Commands {
@@ -12,58 +44,1499 @@ impl Commands {
         Self { handler }
     }
 
-    pub async fn handle_command(
+    pub async fn handle_command<B: BotApi>(
         &self,
-        bot: &Bot,
+        bot: &B,
         message: &Message,
-        command: &str,
+        command: Command,
     ) -> Result<(), RequestError> {
+        let required = command.required_role();
+        if !self
+            .handler
+            .access()
+            .message_has_role(bot, message, required)
+            .await
+        {
+            info!(
+                "Rejected {command:?} from {:?} in chat {}",
+                message.from().map(|user| user.id),
+                message.chat.id
+            );
+            let settings = self.handler.chat_settings(message.chat.id.0).await;
+            let text = settings.text(
+                format!("⛔ 这个命令需要{}权限", required.label()),
+                format!("⛔ This command requires the {} role", required.as_str()),
+            );
+            bot.send_message(message.chat.id, &text).await?;
+            return Ok(());
+        }
+
         match command {
-            "/start" => self.handle_start(bot, message).await,
-            "/help" => self.handle_help(bot, message).await,
-            "/reprocess" => self.handle_reprocess(bot, message).await,
-            "/status" => self.handle_status(bot, message).await,
-            _ => {
-                bot.send_message(message.chat.id, "Unknown command").await?;
-                Ok(())
-            }
+            Command::Start => self.handle_start(bot, message).await,
+            Command::Help => self.handle_help(bot, message).await,
+            Command::Reprocess => self.handle_reprocess(bot, message).await,
+            Command::Undo => self.handler.undo_message(bot, message).await,
+            Command::Status => self.handle_status(bot, message).await,
+            Command::Reconcile(args) => self.handle_reconcile(bot, message, &args).await,
+            Command::Adjustments => self.handle_adjustments(bot, message).await,
+            Command::Balance(args) => self.handle_balance(bot, message, &args).await,
+            Command::Wallets => self.handle_wallets(bot, message).await,
+            Command::Categories(args) => self.handle_categories(bot, message, &args).await,
+            Command::History(args) => self.handle_history(bot, message, &args).await,
+            Command::Report(args) => self.handle_report(bot, message, &args).await,
+            Command::Summary(args) => self.handle_summary(bot, message, &args).await,
+            Command::Export(args) => self.handle_export(bot, message, &args).await,
+            Command::Import(args) => self.handle_import(bot, message, &args).await,
+            Command::Access(args) => self.handle_access(bot, message, &args).await,
+            Command::Output(args) => self.handle_output(bot, message, &args).await,
+            Command::Settings => self.handle_settings(bot, message).await,
+            Command::Threshold(args) => self.handle_threshold(bot, message, &args).await,
         }
     }
 
-    async fn handle_start(&self, bot: &Bot, message: &Message) -> Result<(), RequestError> {
-        let welcome_text = "欢迎使用 WalletBot！\n\n我可以帮助你管理钱包交易记录。\n\n支持的消息格式：\n#钱包名称 #月份 #年份\n#出账/入账 金额元\n\n输入 /help 查看更多命令。";
+    /// 处理内联键盘按钮的回调
+    pub async fn handle_callback_query<B: BotApi>(
+        &self,
+        bot: &B,
+        query: &CallbackQuery,
+    ) -> Result<(), RequestError> {
+        let Some(data) = query.data.as_deref() else {
+            return Ok(());
+        };
+        debug!("Handling callback query: {data}");
+
+        let access = self.handler.access();
+        if let Some((id, page)) = HistoryQuery::from_callback_data(data) {
+            if let Some(message) = &query.message {
+                if !access.callback_has_role(bot, query, Role::Reader).await {
+                    bot.answer_callback_query(&query.id).await?;
+                    return Ok(());
+                }
+                let chat_id = message.chat.id.0;
+                match self.handler.db().get_history_query(chat_id, id).await {
+                    Ok(Some(filter)) => {
+                        let history = HistoryQuery { id, filter };
+                        let (text, keyboard) = self.render_history(chat_id, &history, page).await;
+                        bot.edit_message_with_keyboard(
+                            message.chat.id,
+                            message.id,
+                            &text,
+                            keyboard,
+                        )
+                        .await?;
+                    }
+                    Ok(None) => debug!("History query {id} not found in chat {chat_id}"),
+                    Err(e) => error!("Failed to load history query {id}: {e}"),
+                }
+            }
+        } else if let Some(action) = SettingsAction::from_callback_data(data) {
+            if let Some(message) = &query.message {
+                if access.callback_has_role(bot, query, Role::Admin).await {
+                    self.apply_settings_action(bot, message, action).await?;
+                } else {
+                    info!(
+                        "Rejected settings change from {} in chat {}",
+                        query.from.id, message.chat.id
+                    );
+                }
+            }
+        }
 
-        bot.send_message(message.chat.id, welcome_text).await?;
+        bot.answer_callback_query(&query.id).await?;
         Ok(())
     }
 
-    async fn handle_help(&self, bot: &Bot, message: &Message) -> Result<(), RequestError> {
-        let help_text = "WalletBot 帮助\n\n支持的命令：\n/start - 开始使用\n/help - 显示帮助\n/reprocess - 重新处理消息\n/status - 查看状态\n\n消息格式：\n#钱包名称 #月份 #年份\n#出账 1000.00元\n\n或者：\n#钱包名称 #月份 #年份\n#入账 500.00元\n\n我会自动计算并添加 #总额 信息。";
+    async fn handle_start<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+    ) -> Result<(), RequestError> {
+        let settings = self.handler.chat_settings(message.chat.id.0).await;
+        bot.send_message(message.chat.id, Formatter::format_welcome(&settings))
+            .await?;
+        Ok(())
+    }
 
-        bot.send_message(message.chat.id, help_text).await?;
+    async fn handle_help<B: BotApi>(&self, bot: &B, message: &Message) -> Result<(), RequestError> {
+        let settings = self.handler.chat_settings(message.chat.id.0).await;
+        bot.send_message(message.chat.id, Formatter::format_help(&settings))
+            .await?;
         Ok(())
     }
 
-    async fn handle_reprocess(&self, bot: &Bot, message: &Message) -> Result<(), RequestError> {
+    async fn handle_reprocess<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+    ) -> Result<(), RequestError> {
         info!("Reprocessing message requested by user");
 
-        // 这里应该重新处理回复的消息
+        // 重新处理回复的消息，结果由处理器回复
         if let Some(reply_to) = message.reply_to_message() {
             self.handler.reprocess_message(bot, reply_to).await?;
-            bot.send_message(message.chat.id, "Message reprocessed successfully")
-                .await?;
         } else {
-            bot.send_message(message.chat.id, "Please reply to a message to reprocess it")
-                .await?;
+            let settings = self.handler.chat_settings(message.chat.id.0).await;
+            let text = settings.text(
+                "❌ 请回复要重新处理的消息",
+                "❌ Please reply to a message to reprocess it",
+            );
+            bot.send_message(message.chat.id, text).await?;
         }
 
         Ok(())
     }
 
-    async fn handle_status(&self, bot: &Bot, message: &Message) -> Result<(), RequestError> {
+    async fn handle_status<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+    ) -> Result<(), RequestError> {
         let status_text = "WalletBot Status: ✅ Running\n\nDatabase: ✅ Connected\nParser: ✅ Ready\nCalculator: ✅ Ready";
 
         bot.send_message(message.chat.id, status_text).await?;
         Ok(())
     }
+
+    async fn handle_reconcile<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        args: &str,
+    ) -> Result<(), RequestError> {
+        let mut wallet_name = None;
+        let mut fix = false;
+        for arg in args.split_whitespace() {
+            match arg {
+                "fix" | "修正" => fix = true,
+                name => wallet_name = Some(name.trim_start_matches('#').to_string()),
+            }
+        }
+        info!(
+            "Reconciling balances in chat {} (wallet: {wallet_name:?}, fix: {fix})",
+            message.chat.id
+        );
+
+        let calculator = self.handler.calculator();
+        let result = match &wallet_name {
+            Some(name) => calculator
+                .reconcile_wallet(message.chat.id.0, name, fix)
+                .await
+                .map(|r| vec![r]),
+            None => calculator.reconcile_chat(message.chat.id.0, fix).await,
+        };
+
+        let settings = self.handler.chat_settings(message.chat.id.0).await;
+        let text = match result {
+            Ok(results) => {
+                let mut text = Formatter::format_reconciliation(&settings, &results);
+                if !fix && results.iter().any(|r| !r.is_consistent()) {
+                    text.push_str(match settings.language {
+                        Language::Chinese => "\n\n使用 /reconcile fix 按账本修正余额",
+                        Language::English => {
+                            "\n\nUse /reconcile fix to correct balances from the ledger"
+                        }
+                    });
+                }
+                text
+            }
+            Err(e) => {
+                error!("Failed to reconcile balances: {e}");
+                settings.text(
+                    format!("❌ 核对余额失败：{e}"),
+                    format!("❌ Failed to reconcile balances: {e}"),
+                )
+            }
+        };
+
+        bot.send_message(message.chat.id, &text).await?;
+        Ok(())
+    }
+
+    async fn handle_adjustments<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+    ) -> Result<(), RequestError> {
+        let settings = self.handler.chat_settings(message.chat.id.0).await;
+        let text = match self
+            .handler
+            .db()
+            .get_monthly_adjustments(message.chat.id.0)
+            .await
+        {
+            Ok(months) => Formatter::format_monthly_adjustments(&settings, &months),
+            Err(e) => {
+                error!("Failed to load balance adjustments: {e}");
+                settings.text(
+                    format!("❌ 查询余额调整失败：{e}"),
+                    format!("❌ Failed to load balance adjustments: {e}"),
+                )
+            }
+        };
+
+        bot.send_message(message.chat.id, &text).await?;
+        Ok(())
+    }
+
+    async fn handle_balance<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        args: &str,
+    ) -> Result<(), RequestError> {
+        let chat_id = message.chat.id.0;
+        let db = self.handler.db();
+        let wallet_name = args.trim().trim_start_matches('#');
+
+        let result = if wallet_name.is_empty() {
+            db.get_wallet_summaries(chat_id).await
+        } else {
+            db.get_wallet_summary(chat_id, wallet_name)
+                .await
+                .map(|wallet| wallet.into_iter().collect())
+        };
+
+        let settings = self.handler.chat_settings(chat_id).await;
+        let text = match result {
+            Ok(wallets) if wallets.is_empty() && !wallet_name.is_empty() => {
+                match settings.language {
+                    Language::Chinese => {
+                        format!("❌ 钱包 {wallet_name} 不存在，使用 /wallets 查看所有钱包")
+                    }
+                    Language::English => format!(
+                        "❌ Wallet {wallet_name} does not exist, use /wallets to list all wallets"
+                    ),
+                }
+            }
+            Ok(wallets) => Formatter::format_balances(&settings, &wallets),
+            Err(e) => {
+                error!("Failed to load wallet balances: {e}");
+                settings.text(
+                    format!("❌ 查询余额失败：{e}"),
+                    format!("❌ Failed to load balances: {e}"),
+                )
+            }
+        };
+
+        bot.send_message(message.chat.id, &text).await?;
+        Ok(())
+    }
+
+    async fn handle_wallets<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+    ) -> Result<(), RequestError> {
+        let settings = self.handler.chat_settings(message.chat.id.0).await;
+        let text = match self
+            .handler
+            .db()
+            .get_wallet_summaries(message.chat.id.0)
+            .await
+        {
+            Ok(wallets) => Formatter::format_wallets(&settings, &wallets),
+            Err(e) => {
+                error!("Failed to list wallets: {e}");
+                settings.text(
+                    format!("❌ 查询钱包列表失败：{e}"),
+                    format!("❌ Failed to list wallets: {e}"),
+                )
+            }
+        };
+
+        bot.send_message(message.chat.id, &text).await?;
+        Ok(())
+    }
+
+    async fn handle_history<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        args: &str,
+    ) -> Result<(), RequestError> {
+        // 没有写钱包时查看默认钱包
+        let settings = self.handler.chat_settings(message.chat.id.0).await;
+        let args = match &settings.default_wallet {
+            Some(wallet) if args.trim().is_empty() => wallet.clone(),
+            _ => args.to_string(),
+        };
+        let Some(filter) = HistoryQuery::parse(&args) else {
+            bot.send_message(
+                message.chat.id,
+                settings.text(
+                    "❌ 用法：/history <钱包名> [月份] [年份] [类型]\n例如 /history 支付宝 12月 2024年 出账",
+                    "❌ Usage: /history <wallet> [month] [year] [type]\ne.g. /history 支付宝 12月 2024年 出账",
+                ),
+            )
+            .await?;
+            return Ok(());
+        };
+        let id = match self
+            .handler
+            .db()
+            .save_history_query(message.chat.id.0, &filter)
+            .await
+        {
+            Ok(id) => id,
+            Err(e) => {
+                error!("Failed to save history query: {e}");
+                let text = settings.text(
+                    format!("❌ 查询交易记录失败：{e}"),
+                    format!("❌ Failed to load transactions: {e}"),
+                );
+                bot.send_message(message.chat.id, &text).await?;
+                return Ok(());
+            }
+        };
+
+        let history = HistoryQuery { id, filter };
+        let (text, keyboard) = self.render_history(message.chat.id.0, &history, 0).await;
+        bot.send_message_with_keyboard(message.chat.id, &text, keyboard)
+            .await?;
+        Ok(())
+    }
+
+    /// 查询一页交易记录并生成消息文本和翻页按钮
+    async fn render_history(
+        &self,
+        chat_id: i64,
+        history: &HistoryQuery,
+        page: usize,
+    ) -> (String, Option<InlineKeyboardMarkup>) {
+        let settings = self.handler.chat_settings(chat_id).await;
+        match self
+            .handler
+            .db()
+            .get_transactions_page(chat_id, &history.filter, page, HISTORY_PAGE_SIZE)
+            .await
+        {
+            Ok(result) => (
+                Formatter::format_history(&settings, &history.title(&settings), &result),
+                history.keyboard(&result),
+            ),
+            Err(e) => {
+                error!("Failed to load transaction history: {e}");
+                let text = settings.text(
+                    format!("❌ 查询交易记录失败：{e}"),
+                    format!("❌ Failed to load transactions: {e}"),
+                );
+                (text, None)
+            }
+        }
+    }
+
+    async fn handle_report<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        args: &str,
+    ) -> Result<(), RequestError> {
+        let (period, rest) = ReportPeriod::parse_args(args.split_whitespace());
+        let chat_id = message.chat.id.0;
+        let generator = ReportGenerator::new(self.handler.db().clone());
+        let settings = self.handler.chat_settings(chat_id).await;
+
+        let result = match rest.as_slice() {
+            [] => generator
+                .chat_report(chat_id, period)
+                .await
+                .map(|report| Formatter::format_chat_report(&settings, &report)),
+            [wallet_name] => generator
+                .wallet_report(chat_id, wallet_name.trim_start_matches('#'), period)
+                .await
+                .map(|report| Formatter::format_report(&settings, &report)),
+            _ => {
+                bot.send_message(
+                    message.chat.id,
+                    settings.text(
+                        "❌ 用法：/report [钱包名] [月份] [年份]\n例如 /report 12月 2024年 或 /report 支付宝 2024年",
+                        "❌ Usage: /report [wallet] [month] [year]\ne.g. /report 12月 2024年 or /report 支付宝 2024年",
+                    ),
+                )
+                .await?;
+                return Ok(());
+            }
+        };
+
+        let text = result.unwrap_or_else(|e| {
+            error!("Failed to build report: {e}");
+            settings.text(
+                format!("❌ 生成报表失败：{e}"),
+                format!("❌ Failed to build the report: {e}"),
+            )
+        });
+        bot.send_message(message.chat.id, &text).await?;
+        Ok(())
+    }
+
+    async fn handle_export<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        args: &str,
+    ) -> Result<(), RequestError> {
+        let settings = self.handler.chat_settings(message.chat.id.0).await;
+        let Ok(query) = ExportQuery::parse_args(args.split_whitespace()) else {
+            bot.send_message(
+                message.chat.id,
+                settings.text(
+                    "❌ 用法：/export [钱包名] [月份] [年份]\n例如 /export 支付宝 2024年 或 /export 12月 2024年",
+                    "❌ Usage: /export [wallet] [month] [year]\ne.g. /export 支付宝 2024年 or /export 12月 2024年",
+                ),
+            )
+            .await?;
+            return Ok(());
+        };
+        let chat_id = message.chat.id.0;
+        info!("Exporting chat {chat_id} as CSV ({query:?})");
+
+        let exporter = CsvExporter::new(self.handler.db().clone());
+        for kind in [ExportKind::Transactions, ExportKind::Balances] {
+            let file = match exporter.export(chat_id, &query, kind).await {
+                Ok(file) => file,
+                Err(e) => {
+                    error!("Failed to export {kind:?}: {e}");
+                    let text = settings.text(
+                        format!("❌ 导出失败：{e}"),
+                        format!("❌ Export failed: {e}"),
+                    );
+                    bot.send_message(message.chat.id, &text).await?;
+                    return Ok(());
+                }
+            };
+            let rows = file.rows;
+            let caption = match kind {
+                ExportKind::Transactions => settings.text(
+                    format!("📤 交易记录（{rows} 笔）"),
+                    format!("📤 Transactions ({rows})"),
+                ),
+                ExportKind::Balances => settings.text(
+                    format!("📤 钱包余额（{rows} 个钱包）"),
+                    format!("📤 Wallet balances ({rows} wallets)"),
+                ),
+            };
+            // 加上 BOM，Excel 才能正确识别 UTF-8 编码的中文
+            let content = format!("\u{feff}{}", file.content);
+            bot.send_document(
+                message.chat.id,
+                &file.file_name,
+                content.into_bytes(),
+                &caption,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// 导入消息自带的文件或被回复消息中的文件，参数为 dry 时只预览
+    pub async fn handle_import<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        args: &str,
+    ) -> Result<(), RequestError> {
+        let settings = self.handler.chat_settings(message.chat.id.0).await;
+        let dry_run = match args.trim() {
+            "" => false,
+            "dry" | "--dry-run" | "预览" => true,
+            _ => {
+                bot.send_message(
+                    message.chat.id,
+                    settings.text(
+                    "❌ 用法：回复 CSV 或 JSON 文件发送 /import，加上 dry 只预览不写入",
+                    "❌ Usage: reply to a CSV or JSON file with /import; add dry to preview without writing",
+                ),
+                )
+                .await?;
+                return Ok(());
+            }
+        };
+        let Some(document) = message
+            .document()
+            .or_else(|| message.reply_to_message().and_then(Message::document))
+        else {
+            bot.send_message(
+                message.chat.id,
+                settings.text(
+                    "❌ 请回复要导入的 CSV 或 JSON 文件，或在发送文件时附上 /import",
+                    "❌ Reply to the CSV or JSON file to import, or send the file with /import as its caption",
+                ),
+            )
+            .await?;
+            return Ok(());
+        };
+        if document.file.size > MAX_IMPORT_FILE_SIZE {
+            bot.send_message(
+                message.chat.id,
+                &settings.text(
+                    format!("❌ 文件太大，最多 {} KB", MAX_IMPORT_FILE_SIZE / 1024),
+                    format!(
+                        "❌ The file is too large, the limit is {} KB",
+                        MAX_IMPORT_FILE_SIZE / 1024
+                    ),
+                ),
+            )
+            .await?;
+            return Ok(());
+        }
+
+        let bytes = match bot.download_file(&document.file.id).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                error!("Failed to download import file: {e}");
+                let text = settings.text(
+                    "❌ 下载文件失败，请稍后重试",
+                    "❌ Failed to download the file, please try again later",
+                );
+                bot.send_message(message.chat.id, text).await?;
+                return Ok(());
+            }
+        };
+        let Ok(content) = String::from_utf8(bytes) else {
+            let text = settings.text(
+                "❌ 文件不是 UTF-8 编码的文本",
+                "❌ The file is not UTF-8 encoded text",
+            );
+            bot.send_message(message.chat.id, text).await?;
+            return Ok(());
+        };
+
+        let chat_id = message.chat.id.0;
+        info!(
+            "Importing {:?} into chat {chat_id} (dry run: {dry_run})",
+            document.file_name
+        );
+        let format = ImportFormat::detect(document.file_name.as_deref(), &content);
+        let text = match Importer::new(self.handler.db().clone())
+            .import(chat_id, &content, format, dry_run)
+            .await
+        {
+            Ok(report) => Formatter::format_import_report(&settings, &report),
+            Err(e) => {
+                error!("Failed to import file: {e}");
+                settings.text(
+                    format!("❌ 导入失败：{e}"),
+                    format!("❌ Import failed: {e}"),
+                )
+            }
+        };
+        bot.send_message(message.chat.id, &text).await?;
+        Ok(())
+    }
+
+    async fn handle_summary<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        args: &str,
+    ) -> Result<(), RequestError> {
+        let settings = self.handler.chat_settings(message.chat.id.0).await;
+        let chat_id = message.chat.id.0;
+        let db = self.handler.db();
+
+        let Some(action) = SummaryAction::parse(args) else {
+            bot.send_message(
+                message.chat.id,
+                settings.text(
+                    "❌ 用法：/summary [on [日] [时] | off]\n例如 /summary on 1 9 表示每月 1 日 9 点发送上个月的总结，日期为 1-28，时区使用 /settings 设置",
+                    "❌ Usage: /summary [on [day] [hour] | off]\ne.g. /summary on 1 9 posts last month's summary at 9:00 on the 1st; the day is 1-28 and the timezone is set with /settings",
+                ),
+            )
+            .await?;
+            return Ok(());
+        };
+
+        let result = async {
+            let current = db
+                .get_summary_subscription(chat_id)
+                .await?
+                .unwrap_or_else(|| SummarySubscription {
+                    enabled: false,
+                    ..SummarySubscription::new(chat_id)
+                });
+            let subscription = match action {
+                SummaryAction::Status => return Ok(current),
+                SummaryAction::Off => SummarySubscription {
+                    enabled: false,
+                    ..current
+                },
+                SummaryAction::On { day, hour } => SummarySubscription {
+                    enabled: true,
+                    day_of_month: day.unwrap_or(current.day_of_month),
+                    hour: hour.unwrap_or(current.hour),
+                    ..current
+                },
+            };
+            db.save_summary_subscription(&subscription).await?;
+            info!("Updated summary subscription of chat {chat_id}: {subscription:?}");
+            Ok::<_, anyhow::Error>(subscription)
+        }
+        .await;
+
+        let timezone = settings.timezone_label();
+        let text = match result {
+            Ok(subscription) if subscription.enabled => {
+                let (day, hour) = (subscription.day_of_month, subscription.hour);
+                settings.text(
+                    format!("🗓️ 月度总结已开启：每月 {day} 日 {hour} 点（{timezone}）后发送上个月的总结"),
+                    format!("🗓️ Monthly summary is on: last month's summary is posted after {hour}:00 ({timezone}) on day {day} of each month"),
+                )
+            }
+            Ok(_) => settings
+                .text(
+                    "🗓️ 月度总结未开启，使用 /summary on 开启",
+                    "🗓️ Monthly summary is off, use /summary on to turn it on",
+                )
+                .to_string(),
+            Err(e) => {
+                error!("Failed to update summary subscription: {e}");
+                settings.text(
+                    format!("❌ 更新月度总结设置失败：{e}"),
+                    format!("❌ Failed to update the monthly summary: {e}"),
+                )
+            }
+        };
+        bot.send_message(message.chat.id, &text).await?;
+        Ok(())
+    }
+
+    /// 查看或修改记账结果的输出方式
+    async fn handle_output<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        args: &str,
+    ) -> Result<(), RequestError> {
+        let settings = self.handler.chat_settings(message.chat.id.0).await;
+        let chat_id = message.chat.id.0;
+        let db = self.handler.db();
+
+        let mut words = args.split_whitespace();
+        let change = match words.next() {
+            None => None,
+            Some(mode) => {
+                let ttl = words.next().map(|ttl| {
+                    ttl.parse::<u32>()
+                        .ok()
+                        .filter(|ttl| (1..=MAX_CONFIRMATION_TTL).contains(ttl))
+                });
+                match (OutputMode::parse(mode), ttl) {
+                    (Some(mode), None) => Some((mode, None)),
+                    (Some(OutputMode::TemporaryConfirm), Some(Some(ttl))) => {
+                        Some((OutputMode::TemporaryConfirm, Some(ttl)))
+                    }
+                    _ => {
+                        bot.send_message(
+                            message.chat.id,
+                            settings.text(
+                                "❌ 用法：/output [edit | confirm | temporary [秒] | discussion]\n例如 /output temporary 60 表示确认消息 60 秒后自动删除",
+                                "❌ Usage: /output [edit | confirm | temporary [seconds] | discussion]\ne.g. /output temporary 60 deletes confirmations after 60 seconds",
+                            ),
+                        )
+                        .await?;
+                        return Ok(());
+                    }
+                }
+            }
+        };
+
+        let result = async {
+            let mut settings = db.get_chat_settings(chat_id).await?;
+            if let Some((mode, ttl)) = change {
+                settings.output_mode = mode;
+                if let Some(ttl) = ttl {
+                    settings.confirmation_ttl = ttl;
+                }
+                db.save_chat_settings(&settings).await?;
+                info!("Updated output mode of chat {chat_id}: {settings:?}");
+            }
+            Ok::<_, anyhow::Error>(settings)
+        }
+        .await;
+
+        let text = match result {
+            Ok(settings) => Formatter::format_output_mode(&settings),
+            Err(e) => {
+                error!("Failed to update output mode: {e}");
+                settings.text(
+                    format!("❌ 更新输出方式失败：{e}"),
+                    format!("❌ Failed to update the output mode: {e}"),
+                )
+            }
+        };
+        bot.send_message(message.chat.id, &text).await?;
+        Ok(())
+    }
+
+    /// 显示聊天的设置和修改按钮
+    async fn handle_settings<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+    ) -> Result<(), RequestError> {
+        let chat_id = message.chat.id.0;
+        match self.handler.db().get_chat_settings(chat_id).await {
+            Ok(settings) => {
+                let text = Formatter::format_chat_settings(&settings);
+                bot.send_message_with_keyboard(message.chat.id, &text, Some(settings_keyboard()))
+                    .await?;
+            }
+            Err(e) => {
+                error!("Failed to load settings of chat {chat_id}: {e}");
+                let text = self.handler.chat_settings(chat_id).await.text(
+                    format!("❌ 查询聊天设置失败：{e}"),
+                    format!("❌ Failed to load chat settings: {e}"),
+                );
+                bot.send_message(message.chat.id, &text).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 应用 /settings 按钮的修改，并刷新设置消息
+    async fn apply_settings_action<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        action: SettingsAction,
+    ) -> Result<(), RequestError> {
+        let chat_id = message.chat.id.0;
+        let db = self.handler.db();
+
+        let result = async {
+            let mut settings = db.get_chat_settings(chat_id).await?;
+            let wallets = match action {
+                SettingsAction::DefaultWallet => db
+                    .get_wallet_summaries(chat_id)
+                    .await?
+                    .into_iter()
+                    .map(|wallet| wallet.name)
+                    .collect(),
+                _ => Vec::new(),
+            };
+            let changed = action.apply(&mut settings, &wallets);
+            if changed {
+                db.save_chat_settings(&settings).await?;
+                info!("Updated settings of chat {chat_id}: {settings:?}");
+            }
+            Ok::<_, anyhow::Error>((settings, changed))
+        }
+        .await;
+
+        match result {
+            // 内容没有变化时 Telegram 会拒绝编辑
+            Ok((settings, true)) => {
+                let text = Formatter::format_chat_settings(&settings);
+                bot.edit_message_with_keyboard(
+                    message.chat.id,
+                    message.id,
+                    &text,
+                    Some(settings_keyboard()),
+                )
+                .await?;
+            }
+            Ok((_, false)) => debug!("Settings action {action:?} changed nothing"),
+            Err(e) => error!("Failed to update settings of chat {chat_id}: {e}"),
+        }
+        Ok(())
+    }
+
+    /// 查看或修改需要确认的大额交易阈值
+    async fn handle_threshold<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        args: &str,
+    ) -> Result<(), RequestError> {
+        let settings = self.handler.chat_settings(message.chat.id.0).await;
+        let chat_id = message.chat.id.0;
+        let db = self.handler.db();
+
+        let Some(action) = ThresholdAction::parse(args) else {
+            bot.send_message(
+                message.chat.id,
+                settings.text(
+                    "❌ 用法：/threshold [金额] [倍数x] | off\n例如 /threshold 1000 5x 表示单笔超过 1000 元或超过钱包平时金额 5 倍时先确认再记账，倍数为 2-100",
+                    "❌ Usage: /threshold [amount] [ratio x] | off\ne.g. /threshold 1000 5x asks for confirmation when one entry is above 1000 or 5 times the wallet's usual amount; the ratio is 2-100",
+                ),
+            )
+            .await?;
+            return Ok(());
+        };
+
+        let result = async {
+            let mut settings = db.get_chat_settings(chat_id).await?;
+            let (above, ratio) = match action {
+                ThresholdAction::Show => return Ok(settings),
+                ThresholdAction::Off => (None, None),
+                ThresholdAction::Set { above, ratio } => (above, ratio),
+            };
+            settings.confirm_above = above;
+            settings.confirm_ratio = ratio;
+            db.save_chat_settings(&settings).await?;
+            info!("Updated large transaction threshold of chat {chat_id}: {above:?} {ratio:?}");
+            Ok::<_, anyhow::Error>(settings)
+        }
+        .await;
+
+        let text = match result {
+            Ok(settings) => {
+                let threshold = Formatter::format_large_amount_threshold(&settings);
+                settings.text(
+                    format!("🔔 大额确认：{threshold}\n超过阈值的交易会先发送“确认 / 取消”按钮，有记账权限的成员确认后才会记账。"),
+                    format!("🔔 Large transaction confirmation: {threshold}\nTransactions above the threshold get Confirm / Cancel buttons and are recorded only after a member who can record confirms."),
+                )
+            }
+            Err(e) => {
+                error!("Failed to update large transaction threshold: {e}");
+                settings.text(
+                    format!("❌ 更新大额确认设置失败：{e}"),
+                    format!("❌ Failed to update the large transaction threshold: {e}"),
+                )
+            }
+        };
+        bot.send_message(message.chat.id, &text).await?;
+        Ok(())
+    }
+
+    /// 查看或修改聊天的访问策略和成员角色
+    async fn handle_access<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        args: &str,
+    ) -> Result<(), RequestError> {
+        let settings = self.handler.chat_settings(message.chat.id.0).await;
+        let chat_id = message.chat.id.0;
+        let db = self.handler.db();
+
+        let Some(action) = AccessAction::parse(args) else {
+            bot.send_message(
+                message.chat.id,
+                settings.text(
+                    "❌ 用法：/access [open|restrict|ignore|reject]\n/access admin|writer|reader [用户ID]\n/access remove [用户ID]\n不写用户 ID 时回复该用户的消息",
+                    "❌ Usage: /access [open|restrict|ignore|reject]\n/access admin|writer|reader [user ID]\n/access remove [user ID]\nReply to the user's message instead of giving an ID",
+                ),
+            )
+            .await?;
+            return Ok(());
+        };
+        // 没有给出用户 ID 时使用被回复消息的发送者
+        let missing_user = settings.text(
+            "❌ 请给出用户 ID 或回复该用户的消息",
+            "❌ Give a user ID or reply to the user's message",
+        );
+        let target = |user_id: Option<i64>| {
+            user_id.or_else(|| {
+                message
+                    .reply_to_message()
+                    .and_then(Message::from)
+                    .map(|user| user.id.0 as i64)
+            })
+        };
+
+        let result = async {
+            let mut policy = db.get_access_policy(chat_id).await?;
+            let notice = match action {
+                AccessAction::Show => None,
+                AccessAction::Restrict(restricted) => {
+                    policy.restricted = restricted;
+                    db.save_access_policy(&policy).await?;
+                    None
+                }
+                AccessAction::RejectUnauthorized(reject) => {
+                    policy.reject_unauthorized = reject;
+                    db.save_access_policy(&policy).await?;
+                    None
+                }
+                AccessAction::Grant(role, user_id) => match target(user_id) {
+                    Some(user_id) => {
+                        db.set_chat_role(chat_id, user_id, role).await?;
+                        Some(settings.text(
+                            format!("✅ 用户 {user_id} 的角色已设为{}", role.label()),
+                            format!("✅ User {user_id} is now {}", role.as_str()),
+                        ))
+                    }
+                    None => Some(missing_user.to_string()),
+                },
+                AccessAction::Revoke(user_id) => match target(user_id) {
+                    Some(user_id) if db.remove_chat_role(chat_id, user_id).await? => {
+                        Some(settings.text(
+                            format!("✅ 已撤销用户 {user_id} 的授权"),
+                            format!("✅ Removed the role of user {user_id}"),
+                        ))
+                    }
+                    Some(user_id) => Some(settings.text(
+                        format!("ℹ️ 用户 {user_id} 没有单独的授权"),
+                        format!("ℹ️ User {user_id} has no role of their own"),
+                    )),
+                    None => Some(missing_user.to_string()),
+                },
+            };
+            info!("Access of chat {chat_id} after {action:?}: {policy:?}");
+            let permissions = db.get_chat_permissions(chat_id).await?;
+            Ok::<_, anyhow::Error>((notice, policy, permissions))
+        }
+        .await;
+
+        let text = match result {
+            Ok((notice, policy, permissions)) => {
+                let overview = Formatter::format_access(&policy, &permissions);
+                match notice {
+                    Some(notice) => format!("{notice}\n\n{overview}"),
+                    None => overview,
+                }
+            }
+            Err(e) => {
+                error!("Failed to update access control: {e}");
+                settings.text(
+                    format!("❌ 更新权限设置失败：{e}"),
+                    format!("❌ Failed to update access control: {e}"),
+                )
+            }
+        };
+        bot.send_message(message.chat.id, &text).await?;
+        Ok(())
+    }
+
+    async fn handle_categories<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        args: &str,
+    ) -> Result<(), RequestError> {
+        let settings = self.handler.chat_settings(message.chat.id.0).await;
+        let Some((month, year)) = parse_period(args) else {
+            bot.send_message(
+                message.chat.id,
+                settings.text(
+                    "❌ 无法识别的时间，用法：/categories [月份] [年份]，例如 /categories 12月 2024年",
+                    "❌ Unrecognised period. Usage: /categories [month] [year], e.g. /categories 12月 2024年",
+                ),
+            )
+            .await?;
+            return Ok(());
+        };
+
+        let text = match self
+            .handler
+            .db()
+            .get_category_totals(message.chat.id.0, month, year)
+            .await
+        {
+            Ok(totals) => Formatter::format_category_totals(
+                &settings,
+                &totals,
+                &Formatter::format_period(&settings, month, year),
+            ),
+            Err(e) => {
+                error!("Failed to load category totals: {e}");
+                settings.text(
+                    format!("❌ 查询分类汇总失败：{e}"),
+                    format!("❌ Failed to load category totals: {e}"),
+                )
+            }
+        };
+
+        bot.send_message(message.chat.id, &text).await?;
+        Ok(())
+    }
+}
+
+/// 解析命令中的月份和年份参数，例如 "12月 2024年"
+fn parse_period(args: &str) -> Option<(Option<u32>, Option<u32>)> {
+    let mut month = None;
+    let mut year = None;
+    for arg in args.split_whitespace() {
+        match PeriodArg::parse(arg)? {
+            PeriodArg::Month(m) => month = Some(m),
+            PeriodArg::Year(y) => year = Some(y),
+        }
+    }
+    Some((month, year))
+}
+
+/// /summary 的操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SummaryAction {
+    Status,
+    Off,
+    On { day: Option<u32>, hour: Option<u32> },
+}
+
+impl SummaryAction {
+    /// 解析 "on [日] [时]"、"off" 或空参数
+    fn parse(args: &str) -> Option<Self> {
+        let mut args = args.split_whitespace();
+        let action = match args.next() {
+            None => Self::Status,
+            Some("off" | "关闭") => Self::Off,
+            Some("on" | "开启") => {
+                let day = match args.next() {
+                    Some(d) => Some(
+                        d.trim_end_matches('日')
+                            .parse::<u32>()
+                            .ok()
+                            .filter(|d| (1..=28).contains(d))?,
+                    ),
+                    None => None,
+                };
+                let hour = match args.next() {
+                    Some(h) => Some(
+                        h.trim_end_matches('点')
+                            .parse::<u32>()
+                            .ok()
+                            .filter(|h| *h < 24)?,
+                    ),
+                    None => None,
+                };
+                Self::On { day, hour }
+            }
+            Some(_) => return None,
+        };
+        args.next().is_none().then_some(action)
+    }
+}
+
+/// /access 命令的操作
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AccessAction {
+    Show,
+    Grant(Role, Option<i64>), // 未给出用户 ID 时使用被回复消息的发送者
+    Revoke(Option<i64>),
+    Restrict(bool),
+    RejectUnauthorized(bool),
+}
+
+impl AccessAction {
+    /// 解析 "[open|restrict|ignore|reject|admin|writer|reader|remove] [用户ID]"
+    fn parse(args: &str) -> Option<Self> {
+        let parts: Vec<&str> = args.split_whitespace().collect();
+        let user_id = match parts.get(1) {
+            Some(id) => Some(id.parse::<i64>().ok()?),
+            None => None,
+        };
+        if parts.len() > 2 {
+            return None;
+        }
+
+        match parts.first().copied() {
+            None => Some(AccessAction::Show),
+            Some("remove") => Some(AccessAction::Revoke(user_id)),
+            Some(action) if user_id.is_none() => match action {
+                "open" => Some(AccessAction::Restrict(false)),
+                "restrict" => Some(AccessAction::Restrict(true)),
+                "ignore" => Some(AccessAction::RejectUnauthorized(false)),
+                "reject" => Some(AccessAction::RejectUnauthorized(true)),
+                role => Role::parse(role).map(|role| AccessAction::Grant(role, None)),
+            },
+            Some(role) => Role::parse(role).map(|role| AccessAction::Grant(role, user_id)),
+        }
+    }
+}
+
+/// /history 的查询条件。
+///
+/// 条件保存在数据库中，翻页按钮的回调数据只带查询编号和页码，
+/// 避免中文钱包名和分类超出 Telegram 的 64 字节限制。
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct HistoryQuery {
+    id: i64,
+    filter: TransactionFilter,
+}
+
+impl HistoryQuery {
+    /// 解析 "<钱包名> [月份] [年份] [类型] [分类]"
+    fn parse(args: &str) -> Option<TransactionFilter> {
+        let mut args = args.split_whitespace();
+        let wallet_name = args.next()?.trim_start_matches('#').to_string();
+        let mut filter = TransactionFilter {
+            wallet_name: Some(wallet_name),
+            ..Default::default()
+        };
+
+        for arg in args {
+            match PeriodArg::parse(arg) {
+                Some(PeriodArg::Month(m)) => filter.month = Some(m),
+                Some(PeriodArg::Year(y)) => filter.year = Some(y),
+                None => {
+                    let tag = arg.trim_start_matches('#');
+                    match tag {
+                        "出账" | "入账" | "收入" | "支出" | "转出" | "转入" => {
+                            filter.transaction_type = Some(tag.to_string())
+                        }
+                        _ => filter.category = Some(tag.to_string()),
+                    }
+                }
+            }
+        }
+
+        Some(filter)
+    }
+
+    fn title(&self, settings: &ChatSettings) -> String {
+        let filter = &self.filter;
+        let wallet_name = filter.wallet_name.as_deref().unwrap_or_default();
+        let period = Formatter::format_period(settings, filter.month, filter.year);
+        let mut title = match settings.language {
+            Language::Chinese => format!("{wallet_name} 交易记录 · {period}"),
+            Language::English => format!("{wallet_name} transactions · {period}"),
+        };
+        for extra in [&filter.transaction_type, &filter.category]
+            .into_iter()
+            .flatten()
+        {
+            title.push_str(&format!(" · {extra}"));
+        }
+        title
+    }
+
+    /// 编码为 "history:查询编号:页码"
+    fn to_callback_data(&self, page: usize) -> String {
+        format!("{HISTORY_CALLBACK_PREFIX}:{}:{page}", self.id)
+    }
+
+    /// 解析出查询编号和页码
+    fn from_callback_data(data: &str) -> Option<(i64, usize)> {
+        let mut parts = data.split(':');
+        if parts.next()? != HISTORY_CALLBACK_PREFIX {
+            return None;
+        }
+        let id = parts.next()?.parse().ok()?;
+        let page = parts.next()?.parse().ok()?;
+        if parts.next().is_some() {
+            return None;
+        }
+        Some((id, page))
+    }
+
+    /// 上一页/下一页按钮，只有一页时不显示键盘
+    fn keyboard(&self, page: &TransactionPage) -> Option<InlineKeyboardMarkup> {
+        let mut buttons = Vec::new();
+        if page.has_previous() {
+            buttons.push(InlineKeyboardButton::callback(
+                "⬅️ 上一页",
+                self.to_callback_data(page.page - 1),
+            ));
+        }
+        if page.has_next() {
+            buttons.push(InlineKeyboardButton::callback(
+                "下一页 ➡️",
+                self.to_callback_data(page.page + 1),
+            ));
+        }
+        (!buttons.is_empty()).then(|| InlineKeyboardMarkup::new(vec![buttons]))
+    }
+}
+
+/// /threshold 的参数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ThresholdAction {
+    Show,
+    Off,
+    /// 同时替换两个阈值，没有写的一项不再判断
+    Set {
+        above: Option<Money>,
+        ratio: Option<u32>,
+    },
+}
+
+impl ThresholdAction {
+    /// 解析 "[金额] [倍数x]"、"off" 或空参数，金额可以带“元”，倍数也可以写作“5倍”
+    fn parse(args: &str) -> Option<Self> {
+        let words: Vec<&str> = args.split_whitespace().collect();
+        match words.as_slice() {
+            [] => return Some(Self::Show),
+            ["off" | "关闭"] => return Some(Self::Off),
+            [_, _, _, ..] => return None,
+            _ => {}
+        }
+
+        let (mut above, mut ratio) = (None, None);
+        for word in words {
+            if let Some(n) = word.strip_suffix(['x', 'X', '倍']) {
+                let n = n.parse().ok().filter(|n| CONFIRM_RATIOS.contains(n))?;
+                if ratio.replace(n).is_some() {
+                    return None;
+                }
+            } else {
+                let amount = word
+                    .trim_end_matches('元')
+                    .parse::<Money>()
+                    .ok()
+                    .filter(|amount| *amount > Money::ZERO)?;
+                if above.replace(amount).is_some() {
+                    return None;
+                }
+            }
+        }
+        Some(Self::Set { above, ratio })
+    }
+}
+
+/// /settings 消息下面的按钮
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SettingsAction {
+    OutputMode,
+    Currency,
+    DefaultWallet,
+    TimezoneDown,
+    TimezoneUp,
+    Language,
+}
+
+impl SettingsAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SettingsAction::OutputMode => "output",
+            SettingsAction::Currency => "currency",
+            SettingsAction::DefaultWallet => "wallet",
+            SettingsAction::TimezoneDown => "tz-",
+            SettingsAction::TimezoneUp => "tz+",
+            SettingsAction::Language => "lang",
+        }
+    }
+
+    /// 编码为 "settings:动作"
+    fn callback_data(&self) -> String {
+        format!("{SETTINGS_CALLBACK_PREFIX}:{}", self.as_str())
+    }
+
+    fn from_callback_data(data: &str) -> Option<Self> {
+        let action = data
+            .strip_prefix(SETTINGS_CALLBACK_PREFIX)?
+            .strip_prefix(':')?;
+        [
+            SettingsAction::OutputMode,
+            SettingsAction::Currency,
+            SettingsAction::DefaultWallet,
+            SettingsAction::TimezoneDown,
+            SettingsAction::TimezoneUp,
+            SettingsAction::Language,
+        ]
+        .into_iter()
+        .find(|candidate| candidate.as_str() == action)
+    }
+
+    /// 修改设置，返回设置是否有变化。
+    ///
+    /// 可选项按顺序轮换；默认钱包在“未设置”和聊天的钱包之间轮换，时区到达范围边界后不再变化。
+    fn apply(&self, settings: &mut ChatSettings, wallets: &[String]) -> bool {
+        let before = settings.clone();
+        match self {
+            SettingsAction::OutputMode => {
+                settings.output_mode = next_option(&OutputMode::ALL, &settings.output_mode);
+            }
+            SettingsAction::Currency => {
+                let currencies = CURRENCIES.map(str::to_string);
+                settings.currency = next_option(&currencies, &settings.currency);
+            }
+            SettingsAction::DefaultWallet => {
+                let options: Vec<Option<String>> = std::iter::once(None)
+                    .chain(wallets.iter().cloned().map(Some))
+                    .collect();
+                settings.default_wallet = next_option(&options, &settings.default_wallet);
+            }
+            SettingsAction::TimezoneDown | SettingsAction::TimezoneUp => {
+                let step = if *self == SettingsAction::TimezoneUp {
+                    1
+                } else {
+                    -1
+                };
+                let offset = settings.utc_offset + step;
+                if UTC_OFFSETS.contains(&offset) {
+                    settings.utc_offset = offset;
+                }
+            }
+            SettingsAction::Language => {
+                settings.language = match settings.language {
+                    Language::Chinese => Language::English,
+                    Language::English => Language::Chinese,
+                };
+            }
+        }
+        *settings != before
+    }
+}
+
+/// 列表中 current 的下一项，到末尾或不在列表中时回到第一项
+fn next_option<T: Clone + PartialEq>(options: &[T], current: &T) -> T {
+    let next = options
+        .iter()
+        .position(|option| option == current)
+        .map_or(0, |i| (i + 1) % options.len());
+    options[next].clone()
+}
+
+fn settings_keyboard() -> InlineKeyboardMarkup {
+    let button = |text: &str, action: SettingsAction| {
+        InlineKeyboardButton::callback(text, action.callback_data())
+    };
+    InlineKeyboardMarkup::new(vec![
+        vec![button("📤 切换输出方式", SettingsAction::OutputMode)],
+        vec![button("💱 切换货币单位", SettingsAction::Currency)],
+        vec![button("👛 切换默认钱包", SettingsAction::DefaultWallet)],
+        vec![
+            button("🕒 时区 -1", SettingsAction::TimezoneDown),
+            button("🕒 时区 +1", SettingsAction::TimezoneUp),
+        ],
+        vec![button("🌐 切换语言", SettingsAction::Language)],
+    ])
+}
+
+/// 时间范围的显示文字
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Telegram 对回调数据长度的限制（字节）
+    const MAX_CALLBACK_DATA_LEN: usize = 64;
+
+    #[test]
+    fn test_parse_period() {
+        assert_eq!(parse_period("12月 2024年"), Some((Some(12), Some(2024))));
+        assert_eq!(parse_period("#3月"), Some((Some(3), None)));
+        assert_eq!(parse_period("2024"), Some((None, Some(2024))));
+        assert_eq!(parse_period(""), Some((None, None)));
+        assert_eq!(parse_period("13月"), None);
+        assert_eq!(parse_period("上个月"), None);
+    }
+
+    #[test]
+    fn test_history_query_round_trip() {
+        let filter = HistoryQuery::parse("#支付宝 12月 2024年 出账 #餐饮").unwrap();
+        assert_eq!(
+            filter,
+            TransactionFilter {
+                wallet_name: Some("支付宝".to_string()),
+                month: Some(12),
+                year: Some(2024),
+                transaction_type: Some("出账".to_string()),
+                category: Some("餐饮".to_string()),
+            }
+        );
+        assert!(HistoryQuery::parse("").is_none());
+
+        // 再长的中文条件也只在回调数据中保存编号
+        let filter = HistoryQuery::parse(&format!(
+            "{} 12月 2024年 {}",
+            "钱".repeat(20),
+            "分类".repeat(10)
+        ))
+        .unwrap();
+        let query = HistoryQuery { id: 123456, filter };
+        let data = query.to_callback_data(3);
+        assert_eq!(data, "history:123456:3");
+        assert!(data.len() <= MAX_CALLBACK_DATA_LEN);
+        assert_eq!(HistoryQuery::from_callback_data(&data), Some((123456, 3)));
+
+        assert!(HistoryQuery::from_callback_data("confirm:1").is_none());
+        assert!(HistoryQuery::from_callback_data("history:3:支付宝:12:2024:出账:餐饮").is_none());
+    }
+
+    #[test]
+    fn test_summary_action() {
+        assert_eq!(SummaryAction::parse(""), Some(SummaryAction::Status));
+        assert_eq!(SummaryAction::parse("off"), Some(SummaryAction::Off));
+        assert_eq!(
+            SummaryAction::parse("on"),
+            Some(SummaryAction::On {
+                day: None,
+                hour: None
+            })
+        );
+        assert_eq!(
+            SummaryAction::parse("开启 3日 20点"),
+            Some(SummaryAction::On {
+                day: Some(3),
+                hour: Some(20)
+            })
+        );
+        assert!(SummaryAction::parse("on 31").is_none());
+        assert!(SummaryAction::parse("on 1 24").is_none());
+        assert!(SummaryAction::parse("off now").is_none());
+        assert!(SummaryAction::parse("weekly").is_none());
+    }
+
+    #[test]
+    fn test_parse_access_action() {
+        assert_eq!(AccessAction::parse(""), Some(AccessAction::Show));
+        assert_eq!(
+            AccessAction::parse("writer 123"),
+            Some(AccessAction::Grant(Role::Writer, Some(123)))
+        );
+        assert_eq!(
+            AccessAction::parse("只读"),
+            Some(AccessAction::Grant(Role::Reader, None))
+        );
+        assert_eq!(
+            AccessAction::parse("remove 123"),
+            Some(AccessAction::Revoke(Some(123)))
+        );
+        assert_eq!(
+            AccessAction::parse("restrict"),
+            Some(AccessAction::Restrict(true))
+        );
+        assert_eq!(
+            AccessAction::parse("reject"),
+            Some(AccessAction::RejectUnauthorized(true))
+        );
+        assert_eq!(AccessAction::parse("guest 123"), None);
+        assert_eq!(AccessAction::parse("writer abc"), None);
+        assert_eq!(AccessAction::parse("open 123"), None);
+        assert_eq!(AccessAction::parse("writer 1 2"), None);
+    }
+
+    #[test]
+    fn test_settings_action() {
+        for action in [SettingsAction::Currency, SettingsAction::TimezoneUp] {
+            assert_eq!(
+                SettingsAction::from_callback_data(&action.callback_data()),
+                Some(action)
+            );
+        }
+        assert_eq!(SettingsAction::from_callback_data("settings:unknown"), None);
+        assert_eq!(SettingsAction::from_callback_data("history:0:::::"), None);
+
+        let mut settings = ChatSettings::new(1);
+        let wallets = ["支付宝".to_string(), "微信".to_string()];
+        for expected in [Some("支付宝"), Some("微信"), None] {
+            assert!(SettingsAction::DefaultWallet.apply(&mut settings, &wallets));
+            assert_eq!(settings.default_wallet.as_deref(), expected);
+        }
+
+        settings.currency = "CHF".to_string();
+        assert!(SettingsAction::Currency.apply(&mut settings, &[]));
+        assert_eq!(settings.currency, "元");
+
+        settings.utc_offset = 14;
+        assert!(!SettingsAction::TimezoneUp.apply(&mut settings, &[]));
+        assert!(SettingsAction::TimezoneDown.apply(&mut settings, &[]));
+        assert_eq!(settings.utc_offset, 13);
+    }
+
+    #[test]
+    fn test_threshold_action() {
+        assert_eq!(ThresholdAction::parse(""), Some(ThresholdAction::Show));
+        assert_eq!(ThresholdAction::parse("off"), Some(ThresholdAction::Off));
+        assert_eq!(
+            ThresholdAction::parse("1000元 5x"),
+            Some(ThresholdAction::Set {
+                above: Some(Money::from_yuan(1000)),
+                ratio: Some(5)
+            })
+        );
+        assert_eq!(
+            ThresholdAction::parse("10倍"),
+            Some(ThresholdAction::Set {
+                above: None,
+                ratio: Some(10)
+            })
+        );
+        assert!(ThresholdAction::parse("1x").is_none());
+        assert!(ThresholdAction::parse("0").is_none());
+        assert!(ThresholdAction::parse("100 200").is_none());
+        assert!(ThresholdAction::parse("off 100").is_none());
+        assert!(ThresholdAction::parse("abc").is_none());
+    }
 }
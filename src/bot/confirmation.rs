@@ -0,0 +1,253 @@
+use crate::database::models::ChatSettings;
+use crate::database::operations::DatabaseOperations;
+use crate::money::Money;
+use anyhow::Result;
+use log::debug;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, Message};
+
+/// 确认/取消按钮的回调数据前缀
+const CALLBACK_PREFIX: &str = "pending";
+/// 计算钱包平时金额时参考的最近交易数
+const TYPICAL_SAMPLE: usize = 50;
+/// 最近的交易少于这个数时不按倍数判断
+const MIN_TYPICAL_SAMPLE: usize = 5;
+/// 最多保留的待确认交易数，超过后清空，避免长时间运行时无限增长
+const MAX_PENDING: usize = 1000;
+
+/// 等待确认的交易默认保留的时间
+pub const DEFAULT_PENDING_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// 需要确认的金额
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LargeAmount {
+    pub wallet_name: String,
+    pub amount: Money,
+    pub reason: LargeAmountReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LargeAmountReason {
+    Above(Money),                         // 超过设置的金额
+    Ratio { typical: Money, ratio: u32 }, // 超过钱包平时金额的倍数
+}
+
+/// 按聊天设置的阈值检查一条消息中的金额，返回第一笔需要确认的金额。
+///
+/// 平时金额是钱包最近几十笔收支金额的中位数，交易太少时只按固定金额判断。
+pub async fn find_large_amount(
+    db: &DatabaseOperations,
+    settings: &ChatSettings,
+    wallet_name: &str,
+    amounts: &[Money],
+) -> Result<Option<LargeAmount>> {
+    let large = |amount: Money, reason| LargeAmount {
+        wallet_name: wallet_name.to_string(),
+        amount,
+        reason,
+    };
+
+    if let Some(limit) = settings.confirm_above {
+        if let Some(&amount) = amounts.iter().find(|amount| amount.abs() > limit) {
+            return Ok(Some(large(amount, LargeAmountReason::Above(limit))));
+        }
+    }
+
+    let Some(ratio) = settings.confirm_ratio else {
+        return Ok(None);
+    };
+    let Some(typical) = typical_amount(
+        db.get_recent_amounts(settings.chat_id, wallet_name, TYPICAL_SAMPLE)
+            .await?,
+    ) else {
+        debug!("Wallet {wallet_name} has too few transactions for a typical amount");
+        return Ok(None);
+    };
+    let limit = Money::from_cents(typical.cents().saturating_mul(ratio.into()));
+    Ok(amounts
+        .iter()
+        .find(|amount| amount.abs() > limit)
+        .map(|&amount| large(amount, LargeAmountReason::Ratio { typical, ratio })))
+}
+
+/// 金额的中位数，样本太少或全为 0 时返回 None
+fn typical_amount(amounts: Vec<Money>) -> Option<Money> {
+    if amounts.len() < MIN_TYPICAL_SAMPLE {
+        return None;
+    }
+    let mut amounts: Vec<Money> = amounts.into_iter().map(Money::abs).collect();
+    amounts.sort();
+    let typical = amounts[amounts.len() / 2];
+    (!typical.is_zero()).then_some(typical)
+}
+
+/// 确认/取消按钮
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmationAction {
+    Confirm(u64),
+    Cancel(u64),
+}
+
+impl ConfirmationAction {
+    /// 编码为 "pending:confirm:编号" 或 "pending:cancel:编号"
+    pub fn callback_data(&self) -> String {
+        match self {
+            ConfirmationAction::Confirm(id) => format!("{CALLBACK_PREFIX}:confirm:{id}"),
+            ConfirmationAction::Cancel(id) => format!("{CALLBACK_PREFIX}:cancel:{id}"),
+        }
+    }
+
+    pub fn from_callback_data(data: &str) -> Option<Self> {
+        let mut parts = data.split(':');
+        if parts.next()? != CALLBACK_PREFIX {
+            return None;
+        }
+        let action = parts.next()?;
+        let id = parts.next()?.parse().ok()?;
+        if parts.next().is_some() {
+            return None;
+        }
+        match action {
+            "confirm" => Some(ConfirmationAction::Confirm(id)),
+            "cancel" => Some(ConfirmationAction::Cancel(id)),
+            _ => None,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        match self {
+            ConfirmationAction::Confirm(id) | ConfirmationAction::Cancel(id) => *id,
+        }
+    }
+
+    pub fn keyboard(id: u64) -> InlineKeyboardMarkup {
+        InlineKeyboardMarkup::new(vec![vec![
+            InlineKeyboardButton::callback(
+                "✅ 确认",
+                ConfirmationAction::Confirm(id).callback_data(),
+            ),
+            InlineKeyboardButton::callback(
+                "❌ 取消",
+                ConfirmationAction::Cancel(id).callback_data(),
+            ),
+        ]])
+    }
+}
+
+/// 等待确认的交易消息，确认后按原消息重新走一遍记账流程
+#[derive(Debug, Clone)]
+pub struct PendingTransaction {
+    pub message: Message,
+    pub text: String,
+    pub is_transfer: bool,
+    pub is_edit: bool, // 已记账消息的编辑，确认后按编辑重新计算，确认前保留原来的记账
+    created_at: Instant,
+}
+
+/// 等待确认的交易。
+///
+/// 只保存在内存中：过期或重启后交易不会被记账，需要重新发送或使用 /reprocess。
+/// 同一条消息再次需要确认时（例如被编辑），旧的确认按钮失效。
+#[derive(Clone, Debug)]
+pub struct PendingConfirmations {
+    timeout: Duration,
+    state: Arc<Mutex<PendingState>>,
+}
+
+#[derive(Debug, Default)]
+struct PendingState {
+    next_id: u64,
+    entries: HashMap<u64, PendingTransaction>,
+}
+
+impl Default for PendingConfirmations {
+    fn default() -> Self {
+        Self::new(DEFAULT_PENDING_TIMEOUT)
+    }
+}
+
+impl PendingConfirmations {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            state: Arc::default(),
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// 登记一条等待确认的消息，返回按钮使用的编号
+    pub fn insert(&self, message: &Message, text: &str, is_transfer: bool, is_edit: bool) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let timeout = self.timeout;
+        state.entries.retain(|_, pending| {
+            pending.created_at.elapsed() < timeout
+                && (pending.message.chat.id, pending.message.id) != (message.chat.id, message.id)
+        });
+        if state.entries.len() >= MAX_PENDING {
+            state.entries.clear();
+        }
+
+        state.next_id += 1;
+        let id = state.next_id;
+        state.entries.insert(
+            id,
+            PendingTransaction {
+                message: message.clone(),
+                text: text.to_string(),
+                is_transfer,
+                is_edit,
+                created_at: Instant::now(),
+            },
+        );
+        id
+    }
+
+    /// 取出等待确认的交易，已过期或已处理时返回 None
+    pub fn take(&self, id: u64) -> Option<PendingTransaction> {
+        let pending = self.state.lock().unwrap().entries.remove(&id)?;
+        (pending.created_at.elapsed() < self.timeout).then_some(pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confirmation_action_round_trip() {
+        for action in [
+            ConfirmationAction::Confirm(42),
+            ConfirmationAction::Cancel(7),
+        ] {
+            assert_eq!(
+                ConfirmationAction::from_callback_data(&action.callback_data()),
+                Some(action)
+            );
+        }
+        assert_eq!(ConfirmationAction::from_callback_data("pending:ok:1"), None);
+        assert_eq!(
+            ConfirmationAction::from_callback_data("pending:confirm:x"),
+            None
+        );
+        assert_eq!(
+            ConfirmationAction::from_callback_data("settings:lang"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_typical_amount() {
+        let amounts = |cents: &[i64]| cents.iter().map(|&c| Money::from_cents(c)).collect();
+        assert_eq!(typical_amount(amounts(&[100, 200, 300])), None);
+        assert_eq!(
+            typical_amount(amounts(&[100, 9000, 300, 200, 500])),
+            Some(Money::from_cents(300))
+        );
+        assert_eq!(typical_amount(amounts(&[0, 0, 0, 0, 100])), None);
+    }
+}
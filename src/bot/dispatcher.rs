@@ -1,9 +1,20 @@
 use anyhow::Result;
 use log::{debug, error, info};
-use teloxide::{prelude::*, types::Update, utils::command::BotCommands, RequestError};
+use std::net::SocketAddr;
+use teloxide::{
+    dispatching::UpdateHandler, prelude::*, types::Update, update_listeners::webhooks,
+    utils::command::BotCommands, RequestError,
+};
 
 use crate::bot::commands::Commands;
+use crate::bot::confirmation::ConfirmationAction;
 use crate::bot::handler::MessageHandler;
+use crate::bot::permissions::ChatAllowlist;
+use crate::bot::scheduler::SummaryScheduler;
+use crate::bot::traits::BotApi;
+use crate::config::Settings;
+use crate::database::models::Role;
+use std::time::Duration;
 
 #[derive(BotCommands, Clone, Debug)]
 #[command(rename_rule = "lowercase", description = "支持的命令:")]
@@ -14,13 +25,85 @@ pub enum Command {
     Help,
     #[command(description = "重新处理消息")]
     Reprocess,
+    #[command(description = "撤销最近一笔记账，回复某条消息时撤销该消息")]
+    Undo,
     #[command(description = "查看机器人状态")]
     Status,
+    #[command(description = "按账本核对余额，用法：/reconcile [钱包名] [fix]")]
+    Reconcile(String),
+    #[command(description = "按月查看未被交易解释的余额变化")]
+    Adjustments,
+    #[command(description = "查看钱包余额，用法：/balance [钱包名]")]
+    Balance(String),
+    #[command(description = "列出本聊天的所有钱包")]
+    Wallets,
+    #[command(description = "分页查看交易记录，用法：/history <钱包名> [月份] [年份] [类型]")]
+    History(String),
+    #[command(description = "月度/年度报表，用法：/report [钱包名] [月份] [年份]")]
+    Report(String),
+    #[command(description = "月度总结定时发送，用法：/summary [on [日] [时] | off]")]
+    Summary(String),
+    #[command(description = "按分类汇总收支，用法：/categories [月份] [年份]")]
+    Categories(String),
+    #[command(description = "导出交易记录和钱包余额为 CSV，用法：/export [钱包名] [月份] [年份]")]
+    Export(String),
+    #[command(description = "导入 CSV/JSON 文件中的历史交易，回复文件使用：/import [dry]")]
+    Import(String),
+    #[command(
+        description = "查看或设置权限，用法：/access [open|restrict|ignore|reject|admin|writer|reader|remove] [用户ID]"
+    )]
+    Access(String),
+    #[command(
+        description = "记账结果的输出方式，用法：/output [edit|confirm|temporary [秒]|discussion]"
+    )]
+    Output(String),
+    #[command(description = "查看和修改本聊天的设置：输出方式、货币、默认钱包、时区和语言")]
+    Settings,
+    #[command(description = "大额交易先确认再记账，用法：/threshold [金额] [倍数x] | off")]
+    Threshold(String),
+}
+
+impl Command {
+    /// 使用命令需要的最低角色
+    pub fn required_role(&self) -> Role {
+        match self {
+            Command::Start | Command::Help => Role::Guest,
+            Command::Status
+            | Command::Adjustments
+            | Command::Balance(_)
+            | Command::Wallets
+            | Command::History(_)
+            | Command::Report(_)
+            | Command::Categories(_)
+            | Command::Export(_) => Role::Reader,
+            // 只查看结果时只读即可，修正余额和修改订阅需要管理员
+            Command::Reconcile(args)
+                if !args.split_whitespace().any(|a| a == "fix" || a == "修正") =>
+            {
+                Role::Reader
+            }
+            Command::Summary(args) | Command::Output(args) | Command::Threshold(args)
+                if args.trim().is_empty() =>
+            {
+                Role::Reader
+            }
+            Command::Undo => Role::Writer,
+            Command::Reconcile(_)
+            | Command::Summary(_)
+            | Command::Reprocess
+            | Command::Import(_)
+            | Command::Access(_)
+            | Command::Output(_)
+            | Command::Threshold(_)
+            | Command::Settings => Role::Admin,
+        }
+    }
 }
 
 pub struct BotDispatcher {
     message_handler: MessageHandler,
     commands: Commands,
+    allowlist: ChatAllowlist,
 }
 
 impl BotDispatcher {
@@ -29,55 +112,101 @@ impl BotDispatcher {
         Self {
             message_handler,
             commands,
+            allowlist: ChatAllowlist::default(),
         }
     }
 
-    pub async fn run(self, bot: Bot) -> Result<()> {
-        info!("🤖 Starting WalletBot dispatcher...");
+    /// 只处理允许列表中的聊天
+    pub fn with_allowlist(mut self, allowlist: ChatAllowlist) -> Self {
+        self.allowlist = allowlist;
+        self
+    }
 
+    /// 更新的处理树，消息、命令和回调都通过 `B` 调用 Telegram。
+    ///
+    /// 正式运行时 `B` 是 teloxide 的 `Bot`，测试时可以把 mock 放进依赖中直接分发更新。
+    pub fn schema<B>(&self) -> UpdateHandler<RequestError>
+    where
+        B: BotApi + Clone + 'static,
+    {
         let message_handler = self.message_handler.clone();
         let edited_message_handler = self.message_handler.clone();
         let channel_post_handler = self.message_handler.clone();
         let edited_channel_post_handler = self.message_handler.clone();
         let commands = self.commands.clone();
+        let document_commands = self.commands.clone();
+        let callback_commands = self.commands.clone();
+        let confirmation_handler = self.message_handler.clone();
+        let allowlist = self.allowlist.clone();
+        let rejected_chats = self.allowlist.clone();
 
-        Dispatcher::builder(
-            bot,
-            dptree::entry()
-                // 处理常规消息
-                .branch(Update::filter_message()
+        dptree::entry()
+            // 不在允许列表中的聊天，任何更新都不会进入下面的处理
+            .branch(
+                dptree::filter(move |update: Update| {
+                    update.chat().is_some_and(|chat| !allowlist.allows(chat))
+                })
+                .endpoint(move |bot: B, update: Update| {
+                    let allowlist = rejected_chats.clone();
+                    async move {
+                        if let Some(chat) = update.chat() {
+                            allowlist.reject(&bot, chat).await;
+                        }
+                        Ok::<(), RequestError>(())
+                    }
+                }),
+            )
+            // 处理常规消息
+            .branch(
+                Update::filter_message()
                     .branch(dptree::entry().filter_command::<Command>().endpoint(
-                        move |bot: Bot, msg: Message, cmd: Command| {
+                        move |bot: B, msg: Message, cmd: Command| {
                             let commands = commands.clone();
                             async move {
                                 debug!("Handling command: {cmd:?}");
 
-                                let command_str = match cmd {
-                                    Command::Start => "/start",
-                                    Command::Help => "/help",
-                                    Command::Reprocess => "/reprocess",
-                                    Command::Status => "/status",
-                                };
-
-                                if let Err(e) = commands.handle_command(&bot, &msg, command_str).await {
-                                    error!("Failed to handle command {command_str}: {e}");
+                                if let Err(e) =
+                                    commands.handle_command(&bot, &msg, cmd.clone()).await
+                                {
+                                    error!("Failed to handle command {cmd:?}: {e}");
                                 }
 
                                 Ok::<(), RequestError>(())
                             }
-                        }
+                        },
                     ))
+                    // 发送文件时附上 /import 说明，直接导入该文件
+                    .branch(
+                        dptree::filter_map(|msg: Message| {
+                            msg.document()?;
+                            import_caption_args(msg.caption()?)
+                        })
+                        .endpoint(
+                            move |bot: B, msg: Message, args: String| {
+                                let commands = document_commands.clone();
+                                async move {
+                                    let command = Command::Import(args);
+                                    if let Err(e) =
+                                        commands.handle_command(&bot, &msg, command).await
+                                    {
+                                        error!("Failed to import document: {e}");
+                                    }
+                                    Ok::<(), RequestError>(())
+                                }
+                            },
+                        ),
+                    )
                     .branch(
-                        dptree::filter(|msg: Message| msg.text().is_some())
-                            .endpoint(move |bot: Bot, msg: Message| {
+                        dptree::filter(|msg: Message| msg.text().is_some()).endpoint(
+                            move |bot: B, msg: Message| {
                                 let handler = message_handler.clone();
                                 async move {
                                     debug!(
-                                        "📨 Processing message from chat: {}, type: {:?}, user: {:?}",
-                                        msg.chat.id,
-                                        msg.chat.kind,
-                                        msg.from()
-                                    );
+                                    "📨 Processing message from chat: {}, type: {:?}, user: {:?}",
+                                    msg.chat.id,
+                                    msg.chat.kind,
+                                    msg.from()
+                                );
 
                                     if let Some(text) = msg.text() {
                                         debug!("📄 Message text: {text}");
@@ -87,99 +216,200 @@ impl BotDispatcher {
                                             error!("❌ Failed to handle message: {e}");
 
                                             // 只在可以发送消息的聊天中发送错误
-                                            if !matches!(msg.chat.kind, teloxide::types::ChatKind::Public(_)) {
-                                                let error_text = "❌ 处理消息时出现错误，请稍后重试。";
-                                                let _ = bot.send_message(msg.chat.id, error_text).await;
+                                            if !matches!(
+                                                msg.chat.kind,
+                                                teloxide::types::ChatKind::Public(_)
+                                            ) {
+                                                let error_text =
+                                                    "❌ 处理消息时出现错误，请稍后重试。";
+                                                let _ =
+                                                    bot.send_message(msg.chat.id, error_text).await;
                                             }
                                         }
                                     }
 
                                     Ok::<(), RequestError>(())
                                 }
-                            }),
-                    ))
-                // 处理编辑的消息
-                .branch(Update::filter_edited_message().branch(
-                    dptree::filter(|msg: Message| msg.text().is_some())
-                        .endpoint(move |bot: Bot, msg: Message| {
-                            let handler = edited_message_handler.clone();
-                            async move {
-                                debug!("📝 Processing edited message from chat: {}", msg.chat.id);
-                                if let Some(text) = msg.text() {
-                                    debug!("📄 Edited message text: {text}");
+                            },
+                        ),
+                    ),
+            )
+            // 大额交易的“确认 / 取消”按钮
+            .branch(
+                Update::filter_callback_query()
+                    .filter(|query: CallbackQuery| {
+                        query
+                            .data
+                            .as_deref()
+                            .and_then(ConfirmationAction::from_callback_data)
+                            .is_some()
+                    })
+                    .endpoint(move |bot: B, query: CallbackQuery| {
+                        let handler = confirmation_handler.clone();
+                        async move {
+                            if let Err(e) = handler.handle_confirmation_callback(&bot, &query).await
+                            {
+                                error!("❌ Failed to handle confirmation: {e}");
+                            }
+                            Ok::<(), RequestError>(())
+                        }
+                    }),
+            )
+            // 处理其他内联键盘按钮
+            .branch(Update::filter_callback_query().endpoint(
+                move |bot: B, query: CallbackQuery| {
+                    let commands = callback_commands.clone();
+                    async move {
+                        if let Err(e) = commands.handle_callback_query(&bot, &query).await {
+                            error!("❌ Failed to handle callback query: {e}");
+                        }
+                        Ok::<(), RequestError>(())
+                    }
+                },
+            ))
+            // 处理编辑的消息
+            .branch(Update::filter_edited_message().branch(
+                dptree::filter(|msg: Message| msg.text().is_some()).endpoint(
+                    move |bot: B, msg: Message| {
+                        let handler = edited_message_handler.clone();
+                        async move {
+                            debug!("📝 Processing edited message from chat: {}", msg.chat.id);
+                            if let Some(text) = msg.text() {
+                                debug!("📄 Edited message text: {text}");
 
-                                    if let Err(e) = handler.handle_message(&bot, &msg).await {
-                                        error!("❌ Failed to handle edited message: {e}");
+                                if let Err(e) = handler.handle_edited_message(&bot, &msg).await {
+                                    error!("❌ Failed to handle edited message: {e}");
 
-                                        if !matches!(msg.chat.kind, teloxide::types::ChatKind::Public(_)) {
-                                            let error_text = "❌ 处理编辑消息时出现错误。";
-                                            let _ = bot.send_message(msg.chat.id, error_text).await;
-                                        }
+                                    if !matches!(
+                                        msg.chat.kind,
+                                        teloxide::types::ChatKind::Public(_)
+                                    ) {
+                                        let error_text = "❌ 处理编辑消息时出现错误。";
+                                        let _ = bot.send_message(msg.chat.id, error_text).await;
                                     }
                                 }
-                                Ok::<(), RequestError>(())
                             }
-                        }),
-                ))
-                // 处理频道帖子
-                .branch(Update::filter_channel_post().branch(
-                    dptree::filter(|post: Message| post.text().is_some())
-                        .endpoint(move |bot: Bot, post: Message| {
-                            let handler = channel_post_handler.clone();
-                            async move {
-                                debug!(
-                                    "📢 Processing channel post from channel: {}, title: {:?}",
-                                    post.chat.id,
-                                    post.chat.title()
-                                );
+                            Ok::<(), RequestError>(())
+                        }
+                    },
+                ),
+            ))
+            // 处理频道帖子
+            .branch(Update::filter_channel_post().branch(
+                dptree::filter(|post: Message| post.text().is_some()).endpoint(
+                    move |bot: B, post: Message| {
+                        let handler = channel_post_handler.clone();
+                        async move {
+                            debug!(
+                                "📢 Processing channel post from channel: {}, title: {:?}",
+                                post.chat.id,
+                                post.chat.title()
+                            );
 
-                                if let Some(text) = post.text() {
-                                    debug!("📄 Channel post text: {text}");
+                            if let Some(text) = post.text() {
+                                debug!("📄 Channel post text: {text}");
 
-                                    // 处理频道帖子
-                                    if let Err(e) = handler.handle_message(&bot, &post).await {
-                                        error!("❌ Failed to handle channel post: {e}");
-                                        // 频道消息通常无法回复，所以不发送错误消息
-                                    }
+                                // 处理频道帖子
+                                if let Err(e) = handler.handle_message(&bot, &post).await {
+                                    error!("❌ Failed to handle channel post: {e}");
+                                    // 频道消息通常无法回复，所以不发送错误消息
                                 }
-
-                                Ok::<(), RequestError>(())
                             }
-                        }),
-                ))
-                // 处理编辑的频道帖子
-                .branch(Update::filter_edited_channel_post().branch(
-                    dptree::filter(|post: Message| post.text().is_some())
-                        .endpoint(move |bot: Bot, post: Message| {
-                            let handler = edited_channel_post_handler.clone();
-                            async move {
-                                debug!("📝 Processing edited channel post from channel: {}", post.chat.id);
-                                if let Some(text) = post.text() {
-                                    debug!("📄 Edited channel post text: {text}");
 
-                                    if let Err(e) = handler.handle_message(&bot, &post).await {
-                                        error!("❌ Failed to handle edited channel post: {e}");
-                                    }
+                            Ok::<(), RequestError>(())
+                        }
+                    },
+                ),
+            ))
+            // 处理编辑的频道帖子
+            .branch(Update::filter_edited_channel_post().branch(
+                dptree::filter(|post: Message| post.text().is_some()).endpoint(
+                    move |bot: B, post: Message| {
+                        let handler = edited_channel_post_handler.clone();
+                        async move {
+                            debug!(
+                                "📝 Processing edited channel post from channel: {}",
+                                post.chat.id
+                            );
+                            if let Some(text) = post.text() {
+                                debug!("📄 Edited channel post text: {text}");
+
+                                if let Err(e) = handler.handle_edited_message(&bot, &post).await {
+                                    error!("❌ Failed to handle edited channel post: {e}");
                                 }
-                                Ok::<(), RequestError>(())
                             }
-                        }),
-                )),
-        )
-        .enable_ctrlc_handler()
-        .build()
-        .dispatch()
-        .await;
+                            Ok::<(), RequestError>(())
+                        }
+                    },
+                ),
+            ))
+    }
+
+    /// 处理更新直到进程退出：给出 webhook 配置时由本地 HTTP 服务接收更新，否则使用长轮询
+    pub async fn run(self, bot: Bot, webhook: Option<webhooks::Options>) -> Result<()> {
+        info!("🤖 Starting WalletBot dispatcher...");
+
+        let mut dispatcher = Dispatcher::builder(bot.clone(), self.schema::<Bot>())
+            .enable_ctrlc_handler()
+            .build();
+
+        match webhook {
+            Some(options) => {
+                info!(
+                    "🌐 Receiving updates via webhook {} on {}",
+                    options.url, options.address
+                );
+                // 请求头中的密钥与设置的不一致时，请求会被拒绝，不会进入处理流程
+                let listener = webhooks::axum(bot, options).await?;
+                dispatcher
+                    .dispatch_with_listener(
+                        listener,
+                        LoggingErrorHandler::with_custom_text("Webhook update listener error"),
+                    )
+                    .await;
+            }
+            None => {
+                info!("🔄 Receiving updates via long polling");
+                dispatcher.dispatch().await;
+            }
+        }
 
         Ok(())
     }
 }
 
+/// 文件说明以 /import 开头时返回命令参数，支持 /import@机器人名 的写法
+fn import_caption_args(caption: &str) -> Option<String> {
+    let rest = caption.strip_prefix("/import")?;
+    let rest = match rest.strip_prefix('@') {
+        Some(mention) => mention
+            .split_once(char::is_whitespace)
+            .map_or("", |(_, args)| args),
+        None if rest.is_empty() || rest.starts_with(char::is_whitespace) => rest,
+        None => return None,
+    };
+    Some(rest.trim().to_string())
+}
+
+/// 根据配置生成 webhook 选项，没有设置 WEBHOOK_URL 时返回 None（使用长轮询）
+pub fn webhook_options(settings: &Settings) -> Result<Option<webhooks::Options>> {
+    let Some(url) = &settings.webhook_url else {
+        return Ok(None);
+    };
+    let url = url::Url::parse(url)?;
+    let address = SocketAddr::from(([0, 0, 0, 0], settings.webhook_port));
+    let options = webhooks::Options::new(address, url);
+    Ok(Some(match &settings.webhook_secret {
+        Some(secret) => options.secret_token(secret.clone()),
+        None => options,
+    }))
+}
+
 /// 启动机器人的主函数
-pub async fn start_bot(token: &str, message_handler: MessageHandler) -> Result<()> {
+pub async fn start_bot(settings: &Settings, message_handler: MessageHandler) -> Result<()> {
     info!("🚀 Initializing Telegram Bot...");
 
-    let bot = Bot::new(token);
+    let bot = Bot::new(&settings.telegram_bot_token);
 
     // 获取机器人信息
     match bot.get_me().await {
@@ -195,14 +425,25 @@ pub async fn start_bot(token: &str, message_handler: MessageHandler) -> Result<(
         }
     }
 
+    // 月度总结定时任务与消息处理并行运行
+    let allowlist = ChatAllowlist::from_settings(settings);
+    let scheduler = SummaryScheduler::new(
+        bot.clone(),
+        message_handler.db().clone(),
+        Duration::from_secs(settings.summary_check_interval),
+    )
+    .with_allowlist(allowlist.clone());
+    let scheduler = tokio::spawn(scheduler.run());
+
     // 创建并启动调度器
-    let dispatcher = BotDispatcher::new(message_handler);
+    let dispatcher = BotDispatcher::new(message_handler).with_allowlist(allowlist);
 
     info!("🎯 Starting message processing...");
     info!("💡 Bot is now ready to receive messages!");
     info!("📝 Send a wallet transaction message to get started.");
 
-    dispatcher.run(bot).await?;
+    dispatcher.run(bot, webhook_options(settings)?).await?;
+    scheduler.abort();
 
     Ok(())
 }
@@ -1,15 +1,33 @@
+use crate::bot::confirmation::{
+    find_large_amount, ConfirmationAction, LargeAmount, PendingConfirmations,
+};
+use crate::bot::output::OutputRouter;
+use crate::bot::permissions::AccessControl;
+use crate::bot::traits::BotApi;
 use crate::calculator::balance::BalanceCalculator;
-use crate::database::models::BalanceUpdateSource;
+use crate::database::models::{
+    BalanceUpdateSource, ChatSettings, Message as MessageRecord, ParsedMessage, Role, WalletSummary,
+};
 use crate::database::operations::DatabaseOperations;
-use crate::parser::message::MessageParser;
+use crate::money::Money;
+use crate::parser::message::{MessageParser, UNDONE_MARK};
+use crate::utils::{Confirmation, Formatter};
 use anyhow::Result;
 use log::{debug, error, info, warn};
-use teloxide::{requests::Requester, types::Message, Bot, RequestError};
+use std::borrow::Cow;
+use std::time::Duration;
+use teloxide::{
+    types::{CallbackQuery, Message},
+    RequestError,
+};
 
 #[derive(Clone, Debug)]
 pub struct MessageHandler {
     parser: MessageParser,
     calculator: BalanceCalculator,
+    access: AccessControl,
+    output: OutputRouter,
+    pending: PendingConfirmations,
     db: DatabaseOperations,
 }
 
@@ -19,11 +37,42 @@ impl MessageHandler {
         Self {
             parser: MessageParser::new(),
             calculator,
+            access: AccessControl::new(db.clone(), Vec::new()),
+            output: OutputRouter::default(),
+            pending: PendingConfirmations::default(),
             db,
         }
     }
 
-    pub async fn handle_message(&self, bot: &Bot, message: &Message) -> Result<(), RequestError> {
+    /// 设置在所有聊天中都拥有管理员权限的用户
+    pub fn with_admins(mut self, admins: Vec<i64>) -> Self {
+        self.access = AccessControl::new(self.db.clone(), admins);
+        self
+    }
+
+    /// 设置大额交易等待确认的时间
+    pub fn with_confirmation_timeout(mut self, timeout: Duration) -> Self {
+        self.pending = PendingConfirmations::new(timeout);
+        self
+    }
+
+    pub fn access(&self) -> &AccessControl {
+        &self.access
+    }
+
+    pub fn calculator(&self) -> &BalanceCalculator {
+        &self.calculator
+    }
+
+    pub fn db(&self) -> &DatabaseOperations {
+        &self.db
+    }
+
+    pub async fn handle_message<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+    ) -> Result<(), RequestError> {
         // 记录接收到的消息详情，包括消息类型识别
         debug!(
             "📨 Received message in chat {} ({:?})",
@@ -55,26 +104,42 @@ impl MessageHandler {
             }
         }
 
+        // 频道帖子在讨论组中的自动转发不重复记账，只用来找到评论区
+        if message.is_automatic_forward() {
+            return self.output.handle_automatic_forward(bot, message).await;
+        }
+
         // 只处理文本消息
         if let Some(text) = message.text() {
             debug!("🔄 Processing message: '{text}'");
+            let chat_id = message.chat.id.0;
+            let text = &*self.fill_default_wallet(chat_id, text).await;
 
             // 检查是否是钱包相关消息
-            if !self.parser.is_wallet_message(text) {
+            let is_transfer = self.parser.is_transfer_message(text);
+            if !is_transfer && !self.parser.is_wallet_message(text) {
                 return Ok(());
             }
 
+            if !self
+                .access
+                .message_has_role(bot, message, Role::Writer)
+                .await
+            {
+                return self.reject_unauthorized(bot, message).await;
+            }
+
             // 检查消息是否已经处理过
             debug!("🔍 Checking if message was already processed...");
             let message_id = message.id.0 as i64;
-            let chat_id = message.chat.id.0;
 
             match self.db.is_message_processed(message_id, chat_id).await {
                 Ok(true) => {
                     debug!("⚠️ Message {message_id} already processed, skipping");
                     // 发送重复消息提示
-                    let warning_text = "⚠️ 这条消息已经被处理过了，不会重复记录交易。";
-                    bot.send_message(message.chat.id, warning_text).await?;
+                    let settings = self.chat_settings(chat_id).await;
+                    let warning_text = Formatter::format_already_processed(&settings);
+                    self.deliver(bot, message, warning_text).await?;
                     return Ok(());
                 }
                 Ok(false) => {
@@ -85,135 +150,427 @@ impl MessageHandler {
                 }
             }
 
-            // 检查是否已经包含总额
-            let has_total = self.parser.has_total(text);
-            debug!("📊 Message has_total: {has_total}");
-            if has_total {
-                debug!("📈 Message already has total, switching to manual edit mode");
-                return self.handle_message_with_total(bot, message, text).await;
+            // 金额超过聊天设置的阈值时先询问，确认后才记账
+            if let Some(large) = self.large_amount(chat_id, text, is_transfer).await {
+                return self
+                    .ask_confirmation(bot, message, text, is_transfer, false, &large)
+                    .await;
             }
 
-            // 解析消息
-            if let Some(parsed) = self.parser.parse(text) {
-                debug!("✅ Message parsed successfully");
-                debug!("   └─ Wallet: {}", parsed.wallet_name);
-                debug!("   └─ Type: {}", parsed.transaction_type);
-                debug!("   └─ Amount: {}", parsed.amount);
-                debug!("   └─ Month: {}", parsed.month);
-                debug!("   └─ Year: {}", parsed.year);
-                debug!("   └─ Total: {:?}", parsed.total_amount);
-
-                // 智能计算余额
-                match self
-                    .calculator
-                    .smart_calculate_balance(
-                        message.chat.id.0,
-                        &parsed.wallet_name,
-                        &parsed.transaction_type,
-                        parsed.amount,
-                        &parsed.month,
-                        &parsed.year,
-                        parsed.total_amount,
-                        Some(message.id.0 as i64),
+            return self
+                .process_wallet_text(bot, message, text, is_transfer)
+                .await;
+        }
+
+        Ok(())
+    }
+
+    /// 消息中需要确认的大额交易，带 #总额 的消息和无法解析的消息交给正常流程处理
+    async fn large_amount(
+        &self,
+        chat_id: i64,
+        text: &str,
+        is_transfer: bool,
+    ) -> Option<LargeAmount> {
+        let (wallet_name, amounts) = if is_transfer {
+            let transfer = self.parser.parse_transfer(text)?;
+            (transfer.from_wallet, vec![transfer.amount])
+        } else {
+            if self.parser.has_total(text) {
+                return None;
+            }
+            let parsed = self.parser.parse(text)?;
+            let amounts = parsed.items.iter().map(|item| item.amount).collect();
+            (parsed.wallet_name, amounts)
+        };
+        self.find_large_amount(chat_id, &wallet_name, &amounts)
+            .await
+    }
+
+    /// 已记账消息编辑后需要确认的金额，只检查编辑前没有记过的金额
+    async fn large_edit_amount(
+        &self,
+        record: &MessageRecord,
+        parsed: &ParsedMessage,
+    ) -> Option<LargeAmount> {
+        let mut recorded: Vec<Money> = self
+            .db
+            .get_message_transactions(record.message_id, record.chat_id)
+            .await
+            .unwrap_or_else(|e| {
+                warn!(
+                    "Failed to load transactions of edited message {}: {e}",
+                    record.message_id
+                );
+                Vec::new()
+            })
+            .into_iter()
+            .map(|transaction| transaction.amount)
+            .collect();
+        let amounts: Vec<Money> = parsed
+            .items
+            .iter()
+            .map(|item| item.amount)
+            .filter(|amount| match recorded.iter().position(|r| r == amount) {
+                Some(index) => {
+                    recorded.swap_remove(index);
+                    false
+                }
+                None => true,
+            })
+            .collect();
+        if amounts.is_empty() {
+            return None;
+        }
+        self.find_large_amount(record.chat_id, &parsed.wallet_name, &amounts)
+            .await
+    }
+
+    async fn find_large_amount(
+        &self,
+        chat_id: i64,
+        wallet_name: &str,
+        amounts: &[Money],
+    ) -> Option<LargeAmount> {
+        let settings = self.chat_settings(chat_id).await;
+        if settings.confirm_above.is_none() && settings.confirm_ratio.is_none() {
+            return None;
+        }
+
+        find_large_amount(&self.db, &settings, wallet_name, amounts)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Failed to check transaction size in chat {chat_id}: {e}");
+                None
+            })
+    }
+
+    /// 暂不记账，发送带“确认 / 取消”按钮的提示
+    async fn ask_confirmation<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        text: &str,
+        is_transfer: bool,
+        is_edit: bool,
+        large: &LargeAmount,
+    ) -> Result<(), RequestError> {
+        info!(
+            "⏸️ Message {} in chat {} waits for confirmation (edit: {is_edit}): {large:?}",
+            message.id, message.chat.id
+        );
+        let id = self.pending.insert(message, text, is_transfer, is_edit);
+        let settings = self.chat_settings(message.chat.id.0).await;
+        let prompt =
+            Formatter::format_large_amount_prompt(&settings, large, self.pending.timeout());
+        bot.send_message_with_keyboard(
+            message.chat.id,
+            &prompt,
+            Some(ConfirmationAction::keyboard(id)),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// 处理大额交易的“确认 / 取消”按钮，只有可以记账的成员能操作
+    pub async fn handle_confirmation_callback<B: BotApi>(
+        &self,
+        bot: &B,
+        query: &CallbackQuery,
+    ) -> Result<(), RequestError> {
+        let action = query
+            .data
+            .as_deref()
+            .and_then(ConfirmationAction::from_callback_data);
+        let (Some(action), Some(prompt)) = (action, &query.message) else {
+            bot.answer_callback_query(&query.id).await?;
+            return Ok(());
+        };
+        if !self
+            .access
+            .callback_has_role(bot, query, Role::Writer)
+            .await
+        {
+            info!(
+                "Rejected {action:?} from {} in chat {}",
+                query.from.id, prompt.chat.id
+            );
+            bot.answer_callback_query(&query.id).await?;
+            return Ok(());
+        }
+        bot.answer_callback_query(&query.id).await?;
+
+        // 保留提示中的金额，在后面写上处理结果并去掉按钮
+        let edit_prompt = |status: &str| {
+            let text = format!("{}\n\n{status}", prompt.text().unwrap_or_default());
+            async move {
+                bot.edit_message_with_keyboard(prompt.chat.id, prompt.id, &text, None)
+                    .await
+            }
+        };
+        let settings = self.chat_settings(prompt.chat.id.0).await;
+        let Some(pending) = self.pending.take(action.id()) else {
+            edit_prompt(settings.text(
+                "⌛ 确认已过期或已经处理过，没有记账。如需记账请重新发送消息，或由管理员回复原消息使用 /reprocess。",
+                "⌛ This confirmation has expired or was already handled, nothing was recorded. Send the message again, or ask an admin to reply to it with /reprocess.",
+            ))
+            .await?;
+            return Ok(());
+        };
+        let user = &query.from.first_name;
+        let message = &pending.message;
+
+        match action {
+            ConfirmationAction::Cancel(_) => {
+                info!(
+                    "Cancelled large transaction in message {} of chat {} (edit: {})",
+                    message.id, message.chat.id, pending.is_edit
+                );
+                let status = if pending.is_edit {
+                    settings.text(
+                        format!("❌ {user} 已取消，保留编辑前的记账。"),
+                        format!("❌ {user} cancelled, the entry before the edit is kept."),
+                    )
+                } else {
+                    settings.text(
+                        format!("❌ {user} 已取消，这笔交易没有记账。"),
+                        format!("❌ {user} cancelled, this transaction was not recorded."),
                     )
+                };
+                edit_prompt(&status).await?;
+            }
+            ConfirmationAction::Confirm(_) if pending.is_edit => {
+                // 等待期间消息可能已经被 /undo 撤销
+                let record = match self
+                    .db
+                    .get_message_record(message.id.0 as i64, message.chat.id.0)
                     .await
                 {
-                    Ok(balance_update) => {
-                        // 构建新消息文本
-                        let new_text =
-                            format!("{}\n#总额 {:.2}元", text, balance_update.new_balance);
+                    Ok(Some(record)) if !record.is_voided() => record,
+                    Ok(_) => {
+                        edit_prompt(settings.text(
+                            "⚠️ 这条消息的记账已经撤销，编辑不会重新计算。",
+                            "⚠️ This message's entry was undone, the edit will not be recalculated.",
+                        ))
+                        .await?;
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        error!(
+                            "Failed to load record of edited message {}: {e}",
+                            message.id
+                        );
+                        edit_prompt(settings.text(
+                "❌ 更新编辑后的交易时出现错误，请稍后重试或联系管理员。",
+                "❌ Failed to update the edited transaction, please try again later or contact an admin.",
+            )).await?;
+                        return Ok(());
+                    }
+                };
+                let Some(parsed) = self.parser.parse(&pending.text) else {
+                    error!(
+                        "Failed to parse pending edit of message {} in chat {}",
+                        message.id, message.chat.id
+                    );
+                    edit_prompt(settings.text(
+                        "❌ 编辑后的消息格式不正确，没有重新计算。",
+                        "❌ The edited message is not in a valid format, nothing was recalculated.",
+                    ))
+                    .await?;
+                    return Ok(());
+                };
+                info!(
+                    "Confirmed large edit of message {} in chat {}",
+                    message.id, message.chat.id
+                );
+                edit_prompt(&format!("✅ {user} 已确认。")).await?;
+                self.apply_edit(bot, message, &record, &pending.text, &parsed)
+                    .await?;
+            }
+            ConfirmationAction::Confirm(_) => {
+                // 等待期间消息可能已经被 /reprocess 记账
+                let processed = self
+                    .db
+                    .is_message_recorded(message.id.0 as i64, message.chat.id.0)
+                    .await
+                    .unwrap_or_else(|e| {
+                        warn!("Failed to check message processing status: {e}");
+                        false
+                    });
+                if processed {
+                    edit_prompt(settings.text(
+                        "⚠️ 这条消息已经记过账了，不会重复记录。",
+                        "⚠️ This message was already recorded and will not be recorded twice.",
+                    ))
+                    .await?;
+                    return Ok(());
+                }
+                info!(
+                    "Confirmed large transaction in message {} of chat {}",
+                    message.id, message.chat.id
+                );
+                edit_prompt(&format!("✅ {user} 已确认。")).await?;
+                self.process_wallet_text(bot, message, &pending.text, pending.is_transfer)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
 
-                        // 编辑消息
-                        bot.edit_message_text(message.chat.id, message.id, new_text)
-                            .await?;
+    /// 记账流程：按转账、带总额的消息和普通交易消息分别处理，并改写原消息的 #总额
+    async fn process_wallet_text<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        text: &str,
+        is_transfer: bool,
+    ) -> Result<(), RequestError> {
+        if is_transfer {
+            return self.handle_transfer(bot, message, text).await;
+        }
 
-                        // 记录交易
-                        if let Err(e) = self
-                            .db
-                            .record_transaction(
-                                message.chat.id.0,
-                                &parsed.wallet_name,
-                                &parsed.transaction_type,
-                                parsed.amount,
-                                &parsed.month,
-                                &parsed.year,
-                                Some(message.id.0 as i64),
-                            )
-                            .await
-                        {
-                            error!("Failed to record transaction: {e}");
-                        }
+        // 检查是否已经包含总额
+        let has_total = self.parser.has_total(text);
+        debug!("📊 Message has_total: {has_total}");
+        if has_total {
+            debug!("📈 Message already has total, switching to manual edit mode");
+            return self.handle_message_with_total(bot, message, text).await;
+        }
 
-                        // 记录消息处理状态
-                        if let Err(e) = self
-                            .db
-                            .record_message(
-                                message.id.0 as i64,
-                                message.chat.id.0,
-                                &parsed.wallet_name,
-                                true,
-                                Some(balance_update.old_balance),
-                                Some(balance_update.new_balance),
-                            )
-                            .await
-                        {
-                            error!("Failed to record message: {e}");
-                        }
+        // 解析消息
+        if let Some(parsed) = self.parser.parse(text) {
+            debug!("✅ Message parsed successfully");
+            debug!("   └─ Wallet: {}", parsed.wallet_name);
+            debug!("   └─ Items: {:?}", parsed.items);
+            debug!("   └─ Month: {}", parsed.month);
+            debug!("   └─ Year: {}", parsed.year);
+            debug!("   └─ Total: {:?}", parsed.total_amount);
 
-                        // 发送确认消息
-                        let confirmation_text = format!(
-                            "✅ 交易已记录\n📊 钱包：{}\n💰 当前余额：{:.2}元",
-                            parsed.wallet_name, balance_update.new_balance
-                        );
-                        bot.send_message(message.chat.id, &confirmation_text)
+            // 在同一个事务中更新余额、记录交易和消息
+            match self
+                .calculator
+                .process_message(message.chat.id.0, message.id.0 as i64, &parsed)
+                .await
+            {
+                Ok(balance_update) => {
+                    // 事务提交成功后才修改 Telegram 消息（内容未变化时 Telegram 会拒绝编辑）
+                    let settings = self.chat_settings(message.chat.id.0).await;
+                    let new_text = format!(
+                        "{text}\n{}",
+                        settings.total_line(balance_update.new_balance)
+                    );
+                    if message.text() != Some(new_text.as_str()) {
+                        bot.edit_message_text(message.chat.id, message.id, &new_text)
                             .await?;
+                    }
 
-                        match balance_update.source {
-                            BalanceUpdateSource::Transaction => {
-                                info!(
-                                    "Successfully processed transaction: {} {} -> {}",
-                                    parsed.wallet_name,
-                                    balance_update.old_balance,
-                                    balance_update.new_balance
-                                );
-                            }
-                            BalanceUpdateSource::ManualEdit => {
-                                info!(
-                                    "Successfully updated balance from manual edit: {} {} -> {}",
-                                    parsed.wallet_name,
-                                    balance_update.old_balance,
-                                    balance_update.new_balance
-                                );
-                            }
-                            BalanceUpdateSource::Initial => {
-                                info!(
-                                    "Successfully set initial balance: {} -> {}",
-                                    parsed.wallet_name, balance_update.new_balance
-                                );
-                            }
+                    // 发送确认消息，多笔明细时附上逐行明细
+                    let confirmation_text = Formatter::format_confirmation(
+                        &settings,
+                        Confirmation::Recorded,
+                        &parsed,
+                        &balance_update,
+                    );
+                    self.notify(bot, message, &confirmation_text).await?;
+
+                    match balance_update.source {
+                        BalanceUpdateSource::Transaction => {
+                            info!(
+                                "Successfully processed transaction: {} {} -> {}",
+                                parsed.wallet_name,
+                                balance_update.old_balance,
+                                balance_update.new_balance
+                            );
+                        }
+                        BalanceUpdateSource::ManualEdit => {
+                            info!(
+                                "Successfully updated balance from manual edit: {} {} -> {}",
+                                parsed.wallet_name,
+                                balance_update.old_balance,
+                                balance_update.new_balance
+                            );
+                        }
+                        BalanceUpdateSource::Initial => {
+                            info!(
+                                "Successfully set initial balance: {} -> {}",
+                                parsed.wallet_name, balance_update.new_balance
+                            );
                         }
                     }
-                    Err(e) => {
-                        error!("Failed to calculate balance: {e}");
-                        // 发送错误消息
-                        let error_text = "❌ 处理交易时出现错误，请稍后重试或联系管理员。";
-                        bot.send_message(message.chat.id, error_text).await?;
-                    }
                 }
-            } else {
-                warn!("Failed to parse wallet message: {text}");
-                // 发送格式错误提示和使用说明
-                let help_text = "❌ 消息格式不正确\n\n📋 正确格式：\n#钱包名称 #月份 #年份\n#出账/入账 金额元\n\n💡 示例：\n#支付宝 #12月 #2024年\n#出账 150.00元\n\n或者：\n#微信 #01月 #2024年\n#入账 200.00元\n\n❓ 需要帮助请输入 /help";
-                bot.send_message(message.chat.id, help_text).await?;
+                Err(e) => {
+                    error!("Failed to calculate balance: {e}");
+                    // 发送错误消息
+                    let settings = self.chat_settings(message.chat.id.0).await;
+                    let error_text = settings.text(
+                        "❌ 处理交易时出现错误，请稍后重试或联系管理员。",
+                        "❌ Failed to process the transaction, please try again later or contact an admin.",
+                    );
+                    self.deliver(bot, message, error_text).await?;
+                }
+            }
+        } else {
+            warn!("Failed to parse wallet message: {text}");
+            // 发送格式错误提示和使用说明
+            let settings = self.chat_settings(message.chat.id.0).await;
+            let help_text = Formatter::format_message_help(&settings);
+            self.deliver(bot, message, help_text).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn handle_transfer<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        text: &str,
+    ) -> Result<(), RequestError> {
+        let Some(transfer) = self.parser.parse_transfer(text) else {
+            warn!("Failed to parse transfer message: {text}");
+            let settings = self.chat_settings(message.chat.id.0).await;
+            let help_text = Formatter::format_transfer_help(&settings);
+            self.deliver(bot, message, help_text).await?;
+            return Ok(());
+        };
+        debug!(
+            "✅ Transfer parsed: {} -> {} {} ({}{})",
+            transfer.from_wallet,
+            transfer.to_wallet,
+            transfer.amount,
+            transfer.month,
+            transfer.year
+        );
+
+        match self
+            .calculator
+            .process_transfer(message.chat.id.0, message.id.0 as i64, &transfer)
+            .await
+        {
+            Ok(update) => {
+                let settings = self.chat_settings(message.chat.id.0).await;
+                let confirmation_text =
+                    Formatter::format_transfer_confirmation(&settings, transfer.amount, &update);
+                // 转账不改写原消息，只编辑原消息的聊天也要回复结果
+                self.deliver(bot, message, &confirmation_text).await?;
+            }
+            Err(e) => {
+                error!("Failed to process transfer: {e}");
+                let settings = self.chat_settings(message.chat.id.0).await;
+                let error_text = settings.text(
+                    "❌ 处理转账时出现错误，请稍后重试或联系管理员。",
+                    "❌ Failed to process the transfer, please try again later or contact an admin.",
+                );
+                self.deliver(bot, message, error_text).await?;
             }
         }
 
         Ok(())
     }
 
-    async fn handle_message_with_total(
+    async fn handle_message_with_total<B: BotApi>(
         &self,
-        bot: &Bot,
+        bot: &B,
         message: &Message,
         text: &str,
     ) -> Result<(), RequestError> {
@@ -222,57 +579,22 @@ impl MessageHandler {
         // 解析消息
         if let Some(parsed) = self.parser.parse(text) {
             // 如果有总额，使用总额更新余额
-            if let Some(total_amount) = parsed.total_amount {
+            if parsed.total_amount.is_some() {
                 match self
                     .calculator
-                    .update_from_manual_total(
-                        message.chat.id.0,
-                        &parsed.wallet_name,
-                        total_amount,
-                        Some(message.id.0 as i64),
-                    )
+                    .process_message(message.chat.id.0, message.id.0 as i64, &parsed)
                     .await
                 {
                     Ok(balance_update) => {
-                        // 记录交易（即使是从总额更新，也需要记录这个交易）
-                        if let Err(e) = self
-                            .db
-                            .record_transaction(
-                                message.chat.id.0,
-                                &parsed.wallet_name,
-                                &parsed.transaction_type,
-                                parsed.amount,
-                                &parsed.month,
-                                &parsed.year,
-                                Some(message.id.0 as i64),
-                            )
-                            .await
-                        {
-                            error!("Failed to record transaction: {e}");
-                        }
-
-                        // 记录消息处理状态
-                        if let Err(e) = self
-                            .db
-                            .record_message(
-                                message.id.0 as i64,
-                                message.chat.id.0,
-                                &parsed.wallet_name,
-                                true,
-                                Some(balance_update.old_balance),
-                                Some(balance_update.new_balance),
-                            )
-                            .await
-                        {
-                            error!("Failed to record message: {e}");
-                        }
-
                         // 发送确认消息（手动总额更新）
-                        let confirmation_text = format!(
-                            "✅ 余额已更新（手动总额）\n📊 钱包：{}\n💰 当前余额：{:.2}元",
-                            parsed.wallet_name, balance_update.new_balance
+                        let settings = self.chat_settings(message.chat.id.0).await;
+                        let confirmation_text = Formatter::format_confirmation(
+                            &settings,
+                            Confirmation::ManualTotal,
+                            &parsed,
+                            &balance_update,
                         );
-                        let _ = bot.send_message(message.chat.id, &confirmation_text).await;
+                        self.notify(bot, message, &confirmation_text).await?;
 
                         info!(
                             "Successfully processed message with manual total: {} {} -> {}",
@@ -283,28 +605,436 @@ impl MessageHandler {
                     }
                     Err(e) => {
                         error!("Failed to update balance from manual total: {e}");
+                        let settings = self.chat_settings(message.chat.id.0).await;
+                        let error_text = settings.text(
+                            "❌ 按 #总额 更新余额时出现错误，请稍后重试或联系管理员。",
+                            "❌ Failed to update the balance from #总额, please try again later or contact an admin.",
+                        );
+                        self.deliver(bot, message, error_text).await?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 处理被编辑的消息：撤销原交易，按新内容重新记账并改写 #总额
+    pub async fn handle_edited_message<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+    ) -> Result<(), RequestError> {
+        let Some(text) = message.text() else {
+            return Ok(());
+        };
+        let chat_id = message.chat.id.0;
+        let text = &*self.fill_default_wallet(chat_id, text).await;
+
+        let is_transfer = self.parser.is_transfer_message(text);
+        if !is_transfer && !self.parser.is_wallet_message(text) {
+            return Ok(());
+        }
+
+        if !self
+            .access
+            .message_has_role(bot, message, Role::Writer)
+            .await
+        {
+            return self.reject_unauthorized(bot, message).await;
+        }
+
+        let message_id = message.id.0 as i64;
+
+        let record = match self.db.get_message_record(message_id, chat_id).await {
+            Ok(Some(record)) => record,
+            Ok(None) => {
+                debug!("📝 Edited message {message_id} was never processed, handling as new");
+                return self.handle_message(bot, message).await;
+            }
+            Err(e) => {
+                error!("Failed to load record of edited message {message_id}: {e}");
+                return Ok(());
+            }
+        };
+
+        if record.is_voided() {
+            debug!("📝 Edited message {message_id} was undone, ignoring edit");
+            return Ok(());
+        }
+
+        if is_transfer {
+            warn!("Edited transfer message {message_id} was already processed, ignoring edit");
+            let notice = self.chat_settings(chat_id).await.text(
+                "⚠️ 已记录的转账不会随消息编辑重新计算，如需修改请发送一笔反向转账。",
+                "⚠️ Recorded transfers are not recalculated when the message is edited; send a reverse transfer to correct it.",
+            );
+            self.deliver(bot, message, notice).await?;
+            return Ok(());
+        }
+
+        let Some(parsed) = self.parser.parse(text) else {
+            warn!("Failed to parse edited wallet message: {text}");
+            return Ok(());
+        };
+
+        // 金额改大时同样先询问，确认前保留编辑前的记账
+        if let Some(large) = self.large_edit_amount(&record, &parsed).await {
+            return self
+                .ask_confirmation(bot, message, text, false, true, &large)
+                .await;
+        }
+
+        self.apply_edit(bot, message, &record, text, &parsed).await
+    }
+
+    /// 按编辑后的内容重新计算已记账的消息，并改写 #总额
+    async fn apply_edit<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        record: &MessageRecord,
+        text: &str,
+        parsed: &ParsedMessage,
+    ) -> Result<(), RequestError> {
+        let chat_id = message.chat.id.0;
+        let message_id = record.message_id;
+        match self
+            .calculator
+            .recalculate_edited_message(chat_id, record, parsed)
+            .await
+        {
+            Ok(balance_update) => {
+                // 改写 #总额 行（内容未变化时 Telegram 会拒绝编辑）
+                let settings = self.chat_settings(chat_id).await;
+                let new_text = format!(
+                    "{}\n{}",
+                    self.parser.strip_total(text),
+                    settings.total_line(balance_update.new_balance)
+                );
+                if new_text != text {
+                    bot.edit_message_text(message.chat.id, message.id, &new_text)
+                        .await?;
+                }
+
+                if balance_update.new_balance != balance_update.old_balance {
+                    let confirmation_text = Formatter::format_confirmation(
+                        &settings,
+                        Confirmation::Updated,
+                        parsed,
+                        &balance_update,
+                    );
+                    self.notify(bot, message, &confirmation_text).await?;
+                }
+
+                info!(
+                    "Successfully re-applied edited message {message_id}: {} {} -> {}",
+                    parsed.wallet_name, balance_update.old_balance, balance_update.new_balance
+                );
+            }
+            Err(e) => {
+                error!("Failed to recalculate edited message {message_id}: {e}");
+                let settings = self.chat_settings(chat_id).await;
+                self.deliver(bot, message, settings.text(
+                "❌ 更新编辑后的交易时出现错误，请稍后重试或联系管理员。",
+                "❌ Failed to update the edited transaction, please try again later or contact an admin.",
+            ))
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 没有记账权限的交易消息：按聊天的访问策略回复提示或静默忽略
+    async fn reject_unauthorized<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+    ) -> Result<(), RequestError> {
+        let chat_id = message.chat.id.0;
+        info!(
+            "Ignoring wallet message {} from unauthorized user {:?} in chat {chat_id}",
+            message.id,
+            message.from().map(|user| user.id)
+        );
+        if self.access.policy(chat_id).await.reject_unauthorized {
+            let text = self.chat_settings(chat_id).await.text(
+                "⛔ 你没有在这个聊天记账的权限，请联系管理员使用 /access 授权。",
+                "⛔ You are not allowed to record in this chat, ask an admin to grant access with /access.",
+            );
+            bot.send_message(message.chat.id, text).await?;
+        }
+        Ok(())
+    }
+
+    /// 按聊天设置的输出方式发送记账结果
+    async fn notify<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        text: &str,
+    ) -> Result<(), RequestError> {
+        let settings = self.chat_settings(message.chat.id.0).await;
+        self.output.send(bot, message, &settings, text).await
+    }
+
+    /// 发送错误、格式说明等必须送达的消息，只编辑原消息的聊天中改为回复原消息
+    async fn deliver<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        text: &str,
+    ) -> Result<(), RequestError> {
+        let settings = self.chat_settings(message.chat.id.0).await;
+        self.output.deliver(bot, message, &settings, text).await
+    }
+
+    /// 聊天的设置，读取失败时使用默认设置
+    pub async fn chat_settings(&self, chat_id: i64) -> ChatSettings {
+        self.db
+            .get_chat_settings(chat_id)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Failed to load settings of chat {chat_id}: {e}");
+                ChatSettings::new(chat_id)
+            })
+    }
+
+    /// 没有写钱包的记账消息记到聊天的默认钱包，改写后的 #总额 消息中会带上钱包标签
+    async fn fill_default_wallet<'a>(&self, chat_id: i64, text: &'a str) -> Cow<'a, str> {
+        if !self.parser.is_walletless_message(text) {
+            return Cow::Borrowed(text);
+        }
+        match self.chat_settings(chat_id).await.default_wallet {
+            Some(wallet) => {
+                debug!("📝 Message has no wallet, using default wallet {wallet}");
+                Cow::Owned(self.parser.with_wallet(text, &wallet))
+            }
+            None => Cow::Borrowed(text),
+        }
+    }
+
+    /// 撤销记账：回复某条消息时撤销该消息，否则撤销聊天中最近的一条。
+    ///
+    /// 交易只标记为作废；能拿到原消息内容时把其中的 #总额 行改为 #已撤销。
+    pub async fn undo_message<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+    ) -> Result<(), RequestError> {
+        let chat_id = message.chat.id.0;
+        let settings = self.chat_settings(chat_id).await;
+        let target = message.reply_to_message();
+        let record = match target {
+            Some(target) => {
+                self.db
+                    .get_message_record(target.id.0 as i64, chat_id)
+                    .await
+            }
+            None => self.db.get_latest_message_record(chat_id).await,
+        };
+
+        let record = match record {
+            Ok(Some(record)) if record.is_voided() => {
+                let text = settings.text(
+                    "⚠️ 这条消息的记账已经撤销过了。",
+                    "⚠️ This message's entry was already undone.",
+                );
+                bot.send_message(message.chat.id, text).await?;
+                return Ok(());
+            }
+            Ok(Some(record)) => record,
+            Ok(None) => {
+                let text = if target.is_some() {
+                    settings.text(
+                        "❌ 回复的消息没有记过账，无法撤销。",
+                        "❌ The replied-to message was never recorded, nothing to undo.",
+                    )
+                } else {
+                    settings.text(
+                        "❌ 这个聊天中没有可以撤销的记账。",
+                        "❌ There is nothing to undo in this chat.",
+                    )
+                };
+                bot.send_message(message.chat.id, text).await?;
+                return Ok(());
+            }
+            Err(e) => {
+                error!("Failed to find message to undo in chat {chat_id}: {e}");
+                let text = settings.text(
+                    format!("❌ 查询要撤销的消息失败：{e}"),
+                    format!("❌ Failed to find the message to undo: {e}"),
+                );
+                bot.send_message(message.chat.id, &text).await?;
+                return Ok(());
+            }
+        };
+
+        let undone = match self
+            .calculator
+            .undo_message(chat_id, record.message_id)
+            .await
+        {
+            Ok(undone) => undone,
+            Err(e) => {
+                error!("Failed to undo message {}: {e}", record.message_id);
+                let text = settings.text(
+                    "❌ 撤销记账时出现错误，请稍后重试或联系管理员。",
+                    "❌ Failed to undo the entry, please try again later or contact an admin.",
+                );
+                bot.send_message(message.chat.id, text).await?;
+                return Ok(());
+            }
+        };
+
+        let mut text = Formatter::format_undo(&settings, &undone);
+        // 机器人只能拿到被回复消息的内容，撤销最近一条时无法改写原消息
+        let annotated = match target.and_then(|target| target.text().map(|t| (target, t))) {
+            Some((target, original)) => {
+                let new_text = format!("{}\n{UNDONE_MARK}", self.parser.strip_total(original));
+                match bot
+                    .edit_message_text(message.chat.id, target.id, &new_text)
+                    .await
+                {
+                    Ok(_) => true,
+                    Err(e) => {
+                        warn!("Failed to annotate undone message {}: {e}", target.id);
+                        false
                     }
                 }
             }
+            None => false,
+        };
+        if !annotated {
+            text.push_str(settings.text(
+                "\n\nℹ️ 原消息中的 #总额 没有修改，请手动删除。",
+                "\n\nℹ️ The #总额 line in the original message was not changed, please remove it manually.",
+            ));
         }
+        bot.send_message(message.chat.id, &text).await?;
 
+        info!(
+            "Undid message {} in chat {chat_id} ({} transactions)",
+            undone.message_id,
+            undone.transactions.len()
+        );
         Ok(())
     }
 
-    /// 重新处理消息（管理员命令）
-    pub async fn reprocess_message(
+    /// 重新处理消息（管理员命令）：撤销之前的记账，去掉机器人追加的 #总额 后按正常流程重新记账，
+    /// 最后回复重新处理前后的交易和余额
+    pub async fn reprocess_message<B: BotApi>(
         &self,
-        bot: &Bot,
+        bot: &B,
         message: &Message,
     ) -> Result<(), RequestError> {
         info!("Reprocessing message: {}", message.id);
 
-        // 重置处理状态
-        // 这里可以添加重置逻辑
+        let message_id = message.id.0 as i64;
+        let chat_id = message.chat.id.0;
+        let settings = self.chat_settings(chat_id).await;
+        let Some(original) = message.text() else {
+            let text = settings.text(
+                "❌ 只能重新处理文本消息。",
+                "❌ Only text messages can be reprocessed.",
+            );
+            bot.send_message(message.chat.id, text).await?;
+            return Ok(());
+        };
+        let original = &*self.fill_default_wallet(message.chat.id.0, original).await;
+        let is_transfer = self.parser.is_transfer_message(original);
+        let valid = if is_transfer {
+            self.parser.parse_transfer(original).is_some()
+        } else {
+            self.parser.is_wallet_message(original) && self.parser.parse(original).is_some()
+        };
+        if !valid {
+            let text = settings.text(
+                "❌ 这条消息不是有效的记账消息，无法重新处理。",
+                "❌ This is not a valid wallet message and cannot be reprocessed.",
+            );
+            bot.send_message(message.chat.id, text).await?;
+            return Ok(());
+        }
+
+        let record = match self.db.get_message_record(message_id, chat_id).await {
+            Ok(record) => record,
+            Err(e) => {
+                error!("Failed to load record of message {message_id}: {e}");
+                let text = settings.text(
+                    format!("❌ 查询消息记录失败：{e}"),
+                    format!("❌ Failed to load the message record: {e}"),
+                );
+                bot.send_message(message.chat.id, &text).await?;
+                return Ok(());
+            }
+        };
+        let wallets_before = self.wallet_summaries(chat_id).await;
+
+        // 去掉撤销标记；只有消息本来就带着总额时才保留 #总额 行
+        let mut text = original
+            .lines()
+            .filter(|line| line.trim() != UNDONE_MARK)
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut before = Vec::new();
+        if let Some(record) = record {
+            if !record.has_total {
+                text = self.parser.strip_total(&text);
+            }
+            if !record.is_voided() {
+                match self.calculator.undo_message(chat_id, message_id).await {
+                    Ok(undone) => before = undone.transactions,
+                    Err(e) => {
+                        error!("Failed to void message {message_id} before reprocessing: {e}");
+                        let text = settings.text(
+                            "❌ 撤销之前的记账时出现错误，请稍后重试或联系管理员。",
+                            "❌ Failed to undo the previous entry, please try again later or contact an admin.",
+                        );
+                        bot.send_message(message.chat.id, text).await?;
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        self.process_wallet_text(bot, message, &text, is_transfer)
+            .await?;
 
-        // 重新处理
-        self.handle_message(bot, message).await
+        let after = self
+            .db
+            .get_message_transactions(message_id, chat_id)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Failed to load transactions of reprocessed message {message_id}: {e}");
+                Vec::new()
+            });
+        let wallets_after = self.wallet_summaries(chat_id).await;
+        let diff = Formatter::format_reprocess(
+            &settings,
+            &before,
+            &after,
+            &wallets_before,
+            &wallets_after,
+        );
+        bot.send_message(message.chat.id, &diff).await?;
+
+        info!(
+            "Reprocessed message {message_id} in chat {chat_id}: {} → {} transactions",
+            before.len(),
+            after.len()
+        );
+        Ok(())
     }
-}
 
-// Tests will be added later
+    async fn wallet_summaries(&self, chat_id: i64) -> Vec<WalletSummary> {
+        self.db
+            .get_wallet_summaries(chat_id)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Failed to load wallets of chat {chat_id}: {e}");
+                Vec::new()
+            })
+    }
+}
@@ -1,6 +1,10 @@
 pub mod commands;
+pub mod confirmation;
 pub mod dispatcher;
 pub mod handler;
+pub mod output;
+pub mod permissions;
+pub mod scheduler;
 pub mod traits;
 
 pub use dispatcher::start_bot;
@@ -0,0 +1,141 @@
+use crate::bot::traits::BotApi;
+use crate::database::models::{ChatSettings, OutputMode};
+use log::debug;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use teloxide::{types::Message, RequestError};
+
+/// 最多记住的频道帖子数，超过后清空，避免长时间运行时无限增长
+const MAX_TRACKED_POSTS: usize = 1000;
+
+/// 按聊天设置的输出方式发送记账结果。
+///
+/// 评论区模式下，频道帖子的确认要回复讨论组中自动转发的那条消息。
+/// 帖子和自动转发两条更新到达的先后不确定，先到的一方留在这里等待另一方。
+#[derive(Clone, Debug, Default)]
+pub struct OutputRouter {
+    threads: Arc<Mutex<DiscussionThreads>>,
+}
+
+#[derive(Debug, Default)]
+struct DiscussionThreads {
+    forwards: HashMap<(i64, i32), Message>, // 频道帖子 → 讨论组中的自动转发
+    pending: HashMap<(i64, i32), Vec<String>>, // 等待自动转发的确认
+}
+
+impl DiscussionThreads {
+    fn make_room(&mut self) {
+        if self.forwards.len() + self.pending.len() >= MAX_TRACKED_POSTS {
+            self.forwards.clear();
+            self.pending.clear();
+        }
+    }
+}
+
+impl OutputRouter {
+    /// 发送关于 message 的记账结果
+    pub async fn send<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        settings: &ChatSettings,
+        text: &str,
+    ) -> Result<(), RequestError> {
+        match settings.output_mode {
+            OutputMode::EditOnly => {
+                debug!(
+                    "Chat {} only edits posts, not sending: {text}",
+                    message.chat.id
+                );
+            }
+            OutputMode::EditAndConfirm => {
+                bot.send_message(message.chat.id, text).await?;
+            }
+            OutputMode::TemporaryConfirm => {
+                let sent = bot.send_message(message.chat.id, text).await?;
+                let delay = Duration::from_secs(settings.confirmation_ttl.into());
+                bot.delete_message_later(sent.chat.id, sent.id, delay).await;
+            }
+            OutputMode::DiscussionReply if message.chat.is_channel() => {
+                self.reply_in_discussion(bot, message, text).await?;
+            }
+            OutputMode::DiscussionReply => {
+                bot.reply_to_message(message, text).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 发送必须送达的消息：错误、格式说明，以及没有改写原消息的记账结果（如转账）。
+    ///
+    /// 只编辑原消息的聊天中改为回复原消息，其他输出方式与 send 相同。
+    pub async fn deliver<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        settings: &ChatSettings,
+        text: &str,
+    ) -> Result<(), RequestError> {
+        if settings.output_mode == OutputMode::EditOnly {
+            bot.reply_to_message(message, text).await?;
+            return Ok(());
+        }
+        self.send(bot, message, settings, text).await
+    }
+
+    async fn reply_in_discussion<B: BotApi>(
+        &self,
+        bot: &B,
+        post: &Message,
+        text: &str,
+    ) -> Result<(), RequestError> {
+        let key = (post.chat.id.0, post.id.0);
+        let forward = {
+            let mut threads = self.threads.lock().unwrap();
+            let forward = threads.forwards.get(&key).cloned();
+            if forward.is_none() {
+                debug!(
+                    "Waiting for discussion thread of post {} in {}",
+                    post.id, post.chat.id
+                );
+                threads.make_room();
+                threads
+                    .pending
+                    .entry(key)
+                    .or_default()
+                    .push(text.to_string());
+            }
+            forward
+        };
+        if let Some(forward) = forward {
+            bot.reply_to_message(&forward, text).await?;
+        }
+        Ok(())
+    }
+
+    /// 讨论组收到频道帖子的自动转发：记下对应关系，并发出先到的确认
+    pub async fn handle_automatic_forward<B: BotApi>(
+        &self,
+        bot: &B,
+        forward: &Message,
+    ) -> Result<(), RequestError> {
+        let (Some(channel), Some(post_id)) = (
+            forward.forward_from_chat(),
+            forward.forward_from_message_id(),
+        ) else {
+            return Ok(());
+        };
+        let key = (channel.id.0, post_id);
+        let pending = {
+            let mut threads = self.threads.lock().unwrap();
+            threads.make_room();
+            threads.forwards.insert(key, forward.clone());
+            threads.pending.remove(&key).unwrap_or_default()
+        };
+        for text in pending {
+            bot.reply_to_message(forward, &text).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,227 @@
+use crate::bot::traits::BotApi;
+use crate::config::Settings;
+use crate::database::models::{AccessPolicy, Role};
+use crate::database::operations::DatabaseOperations;
+use log::{debug, info, warn};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use teloxide::types::{CallbackQuery, Chat, ChatId, Message, User};
+
+/// 判断发送者在聊天中的角色。
+///
+/// 全局管理员、私聊中的用户、频道帖子和 Telegram 群组管理员拥有管理员权限；
+/// 其他成员使用 /access 单独授予的角色，没有授权时按聊天的访问策略决定：
+/// 开放的聊天中所有成员都可以记账，受限的聊天中只能使用 /start 和 /help。
+#[derive(Clone, Debug)]
+pub struct AccessControl {
+    db: DatabaseOperations,
+    admins: Vec<i64>,
+}
+
+impl AccessControl {
+    pub fn new(db: DatabaseOperations, admins: Vec<i64>) -> Self {
+        Self { db, admins }
+    }
+
+    pub fn is_global_admin(&self, user_id: i64) -> bool {
+        self.admins.contains(&user_id)
+    }
+
+    /// 消息的发送者是否至少拥有 required 角色
+    pub async fn message_has_role<B: BotApi>(
+        &self,
+        bot: &B,
+        message: &Message,
+        required: Role,
+    ) -> bool {
+        // 匿名管理员以群组本身的身份发言
+        if message
+            .sender_chat()
+            .is_some_and(|sender| sender.id == message.chat.id)
+        {
+            return true;
+        }
+        self.has_role(bot, &message.chat, message.from(), required)
+            .await
+    }
+
+    /// 用户在聊天中是否至少拥有 required 角色，只有本地记录不够时才查询 Telegram
+    pub async fn has_role<B: BotApi>(
+        &self,
+        bot: &B,
+        chat: &Chat,
+        user: Option<&User>,
+        required: Role,
+    ) -> bool {
+        if required == Role::Guest || chat.is_channel() {
+            // 只有频道管理员能发帖
+            return true;
+        }
+        let Some(user) = user else {
+            return false;
+        };
+        let user_id = user.id.0 as i64;
+        if self.is_global_admin(user_id) || chat.is_private() {
+            return true;
+        }
+
+        let role = match self.db.get_chat_role(chat.id.0, user_id).await {
+            Ok(Some(role)) => role,
+            Ok(None) if self.policy(chat.id.0).await.restricted => Role::Guest,
+            Ok(None) => Role::Writer,
+            Err(e) => {
+                warn!(
+                    "Failed to load role of user {user_id} in chat {}: {e}",
+                    chat.id
+                );
+                Role::Guest
+            }
+        };
+        if role >= required {
+            return true;
+        }
+
+        match bot.is_chat_admin(chat.id, user.id).await {
+            Ok(is_admin) => {
+                debug!(
+                    "User {user_id} is {}an administrator of chat {}",
+                    if is_admin { "" } else { "not " },
+                    chat.id
+                );
+                is_admin
+            }
+            Err(e) => {
+                warn!("Failed to check administrator status of user {user_id}: {e}");
+                false
+            }
+        }
+    }
+
+    /// 按下内联键盘按钮的用户是否至少拥有 required 角色。
+    ///
+    /// 频道的订阅者也能按下帖子下面的按钮，所以频道中超过只读的操作只允许频道管理员。
+    pub async fn callback_has_role<B: BotApi>(
+        &self,
+        bot: &B,
+        query: &CallbackQuery,
+        required: Role,
+    ) -> bool {
+        let Some(message) = &query.message else {
+            return false;
+        };
+        let chat = &message.chat;
+        if !chat.is_channel() || required <= Role::Reader {
+            return self.has_role(bot, chat, Some(&query.from), required).await;
+        }
+
+        let user_id = query.from.id;
+        if self.is_global_admin(user_id.0 as i64) {
+            return true;
+        }
+        bot.is_chat_admin(chat.id, user_id)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Failed to check administrator status of user {user_id}: {e}");
+                false
+            })
+    }
+
+    /// 聊天的访问策略，读取失败时使用默认策略
+    pub async fn policy(&self, chat_id: i64) -> AccessPolicy {
+        self.db
+            .get_access_policy(chat_id)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Failed to load access policy of chat {chat_id}: {e}");
+                AccessPolicy {
+                    chat_id,
+                    ..Default::default()
+                }
+            })
+    }
+}
+
+/// 允许使用机器人的聊天。
+///
+/// 列表为空时不限制；否则其他聊天的更新不会进入处理流程，
+/// 可以选择自动退出，并且每个聊天只通知一次全局管理员。
+#[derive(Clone, Debug, Default)]
+pub struct ChatAllowlist {
+    chat_ids: Vec<i64>,
+    admins: Vec<i64>,
+    leave_unknown: bool,
+    notified: Arc<Mutex<HashSet<i64>>>,
+}
+
+impl ChatAllowlist {
+    pub fn new(chat_ids: Vec<i64>, admins: Vec<i64>, leave_unknown: bool) -> Self {
+        Self {
+            chat_ids,
+            admins,
+            leave_unknown,
+            notified: Arc::default(),
+        }
+    }
+
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self::new(
+            settings.chat_allowlist(),
+            settings.admin_user_ids.clone(),
+            settings.leave_unknown_chats,
+        )
+    }
+
+    /// 聊天是否可以使用机器人，全局管理员的私聊始终允许
+    pub fn allows(&self, chat: &Chat) -> bool {
+        self.chat_ids.is_empty()
+            || self.chat_ids.contains(&chat.id.0)
+            || (chat.is_private() && self.admins.contains(&chat.id.0))
+    }
+
+    /// 只知道聊天 ID 时的检查，私聊的 ID 就是用户 ID
+    pub fn allows_chat_id(&self, chat_id: i64) -> bool {
+        self.chat_ids.is_empty()
+            || self.chat_ids.contains(&chat_id)
+            || (chat_id > 0 && self.admins.contains(&chat_id))
+    }
+
+    /// 处理来自未允许聊天的更新：记录日志，按配置退出，并通知全局管理员
+    pub async fn reject<B: BotApi>(&self, bot: &B, chat: &Chat) {
+        let chat_id = chat.id.0;
+        let title = chat.title().unwrap_or("私聊");
+        // 同一个聊天的后续更新只记录调试日志
+        let first_time = self.notified.lock().unwrap().insert(chat_id);
+        if !first_time {
+            debug!("Ignoring update from chat {chat_id} which is not in the allowlist");
+            return;
+        }
+        warn!("🚫 Update from chat {chat_id} ({title}) which is not in the allowlist");
+
+        let left = self.leave_unknown && !chat.is_private() && {
+            match bot.leave_chat(chat.id).await {
+                Ok(()) => {
+                    info!("👋 Left chat {chat_id} ({title})");
+                    true
+                }
+                Err(e) => {
+                    warn!("Failed to leave chat {chat_id}: {e}");
+                    false
+                }
+            }
+        };
+
+        let text = format!(
+            "🚫 机器人收到了不在允许列表中的聊天的消息：{title}（{chat_id}）\n{}",
+            if left {
+                "已自动退出该聊天。"
+            } else {
+                "消息已被忽略，如需使用请把聊天 ID 加入 ALLOWED_CHAT_IDS。"
+            }
+        );
+        for admin in &self.admins {
+            if let Err(e) = bot.send_message(ChatId(*admin), &text).await {
+                warn!("Failed to notify admin {admin} about chat {chat_id}: {e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use crate::bot::permissions::ChatAllowlist;
+use crate::bot::traits::BotApi;
+use crate::calculator::report::{ReportGenerator, ReportPeriod};
+use crate::database::models::{Language, SummarySubscription};
+use crate::database::operations::DatabaseOperations;
+use crate::utils::Formatter;
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use log::{debug, error, info, warn};
+use std::time::Duration;
+use teloxide::types::ChatId;
+
+/// 与 Dispatcher 并行运行的定时任务：月份结束后向订阅的聊天发送上个月的总结。
+///
+/// 每个聊天的总结只发送到该聊天本身，不在允许列表中的聊天不再发送。
+pub struct SummaryScheduler<B> {
+    bot: B,
+    db: DatabaseOperations,
+    generator: ReportGenerator,
+    allowlist: ChatAllowlist,
+    check_interval: Duration,
+}
+
+impl<B: BotApi> SummaryScheduler<B> {
+    pub fn new(bot: B, db: DatabaseOperations, check_interval: Duration) -> Self {
+        Self {
+            bot,
+            generator: ReportGenerator::new(db.clone()),
+            db,
+            allowlist: ChatAllowlist::default(),
+            check_interval,
+        }
+    }
+
+    /// 只向允许列表中的聊天发送总结
+    pub fn with_allowlist(mut self, allowlist: ChatAllowlist) -> Self {
+        self.allowlist = allowlist;
+        self
+    }
+
+    /// 按固定间隔检查是否有到期的总结，直到进程退出
+    pub async fn run(self) {
+        info!(
+            "🗓️ Monthly summary scheduler started (checking every {}s)",
+            self.check_interval.as_secs()
+        );
+        let mut ticker = tokio::time::interval(self.check_interval);
+        loop {
+            ticker.tick().await;
+            match self.post_due_summaries(Utc::now()).await {
+                Ok(0) => {}
+                Ok(posted) => info!("🗓️ Posted {posted} monthly summaries"),
+                Err(e) => error!("Failed to post monthly summaries: {e}"),
+            }
+        }
+    }
+
+    /// 发送所有已到期且尚未发送的总结，返回发送的数量
+    pub async fn post_due_summaries(&self, now: DateTime<Utc>) -> Result<usize> {
+        let mut posted = 0;
+        for subscription in self.db.get_enabled_summary_subscriptions().await? {
+            let chat_id = subscription.chat_id;
+            if !self.allowlist.allows_chat_id(chat_id) {
+                debug!("Chat {chat_id} is not in the allowlist, skipping its summary");
+                continue;
+            }
+            // 单个聊天出错时只记录日志，不影响其他聊天的总结
+            let settings = match self.db.get_chat_settings(chat_id).await {
+                Ok(settings) => settings,
+                Err(e) => {
+                    error!("Failed to load settings of chat {chat_id}: {e}");
+                    continue;
+                }
+            };
+            let Some(period) = due_period(&subscription, settings.local_time(now)) else {
+                continue;
+            };
+            let Some(month) = period.month else {
+                continue;
+            };
+
+            // 先登记再发送，重启或多次检查都不会重复发送同一个月
+            match self
+                .db
+                .claim_summary_period(chat_id, period.year, month)
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    error!(
+                        "Failed to claim {} summary of chat {chat_id}: {e}",
+                        period.label()
+                    );
+                    continue;
+                }
+            }
+
+            match self.post_summary(chat_id, period).await {
+                Ok(true) => posted += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    warn!(
+                        "Failed to post {} summary of chat {chat_id}: {e}",
+                        period.label()
+                    );
+                    if let Err(e) = self
+                        .db
+                        .release_summary_period(chat_id, period.year, month)
+                        .await
+                    {
+                        error!(
+                            "Failed to release {} summary of chat {chat_id}: {e}",
+                            period.label()
+                        );
+                    }
+                }
+            }
+        }
+        Ok(posted)
+    }
+
+    /// 生成并发送一个聊天的月度总结，聊天中没有钱包时不发送
+    async fn post_summary(&self, chat_id: i64, period: ReportPeriod) -> Result<bool> {
+        let report = self.generator.chat_report(chat_id, period).await?;
+        if report.wallets.is_empty() {
+            debug!(
+                "Chat {chat_id} has no wallets, skipping {} summary",
+                period.label()
+            );
+            return Ok(false);
+        }
+
+        let settings = self.db.get_chat_settings(chat_id).await?;
+        let period_label = Formatter::format_period(&settings, period.month, Some(period.year));
+        let summary = Formatter::format_chat_report(&settings, &report);
+        let text = match settings.language {
+            Language::Chinese => format!("🗓️ {period_label} 月度总结\n{summary}"),
+            Language::English => format!("🗓️ Monthly summary for {period_label}\n{summary}"),
+        };
+        self.bot.send_message(ChatId(chat_id), &text).await?;
+        Ok(true)
+    }
+}
+
+/// 订阅在给定时间是否应当发送总结，返回要总结的月份（上一个月）。
+///
+/// `now` 是聊天时区的当地时间，每月 day_of_month 日 hour 点之后才到期，
+/// 日期超过当月天数时按 28 日计算。
+pub fn due_period(subscription: &SummarySubscription, now: DateTime<Utc>) -> Option<ReportPeriod> {
+    if !subscription.enabled {
+        return None;
+    }
+    let day = subscription.day_of_month.clamp(1, 28);
+    let reached = (now.day(), now.hour()) >= (day, subscription.hour);
+    reached.then(|| ReportPeriod::month_before(&now))
+}
@@ -1,13 +1,19 @@
 use async_trait::async_trait;
+use log::warn;
+use std::time::Duration;
 use teloxide::{
-    types::{ChatId, Message, MessageId},
-    RequestError,
+    net::Download,
+    payloads::{EditMessageTextSetters, SendDocumentSetters, SendMessageSetters},
+    requests::Requester,
+    types::{ChatId, InlineKeyboardMarkup, InputFile, Message, MessageId, UserId},
+    Bot, RequestError,
 };
 
 /// 抽象Bot API操作的trait，用于测试时mock
+///
+/// 消息处理、命令和调度器只通过这个 trait 调用 Telegram，正式运行时使用 teloxide 的 `Bot`。
 #[async_trait]
-#[allow(dead_code)]
-pub trait BotApi {
+pub trait BotApi: Send + Sync {
     /// 发送消息
     async fn send_message(&self, chat_id: ChatId, text: &str) -> Result<Message, RequestError>;
 
@@ -20,16 +26,166 @@ pub trait BotApi {
     ) -> Result<Message, RequestError>;
 
     /// 删除消息
+    #[allow(dead_code)]
     async fn delete_message(
         &self,
         chat_id: ChatId,
         message_id: MessageId,
     ) -> Result<(), RequestError>;
 
+    /// 等待 delay 后在后台删除消息，不等待删除完成
+    async fn delete_message_later(&self, chat_id: ChatId, message_id: MessageId, delay: Duration);
+
     /// 发送回复消息
     async fn reply_to_message(
         &self,
         message: &Message,
         text: &str,
     ) -> Result<Message, RequestError>;
+
+    /// 发送带内联键盘的消息（不显示链接预览）
+    async fn send_message_with_keyboard(
+        &self,
+        chat_id: ChatId,
+        text: &str,
+        keyboard: Option<InlineKeyboardMarkup>,
+    ) -> Result<Message, RequestError>;
+
+    /// 编辑消息文本并替换内联键盘（不显示链接预览）
+    async fn edit_message_with_keyboard(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        text: &str,
+        keyboard: Option<InlineKeyboardMarkup>,
+    ) -> Result<Message, RequestError>;
+
+    /// 应答内联键盘按钮的回调
+    async fn answer_callback_query(&self, query_id: &str) -> Result<(), RequestError>;
+
+    /// 发送内存中的文件
+    async fn send_document(
+        &self,
+        chat_id: ChatId,
+        file_name: &str,
+        content: Vec<u8>,
+        caption: &str,
+    ) -> Result<Message, RequestError>;
+
+    /// 下载用户发送的文件
+    async fn download_file(&self, file_id: &str) -> Result<Vec<u8>, RequestError>;
+
+    /// 用户是否是群组或频道的创建者或管理员
+    async fn is_chat_admin(&self, chat_id: ChatId, user_id: UserId) -> Result<bool, RequestError>;
+
+    /// 退出群组或频道
+    async fn leave_chat(&self, chat_id: ChatId) -> Result<(), RequestError>;
+}
+
+#[async_trait]
+impl BotApi for Bot {
+    async fn send_message(&self, chat_id: ChatId, text: &str) -> Result<Message, RequestError> {
+        Requester::send_message(self, chat_id, text).await
+    }
+
+    async fn edit_message_text(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        text: &str,
+    ) -> Result<Message, RequestError> {
+        Requester::edit_message_text(self, chat_id, message_id, text).await
+    }
+
+    async fn delete_message(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+    ) -> Result<(), RequestError> {
+        Requester::delete_message(self, chat_id, message_id).await?;
+        Ok(())
+    }
+
+    async fn delete_message_later(&self, chat_id: ChatId, message_id: MessageId, delay: Duration) {
+        let bot = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if let Err(e) = Requester::delete_message(&bot, chat_id, message_id).await {
+                warn!("Failed to delete message {message_id} in chat {chat_id}: {e}");
+            }
+        });
+    }
+
+    async fn reply_to_message(
+        &self,
+        message: &Message,
+        text: &str,
+    ) -> Result<Message, RequestError> {
+        Requester::send_message(self, message.chat.id, text)
+            .reply_to_message_id(message.id)
+            .await
+    }
+
+    async fn send_message_with_keyboard(
+        &self,
+        chat_id: ChatId,
+        text: &str,
+        keyboard: Option<InlineKeyboardMarkup>,
+    ) -> Result<Message, RequestError> {
+        let request = Requester::send_message(self, chat_id, text).disable_web_page_preview(true);
+        match keyboard {
+            Some(keyboard) => request.reply_markup(keyboard).await,
+            None => request.await,
+        }
+    }
+
+    async fn edit_message_with_keyboard(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        text: &str,
+        keyboard: Option<InlineKeyboardMarkup>,
+    ) -> Result<Message, RequestError> {
+        let request = Requester::edit_message_text(self, chat_id, message_id, text)
+            .disable_web_page_preview(true);
+        match keyboard {
+            Some(keyboard) => request.reply_markup(keyboard).await,
+            None => request.await,
+        }
+    }
+
+    async fn answer_callback_query(&self, query_id: &str) -> Result<(), RequestError> {
+        Requester::answer_callback_query(self, query_id).await?;
+        Ok(())
+    }
+
+    async fn send_document(
+        &self,
+        chat_id: ChatId,
+        file_name: &str,
+        content: Vec<u8>,
+        caption: &str,
+    ) -> Result<Message, RequestError> {
+        let file = InputFile::memory(content).file_name(file_name.to_string());
+        Requester::send_document(self, chat_id, file)
+            .caption(caption)
+            .await
+    }
+
+    async fn download_file(&self, file_id: &str) -> Result<Vec<u8>, RequestError> {
+        let file = Requester::get_file(self, file_id).await?;
+        let mut bytes = Vec::new();
+        Download::download_file(self, &file.path, &mut bytes).await?;
+        Ok(bytes)
+    }
+
+    async fn is_chat_admin(&self, chat_id: ChatId, user_id: UserId) -> Result<bool, RequestError> {
+        let member = Requester::get_chat_member(self, chat_id, user_id).await?;
+        Ok(member.is_privileged())
+    }
+
+    async fn leave_chat(&self, chat_id: ChatId) -> Result<(), RequestError> {
+        Requester::leave_chat(self, chat_id).await?;
+        Ok(())
+    }
 }
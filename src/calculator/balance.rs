@@ -1,7 +1,19 @@
-use crate::database::models::{BalanceUpdate, BalanceUpdateSource};
-use crate::database::operations::DatabaseOperations;
+use crate::database::models::{
+    signed_amount, BalanceAdjustment, BalanceUpdate, BalanceUpdateSource, Message, MessageEdit,
+    ParsedMessage, ParsedTransfer, TransferUpdate, UndoneMessage, Wallet, WalletReconciliation,
+};
+use crate::database::operations::{DatabaseOperations, UnitOfWork};
+use crate::money::Money;
 use anyhow::Result;
 use log::{debug, info, warn};
+use std::collections::HashMap;
+
+/// 不修改数据库的账本重放结果：每条消息前后的余额（按消息记录编号）和最终余额
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LedgerReplay {
+    snapshots: HashMap<i64, (Money, Money)>,
+    balance: Money,
+}
 
 #[derive(Clone, Debug)]
 pub struct BalanceCalculator {
@@ -13,206 +25,501 @@ impl BalanceCalculator {
         Self { db }
     }
 
-    /// 计算基于交易的新余额
-    pub async fn calculate_transaction_balance(
+    /// 原子地处理一条钱包消息：更新余额、记录交易并标记消息已处理。
+    ///
+    /// 三个写操作在同一个数据库事务中完成，任何一步失败都会整体回滚。
+    pub async fn process_message(
         &self,
         chat_id: i64,
-        wallet_name: &str,
-        transaction_type: &str,
-        amount: f64,
-        _month: &str,
-        _year: &str,
-    ) -> Result<f64> {
-        debug!("💰 Starting transaction balance calculation");
-        debug!("   ├─ Chat ID: {chat_id}");
-        debug!("   ├─ Wallet: {wallet_name}");
-        debug!("   ├─ Transaction type: {transaction_type}");
-        debug!("   ├─ Amount: {amount}");
-
-        // 获取或创建钱包
-        debug!("🗄️ Getting or creating wallet for chat {chat_id}: {wallet_name}");
-        let wallet = self.db.get_or_create_wallet(chat_id, wallet_name).await?;
-
-        // 获取当前余额
-        let current_balance = wallet.current_balance;
-        debug!("💵 Current balance for {wallet_name}: {current_balance}");
-
-        // 计算新余额
-        let new_balance = match transaction_type {
-            "出账" => {
-                debug!(
-                    "➖ Calculating outgoing transaction: {current_balance} - {amount} = {}",
-                    current_balance - amount
-                );
-                current_balance - amount
-            }
-            "入账" => {
-                debug!(
-                    "➕ Calculating incoming transaction: {current_balance} + {amount} = {}",
-                    current_balance + amount
-                );
-                current_balance + amount
-            }
-            _ => {
-                warn!("⚠️ Unknown transaction type: {transaction_type}");
-                current_balance
-            }
-        };
+        message_id: i64,
+        parsed: &ParsedMessage,
+    ) -> Result<BalanceUpdate> {
+        debug!("🧮 Processing wallet message {message_id} in a unit of work");
 
-        info!("✅ Transaction balance calculated: {wallet_name} {current_balance} → {new_balance}");
-        Ok(new_balance)
+        self.db
+            .unit_of_work(|uow| {
+                let wallet = uow.get_or_create_wallet(chat_id, &parsed.wallet_name)?;
+                let old_balance = wallet.current_balance;
+
+                let (new_balance, source) = match parsed.total_amount {
+                    Some(total) => (total, BalanceUpdateSource::ManualEdit),
+                    None => (
+                        old_balance + parsed.delta(),
+                        BalanceUpdateSource::Transaction,
+                    ),
+                };
+                debug!("💵 {}: {old_balance} → {new_balance}", parsed.wallet_name);
+
+                if let Some(total) = parsed.total_amount {
+                    let computed = old_balance + parsed.delta();
+                    if let Some(adjustment) = Self::manual_total_adjustment(
+                        chat_id,
+                        &wallet,
+                        message_id,
+                        parsed,
+                        computed,
+                        total,
+                        "手动总额与计算余额不一致",
+                    )? {
+                        uow.record_adjustment(&adjustment)?;
+                    }
+                }
+
+                uow.update_wallet_balance(chat_id, &parsed.wallet_name, new_balance)?;
+                for item in &parsed.items {
+                    uow.record_line_item(
+                        chat_id,
+                        &parsed.wallet_name,
+                        item,
+                        &parsed.month,
+                        &parsed.year,
+                        message_id,
+                    )?;
+                }
+                uow.record_message(
+                    message_id,
+                    chat_id,
+                    &parsed.wallet_name,
+                    parsed.total_amount.is_some(),
+                    Some(old_balance),
+                    Some(new_balance),
+                )?;
+
+                Ok(BalanceUpdate {
+                    wallet_name: parsed.wallet_name.clone(),
+                    old_balance,
+                    new_balance,
+                    source,
+                    message_id: Some(message_id),
+                    chat_id: Some(chat_id),
+                })
+            })
+            .await
     }
 
-    /// 从手动编辑的总额更新余额
-    pub async fn update_from_manual_total(
+    /// 原子地处理一笔转账：转出钱包扣款、转入钱包入账，并记录两行互相链接的交易。
+    ///
+    /// 消息记录挂在转出钱包名下，余额快照也是转出钱包的。
+    pub async fn process_transfer(
         &self,
         chat_id: i64,
-        wallet_name: &str,
-        total_amount: f64,
-        _message_id: Option<i64>,
-    ) -> Result<BalanceUpdate> {
-        debug!("📝 Starting manual total update");
-        debug!("   ├─ Wallet: {wallet_name}");
-        debug!("   ├─ Total amount: {total_amount}");
-
-        // 获取或创建钱包
-        debug!("🗄️ Getting wallet for manual update: {wallet_name} in chat {chat_id}");
-        let wallet = self.db.get_or_create_wallet(chat_id, wallet_name).await?;
-        let old_balance = wallet.current_balance;
-        debug!("💵 Current balance: {old_balance} -> {total_amount}");
-
-        // 更新钱包余额
-        debug!("🔄 Updating wallet balance...");
-        self.db
-            .update_wallet_balance(chat_id, wallet_name, total_amount)
-            .await?;
+        message_id: i64,
+        transfer: &ParsedTransfer,
+    ) -> Result<TransferUpdate> {
+        debug!(
+            "🔁 Processing transfer message {message_id}: {} -> {} {}",
+            transfer.from_wallet, transfer.to_wallet, transfer.amount
+        );
 
-        info!("✅ Manual balance update completed: {wallet_name} {old_balance} → {total_amount}");
+        let update = self
+            .db
+            .unit_of_work(|uow| {
+                let from = uow.get_or_create_wallet(chat_id, &transfer.from_wallet)?;
+                let to = uow.get_or_create_wallet(chat_id, &transfer.to_wallet)?;
+                let from_balance = from.current_balance - transfer.amount;
+                let to_balance = to.current_balance + transfer.amount;
 
-        Ok(BalanceUpdate {
-            wallet_name: wallet_name.to_string(),
-            old_balance,
-            new_balance: total_amount,
-            source: BalanceUpdateSource::ManualEdit,
-            message_id: _message_id,
-            chat_id: Some(chat_id),
-        })
+                uow.update_wallet_balance(chat_id, &transfer.from_wallet, from_balance)?;
+                uow.update_wallet_balance(chat_id, &transfer.to_wallet, to_balance)?;
+                uow.record_transfer(
+                    chat_id,
+                    &transfer.from_wallet,
+                    &transfer.to_wallet,
+                    transfer.amount,
+                    &transfer.month,
+                    &transfer.year,
+                    message_id,
+                    transfer.note.as_deref(),
+                )?;
+                uow.record_message(
+                    message_id,
+                    chat_id,
+                    &transfer.from_wallet,
+                    false,
+                    Some(from.current_balance),
+                    Some(from_balance),
+                )?;
+
+                let balance_update = |wallet: &Wallet, new_balance| BalanceUpdate {
+                    wallet_name: wallet.name.clone(),
+                    old_balance: wallet.current_balance,
+                    new_balance,
+                    source: BalanceUpdateSource::Transaction,
+                    message_id: Some(message_id),
+                    chat_id: Some(chat_id),
+                };
+                Ok(TransferUpdate {
+                    from: balance_update(&from, from_balance),
+                    to: balance_update(&to, to_balance),
+                })
+            })
+            .await?;
+
+        info!(
+            "✅ Transfer completed: {} {} → {}, {} {} → {}",
+            update.from.wallet_name,
+            update.from.old_balance,
+            update.from.new_balance,
+            update.to.wallet_name,
+            update.to.old_balance,
+            update.to.new_balance
+        );
+        Ok(update)
     }
 
-    /// 智能余额计算：优先使用总额，否则计算交易余额
-    #[allow(clippy::too_many_arguments)]
-    pub async fn smart_calculate_balance(
+    /// 重新计算被编辑消息的余额：撤销原交易的影响，再应用新解析出的交易
+    pub async fn recalculate_edited_message(
         &self,
         chat_id: i64,
-        wallet_name: &str,
-        transaction_type: &str,
-        amount: f64,
-        month: &str,
-        year: &str,
-        total_amount: Option<f64>,
-        message_id: Option<i64>,
+        record: &Message,
+        parsed: &ParsedMessage,
     ) -> Result<BalanceUpdate> {
-        debug!("🧮 Starting smart balance calculation");
-        debug!("   ├─ Chat ID: {chat_id}");
-        debug!("   ├─ Wallet: {wallet_name}");
-        debug!("   ├─ Transaction type: {transaction_type}");
-        debug!("   ├─ Amount: {amount}");
-        debug!("   ├─ Month: {month}");
-        debug!("   ├─ Year: {year}");
-        debug!("   ├─ Total amount: {total_amount:?}");
-        debug!("   ├─ Message ID: {message_id:?}");
-
-        match total_amount {
-            Some(total) => {
-                debug!("📊 Using manual total for calculation: {total}");
-                self.update_from_manual_total(chat_id, wallet_name, total, message_id)
-                    .await
-            }
-            None => {
-                debug!("💰 Using transaction-based calculation");
-                // 如果没有总额，基于交易计算
-                debug!(
-                    "🗄️ Getting wallet for transaction calculation: {wallet_name} in chat {chat_id}"
-                );
-                let wallet = self.db.get_or_create_wallet(chat_id, wallet_name).await?;
-                let old_balance = wallet.current_balance;
-                debug!("💵 Current balance: {old_balance}");
+        debug!("✏️ Recalculating edited message {}", record.message_id);
 
-                let new_balance = self
-                    .calculate_transaction_balance(
+        let update = self
+            .db
+            .unit_of_work(|uow| {
+                let old_wallet = uow.get_wallet_by_id(record.wallet_id)?;
+                let old_delta: Money = uow
+                    .get_message_transactions(record.message_id, chat_id)?
+                    .iter()
+                    .map(|t| signed_amount(&t.transaction_type, t.amount))
+                    .sum();
+                let old_original_balance = record.original_balance.unwrap_or(Money::ZERO);
+                let old_new_balance = record
+                    .new_balance
+                    .unwrap_or(old_original_balance + old_delta);
+                let new_delta = parsed.delta();
+                debug!("   ├─ Old delta: {old_delta}, new delta: {new_delta}");
+
+                // 消息中保留的总额如果和记录的余额一致，说明只是机器人之前追加的总额
+                let manual_total = parsed
+                    .total_amount
+                    .filter(|total| Self::exceeds_tolerance(*total, old_new_balance));
+                let was_manual = record.has_total
+                    && Self::exceeds_tolerance(old_new_balance, old_original_balance + old_delta);
+
+                let (original_balance, new_balance, has_total) =
+                    if parsed.wallet_name != old_wallet.name {
+                        debug!(
+                            "   ├─ Wallet changed: {} -> {}",
+                            old_wallet.name, parsed.wallet_name
+                        );
+                        let wallet = uow.get_or_create_wallet(chat_id, &parsed.wallet_name)?;
+                        match manual_total {
+                            Some(total) => (wallet.current_balance, total, true),
+                            None => (
+                                wallet.current_balance,
+                                wallet.current_balance + new_delta,
+                                false,
+                            ),
+                        }
+                    } else {
+                        match manual_total {
+                            Some(total) => (old_original_balance, total, true),
+                            None if was_manual => (old_original_balance, old_new_balance, true),
+                            None => (
+                                old_original_balance,
+                                old_original_balance + new_delta,
+                                false,
+                            ),
+                        }
+                    };
+
+                uow.apply_message_edit(&MessageEdit {
+                    message_id: record.message_id,
+                    chat_id,
+                    old_wallet_id: record.wallet_id,
+                    wallet_name: parsed.wallet_name.clone(),
+                    items: parsed.items.clone(),
+                    month: parsed.month.clone(),
+                    year: parsed.year.clone(),
+                    has_total,
+                    original_balance,
+                    new_balance,
+                })?;
+
+                // 调整记录随消息一起重新生成
+                uow.delete_message_adjustments(record.message_id, chat_id)?;
+                if has_total {
+                    let wallet = uow.get_wallet_by_name(chat_id, &parsed.wallet_name)?;
+                    if let Some(adjustment) = Self::manual_total_adjustment(
                         chat_id,
-                        wallet_name,
-                        transaction_type,
-                        amount,
-                        month,
-                        year,
-                    )
-                    .await?;
-
-                debug!("🔄 Updating wallet balance from {old_balance} to {new_balance}");
-                // 更新钱包余额
-                self.db
-                    .update_wallet_balance(chat_id, wallet_name, new_balance)
-                    .await?;
-
-                debug!("✅ Transaction-based calculation completed");
+                        &wallet,
+                        record.message_id,
+                        parsed,
+                        original_balance + new_delta,
+                        new_balance,
+                        "编辑后的手动总额与计算余额不一致",
+                    )? {
+                        uow.record_adjustment(&adjustment)?;
+                    }
+                }
+
                 Ok(BalanceUpdate {
-                    wallet_name: wallet_name.to_string(),
-                    old_balance,
+                    wallet_name: parsed.wallet_name.clone(),
+                    old_balance: old_new_balance,
                     new_balance,
-                    source: BalanceUpdateSource::Transaction,
-                    message_id,
+                    source: if has_total {
+                        BalanceUpdateSource::ManualEdit
+                    } else {
+                        BalanceUpdateSource::Transaction
+                    },
+                    message_id: Some(record.message_id),
                     chat_id: Some(chat_id),
                 })
-            }
+            })
+            .await?;
+
+        info!(
+            "✅ Edited message recalculated: {} {} → {}",
+            update.wallet_name, update.old_balance, update.new_balance
+        );
+        Ok(update)
+    }
+
+    /// 撤销一条已处理的消息，作废它的交易并恢复相关钱包的余额
+    pub async fn undo_message(&self, chat_id: i64, message_id: i64) -> Result<UndoneMessage> {
+        debug!("↩️ Undoing message {message_id} in chat {chat_id}");
+
+        let undone = self
+            .db
+            .unit_of_work(|uow| uow.void_message(message_id, chat_id))
+            .await?;
+
+        for balance in &undone.balances {
+            info!(
+                "✅ Undo restored {}: {} → {}",
+                balance.wallet_name, balance.old_balance, balance.new_balance
+            );
         }
+        Ok(undone)
     }
 
-    /// 获取最新的余额信息
-    #[allow(dead_code)]
-    pub async fn get_latest_balance(
+    /// 按账本核对单个钱包的余额，`fix` 为真时把重放结果写回数据库
+    pub async fn reconcile_wallet(
         &self,
         chat_id: i64,
         wallet_name: &str,
-        month: &str,
-        year: &str,
-    ) -> Result<f64> {
+        fix: bool,
+    ) -> Result<WalletReconciliation> {
         self.db
-            .get_latest_balance(chat_id, wallet_name, month, year)
+            .unit_of_work(|uow| {
+                let wallet = uow.get_wallet_by_name(chat_id, wallet_name)?;
+                Self::reconcile(uow, &wallet, fix)
+            })
             .await
     }
 
-    /// 检查余额是否需要调整
-    #[allow(dead_code)]
-    pub async fn should_adjust_balance(
+    /// 按账本核对聊天中所有钱包的余额
+    pub async fn reconcile_chat(
         &self,
-        _wallet_name: &str,
-        current_total: f64,
-        calculated_total: f64,
-    ) -> bool {
-        let tolerance = 0.01; // 1分的容差
+        chat_id: i64,
+        fix: bool,
+    ) -> Result<Vec<WalletReconciliation>> {
+        self.db
+            .unit_of_work(|uow| {
+                uow.get_wallets(chat_id)?
+                    .iter()
+                    .map(|wallet| Self::reconcile(uow, wallet, fix))
+                    .collect()
+            })
+            .await
+    }
+
+    /// 从零开始按时间顺序重放交易和手动总额检查点。
+    ///
+    /// 普通消息在上一条余额的基础上累加交易金额；
+    /// 总额与交易计算结果不一致的消息是手动检查点，余额直接取该总额。
+    pub(crate) fn reconcile(
+        uow: &UnitOfWork<'_>,
+        wallet: &Wallet,
+        fix: bool,
+    ) -> Result<WalletReconciliation> {
+        let wallet_id = wallet
+            .id
+            .ok_or_else(|| anyhow::anyhow!("Wallet {} has no id", wallet.name))?;
+        debug!("🔍 Reconciling wallet {} ({wallet_id})", wallet.name);
+
+        let mut balance = Money::ZERO;
+        let mut transaction_count = 0;
+        let mut checkpoint_count = 0;
+        let mut stale_snapshots = 0;
+
+        for entry in uow.get_wallet_ledger(wallet_id)? {
+            let delta = entry.delta();
+            let is_checkpoint = entry.is_checkpoint();
+            let replayed = entry.replay(balance);
+            transaction_count += entry.transactions.len();
+
+            let Some(record) = entry.message else {
+                balance = replayed;
+                continue;
+            };
+
+            let original_balance = record.original_balance.unwrap_or(Money::ZERO);
+            let new_balance = record.new_balance.unwrap_or(original_balance + delta);
+            if is_checkpoint {
+                checkpoint_count += 1;
+            }
+
+            if original_balance != balance || new_balance != replayed {
+                stale_snapshots += 1;
+                debug!(
+                    "   ├─ Message {} snapshot {original_balance} → {new_balance}, replayed {balance} → {replayed}",
+                    record.message_id
+                );
+                if fix {
+                    if let Some(record_id) = record.id {
+                        uow.update_message_balances(record_id, balance, replayed)?;
+                    }
+                    if is_checkpoint {
+                        uow.rebase_message_adjustment(
+                            record.message_id,
+                            record.chat_id,
+                            balance + delta,
+                        )?;
+                    }
+                }
+            }
+            balance = replayed;
+        }
+
+        let drifted = balance != wallet.current_balance;
+        if fix && drifted {
+            uow.update_wallet_balance(wallet.chat_id, &wallet.name, balance)?;
+        }
+        if drifted || stale_snapshots > 0 {
+            warn!(
+                "⚠️ Wallet {} drifted: stored {}, ledger {balance}, {stale_snapshots} stale snapshots",
+                wallet.name, wallet.current_balance
+            );
+        }
+
+        Ok(WalletReconciliation {
+            wallet_name: wallet.name.clone(),
+            stored_balance: wallet.current_balance,
+            ledger_balance: balance,
+            transaction_count,
+            checkpoint_count,
+            stale_snapshots,
+            fixed: fix && (drifted || stale_snapshots > 0),
+        })
+    }
+
+    /// 按账本重放钱包余额，只读取不写入
+    pub(crate) fn replay_ledger(uow: &UnitOfWork<'_>, wallet_id: i64) -> Result<LedgerReplay> {
+        let mut replay = LedgerReplay::default();
+        for entry in uow.get_wallet_ledger(wallet_id)? {
+            let replayed = entry.replay(replay.balance);
+            if let Some(record_id) = entry.message.as_ref().and_then(|record| record.id) {
+                replay
+                    .snapshots
+                    .insert(record_id, (replay.balance, replayed));
+            }
+            replay.balance = replayed;
+        }
+        Ok(replay)
+    }
+
+    /// 把账本中新增的条目（导入、补记的历史交易）造成的变化加到钱包余额和消息的余额快照上。
+    ///
+    /// `before` 是新增条目之前的重放结果，只应用前后两次重放的差值，
+    /// 此前已经存在的偏差保持原样，留给 /reconcile 处理。返回更新后的余额。
+    pub(crate) fn apply_ledger_change(
+        uow: &UnitOfWork<'_>,
+        wallet: &Wallet,
+        before: &LedgerReplay,
+    ) -> Result<Money> {
+        let wallet_id = wallet
+            .id
+            .ok_or_else(|| anyhow::anyhow!("Wallet {} has no id", wallet.name))?;
+
+        let mut balance = Money::ZERO;
+        for entry in uow.get_wallet_ledger(wallet_id)? {
+            let delta = entry.delta();
+            let is_checkpoint = entry.is_checkpoint();
+            let replayed = entry.replay(balance);
+            let previous = balance;
+            balance = replayed;
+
+            let Some(record) = entry.message else {
+                continue;
+            };
+            let Some((record_id, (old_original, old_new))) = record
+                .id
+                .and_then(|id| Some((id, *before.snapshots.get(&id)?)))
+            else {
+                continue;
+            };
+            let (shift_original, shift_new) = (previous - old_original, replayed - old_new);
+            if shift_original.is_zero() && shift_new.is_zero() {
+                continue;
+            }
+
+            let original_balance = record.original_balance.unwrap_or(Money::ZERO);
+            let new_balance = record.new_balance.unwrap_or(original_balance + delta);
+            uow.update_message_balances(
+                record_id,
+                original_balance + shift_original,
+                new_balance + shift_new,
+            )?;
+            if is_checkpoint {
+                uow.rebase_message_adjustment(
+                    record.message_id,
+                    record.chat_id,
+                    original_balance + shift_original + delta,
+                )?;
+            }
+        }
+
+        let new_balance = wallet.current_balance + (balance - before.balance);
+        if new_balance != wallet.current_balance {
+            uow.update_wallet_balance(wallet.chat_id, &wallet.name, new_balance)?;
+        }
+        Ok(new_balance)
+    }
+
+    fn exceeds_tolerance(current_total: Money, calculated_total: Money) -> bool {
+        let tolerance = Money::from_cents(1); // 1分的容差
         (current_total - calculated_total).abs() > tolerance
     }
 
-    /// 生成余额调整记录
-    #[allow(dead_code)]
-    pub async fn create_balance_adjustment(
-        &self,
-        wallet_name: &str,
-        old_balance: f64,
-        new_balance: f64,
+    /// 手动总额与按交易计算的余额相差超过容差时，生成对应的调整记录
+    fn manual_total_adjustment(
+        chat_id: i64,
+        wallet: &Wallet,
+        message_id: i64,
+        parsed: &ParsedMessage,
+        computed_balance: Money,
+        manual_total: Money,
         reason: &str,
-        _message_id: Option<i64>,
-        _chat_id: Option<i64>,
-    ) -> Result<()> {
+    ) -> Result<Option<BalanceAdjustment>> {
+        if !Self::exceeds_tolerance(manual_total, computed_balance) {
+            return Ok(None);
+        }
+
+        let wallet_id = wallet
+            .id
+            .ok_or_else(|| anyhow::anyhow!("Wallet {} has no id", wallet.name))?;
         info!(
-            "Creating balance adjustment for {wallet_name}: {old_balance} -> {new_balance} ({reason})"
+            "📒 Manual total for {} differs from computed balance: {computed_balance} → {manual_total}",
+            wallet.name
         );
 
-        // 这里可以添加审计日志逻辑
-        // 比如记录到专门的 balance_adjustments 表
-
-        Ok(())
+        Ok(Some(BalanceAdjustment {
+            id: None,
+            wallet_id,
+            chat_id,
+            message_id: Some(message_id),
+            old_balance: computed_balance,
+            new_balance: manual_total,
+            difference: manual_total - computed_balance,
+            reason: reason.to_string(),
+            month: parsed.month.clone(),
+            year: parsed.year.clone(),
+            created_at: None,
+        }))
     }
 }
 
@@ -0,0 +1,338 @@
+use crate::database::models::{
+    signed_amount, CategoryTotal, LedgerEntry, Transaction, Wallet, TRANSFER_IN, TRANSFER_OUT,
+};
+use crate::database::operations::{DatabaseOperations, UnitOfWork};
+use crate::money::Money;
+use crate::utils::PeriodArg;
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, Utc};
+use log::debug;
+use std::cmp::Ordering;
+
+/// 报表中列出的最大交易笔数
+pub const LARGEST_TRANSACTIONS: usize = 5;
+
+/// 报表的时间范围：某一年，或某一年中的某个月
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportPeriod {
+    pub year: u32,
+    pub month: Option<u32>,
+}
+
+impl ReportPeriod {
+    pub fn month(year: u32, month: u32) -> Self {
+        Self {
+            year,
+            month: Some(month),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn year(year: u32) -> Self {
+        Self { year, month: None }
+    }
+
+    /// 当前所在的月份
+    pub fn current_month() -> Self {
+        let now = Utc::now();
+        Self::month(now.year() as u32, now.month())
+    }
+
+    /// 给定时间的上一个月
+    pub fn month_before<T: Datelike>(date: &T) -> Self {
+        match date.month() {
+            1 => Self::month(date.year() as u32 - 1, 12),
+            month => Self::month(date.year() as u32, month - 1),
+        }
+    }
+
+    /// 从命令参数解析时间范围，非时间参数原样返回。
+    ///
+    /// 只给出月份时使用今年，什么都没有给出时使用当前月份。
+    pub fn parse_args<'a, I>(args: I) -> (Self, Vec<&'a str>)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut month = None;
+        let mut year = None;
+        let mut rest = Vec::new();
+        for arg in args {
+            match PeriodArg::parse(arg) {
+                Some(PeriodArg::Month(m)) => month = Some(m),
+                Some(PeriodArg::Year(y)) => year = Some(y),
+                None => rest.push(arg),
+            }
+        }
+
+        let current = Self::current_month();
+        let period = match (year, month) {
+            (Some(year), month) => Self { year, month },
+            (None, Some(month)) => Self::month(current.year, month),
+            (None, None) => current,
+        };
+        (period, rest)
+    }
+
+    /// 交易所属的月份和年份相对于这个时间范围的位置
+    pub fn position(&self, month: &str, year: &str) -> Option<Ordering> {
+        let year = leading_number(year)?;
+        match self.month {
+            Some(target) => Some((year, leading_number(month)?).cmp(&(self.year, target))),
+            None => Some(year.cmp(&self.year)),
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self.month {
+            Some(month) => format!("{}年{}月", self.year, month),
+            None => format!("{}年", self.year),
+        }
+    }
+}
+
+/// "12月"、"2024年" 开头的数字
+fn leading_number(s: &str) -> Option<u32> {
+    s.chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect::<String>()
+        .parse()
+        .ok()
+}
+
+/// 报表中的一笔交易
+#[derive(Debug, Clone)]
+pub struct ReportTransaction {
+    pub wallet_name: String,
+    pub transaction: Transaction,
+}
+
+/// 一个钱包（或整个聊天）在某个时间范围内的收支汇总
+#[derive(Debug, Clone)]
+pub struct PeriodReport {
+    pub name: String,
+    pub period: ReportPeriod,
+    pub opening_balance: Money,
+    pub closing_balance: Money,
+    pub income: Money,  // 不含转账
+    pub expense: Money, // 不含转账，为正数
+    pub transfer_in: Money,
+    pub transfer_out: Money,
+    pub transaction_count: usize,
+    pub largest: Vec<ReportTransaction>, // 金额最大的收支（不含转账）
+    pub categories: Vec<CategoryTotal>,  // 支出多的分类在前
+}
+
+impl PeriodReport {
+    fn empty(name: &str, period: ReportPeriod) -> Self {
+        Self {
+            name: name.to_string(),
+            period,
+            opening_balance: Money::ZERO,
+            closing_balance: Money::ZERO,
+            income: Money::ZERO,
+            expense: Money::ZERO,
+            transfer_in: Money::ZERO,
+            transfer_out: Money::ZERO,
+            transaction_count: 0,
+            largest: Vec::new(),
+            categories: Vec::new(),
+        }
+    }
+
+    pub fn net_change(&self) -> Money {
+        self.closing_balance - self.opening_balance
+    }
+
+    /// 期间余额变化中无法由交易解释的部分（手动总额造成的调整）
+    pub fn unexplained(&self) -> Money {
+        self.net_change() - (self.income - self.expense + self.transfer_in - self.transfer_out)
+    }
+
+    fn add_transaction(&mut self, wallet_name: &str, transaction: &Transaction) {
+        self.transaction_count += 1;
+        match transaction.transaction_type.as_str() {
+            TRANSFER_IN => self.transfer_in += transaction.amount,
+            TRANSFER_OUT => self.transfer_out += transaction.amount,
+            _ => {
+                let signed = signed_amount(&transaction.transaction_type, transaction.amount);
+                let (income, expense) = if signed > Money::ZERO {
+                    (transaction.amount, Money::ZERO)
+                } else if signed < Money::ZERO {
+                    (Money::ZERO, transaction.amount)
+                } else {
+                    (Money::ZERO, Money::ZERO)
+                };
+                self.income += income;
+                self.expense += expense;
+                self.add_to_category(&transaction.category, income, expense);
+                self.largest.push(ReportTransaction {
+                    wallet_name: wallet_name.to_string(),
+                    transaction: transaction.clone(),
+                });
+            }
+        }
+    }
+
+    fn add_to_category(&mut self, category: &Option<String>, income: Money, expense: Money) {
+        match self.categories.iter_mut().find(|c| &c.category == category) {
+            Some(total) => {
+                total.count += 1;
+                total.income += income;
+                total.expense += expense;
+            }
+            None => self.categories.push(CategoryTotal {
+                category: category.clone(),
+                count: 1,
+                income,
+                expense,
+            }),
+        }
+    }
+
+    /// 把另一个钱包的报表合并进来
+    fn merge(&mut self, other: &PeriodReport) {
+        self.opening_balance += other.opening_balance;
+        self.closing_balance += other.closing_balance;
+        self.income += other.income;
+        self.expense += other.expense;
+        self.transfer_in += other.transfer_in;
+        self.transfer_out += other.transfer_out;
+        self.transaction_count += other.transaction_count;
+        self.largest.extend(other.largest.iter().cloned());
+        for category in &other.categories {
+            match self
+                .categories
+                .iter_mut()
+                .find(|c| c.category == category.category)
+            {
+                Some(total) => {
+                    total.count += category.count;
+                    total.income += category.income;
+                    total.expense += category.expense;
+                }
+                None => self.categories.push(category.clone()),
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        // 稳定排序，金额相同的交易保持时间顺序
+        self.largest
+            .sort_by_key(|t| std::cmp::Reverse(t.transaction.amount));
+        self.largest.truncate(LARGEST_TRANSACTIONS);
+        self.categories.sort_by(|a, b| {
+            b.expense
+                .cmp(&a.expense)
+                .then_with(|| b.income.cmp(&a.income))
+                .then_with(|| a.category.cmp(&b.category))
+        });
+    }
+}
+
+/// 整个聊天的报表：合计以及每个钱包的明细
+#[derive(Debug, Clone)]
+pub struct ChatReport {
+    pub total: PeriodReport,
+    pub wallets: Vec<PeriodReport>,
+}
+
+/// 按账本生成月度/年度报表
+#[derive(Clone, Debug)]
+pub struct ReportGenerator {
+    db: DatabaseOperations,
+}
+
+impl ReportGenerator {
+    pub fn new(db: DatabaseOperations) -> Self {
+        Self { db }
+    }
+
+    /// 单个钱包在某个时间范围内的报表
+    pub async fn wallet_report(
+        &self,
+        chat_id: i64,
+        wallet_name: &str,
+        period: ReportPeriod,
+    ) -> Result<PeriodReport> {
+        self.db
+            .unit_of_work(|uow| {
+                let wallet = uow.get_wallet_by_name(chat_id, wallet_name)?;
+                Self::build_wallet_report(uow, &wallet, period)
+            })
+            .await
+    }
+
+    /// 聊天中所有钱包在某个时间范围内的报表。
+    ///
+    /// 合计中的转入、转出来自聊天内部的转账，会相互抵消。
+    pub async fn chat_report(&self, chat_id: i64, period: ReportPeriod) -> Result<ChatReport> {
+        self.db
+            .unit_of_work(|uow| {
+                let mut total = PeriodReport::empty("全部钱包", period);
+                let mut wallets = Vec::new();
+                for wallet in uow.get_wallets(chat_id)? {
+                    let report = Self::build_wallet_report(uow, &wallet, period)?;
+                    total.merge(&report);
+                    wallets.push(report);
+                }
+                total.finish();
+                Ok(ChatReport { total, wallets })
+            })
+            .await
+    }
+
+    /// 按交易所属的年月重放钱包余额，统计属于该时间范围的条目。
+    ///
+    /// 导入、补记等晚记录的条目可能属于更早的月份，因此先按 (年, 月, 记录时间) 排序；
+    /// 期初余额是所有更早条目之后的余额，期末余额是所有期内条目之后的余额。
+    fn build_wallet_report(
+        uow: &UnitOfWork<'_>,
+        wallet: &Wallet,
+        period: ReportPeriod,
+    ) -> Result<PeriodReport> {
+        let wallet_id = wallet
+            .id
+            .ok_or_else(|| anyhow!("Wallet {} has no id", wallet.name))?;
+        debug!("📊 Building {} report for {}", period.label(), wallet.name);
+
+        // 账本已按记录时间排列，稳定排序保持同一个月内的先后顺序
+        let mut ledger = uow.get_wallet_ledger(wallet_id)?;
+        ledger.sort_by_key(Self::entry_month);
+
+        let mut report = PeriodReport::empty(&wallet.name, period);
+        let mut balance = Money::ZERO;
+        let mut opening = None;
+        for entry in &ledger {
+            match Self::entry_position(entry, period) {
+                Some(Ordering::Greater) => break,
+                Some(Ordering::Equal) => {
+                    opening.get_or_insert(balance);
+                    for transaction in &entry.transactions {
+                        report.add_transaction(&wallet.name, transaction);
+                    }
+                }
+                _ => {}
+            }
+            balance = entry.replay(balance);
+        }
+
+        report.opening_balance = opening.unwrap_or(balance);
+        report.closing_balance = balance;
+        report.finish();
+        Ok(report)
+    }
+
+    /// 条目所属的 (年, 月)，无法识别时排在最前面
+    fn entry_month(entry: &LedgerEntry) -> Option<(u32, u32)> {
+        let transaction = entry.transactions.first()?;
+        Some((
+            leading_number(&transaction.year)?,
+            leading_number(&transaction.month)?,
+        ))
+    }
+
+    fn entry_position(entry: &LedgerEntry, period: ReportPeriod) -> Option<Ordering> {
+        let transaction = entry.transactions.first()?;
+        period.position(&transaction.month, &transaction.year)
+    }
+}
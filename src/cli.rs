@@ -0,0 +1,373 @@
+use crate::calculator::balance::BalanceCalculator;
+use crate::calculator::report::{ReportGenerator, ReportPeriod};
+use crate::database::operations::DatabaseOperations;
+use crate::export::{CsvExporter, ExportKind, ExportQuery};
+use crate::import::{ImportFormat, Importer};
+use crate::replay::{ChatExport, Replayer};
+use crate::utils::Formatter;
+use anyhow::{anyhow, Result};
+
+pub const USAGE: &str = "用法：
+  walletbot                                   启动机器人
+  walletbot reconcile <chat_id> [钱包名] [--fix]  按账本核对余额，--fix 写回修正结果
+  walletbot adjustments <chat_id>             按月查看未被交易解释的余额变化
+  walletbot report <chat_id> [钱包名] [月份] [年份]  月度/年度报表，例如 report -100123 12月 2024年
+  walletbot export <chat_id> [钱包名] [月份] [年份] [--balances]  导出交易记录（或钱包余额）为 CSV
+  walletbot import <chat_id> <文件> [--dry-run]  从 CSV 或 JSON 导入历史交易，--dry-run 只预览
+  walletbot replay <result.json> [chat_id]     按时间顺序重放 Telegram Desktop 导出的聊天记录，补记漏掉的消息
+  walletbot help                              显示本帮助";
+
+/// 命令行子命令
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CliCommand {
+    /// 启动机器人（默认）
+    Run,
+    /// 显示帮助
+    Help,
+    /// 按账本核对一个钱包或整个聊天的余额
+    Reconcile {
+        chat_id: i64,
+        wallet_name: Option<String>,
+        fix: bool,
+    },
+    /// 按月查看聊天中的余额调整
+    Adjustments { chat_id: i64 },
+    /// 生成一个钱包或整个聊天的月度/年度报表
+    Report {
+        chat_id: i64,
+        wallet_name: Option<String>,
+        period: ReportPeriod,
+    },
+    /// 把交易记录或钱包余额以 CSV 输出到标准输出
+    Export {
+        chat_id: i64,
+        query: ExportQuery,
+        kind: ExportKind,
+    },
+    /// 从 CSV 或 JSON 文件导入历史交易
+    Import {
+        chat_id: i64,
+        path: String,
+        dry_run: bool,
+    },
+    /// 重放 Telegram Desktop 导出的聊天记录，未给出聊天 ID 时使用导出文件中的
+    Replay { path: String, chat_id: Option<i64> },
+}
+
+impl CliCommand {
+    /// 解析命令行参数（不含程序名）
+    pub fn parse<I>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut args = args.into_iter();
+        let Some(command) = args.next() else {
+            return Ok(CliCommand::Run);
+        };
+
+        match command.as_str() {
+            "run" => Ok(CliCommand::Run),
+            "help" | "-h" | "--help" => Ok(CliCommand::Help),
+            "reconcile" => {
+                let mut chat_id = None;
+                let mut wallet_name = None;
+                let mut fix = false;
+                for arg in args {
+                    match arg.as_str() {
+                        "--fix" => fix = true,
+                        _ if chat_id.is_none() => {
+                            chat_id = Some(parse_chat_id(&arg)?);
+                        }
+                        _ if wallet_name.is_none() => wallet_name = Some(arg),
+                        _ => return Err(anyhow!("Unexpected argument: {arg}")),
+                    }
+                }
+
+                Ok(CliCommand::Reconcile {
+                    chat_id: chat_id.ok_or_else(|| anyhow!("reconcile requires a chat id"))?,
+                    wallet_name,
+                    fix,
+                })
+            }
+            "adjustments" => {
+                let chat_id = args
+                    .next()
+                    .ok_or_else(|| anyhow!("adjustments requires a chat id"))?;
+                if let Some(extra) = args.next() {
+                    return Err(anyhow!("Unexpected argument: {extra}"));
+                }
+                Ok(CliCommand::Adjustments {
+                    chat_id: parse_chat_id(&chat_id)?,
+                })
+            }
+            "report" => {
+                let chat_id = args
+                    .next()
+                    .ok_or_else(|| anyhow!("report requires a chat id"))?;
+                let rest: Vec<String> = args.collect();
+                let (period, names) = ReportPeriod::parse_args(rest.iter().map(String::as_str));
+                if names.len() > 1 {
+                    return Err(anyhow!("Unexpected argument: {}", names[1]));
+                }
+                Ok(CliCommand::Report {
+                    chat_id: parse_chat_id(&chat_id)?,
+                    wallet_name: names.first().map(|name| name.to_string()),
+                    period,
+                })
+            }
+            "export" => {
+                let chat_id = args
+                    .next()
+                    .ok_or_else(|| anyhow!("export requires a chat id"))?;
+                let (flags, rest): (Vec<String>, Vec<String>) =
+                    args.partition(|arg| arg.starts_with("--"));
+                let mut kind = ExportKind::Transactions;
+                for flag in flags {
+                    match flag.as_str() {
+                        "--balances" => kind = ExportKind::Balances,
+                        _ => return Err(anyhow!("Unexpected argument: {flag}")),
+                    }
+                }
+                Ok(CliCommand::Export {
+                    chat_id: parse_chat_id(&chat_id)?,
+                    query: ExportQuery::parse_args(rest.iter().map(String::as_str))?,
+                    kind,
+                })
+            }
+            "import" => {
+                let mut chat_id = None;
+                let mut path = None;
+                let mut dry_run = false;
+                for arg in args {
+                    match arg.as_str() {
+                        "--dry-run" => dry_run = true,
+                        _ if chat_id.is_none() => chat_id = Some(parse_chat_id(&arg)?),
+                        _ if path.is_none() => path = Some(arg),
+                        _ => return Err(anyhow!("Unexpected argument: {arg}")),
+                    }
+                }
+
+                Ok(CliCommand::Import {
+                    chat_id: chat_id.ok_or_else(|| anyhow!("import requires a chat id"))?,
+                    path: path.ok_or_else(|| anyhow!("import requires a file"))?,
+                    dry_run,
+                })
+            }
+            "replay" => {
+                let path = args
+                    .next()
+                    .ok_or_else(|| anyhow!("replay requires a result.json file"))?;
+                let chat_id = args.next().map(|id| parse_chat_id(&id)).transpose()?;
+                if let Some(extra) = args.next() {
+                    return Err(anyhow!("Unexpected argument: {extra}"));
+                }
+                Ok(CliCommand::Replay { path, chat_id })
+            }
+            other => Err(anyhow!("Unknown command: {other}")),
+        }
+    }
+}
+
+/// 执行不需要连接 Telegram 的子命令，结果输出到标准输出
+pub async fn execute(command: CliCommand, db: DatabaseOperations) -> Result<()> {
+    match command {
+        CliCommand::Run | CliCommand::Help => {
+            Err(anyhow!("{command:?} is handled by the entry point"))
+        }
+        CliCommand::Reconcile {
+            chat_id,
+            wallet_name,
+            fix,
+        } => {
+            let settings = db.get_chat_settings(chat_id).await?;
+            let calculator = BalanceCalculator::new(db);
+            let results = match wallet_name {
+                Some(name) => vec![calculator.reconcile_wallet(chat_id, &name, fix).await?],
+                None => calculator.reconcile_chat(chat_id, fix).await?,
+            };
+            println!("{}", Formatter::format_reconciliation(&settings, &results));
+            Ok(())
+        }
+        CliCommand::Adjustments { chat_id } => {
+            let settings = db.get_chat_settings(chat_id).await?;
+            let months = db.get_monthly_adjustments(chat_id).await?;
+            println!(
+                "{}",
+                Formatter::format_monthly_adjustments(&settings, &months)
+            );
+            Ok(())
+        }
+        CliCommand::Report {
+            chat_id,
+            wallet_name,
+            period,
+        } => {
+            let settings = db.get_chat_settings(chat_id).await?;
+            let generator = ReportGenerator::new(db);
+            let text = match wallet_name {
+                Some(name) => Formatter::format_report(
+                    &settings,
+                    &generator.wallet_report(chat_id, &name, period).await?,
+                ),
+                None => Formatter::format_chat_report(
+                    &settings,
+                    &generator.chat_report(chat_id, period).await?,
+                ),
+            };
+            println!("{text}");
+            Ok(())
+        }
+        CliCommand::Export {
+            chat_id,
+            query,
+            kind,
+        } => {
+            let file = CsvExporter::new(db).export(chat_id, &query, kind).await?;
+            print!("{}", file.content);
+            Ok(())
+        }
+        CliCommand::Import {
+            chat_id,
+            path,
+            dry_run,
+        } => {
+            let content = std::fs::read_to_string(&path)
+                .map_err(|e| anyhow!("Failed to read {path}: {e}"))?;
+            let format = ImportFormat::detect(Some(&path), &content);
+            let settings = db.get_chat_settings(chat_id).await?;
+            let report = Importer::new(db)
+                .import(chat_id, &content, format, dry_run)
+                .await?;
+            println!("{}", Formatter::format_import_report(&settings, &report));
+            if report.is_rejected() {
+                return Err(anyhow!("Import rejected, nothing was written"));
+            }
+            Ok(())
+        }
+        CliCommand::Replay { path, chat_id } => {
+            let content = std::fs::read_to_string(&path)
+                .map_err(|e| anyhow!("Failed to read {path}: {e}"))?;
+            let export = ChatExport::from_json(&content)?;
+            let chat_id = chat_id.unwrap_or_else(|| export.bot_chat_id());
+            if let Some(name) = &export.name {
+                println!("💬 {name}（{chat_id}）");
+            }
+            let report = Replayer::new(db.clone()).replay(chat_id, &export).await?;
+            println!("{}", Formatter::format_replay_report(&report));
+            println!();
+            println!(
+                "{}",
+                Formatter::format_balances(
+                    &db.get_chat_settings(chat_id).await?,
+                    &db.get_wallet_summaries(chat_id).await?
+                )
+            );
+            Ok(())
+        }
+    }
+}
+
+fn parse_chat_id(arg: &str) -> Result<i64> {
+    arg.parse::<i64>()
+        .map_err(|_| anyhow!("Invalid chat id: {arg}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliCommand> {
+        CliCommand::parse(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_parse_cli_command() {
+        assert_eq!(parse(&[]).unwrap(), CliCommand::Run);
+        assert_eq!(parse(&["--help"]).unwrap(), CliCommand::Help);
+        assert_eq!(
+            parse(&["reconcile", "-100123", "支付宝", "--fix"]).unwrap(),
+            CliCommand::Reconcile {
+                chat_id: -100123,
+                wallet_name: Some("支付宝".to_string()),
+                fix: true,
+            }
+        );
+        assert_eq!(
+            parse(&["reconcile", "42"]).unwrap(),
+            CliCommand::Reconcile {
+                chat_id: 42,
+                wallet_name: None,
+                fix: false,
+            }
+        );
+
+        assert_eq!(
+            parse(&["adjustments", "-100123"]).unwrap(),
+            CliCommand::Adjustments { chat_id: -100123 }
+        );
+
+        assert_eq!(
+            parse(&["report", "-100123", "支付宝", "12月", "2024年"]).unwrap(),
+            CliCommand::Report {
+                chat_id: -100123,
+                wallet_name: Some("支付宝".to_string()),
+                period: ReportPeriod::month(2024, 12),
+            }
+        );
+        assert_eq!(
+            parse(&["report", "-100123", "2024年"]).unwrap(),
+            CliCommand::Report {
+                chat_id: -100123,
+                wallet_name: None,
+                period: ReportPeriod::year(2024),
+            }
+        );
+
+        assert_eq!(
+            parse(&["export", "-100123", "支付宝", "2024年", "--balances"]).unwrap(),
+            CliCommand::Export {
+                chat_id: -100123,
+                query: ExportQuery {
+                    wallet_name: Some("支付宝".to_string()),
+                    period: Some(ReportPeriod::year(2024)),
+                },
+                kind: ExportKind::Balances,
+            }
+        );
+
+        assert_eq!(
+            parse(&["import", "-100123", "--dry-run", "history.csv"]).unwrap(),
+            CliCommand::Import {
+                chat_id: -100123,
+                path: "history.csv".to_string(),
+                dry_run: true,
+            }
+        );
+
+        assert_eq!(
+            parse(&["replay", "result.json"]).unwrap(),
+            CliCommand::Replay {
+                path: "result.json".to_string(),
+                chat_id: None,
+            }
+        );
+        assert_eq!(
+            parse(&["replay", "result.json", "-100123"]).unwrap(),
+            CliCommand::Replay {
+                path: "result.json".to_string(),
+                chat_id: Some(-100123),
+            }
+        );
+
+        assert!(parse(&["reconcile"]).is_err());
+        assert!(parse(&["replay"]).is_err());
+        assert!(parse(&["import", "-100123"]).is_err());
+        assert!(parse(&["export"]).is_err());
+        assert!(parse(&["export", "1", "--all"]).is_err());
+        assert!(parse(&["report"]).is_err());
+        assert!(parse(&["report", "1", "支付宝", "微信"]).is_err());
+        assert!(parse(&["adjustments"]).is_err());
+        assert!(parse(&["reconcile", "abc"]).is_err());
+        assert!(parse(&["unknown"]).is_err());
+    }
+}
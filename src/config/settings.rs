@@ -8,10 +8,18 @@ pub struct Settings {
     pub database_url: String,
     pub bot_name: String,
     pub target_channel_id: Option<i64>,
+    pub admin_user_ids: Vec<i64>,   // 在所有聊天中都拥有管理员权限的用户
+    pub allowed_chat_ids: Vec<i64>, // 允许使用机器人的聊天，为空时不限制
+    pub leave_unknown_chats: bool,  // 被加入不在允许列表中的聊天时自动退出
     pub max_retry_attempts: u32,
     pub processing_timeout: u64,
     pub backup_interval: u64,
     pub backup_retention_days: u32,
+    pub summary_check_interval: u64, // 检查月度总结是否到期的间隔（秒）
+    pub confirmation_timeout: u64,   // 大额交易等待确认的时间（秒）
+    pub webhook_url: Option<String>, // 设置后使用 webhook 模式代替长轮询
+    pub webhook_port: u16,           // webhook 本地监听端口
+    pub webhook_secret: Option<String>, // 校验 X-Telegram-Bot-Api-Secret-Token 请求头，不设置时随机生成
     pub log_level: String,
 }
 
@@ -20,7 +28,7 @@ impl Settings {
         let telegram_bot_token = env::var("TELEGRAM_BOT_TOKEN")
             .map_err(|_| anyhow!("TELEGRAM_BOT_TOKEN must be set"))?;
 
-        let database_url = env::var("DATABASE_URL").unwrap_or_else(|_| "wallet_bot.db".to_string());
+        let database_url = Self::database_url_from_env();
 
         let bot_name = env::var("BOT_NAME").unwrap_or_else(|_| "WalletBot".to_string());
 
@@ -28,6 +36,18 @@ impl Settings {
             .ok()
             .and_then(|id| id.parse::<i64>().ok());
 
+        let admin_user_ids = env::var("ADMIN_USER_IDS")
+            .map(|ids| Self::parse_ids(&ids))
+            .unwrap_or_default();
+
+        let allowed_chat_ids = env::var("ALLOWED_CHAT_IDS")
+            .map(|ids| Self::parse_ids(&ids))
+            .unwrap_or_default();
+
+        let leave_unknown_chats = env::var("LEAVE_UNKNOWN_CHATS")
+            .map(|value| matches!(value.trim(), "true" | "1" | "yes"))
+            .unwrap_or(false);
+
         let max_retry_attempts = env::var("MAX_RETRY_ATTEMPTS")
             .unwrap_or_else(|_| "3".to_string())
             .parse::<u32>()
@@ -48,6 +68,27 @@ impl Settings {
             .parse::<u32>()
             .unwrap_or(7);
 
+        let summary_check_interval = env::var("SUMMARY_CHECK_INTERVAL")
+            .unwrap_or_else(|_| "600".to_string())
+            .parse::<u64>()
+            .unwrap_or(600);
+
+        let confirmation_timeout = env::var("CONFIRMATION_TIMEOUT")
+            .unwrap_or_else(|_| "600".to_string())
+            .parse::<u64>()
+            .unwrap_or(600);
+
+        let webhook_url = env::var("WEBHOOK_URL").ok().filter(|url| !url.is_empty());
+
+        let webhook_port = env::var("WEBHOOK_PORT")
+            .unwrap_or_else(|_| "8080".to_string())
+            .parse::<u16>()
+            .unwrap_or(8080);
+
+        let webhook_secret = env::var("WEBHOOK_SECRET")
+            .ok()
+            .filter(|secret| !secret.is_empty());
+
         let log_level = env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string());
 
         Ok(Settings {
@@ -55,14 +96,41 @@ impl Settings {
             database_url,
             bot_name,
             target_channel_id,
+            admin_user_ids,
+            allowed_chat_ids,
+            leave_unknown_chats,
             max_retry_attempts,
             processing_timeout,
             backup_interval,
             backup_retention_days,
+            summary_check_interval,
+            confirmation_timeout,
+            webhook_url,
+            webhook_port,
+            webhook_secret,
             log_level,
         })
     }
 
+    /// 解析逗号分隔的 ID 列表，忽略无法解析的项
+    pub fn parse_ids(ids: &str) -> Vec<i64> {
+        ids.split(',')
+            .filter_map(|id| id.trim().parse::<i64>().ok())
+            .collect()
+    }
+
+    /// 允许使用机器人的聊天，只由 ALLOWED_CHAT_IDS 决定，为空时不限制。
+    ///
+    /// TARGET_CHANNEL_ID 不会加入列表，只设置它不会把其他聊天挡在外面。
+    pub fn chat_allowlist(&self) -> Vec<i64> {
+        self.allowed_chat_ids.clone()
+    }
+
+    /// 命令行子命令只需要数据库，不要求配置机器人令牌
+    pub fn database_url_from_env() -> String {
+        env::var("DATABASE_URL").unwrap_or_else(|_| "wallet_bot.db".to_string())
+    }
+
     pub fn validate(&self) -> Result<()> {
         if self.telegram_bot_token.is_empty() {
             return Err(anyhow!("Telegram bot token cannot be empty"));
@@ -80,6 +148,33 @@ impl Settings {
             return Err(anyhow!("Processing timeout must be greater than 0"));
         }
 
+        if self.summary_check_interval == 0 {
+            return Err(anyhow!("Summary check interval must be greater than 0"));
+        }
+
+        if self.confirmation_timeout == 0 {
+            return Err(anyhow!("Confirmation timeout must be greater than 0"));
+        }
+
+        if let Some(url) = &self.webhook_url {
+            let url = url::Url::parse(url).map_err(|e| anyhow!("Invalid webhook URL: {e}"))?;
+            if url.scheme() != "https" {
+                return Err(anyhow!("Webhook URL must use https"));
+            }
+        }
+
+        // Telegram 只接受 1-256 个字母、数字、下划线和连字符组成的密钥
+        if let Some(secret) = &self.webhook_secret {
+            let valid_chars = secret
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if !(1..=256).contains(&secret.len()) || !valid_chars {
+                return Err(anyhow!(
+                    "Webhook secret must be 1-256 characters of A-Z, a-z, 0-9, _ and -"
+                ));
+            }
+        }
+
         Ok(())
     }
 }
@@ -91,10 +186,18 @@ impl Default for Settings {
             database_url: "wallet_bot.db".to_string(),
             bot_name: "WalletBot".to_string(),
             target_channel_id: None,
+            admin_user_ids: Vec::new(),
+            allowed_chat_ids: Vec::new(),
+            leave_unknown_chats: false,
             max_retry_attempts: 3,
             processing_timeout: 30,
             backup_interval: 3600,
             backup_retention_days: 7,
+            summary_check_interval: 600,
+            confirmation_timeout: 600,
+            webhook_url: None,
+            webhook_port: 8080,
+            webhook_secret: None,
             log_level: "info".to_string(),
         }
     }
@@ -0,0 +1,374 @@
+use crate::error::WalletBotError;
+use anyhow::Result;
+use log::info;
+use rusqlite::{params, Connection};
+
+/// 单个迁移步骤
+pub enum MigrationStep {
+    /// 一段直接执行的 SQL 脚本
+    Sql(&'static str),
+    /// 需要根据现有数据结构做判断的迁移
+    Rust(fn(&Connection) -> Result<()>),
+}
+
+/// 编译进二进制的迁移，按版本号升序执行
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub step: MigrationStep,
+}
+
+/// 所有迁移，新的迁移只能追加到末尾，已发布的迁移不可修改
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        step: MigrationStep::Sql(
+            "CREATE TABLE IF NOT EXISTS wallets (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                chat_id INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                current_balance REAL NOT NULL DEFAULT 0.0,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                UNIQUE(chat_id, name)
+            );
+
+            CREATE TABLE IF NOT EXISTS transactions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                wallet_id INTEGER NOT NULL,
+                transaction_type TEXT NOT NULL,
+                amount REAL NOT NULL,
+                month TEXT NOT NULL,
+                year TEXT NOT NULL,
+                message_id INTEGER,
+                chat_id INTEGER,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (wallet_id) REFERENCES wallets(id)
+            );
+
+            CREATE TABLE IF NOT EXISTS messages (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                message_id INTEGER NOT NULL,
+                chat_id INTEGER NOT NULL,
+                wallet_id INTEGER NOT NULL,
+                has_total BOOLEAN DEFAULT FALSE,
+                processed BOOLEAN DEFAULT FALSE,
+                original_balance REAL,
+                new_balance REAL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (wallet_id) REFERENCES wallets(id),
+                UNIQUE(message_id, chat_id)
+            );",
+        ),
+    },
+    Migration {
+        version: 2,
+        description: "store money as integer cents",
+        step: MigrationStep::Rust(convert_money_to_cents),
+    },
+    Migration {
+        version: 3,
+        description: "balance adjustments",
+        step: MigrationStep::Sql(
+            "CREATE TABLE balance_adjustments (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                wallet_id INTEGER NOT NULL,
+                chat_id INTEGER NOT NULL,
+                message_id INTEGER,
+                old_balance INTEGER NOT NULL, -- 按交易计算的余额，单位：分
+                new_balance INTEGER NOT NULL, -- 手动总额，单位：分
+                difference INTEGER NOT NULL,  -- new_balance - old_balance
+                reason TEXT NOT NULL,
+                month TEXT NOT NULL,
+                year TEXT NOT NULL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (wallet_id) REFERENCES wallets(id)
+            );
+            CREATE INDEX idx_balance_adjustments_chat ON balance_adjustments(chat_id, year, month);
+            CREATE INDEX idx_balance_adjustments_message ON balance_adjustments(message_id, chat_id);",
+        ),
+    },
+    Migration {
+        version: 4,
+        description: "transaction notes",
+        step: MigrationStep::Sql("ALTER TABLE transactions ADD COLUMN note TEXT;"),
+    },
+    Migration {
+        version: 5,
+        description: "linked transfer transactions",
+        step: MigrationStep::Sql(
+            "ALTER TABLE transactions ADD COLUMN linked_transaction_id INTEGER REFERENCES transactions(id);
+            CREATE INDEX idx_transactions_linked ON transactions(linked_transaction_id);",
+        ),
+    },
+    Migration {
+        version: 6,
+        description: "transaction categories",
+        step: MigrationStep::Sql(
+            "ALTER TABLE transactions ADD COLUMN category TEXT;
+            CREATE INDEX idx_transactions_category ON transactions(chat_id, category);",
+        ),
+    },
+    Migration {
+        version: 7,
+        description: "scheduled monthly summaries",
+        step: MigrationStep::Sql(
+            "CREATE TABLE summary_subscriptions (
+                chat_id INTEGER PRIMARY KEY,
+                enabled BOOLEAN NOT NULL DEFAULT TRUE,
+                day_of_month INTEGER NOT NULL DEFAULT 1, -- 每月几号发送上个月的总结
+                hour INTEGER NOT NULL DEFAULT 9,         -- 几点发送（聊天时区的当地时间）
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            );
+            CREATE TABLE posted_summaries (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                chat_id INTEGER NOT NULL,
+                year INTEGER NOT NULL,
+                month INTEGER NOT NULL,
+                target_chat_id INTEGER NOT NULL,
+                posted_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                UNIQUE(chat_id, year, month)
+            );",
+        ),
+    },
+    Migration {
+        version: 8,
+        description: "dedupe keys for imported transactions",
+        step: MigrationStep::Sql(
+            "ALTER TABLE transactions ADD COLUMN import_key TEXT;
+            CREATE UNIQUE INDEX idx_transactions_import_key
+                ON transactions(chat_id, import_key) WHERE import_key IS NOT NULL;",
+        ),
+    },
+    Migration {
+        version: 9,
+        description: "per-chat access control",
+        step: MigrationStep::Sql(
+            "CREATE TABLE chat_permissions (
+                chat_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                role TEXT NOT NULL, -- admin、writer 或 reader
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (chat_id, user_id)
+            );
+            CREATE TABLE access_policies (
+                chat_id INTEGER PRIMARY KEY,
+                restricted BOOLEAN NOT NULL DEFAULT FALSE,
+                reject_unauthorized BOOLEAN NOT NULL DEFAULT FALSE,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            );",
+        ),
+    },
+    Migration {
+        version: 10,
+        description: "voided transactions",
+        step: MigrationStep::Sql(
+            "ALTER TABLE transactions ADD COLUMN voided_at DATETIME;
+            ALTER TABLE messages ADD COLUMN voided_at DATETIME;",
+        ),
+    },
+    Migration {
+        version: 11,
+        description: "per-chat settings",
+        step: MigrationStep::Sql(
+            "CREATE TABLE chat_settings (
+                chat_id INTEGER PRIMARY KEY,
+                output_mode TEXT NOT NULL DEFAULT 'confirm', -- edit、confirm、temporary 或 discussion
+                confirmation_ttl INTEGER NOT NULL DEFAULT 30, -- temporary 模式下确认消息保留的秒数
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            );",
+        ),
+    },
+    Migration {
+        version: 12,
+        description: "chat currency, default wallet, timezone and language",
+        step: MigrationStep::Sql(
+            "ALTER TABLE chat_settings ADD COLUMN currency TEXT NOT NULL DEFAULT '元';
+            ALTER TABLE chat_settings ADD COLUMN default_wallet TEXT; -- 消息没有写钱包时使用
+            ALTER TABLE chat_settings ADD COLUMN utc_offset INTEGER NOT NULL DEFAULT 0; -- 相对 UTC 的小时数
+            ALTER TABLE chat_settings ADD COLUMN language TEXT NOT NULL DEFAULT 'zh';",
+        ),
+    },
+    Migration {
+        version: 13,
+        description: "large transaction confirmation",
+        step: MigrationStep::Sql(
+            "ALTER TABLE chat_settings ADD COLUMN confirm_above INTEGER; -- 单位：分
+            ALTER TABLE chat_settings ADD COLUMN confirm_ratio INTEGER; -- 钱包平时金额的倍数",
+        ),
+    },
+    Migration {
+        version: 14,
+        description: "history queries",
+        step: MigrationStep::Sql(
+            // /history 翻页按钮只在回调数据中保存查询编号，避免超出 Telegram 的 64 字节限制
+            "CREATE TABLE history_queries (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                chat_id INTEGER NOT NULL,
+                wallet_name TEXT,
+                month INTEGER,
+                year INTEGER,
+                transaction_type TEXT,
+                category TEXT,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            );
+            CREATE INDEX idx_history_queries_chat ON history_queries (chat_id);",
+        ),
+    },
+    Migration {
+        version: 15,
+        description: "drop summary target chat",
+        // 总结只发送到聊天本身，target_chat_id 总是等于 chat_id
+        step: MigrationStep::Sql("ALTER TABLE posted_summaries DROP COLUMN target_chat_id;"),
+    },
+];
+
+/// 当前二进制支持的最新数据库版本
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// 读取数据库当前的版本号，没有任何迁移记录时为 0
+pub fn current_version(conn: &Connection) -> Result<i64> {
+    ensure_version_table(conn)?;
+    let version = conn.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_version",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(version)
+}
+
+/// 把数据库升级到最新版本。
+///
+/// 每个迁移与它的版本记录在同一个事务中提交；
+/// 如果数据库版本比当前二进制更新，拒绝继续运行，避免旧代码写坏新结构。
+/// 迁移期间关闭外键检查，结束后恢复连接原来的设置。
+pub fn run(conn: &Connection) -> Result<()> {
+    let current = current_version(conn)?;
+    let latest = latest_version();
+
+    if current > latest {
+        return Err(WalletBotError::schema_too_new(current, latest).into());
+    }
+    if current == latest {
+        info!("Database schema is up to date (version {})", current);
+        return Ok(());
+    }
+
+    // 重建表时需要暂时关闭外键检查，且该 PRAGMA 在事务内无效
+    let foreign_keys: bool = conn.query_row("PRAGMA foreign_keys", [], |row| row.get(0))?;
+    conn.pragma_update(None, "foreign_keys", false)?;
+    let result = apply_pending(conn, current);
+    conn.pragma_update(None, "foreign_keys", foreign_keys)?;
+    result
+}
+
+fn apply_pending(conn: &Connection, current: i64) -> Result<()> {
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        info!(
+            "Applying database migration {}: {}",
+            migration.version, migration.description
+        );
+
+        let tx = conn.unchecked_transaction()?;
+        match migration.step {
+            MigrationStep::Sql(sql) => tx.execute_batch(sql)?,
+            MigrationStep::Rust(apply) => apply(&tx)?,
+        }
+        tx.execute(
+            "INSERT INTO schema_version (version, description) VALUES (?1, ?2)",
+            params![migration.version, migration.description],
+        )?;
+        tx.commit()?;
+    }
+
+    info!("Database schema migrated to version {}", latest_version());
+    Ok(())
+}
+
+fn ensure_version_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            description TEXT NOT NULL,
+            applied_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// 把以 REAL（元）存储的金额列转换为 INTEGER（分）。
+///
+/// SQLite 不能直接修改列类型，因此按官方推荐的方式重建表：
+/// 新建表、复制数据、删除旧表、重命名新表。
+/// 引入版本号之前已经按分建表的数据库会被跳过，避免重复换算。
+fn convert_money_to_cents(conn: &Connection) -> Result<()> {
+    let column_type: String = conn.query_row(
+        "SELECT type FROM pragma_table_info('wallets') WHERE name = 'current_balance'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !column_type.eq_ignore_ascii_case("REAL") {
+        return Ok(());
+    }
+
+    conn.execute_batch(
+        "CREATE TABLE wallets_new (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            chat_id INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            current_balance INTEGER NOT NULL DEFAULT 0, -- 单位：分
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE(chat_id, name)
+        );
+        INSERT INTO wallets_new (id, chat_id, name, current_balance, created_at, updated_at)
+            SELECT id, chat_id, name, CAST(ROUND(current_balance * 100) AS INTEGER), created_at, updated_at
+            FROM wallets;
+        DROP TABLE wallets;
+        ALTER TABLE wallets_new RENAME TO wallets;
+
+        CREATE TABLE transactions_new (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            wallet_id INTEGER NOT NULL,
+            transaction_type TEXT NOT NULL,
+            amount INTEGER NOT NULL, -- 单位：分
+            month TEXT NOT NULL,
+            year TEXT NOT NULL,
+            message_id INTEGER,
+            chat_id INTEGER,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (wallet_id) REFERENCES wallets(id)
+        );
+        INSERT INTO transactions_new (id, wallet_id, transaction_type, amount, month, year, message_id, chat_id, created_at)
+            SELECT id, wallet_id, transaction_type, CAST(ROUND(amount * 100) AS INTEGER), month, year, message_id, chat_id, created_at
+            FROM transactions;
+        DROP TABLE transactions;
+        ALTER TABLE transactions_new RENAME TO transactions;
+
+        CREATE TABLE messages_new (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            message_id INTEGER NOT NULL,
+            chat_id INTEGER NOT NULL,
+            wallet_id INTEGER NOT NULL,
+            has_total BOOLEAN DEFAULT FALSE,
+            processed BOOLEAN DEFAULT FALSE,
+            original_balance INTEGER, -- 单位：分
+            new_balance INTEGER,      -- 单位：分
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (wallet_id) REFERENCES wallets(id),
+            UNIQUE(message_id, chat_id)
+        );
+        INSERT INTO messages_new (id, message_id, chat_id, wallet_id, has_total, processed, original_balance, new_balance, created_at)
+            SELECT id, message_id, chat_id, wallet_id, has_total, processed,
+                   CAST(ROUND(original_balance * 100) AS INTEGER), CAST(ROUND(new_balance * 100) AS INTEGER), created_at
+            FROM messages;
+        DROP TABLE messages;
+        ALTER TABLE messages_new RENAME TO messages;",
+    )?;
+    Ok(())
+}
@@ -1,3 +1,4 @@
+use crate::money::Money;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
@@ -6,7 +7,7 @@ pub struct Wallet {
     pub id: Option<i64>,
     pub chat_id: i64,
     pub name: String,
-    pub current_balance: f64,
+    pub current_balance: Money,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
 }
@@ -16,12 +17,15 @@ pub struct Transaction {
     pub id: Option<i64>,
     pub wallet_id: i64,
     pub transaction_type: String, // "出账" 或 "入账"
-    pub amount: f64,
+    pub amount: Money,
     pub month: String,
     pub year: String,
     pub message_id: Option<i64>,
     pub chat_id: Option<i64>,
     pub created_at: Option<DateTime<Utc>>,
+    pub note: Option<String>,
+    pub linked_transaction_id: Option<i64>, // 转账另一侧的交易
+    pub category: Option<String>,           // 分类标签，例如 餐饮
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,29 +36,69 @@ pub struct Message {
     pub wallet_id: i64,
     pub has_total: bool,
     pub processed: bool,
-    pub original_balance: Option<f64>, // 消息编辑前的余额
-    pub new_balance: Option<f64>,      // 消息编辑后的余额
+    pub original_balance: Option<Money>, // 消息编辑前的余额
+    pub new_balance: Option<Money>,      // 消息编辑后的余额
     pub created_at: Option<DateTime<Utc>>,
+    pub voided_at: Option<DateTime<Utc>>, // 被 /undo 撤销的时间
+}
+
+impl Message {
+    pub fn is_voided(&self) -> bool {
+        self.voided_at.is_some()
+    }
+}
+
+/// 消息中的一笔交易明细
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineItem {
+    pub transaction_type: String,
+    pub amount: Money,
+    pub note: Option<String>,
+    pub category: Option<String>,
+}
+
+impl LineItem {
+    pub fn signed_amount(&self) -> Money {
+        signed_amount(&self.transaction_type, self.amount)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ParsedMessage {
     pub wallet_name: String,
-    pub transaction_type: String,
-    pub amount: f64,
+    pub items: Vec<LineItem>, // 消息中的全部交易明细，至少一笔
     pub month: String,
     pub year: String,
-    pub total_amount: Option<f64>, // 解析出的总额（如果有）
+    pub total_amount: Option<Money>, // 解析出的总额（如果有）
     #[allow(dead_code)]
     pub original_text: String,
 }
 
+impl ParsedMessage {
+    /// 全部明细对余额的合计影响
+    pub fn delta(&self) -> Money {
+        self.items.iter().map(LineItem::signed_amount).sum()
+    }
+}
+
+/// 解析出的转账：从一个钱包转出，同时转入另一个钱包
 #[derive(Debug, Clone)]
-pub struct BalanceUpdate {
+pub struct ParsedTransfer {
+    pub from_wallet: String,
+    pub to_wallet: String,
+    pub amount: Money,
+    pub month: String,
+    pub year: String,
+    pub note: Option<String>,
     #[allow(dead_code)]
+    pub original_text: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct BalanceUpdate {
     pub wallet_name: String,
-    pub old_balance: f64,
-    pub new_balance: f64,
+    pub old_balance: Money,
+    pub new_balance: Money,
     pub source: BalanceUpdateSource,
     #[allow(dead_code)]
     pub message_id: Option<i64>,
@@ -62,6 +106,434 @@ pub struct BalanceUpdate {
     pub chat_id: Option<i64>,
 }
 
+/// 转账完成后两个钱包各自的余额变化
+#[derive(Debug, Clone)]
+pub struct TransferUpdate {
+    pub from: BalanceUpdate,
+    pub to: BalanceUpdate,
+}
+
+/// 被编辑消息重新计算后需要写回的账目变更
+#[derive(Debug, Clone)]
+pub struct MessageEdit {
+    pub message_id: i64,
+    pub chat_id: i64,
+    pub old_wallet_id: i64,
+    pub wallet_name: String,
+    pub items: Vec<LineItem>,
+    pub month: String,
+    pub year: String,
+    pub has_total: bool,
+    pub original_balance: Money,
+    pub new_balance: Money,
+}
+
+/// 撤销一条消息后某个钱包的余额变化
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UndoneBalance {
+    pub wallet_name: String,
+    pub old_balance: Money,
+    pub new_balance: Money,
+}
+
+/// 被 /undo 撤销的消息，交易只标记为作废，不会被删除
+#[derive(Debug, Clone)]
+pub struct UndoneMessage {
+    pub message_id: i64,
+    pub chat_id: i64,
+    pub transactions: Vec<Transaction>,
+    pub balances: Vec<UndoneBalance>,
+}
+
+/// 手动总额与按交易计算的余额不一致时留下的调整记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BalanceAdjustment {
+    pub id: Option<i64>,
+    pub wallet_id: i64,
+    pub chat_id: i64,
+    pub message_id: Option<i64>,
+    pub old_balance: Money, // 按交易计算的余额
+    pub new_balance: Money, // 手动总额
+    pub difference: Money,
+    pub reason: String,
+    pub month: String,
+    pub year: String,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// 某个月份内未被交易解释的余额变化汇总
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MonthlyAdjustment {
+    pub year: String,
+    pub month: String,
+    pub count: usize,
+    pub gained: Money, // 凭空多出的金额
+    pub lost: Money,   // 凭空消失的金额（为正数）
+}
+
+impl MonthlyAdjustment {
+    pub fn net(&self) -> Money {
+        self.gained - self.lost
+    }
+}
+
+/// 交易查询条件，未设置的条件不参与筛选
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransactionFilter {
+    pub wallet_name: Option<String>,
+    pub month: Option<u32>,
+    pub year: Option<u32>,
+    pub transaction_type: Option<String>,
+    pub category: Option<String>,
+}
+
+/// 分页查询得到的一页交易，按时间倒序排列
+#[derive(Debug, Clone)]
+pub struct TransactionPage {
+    pub transactions: Vec<Transaction>,
+    pub total: usize, // 符合条件的交易总数
+    pub page: usize,  // 从 0 开始
+    pub page_size: usize,
+}
+
+impl TransactionPage {
+    pub fn page_count(&self) -> usize {
+        self.total.div_ceil(self.page_size).max(1)
+    }
+
+    pub fn has_previous(&self) -> bool {
+        self.page > 0
+    }
+
+    pub fn has_next(&self) -> bool {
+        self.page + 1 < self.page_count()
+    }
+}
+
+/// 聊天的月度总结订阅：每月 day_of_month 日 hour 点（聊天的时区）之后发送上个月的总结
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SummarySubscription {
+    pub chat_id: i64,
+    pub enabled: bool,
+    pub day_of_month: u32,
+    pub hour: u32,
+}
+
+impl SummarySubscription {
+    pub fn new(chat_id: i64) -> Self {
+        Self {
+            chat_id,
+            enabled: true,
+            day_of_month: 1,
+            hour: 9,
+        }
+    }
+}
+
+/// 用户在聊天中的角色，按权限从低到高排列
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Role {
+    Guest,  // 没有授权，只能使用 /start、/help
+    Reader, // 可以查询余额、记录和报表
+    Writer, // 可以发送交易消息记账
+    Admin,  // 可以使用管理命令和设置权限
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Guest => "guest",
+            Role::Reader => "reader",
+            Role::Writer => "writer",
+            Role::Admin => "admin",
+        }
+    }
+
+    /// 解析可以授予的角色（不含 guest），同时接受中文名称
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "reader" | "只读" => Some(Role::Reader),
+            "writer" | "记账" => Some(Role::Writer),
+            "admin" | "管理员" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Role::Guest => "访客",
+            Role::Reader => "只读",
+            Role::Writer => "记账",
+            Role::Admin => "管理员",
+        }
+    }
+}
+
+/// 聊天中单独授权的成员
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatPermission {
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub role: Role,
+}
+
+/// 聊天的访问策略，默认所有成员都可以记账，未授权的消息静默忽略
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessPolicy {
+    pub chat_id: i64,
+    pub restricted: bool,          // 只有授权的成员可以记账和查询
+    pub reject_unauthorized: bool, // 回复未授权的交易消息而不是忽略
+}
+
+/// 记账结果的输出方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    EditOnly, // 只在原消息后追加 #总额，转账结果和错误仍回复原消息
+    #[default]
+    EditAndConfirm, // 追加 #总额 并发送确认消息
+    TemporaryConfirm, // 确认消息在一段时间后自动删除
+    DiscussionReply, // 频道帖子的确认发到关联讨论组的评论中，群组中回复原消息
+}
+
+impl OutputMode {
+    pub const ALL: [OutputMode; 4] = [
+        OutputMode::EditOnly,
+        OutputMode::EditAndConfirm,
+        OutputMode::TemporaryConfirm,
+        OutputMode::DiscussionReply,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputMode::EditOnly => "edit",
+            OutputMode::EditAndConfirm => "confirm",
+            OutputMode::TemporaryConfirm => "temporary",
+            OutputMode::DiscussionReply => "discussion",
+        }
+    }
+
+    /// 解析输出方式，同时接受中文名称
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "edit" | "只编辑" => Some(OutputMode::EditOnly),
+            "confirm" | "确认" => Some(OutputMode::EditAndConfirm),
+            "temporary" | "临时" => Some(OutputMode::TemporaryConfirm),
+            "discussion" | "评论" => Some(OutputMode::DiscussionReply),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            OutputMode::EditOnly => "只编辑原消息",
+            OutputMode::EditAndConfirm => "编辑并发送确认",
+            OutputMode::TemporaryConfirm => "确认消息自动删除",
+            OutputMode::DiscussionReply => "在评论区回复",
+        }
+    }
+}
+
+/// 记账确认消息使用的语言
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Language {
+    #[default]
+    Chinese,
+    English,
+}
+
+impl Language {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Language::Chinese => "zh",
+            Language::English => "en",
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "zh" | "中文" => Some(Language::Chinese),
+            "en" | "English" | "英文" => Some(Language::English),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Language::Chinese => "中文",
+            Language::English => "English",
+        }
+    }
+}
+
+/// 聊天自己的设置，没有保存过时使用默认值
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatSettings {
+    pub chat_id: i64,
+    pub output_mode: OutputMode,
+    pub confirmation_ttl: u32, // TemporaryConfirm 模式下确认消息保留的秒数
+    pub currency: String,      // 确认消息、查询结果和 #总额 中金额的单位
+    pub default_wallet: Option<String>, // 消息没有写钱包时记到这个钱包
+    pub utc_offset: i32,       // 时区，相对 UTC 的整小时数
+    pub language: Language,
+    pub confirm_above: Option<Money>, // 单笔金额超过这个数时要先确认再记账
+    pub confirm_ratio: Option<u32>,   // 单笔金额超过钱包平时金额的这个倍数时要先确认再记账
+}
+
+impl ChatSettings {
+    pub fn new(chat_id: i64) -> Self {
+        Self {
+            chat_id,
+            output_mode: OutputMode::default(),
+            confirmation_ttl: 30,
+            currency: "元".to_string(),
+            default_wallet: None,
+            utc_offset: 0,
+            language: Language::default(),
+            confirm_above: None,
+            confirm_ratio: None,
+        }
+    }
+
+    /// 按聊天的货币单位显示金额：“元”写在数字后面，其他符号写在前面
+    pub fn money(&self, amount: Money) -> String {
+        if self.currency == "元" {
+            format!("{amount}元")
+        } else {
+            format!("{}{amount}", self.currency)
+        }
+    }
+
+    /// 按聊天的语言在中文和英文文字之间选择
+    pub fn text<T>(&self, chinese: T, english: T) -> T {
+        match self.language {
+            Language::Chinese => chinese,
+            Language::English => english,
+        }
+    }
+
+    /// 追加到交易消息后的 #总额 行，金额使用聊天的货币单位
+    pub fn total_line(&self, balance: Money) -> String {
+        format!("#总额 {}", self.money(balance))
+    }
+
+    /// 时区的显示文字，例如 UTC、UTC+8、UTC-5
+    pub fn timezone_label(&self) -> String {
+        match self.utc_offset {
+            0 => "UTC".to_string(),
+            offset => format!("UTC{offset:+}"),
+        }
+    }
+
+    /// 把 UTC 时间换算成聊天时区的当地时间（仍用 `DateTime<Utc>` 表示）
+    pub fn local_time(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        now + chrono::Duration::hours(self.utc_offset.into())
+    }
+}
+
+/// 从表格或 JSON 导入的一笔历史交易
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedTransaction {
+    pub wallet_name: String,
+    pub transaction_type: String,
+    pub amount: Money,
+    pub month: String, // "12月"
+    pub year: String,  // "2024年"
+    pub created_at: DateTime<Utc>,
+    pub note: Option<String>,
+    pub category: Option<String>,
+    pub import_key: String, // 去重键，同一个聊天中相同的键只导入一次
+}
+
+/// 带钱包名的交易，用于导出
+#[derive(Debug, Clone)]
+pub struct WalletTransaction {
+    pub wallet_name: String,
+    pub transaction: Transaction,
+}
+
+/// 钱包的当前状态，用于 /balance 和 /wallets
+#[derive(Debug, Clone)]
+pub struct WalletSummary {
+    pub name: String,
+    pub balance: Money,
+    pub transaction_count: usize,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// 某个分类下的收支汇总（未分类的交易 category 为 None）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CategoryTotal {
+    pub category: Option<String>,
+    pub count: usize,
+    pub income: Money,
+    pub expense: Money, // 支出金额（为正数）
+}
+
+/// 账本中的一个条目：一条已处理的消息及其交易，或一笔不属于任何消息的交易
+#[derive(Debug, Clone)]
+pub struct LedgerEntry {
+    pub message: Option<Message>,
+    pub transactions: Vec<Transaction>,
+}
+
+impl LedgerEntry {
+    pub fn created_at(&self) -> Option<DateTime<Utc>> {
+        match &self.message {
+            Some(message) => message.created_at,
+            None => self.transactions.first().and_then(|t| t.created_at),
+        }
+    }
+
+    /// 条目中全部交易对余额的合计影响
+    pub fn delta(&self) -> Money {
+        self.transactions
+            .iter()
+            .map(|t| signed_amount(&t.transaction_type, t.amount))
+            .sum()
+    }
+
+    /// 是否是手动总额检查点：总额与交易计算结果不一致，余额以总额为准
+    pub fn is_checkpoint(&self) -> bool {
+        self.message.as_ref().is_some_and(|record| {
+            let original_balance = record.original_balance.unwrap_or(Money::ZERO);
+            let new_balance = record
+                .new_balance
+                .unwrap_or(original_balance + self.delta());
+            record.has_total && original_balance + self.delta() != new_balance
+        })
+    }
+
+    /// 从给定余额重放这个条目，得到条目之后的余额
+    pub fn replay(&self, balance: Money) -> Money {
+        match &self.message {
+            Some(record) if self.is_checkpoint() => record.new_balance.unwrap_or(balance),
+            _ => balance + self.delta(),
+        }
+    }
+}
+
+/// 按账本重放得到的钱包余额核对结果
+#[derive(Debug, Clone)]
+pub struct WalletReconciliation {
+    pub wallet_name: String,
+    pub stored_balance: Money, // 钱包表中记录的余额
+    pub ledger_balance: Money, // 按账本重放得到的余额
+    pub transaction_count: usize,
+    pub checkpoint_count: usize,
+    pub stale_snapshots: usize, // 余额快照与重放结果不一致的消息数
+    pub fixed: bool,
+}
+
+impl WalletReconciliation {
+    pub fn drift(&self) -> Money {
+        self.ledger_balance - self.stored_balance
+    }
+
+    pub fn is_consistent(&self) -> bool {
+        self.drift().is_zero() && self.stale_snapshots == 0
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum BalanceUpdateSource {
     Transaction, // 从交易计算
@@ -69,3 +541,17 @@ pub enum BalanceUpdateSource {
     #[allow(dead_code)]
     Initial, // 初始设置
 }
+
+/// 转账在转出钱包一侧记录的交易类型
+pub const TRANSFER_OUT: &str = "转出";
+/// 转账在转入钱包一侧记录的交易类型
+pub const TRANSFER_IN: &str = "转入";
+
+/// 交易对余额的影响（出账/支出/转出为负，入账/收入/转入为正，其他类型不影响余额）
+pub fn signed_amount(transaction_type: &str, amount: Money) -> Money {
+    match transaction_type {
+        "出账" | "支出" | TRANSFER_OUT => -amount,
+        "入账" | "收入" | TRANSFER_IN => amount,
+        _ => Money::ZERO,
+    }
+}
@@ -1,11 +1,30 @@
-use crate::database::models::{Transaction, Wallet};
+use crate::database::migrations;
+use crate::database::models::{
+    signed_amount, AccessPolicy, BalanceAdjustment, CategoryTotal, ChatPermission, ChatSettings,
+    ImportedTransaction, Language, LedgerEntry, LineItem, Message, MessageEdit, MonthlyAdjustment,
+    OutputMode, Role, SummarySubscription, Transaction, TransactionFilter, TransactionPage,
+    UndoneBalance, UndoneMessage, Wallet, WalletSummary, WalletTransaction, TRANSFER_IN,
+    TRANSFER_OUT,
+};
+use crate::money::Money;
 use anyhow::Result;
 use chrono::{Datelike, Utc};
 use log::{debug, info};
-use rusqlite::{params, Connection, Result as SqliteResult};
+use rusqlite::{params, Connection, OptionalExtension, Result as SqliteResult};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+/// 按 TransactionFilter 筛选未作废交易的条件，参数依次为聊天、钱包名、月份、年份、类型和分类。
+///
+/// 月份和年份以 "12月"、"2024年" 的形式存储，CAST 取开头的数字
+const TRANSACTION_FILTER_CONDITIONS: &str = "t.chat_id = ?1
+               AND t.voided_at IS NULL
+               AND (?2 IS NULL OR w.name = ?2)
+               AND (?3 IS NULL OR CAST(t.month AS INTEGER) = ?3)
+               AND (?4 IS NULL OR CAST(t.year AS INTEGER) = ?4)
+               AND (?5 IS NULL OR t.transaction_type = ?5)
+               AND (?6 IS NULL OR t.category = ?6)";
+
 #[derive(Clone, Debug)]
 pub struct DatabaseOperations {
     conn: Arc<Mutex<Connection>>,
@@ -14,6 +33,8 @@ pub struct DatabaseOperations {
 impl DatabaseOperations {
     pub async fn new(database_url: &str) -> Result<Self> {
         let conn = Connection::open(database_url)?;
+        // SQLite 默认不检查外键，每个连接打开时都需要开启
+        conn.pragma_update(None, "foreign_keys", true)?;
         let db = Self {
             conn: Arc::new(Mutex::new(conn)),
         };
@@ -24,198 +45,160 @@ impl DatabaseOperations {
 
     async fn init_schema(&self) -> Result<()> {
         let conn = self.conn.lock().await;
-
-        // 创建钱包表
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS wallets (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                chat_id INTEGER NOT NULL,
-                name TEXT NOT NULL,
-                current_balance REAL NOT NULL DEFAULT 0.0,
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                UNIQUE(chat_id, name)
-            )",
-            [],
-        )?;
-
-        // 创建交易表
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS transactions (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                wallet_id INTEGER NOT NULL,
-                transaction_type TEXT NOT NULL,
-                amount REAL NOT NULL,
-                month TEXT NOT NULL,
-                year TEXT NOT NULL,
-                message_id INTEGER,
-                chat_id INTEGER,
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                FOREIGN KEY (wallet_id) REFERENCES wallets(id)
-            )",
-            [],
-        )?;
-
-        // 创建消息表
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS messages (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                message_id INTEGER NOT NULL,
-                chat_id INTEGER NOT NULL,
-                wallet_id INTEGER NOT NULL,
-                has_total BOOLEAN DEFAULT FALSE,
-                processed BOOLEAN DEFAULT FALSE,
-                original_balance REAL,
-                new_balance REAL,
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                FOREIGN KEY (wallet_id) REFERENCES wallets(id),
-                UNIQUE(message_id, chat_id)
-            )",
-            [],
-        )?;
+        migrations::run(&conn)?;
 
         info!("Database schema initialized successfully");
         Ok(())
     }
 
-    pub async fn get_or_create_wallet(&self, chat_id: i64, name: &str) -> Result<Wallet> {
+    /// 数据库当前的结构版本
+    #[allow(dead_code)]
+    pub async fn schema_version(&self) -> Result<i64> {
         let conn = self.conn.lock().await;
+        migrations::current_version(&conn)
+    }
 
-        // 尝试获取现有钱包
-        let mut stmt = conn.prepare("SELECT id, chat_id, name, current_balance, created_at, updated_at FROM wallets WHERE chat_id = ?1 AND name = ?2")?;
-        let mut wallet_iter = stmt.query_map(params![chat_id, name], |row| {
-            Ok(Wallet {
-                id: Some(row.get(0)?),
-                chat_id: row.get(1)?,
-                name: row.get(2)?,
-                current_balance: row.get(3)?,
-                created_at: row.get(4).ok(),
-                updated_at: row.get(5).ok(),
-            })
-        })?;
+    /// 当前连接是否检查外键
+    #[allow(dead_code)]
+    pub async fn foreign_keys_enabled(&self) -> Result<bool> {
+        let conn = self.conn.lock().await;
+        Ok(conn.query_row("PRAGMA foreign_keys", [], |row| row.get(0))?)
+    }
 
-        if let Some(wallet) = wallet_iter.next() {
-            return Ok(wallet?);
-        }
+    /// 在同一个 SQLite 事务中执行一组操作。
+    ///
+    /// 闭包返回 `Ok` 时提交，返回 `Err` 时整个事务回滚，
+    /// 保证余额更新、交易记录和消息记录要么全部生效，要么全部不生效。
+    pub async fn unit_of_work<T, F>(&self, work: F) -> Result<T>
+    where
+        F: FnOnce(&UnitOfWork<'_>) -> Result<T>,
+    {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
 
-        // 如果不存在，创建新钱包
-        let now = Utc::now();
-        conn.execute(
-            "INSERT INTO wallets (chat_id, name, current_balance, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![chat_id, name, 0.0, now, now],
-        )?;
+        let result = work(&UnitOfWork { conn: &tx })?;
 
-        let wallet_id = conn.last_insert_rowid();
-        debug!("Created new wallet: {name} in chat {chat_id} with ID: {wallet_id}");
+        tx.commit()?;
+        Ok(result)
+    }
 
-        Ok(Wallet {
-            id: Some(wallet_id),
-            chat_id,
-            name: name.to_string(),
-            current_balance: 0.0,
-            created_at: Some(now),
-            updated_at: Some(now),
-        })
+    pub async fn get_or_create_wallet(&self, chat_id: i64, name: &str) -> Result<Wallet> {
+        let conn = self.conn.lock().await;
+        Self::get_or_create_wallet_sync(&conn, chat_id, name)
     }
 
+    #[allow(dead_code)]
     pub async fn update_wallet_balance(
         &self,
         chat_id: i64,
         name: &str,
-        balance: f64,
+        balance: Money,
     ) -> Result<()> {
         let conn = self.conn.lock().await;
-        let now = Utc::now();
-
-        conn.execute(
-            "UPDATE wallets SET current_balance = ?1, updated_at = ?2 WHERE chat_id = ?3 AND name = ?4",
-            params![balance, now, chat_id, name],
-        )?;
-
-        info!("Updated wallet balance: {name} in chat {chat_id} -> {balance}");
-        Ok(())
+        Self::update_wallet_balance_sync(&conn, chat_id, name, balance)
     }
 
     #[allow(clippy::too_many_arguments)]
+    #[allow(dead_code)]
     pub async fn record_transaction(
         &self,
         chat_id: i64,
         wallet_name: &str,
         transaction_type: &str,
-        amount: f64,
+        amount: Money,
         month: &str,
         year: &str,
         message_id: Option<i64>,
     ) -> Result<()> {
         let conn = self.conn.lock().await;
-
-        // 获取钱包ID
-        let wallet = self.get_wallet_by_name_sync(&conn, chat_id, wallet_name)?;
-        let wallet_id = wallet.id.unwrap();
-
-        let now = Utc::now();
-        conn.execute(
-            "INSERT INTO transactions (wallet_id, transaction_type, amount, month, year, message_id, chat_id, created_at) 
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-            params![wallet_id, transaction_type, amount, month, year, message_id, Some(chat_id), now],
-        )?;
-
-        debug!("Recorded transaction: {wallet_name} {transaction_type} {amount}");
-        Ok(())
+        Self::record_transaction_sync(
+            &conn,
+            chat_id,
+            wallet_name,
+            transaction_type,
+            amount,
+            month,
+            year,
+            message_id,
+            None,
+            None,
+        )
     }
 
+    #[allow(dead_code)]
     pub async fn record_message(
         &self,
         message_id: i64,
         chat_id: i64,
         wallet_name: &str,
         has_total: bool,
-        original_balance: Option<f64>,
-        new_balance: Option<f64>,
+        original_balance: Option<Money>,
+        new_balance: Option<Money>,
     ) -> Result<()> {
         let conn = self.conn.lock().await;
+        Self::record_message_sync(
+            &conn,
+            message_id,
+            chat_id,
+            wallet_name,
+            has_total,
+            original_balance,
+            new_balance,
+        )
+    }
 
-        // 获取钱包ID
-        let wallet = self.get_wallet_by_name_sync(&conn, chat_id, wallet_name)?;
-        let wallet_id = wallet.id.unwrap();
+    /// 消息是否处理过，被 /undo 撤销的消息也算，重复收到或重放时不会再次记账
+    pub async fn is_message_processed(&self, message_id: i64, chat_id: i64) -> Result<bool> {
+        let conn = self.conn.lock().await;
+        let mut stmt =
+            conn.prepare("SELECT id FROM messages WHERE message_id = ? AND chat_id = ?")?;
+        let rows: Vec<i64> = stmt
+            .query_map(params![message_id, chat_id], |row| row.get(0))?
+            .collect::<SqliteResult<Vec<i64>>>()?;
 
-        let now = Utc::now();
-        conn.execute(
-            "INSERT OR REPLACE INTO messages (message_id, chat_id, wallet_id, has_total, processed, original_balance, new_balance, created_at) 
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-            params![message_id, chat_id, wallet_id, has_total, true, original_balance, new_balance, now],
-        )?;
+        Ok(!rows.is_empty())
+    }
 
-        debug!("Recorded message: {message_id} in chat {chat_id}");
-        Ok(())
+    /// 消息当前是否有生效的记账，被 /undo 撤销的消息不算
+    pub async fn is_message_recorded(&self, message_id: i64, chat_id: i64) -> Result<bool> {
+        Ok(self
+            .get_message_record(message_id, chat_id)
+            .await?
+            .is_some_and(|record| !record.is_voided()))
     }
 
-    #[allow(dead_code)]
-    pub async fn get_latest_balance(
+    /// 获取已处理消息的记录
+    pub async fn get_message_record(
         &self,
+        message_id: i64,
         chat_id: i64,
-        wallet_name: &str,
-        _month: &str,
-        _year: &str,
-    ) -> Result<f64> {
+    ) -> Result<Option<Message>> {
         let conn = self.conn.lock().await;
-
-        // 获取钱包
-        let wallet = self.get_wallet_by_name_sync(&conn, chat_id, wallet_name)?;
-
-        // 返回当前余额
-        Ok(wallet.current_balance)
+        Self::get_message_record_sync(&conn, message_id, chat_id)
     }
 
-    pub async fn is_message_processed(&self, message_id: i64, chat_id: i64) -> Result<bool> {
+    /// 获取聊天中最近一条没有被撤销的已处理消息
+    pub async fn get_latest_message_record(&self, chat_id: i64) -> Result<Option<Message>> {
         let conn = self.conn.lock().await;
-        let mut stmt =
-            conn.prepare("SELECT id FROM messages WHERE message_id = ? AND chat_id = ?")?;
-        let rows: Vec<i64> = stmt
-            .query_map(params![message_id, chat_id], |row| row.get(0))?
-            .collect::<SqliteResult<Vec<i64>>>()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, message_id, chat_id, wallet_id, has_total, processed, original_balance, new_balance, created_at, voided_at
+             FROM messages
+             WHERE chat_id = ?1 AND voided_at IS NULL
+             ORDER BY id DESC
+             LIMIT 1",
+        )?;
+        let mut rows = stmt.query_map(params![chat_id], Self::message_from_row)?;
+        Ok(rows.next().transpose()?)
+    }
 
-        Ok(!rows.is_empty())
+    /// 获取某条消息产生的交易
+    pub async fn get_message_transactions(
+        &self,
+        message_id: i64,
+        chat_id: i64,
+    ) -> Result<Vec<Transaction>> {
+        let conn = self.conn.lock().await;
+        Self::get_message_transactions_sync(&conn, message_id, chat_id)
     }
 
     #[allow(dead_code)]
@@ -225,28 +208,16 @@ impl DatabaseOperations {
         wallet_name: &str,
     ) -> Result<Vec<Transaction>> {
         let conn = self.conn.lock().await;
-        let wallet = self.get_wallet_by_name_sync(&conn, chat_id, wallet_name)?;
+        let wallet = Self::get_wallet_by_name_sync(&conn, chat_id, wallet_name)?;
 
         let mut stmt = conn.prepare(
-            "SELECT id, wallet_id, transaction_type, amount, month, year, message_id, chat_id, created_at 
+            "SELECT id, wallet_id, transaction_type, amount, month, year, message_id, chat_id, created_at, note, linked_transaction_id, category
              FROM transactions 
-             WHERE wallet_id = ? 
+             WHERE wallet_id = ? AND voided_at IS NULL
              ORDER BY created_at DESC"
         )?;
 
-        let rows = stmt.query_map(params![wallet.id], |row| {
-            Ok(Transaction {
-                id: Some(row.get(0)?),
-                wallet_id: row.get(1)?,
-                transaction_type: row.get(2)?,
-                amount: row.get(3)?,
-                month: row.get(4)?,
-                year: row.get(5)?,
-                message_id: row.get(6)?,
-                chat_id: row.get(7)?,
-                created_at: row.get(8)?,
-            })
-        })?;
+        let rows = stmt.query_map(params![wallet.id], Self::transaction_from_row)?;
 
         let mut transactions = Vec::new();
         for row in rows {
@@ -256,97 +227,1488 @@ impl DatabaseOperations {
         Ok(transactions)
     }
 
+    /// 按条件分页查询聊天中的交易，最新的交易在前
+    pub async fn get_transactions_page(
+        &self,
+        chat_id: i64,
+        filter: &TransactionFilter,
+        page: usize,
+        page_size: usize,
+    ) -> Result<TransactionPage> {
+        let conn = self.conn.lock().await;
+        let conditions = TRANSACTION_FILTER_CONDITIONS;
+        let filter_params = params![
+            chat_id,
+            filter.wallet_name,
+            filter.month,
+            filter.year,
+            filter.transaction_type,
+            filter.category
+        ];
+
+        let total: i64 = conn.query_row(
+            &format!(
+                "SELECT COUNT(*) FROM transactions t JOIN wallets w ON w.id = t.wallet_id WHERE {conditions}"
+            ),
+            filter_params,
+            |row| row.get(0),
+        )?;
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT t.id, t.wallet_id, t.transaction_type, t.amount, t.month, t.year, t.message_id, t.chat_id, t.created_at, t.note, t.linked_transaction_id, t.category
+             FROM transactions t
+             JOIN wallets w ON w.id = t.wallet_id
+             WHERE {conditions}
+             ORDER BY t.created_at DESC, t.id DESC
+             LIMIT {page_size} OFFSET {}",
+            page * page_size
+        ))?;
+        let rows = stmt.query_map(filter_params, Self::transaction_from_row)?;
+
+        Ok(TransactionPage {
+            transactions: rows.collect::<SqliteResult<Vec<Transaction>>>()?,
+            total: total as usize,
+            page,
+            page_size,
+        })
+    }
+
+    /// 按条件查询聊天中的全部交易及其钱包名，按时间先后排列
+    pub async fn get_wallet_transactions(
+        &self,
+        chat_id: i64,
+        filter: &TransactionFilter,
+    ) -> Result<Vec<WalletTransaction>> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT t.id, t.wallet_id, t.transaction_type, t.amount, t.month, t.year, t.message_id, t.chat_id, t.created_at, t.note, t.linked_transaction_id, t.category, w.name
+             FROM transactions t
+             JOIN wallets w ON w.id = t.wallet_id
+             WHERE {TRANSACTION_FILTER_CONDITIONS}
+             ORDER BY t.created_at, t.id"
+        ))?;
+        let rows = stmt.query_map(
+            params![
+                chat_id,
+                filter.wallet_name,
+                filter.month,
+                filter.year,
+                filter.transaction_type,
+                filter.category
+            ],
+            |row| {
+                Ok(WalletTransaction {
+                    transaction: Self::transaction_from_row(row)?,
+                    wallet_name: row.get(12)?,
+                })
+            },
+        )?;
+        Ok(rows.collect::<SqliteResult<Vec<WalletTransaction>>>()?)
+    }
+
     #[allow(dead_code)]
-    pub async fn get_balance(&self, chat_id: i64, wallet_name: &str) -> Result<f64> {
+    pub async fn get_balance(&self, chat_id: i64, wallet_name: &str) -> Result<Money> {
         let conn = self.conn.lock().await;
-        let wallet = self.get_wallet_by_name_sync(&conn, chat_id, wallet_name)?;
+        let wallet = Self::get_wallet_by_name_sync(&conn, chat_id, wallet_name)?;
         Ok(wallet.current_balance)
     }
 
-    #[allow(dead_code)]
-    pub async fn create_wallet(&self, chat_id: i64, name: &str) -> Result<Wallet> {
-        self.get_or_create_wallet(chat_id, name).await
+    /// 列出聊天中的所有钱包及其交易笔数，按名称排列
+    pub async fn get_wallet_summaries(&self, chat_id: i64) -> Result<Vec<WalletSummary>> {
+        let conn = self.conn.lock().await;
+        Self::wallet_summaries_sync(&conn, chat_id, None)
+    }
+
+    /// 获取单个钱包的状态，钱包不存在时返回 None
+    pub async fn get_wallet_summary(
+        &self,
+        chat_id: i64,
+        wallet_name: &str,
+    ) -> Result<Option<WalletSummary>> {
+        let conn = self.conn.lock().await;
+        Ok(
+            Self::wallet_summaries_sync(&conn, chat_id, Some(wallet_name))?
+                .into_iter()
+                .next(),
+        )
     }
 
+    /// 获取聊天中的所有余额调整记录，按时间先后排列
     #[allow(dead_code)]
-    pub async fn wallet_exists(&self, chat_id: i64, name: &str) -> Result<bool> {
+    pub async fn get_balance_adjustments(&self, chat_id: i64) -> Result<Vec<BalanceAdjustment>> {
         let conn = self.conn.lock().await;
-        let mut stmt = conn.prepare("SELECT 1 FROM wallets WHERE chat_id = ?1 AND name = ?2")?;
-        let exists = stmt.exists(params![chat_id, name])?;
-        Ok(exists)
+        let mut stmt = conn.prepare(
+            "SELECT id, wallet_id, chat_id, message_id, old_balance, new_balance, difference, reason, month, year, created_at
+             FROM balance_adjustments
+             WHERE chat_id = ?1
+             ORDER BY id",
+        )?;
+        let rows = stmt.query_map(params![chat_id], Self::adjustment_from_row)?;
+        Ok(rows.collect::<SqliteResult<Vec<BalanceAdjustment>>>()?)
+    }
+
+    /// 按月汇总聊天中未被交易解释的余额变化，按时间先后排列
+    pub async fn get_monthly_adjustments(&self, chat_id: i64) -> Result<Vec<MonthlyAdjustment>> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT year, month, COUNT(*),
+                    COALESCE(SUM(CASE WHEN difference > 0 THEN difference ELSE 0 END), 0),
+                    COALESCE(SUM(CASE WHEN difference < 0 THEN -difference ELSE 0 END), 0)
+             FROM balance_adjustments
+             WHERE chat_id = ?1
+             GROUP BY year, month",
+        )?;
+        let rows = stmt.query_map(params![chat_id], |row| {
+            Ok(MonthlyAdjustment {
+                year: row.get(0)?,
+                month: row.get(1)?,
+                count: row.get::<_, i64>(2)? as usize,
+                gained: row.get(3)?,
+                lost: row.get(4)?,
+            })
+        })?;
+        let mut months = rows.collect::<SqliteResult<Vec<MonthlyAdjustment>>>()?;
+
+        // 月份和年份以 "12月"、"2024年" 的形式存储，需要按数值排序
+        let leading_number = |s: &str| -> u32 {
+            s.chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect::<String>()
+                .parse()
+                .unwrap_or(0)
+        };
+        months.sort_by_key(|m| (leading_number(&m.year), leading_number(&m.month)));
+        Ok(months)
+    }
+
+    /// 按分类汇总聊天中的收支，可按月份、年份筛选，支出多的分类排在前面。
+    ///
+    /// 转账只是在钱包之间移动资金，不计入分类汇总。
+    pub async fn get_category_totals(
+        &self,
+        chat_id: i64,
+        month: Option<u32>,
+        year: Option<u32>,
+    ) -> Result<Vec<CategoryTotal>> {
+        let conn = self.conn.lock().await;
+        // 月份和年份以 "12月"、"2024年" 的形式存储，CAST 取开头的数字
+        let mut stmt = conn.prepare(
+            "SELECT category, COUNT(*),
+                    COALESCE(SUM(CASE WHEN transaction_type IN ('入账', '收入') THEN amount ELSE 0 END), 0),
+                    COALESCE(SUM(CASE WHEN transaction_type IN ('出账', '支出') THEN amount ELSE 0 END), 0) AS expense
+             FROM transactions
+             WHERE chat_id = ?1
+               AND voided_at IS NULL
+               AND transaction_type NOT IN (?2, ?3)
+               AND (?4 IS NULL OR CAST(month AS INTEGER) = ?4)
+               AND (?5 IS NULL OR CAST(year AS INTEGER) = ?5)
+             GROUP BY category
+             ORDER BY expense DESC, category",
+        )?;
+        let rows = stmt.query_map(
+            params![chat_id, TRANSFER_OUT, TRANSFER_IN, month, year],
+            |row| {
+                Ok(CategoryTotal {
+                    category: row.get(0)?,
+                    count: row.get::<_, i64>(1)? as usize,
+                    income: row.get(2)?,
+                    expense: row.get(3)?,
+                })
+            },
+        )?;
+        Ok(rows.collect::<SqliteResult<Vec<CategoryTotal>>>()?)
     }
 
+    /// 获取聊天中某个分类下的全部交易，按时间先后排列
     #[allow(dead_code)]
-    pub async fn add_transaction(
+    pub async fn get_category_transactions(
         &self,
         chat_id: i64,
-        wallet_name: &str,
-        transaction_type: &str,
-        amount: f64,
-        _description: &str,
-        _transaction_id: &str,
+        category: &str,
+    ) -> Result<Vec<Transaction>> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT id, wallet_id, transaction_type, amount, month, year, message_id, chat_id, created_at, note, linked_transaction_id, category
+             FROM transactions
+             WHERE chat_id = ?1 AND category = ?2 AND voided_at IS NULL
+             ORDER BY created_at, id",
+        )?;
+        let rows = stmt.query_map(params![chat_id, category], Self::transaction_from_row)?;
+        Ok(rows.collect::<SqliteResult<Vec<Transaction>>>()?)
+    }
+
+    /// 获取聊天的月度总结订阅
+    pub async fn get_summary_subscription(
+        &self,
+        chat_id: i64,
+    ) -> Result<Option<SummarySubscription>> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT chat_id, enabled, day_of_month, hour FROM summary_subscriptions WHERE chat_id = ?1",
+        )?;
+        let mut rows = stmt.query_map(params![chat_id], Self::subscription_from_row)?;
+        Ok(rows.next().transpose()?)
+    }
+
+    /// 所有开启了月度总结的聊天
+    pub async fn get_enabled_summary_subscriptions(&self) -> Result<Vec<SummarySubscription>> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT chat_id, enabled, day_of_month, hour FROM summary_subscriptions
+             WHERE enabled
+             ORDER BY chat_id",
+        )?;
+        let rows = stmt.query_map([], Self::subscription_from_row)?;
+        Ok(rows.collect::<SqliteResult<Vec<SummarySubscription>>>()?)
+    }
+
+    /// 新增或更新聊天的月度总结订阅
+    pub async fn save_summary_subscription(
+        &self,
+        subscription: &SummarySubscription,
     ) -> Result<()> {
-        // 确保钱包存在
-        let _ = self.get_or_create_wallet(chat_id, wallet_name).await?;
+        let conn = self.conn.lock().await;
+        conn.execute(
+            "INSERT INTO summary_subscriptions (chat_id, enabled, day_of_month, hour, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(chat_id) DO UPDATE SET
+                 enabled = excluded.enabled,
+                 day_of_month = excluded.day_of_month,
+                 hour = excluded.hour,
+                 updated_at = excluded.updated_at",
+            params![
+                subscription.chat_id,
+                subscription.enabled,
+                subscription.day_of_month,
+                subscription.hour,
+                Utc::now()
+            ],
+        )?;
+        Ok(())
+    }
 
-        // 对于简化的API，我们使用当前时间
-        let now = Utc::now();
-        let month = format!("{:02}", now.month());
-        let year = now.year().to_string();
+    /// 登记某个月的总结即将发送，已经登记过时返回 false，避免重启后重复发送
+    pub async fn claim_summary_period(&self, chat_id: i64, year: u32, month: u32) -> Result<bool> {
+        let conn = self.conn.lock().await;
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO posted_summaries (chat_id, year, month, posted_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![chat_id, year, month, Utc::now()],
+        )?;
+        Ok(inserted == 1)
+    }
 
-        self.record_transaction(
-            chat_id,
-            wallet_name,
-            transaction_type,
-            amount,
-            &month,
-            &year,
-            None,
-        )
-        .await?;
-
-        // 更新钱包余额
-        let current_balance = self.get_balance(chat_id, wallet_name).await?;
-        let new_balance = match transaction_type {
-            "收入" | "入账" => current_balance + amount,
-            "支出" | "出账" => current_balance - amount,
-            _ => current_balance - amount, // 默认为支出类型
-        };
+    /// 发送失败时撤销登记，下次检查时重试
+    pub async fn release_summary_period(&self, chat_id: i64, year: u32, month: u32) -> Result<()> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            "DELETE FROM posted_summaries WHERE chat_id = ?1 AND year = ?2 AND month = ?3",
+            params![chat_id, year, month],
+        )?;
+        Ok(())
+    }
+
+    /// 用户在聊天中单独授权的角色
+    pub async fn get_chat_role(&self, chat_id: i64, user_id: i64) -> Result<Option<Role>> {
+        let conn = self.conn.lock().await;
+        let mut stmt =
+            conn.prepare("SELECT role FROM chat_permissions WHERE chat_id = ?1 AND user_id = ?2")?;
+        let mut rows = stmt.query_map(params![chat_id, user_id], |row| row.get::<_, String>(0))?;
+        Ok(rows.next().transpose()?.and_then(|role| Role::parse(&role)))
+    }
 
-        self.update_wallet_balance(chat_id, wallet_name, new_balance)
-            .await?;
+    /// 聊天中所有单独授权的成员，管理员在前
+    pub async fn get_chat_permissions(&self, chat_id: i64) -> Result<Vec<ChatPermission>> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT chat_id, user_id, role FROM chat_permissions
+             WHERE chat_id = ?1
+             ORDER BY CASE role WHEN 'admin' THEN 0 WHEN 'writer' THEN 1 ELSE 2 END, user_id",
+        )?;
+        let rows = stmt.query_map(params![chat_id], Self::permission_from_row)?;
+        Ok(rows.collect::<SqliteResult<Vec<ChatPermission>>>()?)
+    }
 
+    /// 授予或修改用户在聊天中的角色
+    pub async fn set_chat_role(&self, chat_id: i64, user_id: i64, role: Role) -> Result<()> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            "INSERT INTO chat_permissions (chat_id, user_id, role) VALUES (?1, ?2, ?3)
+             ON CONFLICT(chat_id, user_id) DO UPDATE SET role = excluded.role",
+            params![chat_id, user_id, role.as_str()],
+        )?;
         Ok(())
     }
 
-    fn get_wallet_by_name_sync(
-        &self,
-        conn: &Connection,
-        chat_id: i64,
-        name: &str,
-    ) -> Result<Wallet> {
-        let mut stmt = conn.prepare("SELECT id, chat_id, name, current_balance, created_at, updated_at FROM wallets WHERE chat_id = ?1 AND name = ?2")?;
-        let mut wallet_iter = stmt.query_map(params![chat_id, name], |row| {
-            Ok(Wallet {
-                id: Some(row.get(0)?),
-                chat_id: row.get(1)?,
-                name: row.get(2)?,
-                current_balance: row.get(3)?,
-                created_at: row.get(4).ok(),
-                updated_at: row.get(5).ok(),
+    /// 撤销用户在聊天中的授权，用户没有被授权时返回 false
+    pub async fn remove_chat_role(&self, chat_id: i64, user_id: i64) -> Result<bool> {
+        let conn = self.conn.lock().await;
+        let removed = conn.execute(
+            "DELETE FROM chat_permissions WHERE chat_id = ?1 AND user_id = ?2",
+            params![chat_id, user_id],
+        )?;
+        Ok(removed == 1)
+    }
+
+    /// 聊天的访问策略，没有设置时使用默认策略
+    pub async fn get_access_policy(&self, chat_id: i64) -> Result<AccessPolicy> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT chat_id, restricted, reject_unauthorized FROM access_policies WHERE chat_id = ?1",
+        )?;
+        let mut rows = stmt.query_map(params![chat_id], |row| {
+            Ok(AccessPolicy {
+                chat_id: row.get(0)?,
+                restricted: row.get(1)?,
+                reject_unauthorized: row.get(2)?,
             })
         })?;
+        Ok(rows.next().transpose()?.unwrap_or(AccessPolicy {
+            chat_id,
+            ..Default::default()
+        }))
+    }
 
-        if let Some(wallet) = wallet_iter.next() {
-            return Ok(wallet?);
-        }
+    /// 新增或更新聊天的访问策略
+    pub async fn save_access_policy(&self, policy: &AccessPolicy) -> Result<()> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            "INSERT INTO access_policies (chat_id, restricted, reject_unauthorized, updated_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(chat_id) DO UPDATE SET
+                 restricted = excluded.restricted,
+                 reject_unauthorized = excluded.reject_unauthorized,
+                 updated_at = excluded.updated_at",
+            params![
+                policy.chat_id,
+                policy.restricted,
+                policy.reject_unauthorized,
+                Utc::now()
+            ],
+        )?;
+        Ok(())
+    }
 
-        Err(anyhow::anyhow!(
-            "Wallet not found: {} in chat {}",
-            name,
-            chat_id
-        ))
+    /// 聊天的设置，没有保存过时使用默认值
+    pub async fn get_chat_settings(&self, chat_id: i64) -> Result<ChatSettings> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT chat_id, output_mode, confirmation_ttl, currency, default_wallet, utc_offset, language,
+                    confirm_above, confirm_ratio
+             FROM chat_settings WHERE chat_id = ?1",
+        )?;
+        let mut rows = stmt.query_map(params![chat_id], |row| {
+            let output_mode: String = row.get(1)?;
+            let language: String = row.get(6)?;
+            Ok(ChatSettings {
+                chat_id: row.get(0)?,
+                output_mode: OutputMode::parse(&output_mode).unwrap_or_default(),
+                confirmation_ttl: row.get(2)?,
+                currency: row.get(3)?,
+                default_wallet: row.get(4)?,
+                utc_offset: row.get(5)?,
+                language: Language::parse(&language).unwrap_or_default(),
+                confirm_above: row.get(7)?,
+                confirm_ratio: row.get(8)?,
+            })
+        })?;
+        Ok(rows
+            .next()
+            .transpose()?
+            .unwrap_or_else(|| ChatSettings::new(chat_id)))
     }
-}
 
-// Tests will be added later
+    /// 新增或更新聊天的设置
+    pub async fn save_chat_settings(&self, settings: &ChatSettings) -> Result<()> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            "INSERT INTO chat_settings (chat_id, output_mode, confirmation_ttl, currency, default_wallet, utc_offset, language,
+                                        confirm_above, confirm_ratio, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+             ON CONFLICT(chat_id) DO UPDATE SET
+                 output_mode = excluded.output_mode,
+                 confirmation_ttl = excluded.confirmation_ttl,
+                 currency = excluded.currency,
+                 default_wallet = excluded.default_wallet,
+                 utc_offset = excluded.utc_offset,
+                 language = excluded.language,
+                 confirm_above = excluded.confirm_above,
+                 confirm_ratio = excluded.confirm_ratio,
+                 updated_at = excluded.updated_at",
+            params![
+                settings.chat_id,
+                settings.output_mode.as_str(),
+                settings.confirmation_ttl,
+                settings.currency,
+                settings.default_wallet,
+                settings.utc_offset,
+                settings.language.as_str(),
+                settings.confirm_above,
+                settings.confirm_ratio,
+                Utc::now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// 保存 /history 的查询条件并返回编号，同一聊天中相同的条件复用已有的编号
+    pub async fn save_history_query(
+        &self,
+        chat_id: i64,
+        filter: &TransactionFilter,
+    ) -> Result<i64> {
+        let conn = self.conn.lock().await;
+        let filter_params = params![
+            chat_id,
+            filter.wallet_name,
+            filter.month,
+            filter.year,
+            filter.transaction_type,
+            filter.category
+        ];
+        let existing = conn
+            .query_row(
+                "SELECT id FROM history_queries
+                 WHERE chat_id = ?1 AND wallet_name IS ?2 AND month IS ?3 AND year IS ?4
+                   AND transaction_type IS ?5 AND category IS ?6",
+                filter_params,
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(id) = existing {
+            return Ok(id);
+        }
+
+        conn.execute(
+            "INSERT INTO history_queries (chat_id, wallet_name, month, year, transaction_type, category)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            filter_params,
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// 按编号读取聊天中保存的 /history 查询条件
+    pub async fn get_history_query(
+        &self,
+        chat_id: i64,
+        id: i64,
+    ) -> Result<Option<TransactionFilter>> {
+        let conn = self.conn.lock().await;
+        Ok(conn
+            .query_row(
+                "SELECT wallet_name, month, year, transaction_type, category
+                 FROM history_queries WHERE chat_id = ?1 AND id = ?2",
+                params![chat_id, id],
+                |row| {
+                    Ok(TransactionFilter {
+                        wallet_name: row.get(0)?,
+                        month: row.get(1)?,
+                        year: row.get(2)?,
+                        transaction_type: row.get(3)?,
+                        category: row.get(4)?,
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    /// 钱包最近 limit 笔收支的金额（不含转账和已撤销的交易），最新的在前
+    pub async fn get_recent_amounts(
+        &self,
+        chat_id: i64,
+        wallet_name: &str,
+        limit: usize,
+    ) -> Result<Vec<Money>> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT t.amount FROM transactions t
+             JOIN wallets w ON w.id = t.wallet_id
+             WHERE w.chat_id = ?1 AND w.name = ?2 AND t.voided_at IS NULL
+               AND t.transaction_type IN ('出账', '入账', '收入', '支出')
+             ORDER BY t.id DESC
+             LIMIT ?3",
+        )?;
+        let amounts = stmt
+            .query_map(params![chat_id, wallet_name, limit as i64], |row| {
+                row.get(0)
+            })?
+            .collect::<rusqlite::Result<Vec<Money>>>()?;
+        Ok(amounts)
+    }
+
+    #[allow(dead_code)]
+    pub async fn create_wallet(&self, chat_id: i64, name: &str) -> Result<Wallet> {
+        self.get_or_create_wallet(chat_id, name).await
+    }
+
+    #[allow(dead_code)]
+    pub async fn wallet_exists(&self, chat_id: i64, name: &str) -> Result<bool> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare("SELECT 1 FROM wallets WHERE chat_id = ?1 AND name = ?2")?;
+        let exists = stmt.exists(params![chat_id, name])?;
+        Ok(exists)
+    }
+
+    #[allow(dead_code)]
+    pub async fn add_transaction(
+        &self,
+        chat_id: i64,
+        wallet_name: &str,
+        transaction_type: &str,
+        amount: Money,
+        _description: &str,
+        _transaction_id: &str,
+    ) -> Result<()> {
+        // 对于简化的API，我们使用当前时间
+        let now = Utc::now();
+        let month = format!("{:02}", now.month());
+        let year = now.year().to_string();
+
+        // 交易记录和余额更新在同一个事务中完成
+        self.unit_of_work(|uow| {
+            let wallet = uow.get_or_create_wallet(chat_id, wallet_name)?;
+
+            uow.record_transaction(
+                chat_id,
+                wallet_name,
+                transaction_type,
+                amount,
+                &month,
+                &year,
+                None,
+            )?;
+
+            // 更新钱包余额
+            let current_balance = wallet.current_balance;
+            let new_balance = match transaction_type {
+                "收入" | "入账" => current_balance + amount,
+                "支出" | "出账" => current_balance - amount,
+                _ => current_balance - amount, // 默认为支出类型
+            };
+
+            uow.update_wallet_balance(chat_id, wallet_name, new_balance)
+        })
+        .await
+    }
+
+    fn wallet_from_row(row: &rusqlite::Row<'_>) -> SqliteResult<Wallet> {
+        Ok(Wallet {
+            id: Some(row.get(0)?),
+            chat_id: row.get(1)?,
+            name: row.get(2)?,
+            current_balance: row.get(3)?,
+            created_at: row.get(4).ok(),
+            updated_at: row.get(5).ok(),
+        })
+    }
+
+    fn transaction_from_row(row: &rusqlite::Row<'_>) -> SqliteResult<Transaction> {
+        Ok(Transaction {
+            id: Some(row.get(0)?),
+            wallet_id: row.get(1)?,
+            transaction_type: row.get(2)?,
+            amount: row.get(3)?,
+            month: row.get(4)?,
+            year: row.get(5)?,
+            message_id: row.get(6)?,
+            chat_id: row.get(7)?,
+            created_at: row.get(8)?,
+            note: row.get(9)?,
+            linked_transaction_id: row.get(10)?,
+            category: row.get(11)?,
+        })
+    }
+
+    fn subscription_from_row(row: &rusqlite::Row<'_>) -> SqliteResult<SummarySubscription> {
+        Ok(SummarySubscription {
+            chat_id: row.get(0)?,
+            enabled: row.get(1)?,
+            day_of_month: row.get(2)?,
+            hour: row.get(3)?,
+        })
+    }
+
+    fn permission_from_row(row: &rusqlite::Row<'_>) -> SqliteResult<ChatPermission> {
+        let role: String = row.get(2)?;
+        Ok(ChatPermission {
+            chat_id: row.get(0)?,
+            user_id: row.get(1)?,
+            role: Role::parse(&role).unwrap_or(Role::Guest),
+        })
+    }
+
+    fn adjustment_from_row(row: &rusqlite::Row<'_>) -> SqliteResult<BalanceAdjustment> {
+        Ok(BalanceAdjustment {
+            id: Some(row.get(0)?),
+            wallet_id: row.get(1)?,
+            chat_id: row.get(2)?,
+            message_id: row.get(3)?,
+            old_balance: row.get(4)?,
+            new_balance: row.get(5)?,
+            difference: row.get(6)?,
+            reason: row.get(7)?,
+            month: row.get(8)?,
+            year: row.get(9)?,
+            created_at: row.get(10).ok(),
+        })
+    }
+
+    fn message_from_row(row: &rusqlite::Row<'_>) -> SqliteResult<Message> {
+        Ok(Message {
+            id: Some(row.get(0)?),
+            message_id: row.get(1)?,
+            chat_id: row.get(2)?,
+            wallet_id: row.get(3)?,
+            has_total: row.get(4)?,
+            processed: row.get(5)?,
+            original_balance: row.get(6)?,
+            new_balance: row.get(7)?,
+            created_at: row.get(8).ok(),
+            voided_at: row.get(9)?,
+        })
+    }
+
+    fn get_or_create_wallet_sync(conn: &Connection, chat_id: i64, name: &str) -> Result<Wallet> {
+        // 尝试获取现有钱包
+        let mut stmt = conn.prepare("SELECT id, chat_id, name, current_balance, created_at, updated_at FROM wallets WHERE chat_id = ?1 AND name = ?2")?;
+        let mut wallet_iter = stmt.query_map(params![chat_id, name], Self::wallet_from_row)?;
+
+        if let Some(wallet) = wallet_iter.next() {
+            return Ok(wallet?);
+        }
+
+        // 如果不存在，创建新钱包
+        let now = Utc::now();
+        conn.execute(
+            "INSERT INTO wallets (chat_id, name, current_balance, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![chat_id, name, Money::ZERO, now, now],
+        )?;
+
+        let wallet_id = conn.last_insert_rowid();
+        debug!("Created new wallet: {name} in chat {chat_id} with ID: {wallet_id}");
+
+        Ok(Wallet {
+            id: Some(wallet_id),
+            chat_id,
+            name: name.to_string(),
+            current_balance: Money::ZERO,
+            created_at: Some(now),
+            updated_at: Some(now),
+        })
+    }
+
+    fn update_wallet_balance_sync(
+        conn: &Connection,
+        chat_id: i64,
+        name: &str,
+        balance: Money,
+    ) -> Result<()> {
+        let now = Utc::now();
+
+        conn.execute(
+            "UPDATE wallets SET current_balance = ?1, updated_at = ?2 WHERE chat_id = ?3 AND name = ?4",
+            params![balance, now, chat_id, name],
+        )?;
+
+        info!("Updated wallet balance: {name} in chat {chat_id} -> {balance}");
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn record_transaction_sync(
+        conn: &Connection,
+        chat_id: i64,
+        wallet_name: &str,
+        transaction_type: &str,
+        amount: Money,
+        month: &str,
+        year: &str,
+        message_id: Option<i64>,
+        note: Option<&str>,
+        category: Option<&str>,
+    ) -> Result<()> {
+        // 获取钱包ID
+        let wallet = Self::get_wallet_by_name_sync(conn, chat_id, wallet_name)?;
+        let wallet_id = wallet.id.unwrap();
+
+        let now = Utc::now();
+        conn.execute(
+            "INSERT INTO transactions (wallet_id, transaction_type, amount, month, year, message_id, chat_id, created_at, note, category) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![wallet_id, transaction_type, amount, month, year, message_id, Some(chat_id), now, note, category],
+        )?;
+
+        debug!("Recorded transaction: {wallet_name} {transaction_type} {amount}");
+        Ok(())
+    }
+
+    /// 记录一笔导入的交易，去重键已存在时不写入并返回 false
+    fn record_imported_transaction_sync(
+        conn: &Connection,
+        chat_id: i64,
+        wallet_id: i64,
+        row: &ImportedTransaction,
+    ) -> Result<bool> {
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO transactions (wallet_id, transaction_type, amount, month, year, chat_id, created_at, note, category, import_key)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                wallet_id,
+                row.transaction_type,
+                row.amount,
+                row.month,
+                row.year,
+                chat_id,
+                row.created_at,
+                row.note,
+                row.category,
+                row.import_key
+            ],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    fn is_imported_sync(conn: &Connection, chat_id: i64, import_key: &str) -> Result<bool> {
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM transactions WHERE chat_id = ?1 AND import_key = ?2",
+            params![chat_id, import_key],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    /// 记录一笔转账：转出、转入两行交易互相链接，返回两行的 ID
+    #[allow(clippy::too_many_arguments)]
+    fn record_transfer_sync(
+        conn: &Connection,
+        chat_id: i64,
+        from_wallet: &str,
+        to_wallet: &str,
+        amount: Money,
+        month: &str,
+        year: &str,
+        message_id: Option<i64>,
+        note: Option<&str>,
+    ) -> Result<(i64, i64)> {
+        let from_id = Self::get_wallet_by_name_sync(conn, chat_id, from_wallet)?
+            .id
+            .unwrap();
+        let to_id = Self::get_wallet_by_name_sync(conn, chat_id, to_wallet)?
+            .id
+            .unwrap();
+
+        let now = Utc::now();
+        let mut insert = conn.prepare(
+            "INSERT INTO transactions (wallet_id, transaction_type, amount, month, year, message_id, chat_id, created_at, note)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        )?;
+        insert.execute(params![
+            from_id,
+            TRANSFER_OUT,
+            amount,
+            month,
+            year,
+            message_id,
+            Some(chat_id),
+            now,
+            note
+        ])?;
+        let out_id = conn.last_insert_rowid();
+        insert.execute(params![
+            to_id,
+            TRANSFER_IN,
+            amount,
+            month,
+            year,
+            message_id,
+            Some(chat_id),
+            now,
+            note
+        ])?;
+        let in_id = conn.last_insert_rowid();
+
+        conn.execute(
+            "UPDATE transactions SET linked_transaction_id = ?1 WHERE id = ?2",
+            params![in_id, out_id],
+        )?;
+        conn.execute(
+            "UPDATE transactions SET linked_transaction_id = ?1 WHERE id = ?2",
+            params![out_id, in_id],
+        )?;
+
+        debug!("Recorded transfer: {from_wallet} -> {to_wallet} {amount}");
+        Ok((out_id, in_id))
+    }
+
+    fn record_message_sync(
+        conn: &Connection,
+        message_id: i64,
+        chat_id: i64,
+        wallet_name: &str,
+        has_total: bool,
+        original_balance: Option<Money>,
+        new_balance: Option<Money>,
+    ) -> Result<()> {
+        // 获取钱包ID
+        let wallet = Self::get_wallet_by_name_sync(conn, chat_id, wallet_name)?;
+        let wallet_id = wallet.id.unwrap();
+
+        let now = Utc::now();
+        conn.execute(
+            "INSERT OR REPLACE INTO messages (message_id, chat_id, wallet_id, has_total, processed, original_balance, new_balance, created_at) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![message_id, chat_id, wallet_id, has_total, true, original_balance, new_balance, now],
+        )?;
+
+        debug!("Recorded message: {message_id} in chat {chat_id}");
+        Ok(())
+    }
+    fn get_message_record_sync(
+        conn: &Connection,
+        message_id: i64,
+        chat_id: i64,
+    ) -> Result<Option<Message>> {
+        let mut stmt = conn.prepare(
+            "SELECT id, message_id, chat_id, wallet_id, has_total, processed, original_balance, new_balance, created_at, voided_at
+             FROM messages
+             WHERE message_id = ?1 AND chat_id = ?2",
+        )?;
+        let mut rows = stmt.query_map(params![message_id, chat_id], Self::message_from_row)?;
+
+        match rows.next() {
+            Some(record) => Ok(Some(record?)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_message_transactions_sync(
+        conn: &Connection,
+        message_id: i64,
+        chat_id: i64,
+    ) -> Result<Vec<Transaction>> {
+        let mut stmt = conn.prepare(
+            "SELECT id, wallet_id, transaction_type, amount, month, year, message_id, chat_id, created_at, note, linked_transaction_id, category
+             FROM transactions
+             WHERE message_id = ?1 AND chat_id = ?2 AND voided_at IS NULL
+             ORDER BY id",
+        )?;
+
+        let rows = stmt.query_map(params![message_id, chat_id], Self::transaction_from_row)?;
+
+        Ok(rows.collect::<SqliteResult<Vec<Transaction>>>()?)
+    }
+
+    /// 某条消息在指定钱包中的交易（转账消息会同时在两个钱包中留下交易）
+    fn get_message_wallet_transactions_sync(
+        conn: &Connection,
+        message_id: i64,
+        chat_id: i64,
+        wallet_id: i64,
+    ) -> Result<Vec<Transaction>> {
+        Ok(
+            Self::get_message_transactions_sync(conn, message_id, chat_id)?
+                .into_iter()
+                .filter(|t| t.wallet_id == wallet_id)
+                .collect(),
+        )
+    }
+
+    /// 将某条消息之后同一钱包的余额快照平移 delta。
+    ///
+    /// 遇到手动总额检查点时停止，因为检查点之后的余额由手动总额决定。
+    /// 返回 delta 是否一直传导到了钱包的当前余额。
+    fn shift_later_balances_sync(
+        conn: &Connection,
+        wallet_id: i64,
+        after_id: i64,
+        delta: Money,
+    ) -> Result<bool> {
+        if delta.is_zero() {
+            return Ok(false);
+        }
+
+        let later: Vec<Message> = {
+            let mut stmt = conn.prepare(
+                "SELECT id, message_id, chat_id, wallet_id, has_total, processed, original_balance, new_balance, created_at, voided_at
+                 FROM messages
+                 WHERE wallet_id = ?1 AND id > ?2 AND voided_at IS NULL
+                 ORDER BY id",
+            )?;
+            let rows = stmt.query_map(params![wallet_id, after_id], Self::message_from_row)?;
+            rows.collect::<SqliteResult<Vec<Message>>>()?
+        };
+
+        for message in later {
+            let original_balance = message.original_balance.unwrap_or(Money::ZERO);
+            let new_balance = message.new_balance.unwrap_or(original_balance);
+
+            // 只有总额与交易计算结果不一致时才是真正的手动检查点
+            let transaction_delta: Money = Self::get_message_wallet_transactions_sync(
+                conn,
+                message.message_id,
+                message.chat_id,
+                wallet_id,
+            )?
+            .iter()
+            .map(|t| signed_amount(&t.transaction_type, t.amount))
+            .sum();
+            let is_checkpoint =
+                message.has_total && original_balance + transaction_delta != new_balance;
+
+            if is_checkpoint {
+                conn.execute(
+                    "UPDATE messages SET original_balance = ?1 WHERE id = ?2",
+                    params![original_balance + delta, message.id],
+                )?;
+                Self::rebase_message_adjustment_sync(
+                    conn,
+                    message.message_id,
+                    message.chat_id,
+                    original_balance + delta + transaction_delta,
+                )?;
+                debug!(
+                    "Balance shift absorbed by manual total in message {}",
+                    message.message_id
+                );
+                return Ok(false);
+            }
+
+            conn.execute(
+                "UPDATE messages SET original_balance = ?1, new_balance = ?2 WHERE id = ?3",
+                params![original_balance + delta, new_balance + delta, message.id],
+            )?;
+        }
+
+        Ok(true)
+    }
+
+    fn add_to_wallet_balance_sync(conn: &Connection, wallet_id: i64, delta: Money) -> Result<()> {
+        conn.execute(
+            "UPDATE wallets SET current_balance = current_balance + ?1, updated_at = ?2 WHERE id = ?3",
+            params![delta, Utc::now(), wallet_id],
+        )?;
+        Ok(())
+    }
+
+    fn get_wallet_by_id_sync(conn: &Connection, wallet_id: i64) -> Result<Wallet> {
+        let mut stmt = conn.prepare("SELECT id, chat_id, name, current_balance, created_at, updated_at FROM wallets WHERE id = ?1")?;
+        let mut wallet_iter = stmt.query_map(params![wallet_id], Self::wallet_from_row)?;
+
+        if let Some(wallet) = wallet_iter.next() {
+            return Ok(wallet?);
+        }
+
+        Err(anyhow::anyhow!("Wallet not found: id {}", wallet_id))
+    }
+
+    fn get_wallet_by_name_sync(conn: &Connection, chat_id: i64, name: &str) -> Result<Wallet> {
+        let mut stmt = conn.prepare("SELECT id, chat_id, name, current_balance, created_at, updated_at FROM wallets WHERE chat_id = ?1 AND name = ?2")?;
+        let mut wallet_iter = stmt.query_map(params![chat_id, name], Self::wallet_from_row)?;
+
+        if let Some(wallet) = wallet_iter.next() {
+            return Ok(wallet?);
+        }
+
+        Err(anyhow::anyhow!(
+            "Wallet not found: {} in chat {}",
+            name,
+            chat_id
+        ))
+    }
+
+    fn wallet_summaries_sync(
+        conn: &Connection,
+        chat_id: i64,
+        wallet_name: Option<&str>,
+    ) -> Result<Vec<WalletSummary>> {
+        let mut stmt = conn.prepare(
+            "SELECT w.name, w.current_balance, w.updated_at, COUNT(t.id)
+             FROM wallets w
+             LEFT JOIN transactions t ON t.wallet_id = w.id AND t.voided_at IS NULL
+             WHERE w.chat_id = ?1 AND (?2 IS NULL OR w.name = ?2)
+             GROUP BY w.id
+             ORDER BY w.name",
+        )?;
+        let rows = stmt.query_map(params![chat_id, wallet_name], |row| {
+            Ok(WalletSummary {
+                name: row.get(0)?,
+                balance: row.get(1)?,
+                updated_at: row.get(2).ok(),
+                transaction_count: row.get::<_, i64>(3)? as usize,
+            })
+        })?;
+        Ok(rows.collect::<SqliteResult<Vec<WalletSummary>>>()?)
+    }
+
+    fn get_wallets_sync(conn: &Connection, chat_id: i64) -> Result<Vec<Wallet>> {
+        let mut stmt = conn.prepare(
+            "SELECT id, chat_id, name, current_balance, created_at, updated_at
+             FROM wallets
+             WHERE chat_id = ?1
+             ORDER BY name",
+        )?;
+        let rows = stmt.query_map(params![chat_id], Self::wallet_from_row)?;
+        Ok(rows.collect::<SqliteResult<Vec<Wallet>>>()?)
+    }
+
+    /// 按时间顺序读取钱包的账本：已处理的消息（含其交易）以及不属于该钱包任何消息的交易。
+    ///
+    /// 转账消息只记在转出钱包名下，转入一侧的交易在转入钱包的账本中是独立条目。
+    /// 被撤销的消息和作废的交易不计入账本。
+    fn get_wallet_ledger_sync(conn: &Connection, wallet_id: i64) -> Result<Vec<LedgerEntry>> {
+        let messages: Vec<Message> = {
+            let mut stmt = conn.prepare(
+                "SELECT id, message_id, chat_id, wallet_id, has_total, processed, original_balance, new_balance, created_at, voided_at
+                 FROM messages
+                 WHERE wallet_id = ?1 AND voided_at IS NULL
+                 ORDER BY id",
+            )?;
+            let rows = stmt.query_map(params![wallet_id], Self::message_from_row)?;
+            rows.collect::<SqliteResult<Vec<Message>>>()?
+        };
+
+        let mut ledger = Vec::with_capacity(messages.len());
+        for message in messages {
+            let transactions = Self::get_message_wallet_transactions_sync(
+                conn,
+                message.message_id,
+                message.chat_id,
+                wallet_id,
+            )?;
+            ledger.push(LedgerEntry {
+                message: Some(message),
+                transactions,
+            });
+        }
+
+        let mut stmt = conn.prepare(
+            "SELECT t.id, t.wallet_id, t.transaction_type, t.amount, t.month, t.year, t.message_id, t.chat_id, t.created_at, t.note, t.linked_transaction_id, t.category
+             FROM transactions t
+             WHERE t.wallet_id = ?1
+               AND t.voided_at IS NULL
+               AND NOT EXISTS (
+                   SELECT 1 FROM messages m
+                   WHERE m.message_id = t.message_id AND m.chat_id = t.chat_id AND m.wallet_id = t.wallet_id
+               )
+             ORDER BY t.id",
+        )?;
+        let standalone = stmt.query_map(params![wallet_id], Self::transaction_from_row)?;
+        for transaction in standalone {
+            ledger.push(LedgerEntry {
+                message: None,
+                transactions: vec![transaction?],
+            });
+        }
+
+        // 稳定排序，同一时间的条目保持各自的插入顺序
+        ledger.sort_by_key(|entry| entry.created_at());
+        Ok(ledger)
+    }
+
+    fn record_adjustment_sync(conn: &Connection, adjustment: &BalanceAdjustment) -> Result<()> {
+        conn.execute(
+            "INSERT INTO balance_adjustments (wallet_id, chat_id, message_id, old_balance, new_balance, difference, reason, month, year, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                adjustment.wallet_id,
+                adjustment.chat_id,
+                adjustment.message_id,
+                adjustment.old_balance,
+                adjustment.new_balance,
+                adjustment.difference,
+                adjustment.reason,
+                adjustment.month,
+                adjustment.year,
+                Utc::now()
+            ],
+        )?;
+
+        debug!(
+            "Recorded balance adjustment of {} in chat {}",
+            adjustment.difference, adjustment.chat_id
+        );
+        Ok(())
+    }
+
+    /// 检查点之前的余额变化后，按新的计算余额重新得出调整差额
+    fn rebase_message_adjustment_sync(
+        conn: &Connection,
+        message_id: i64,
+        chat_id: i64,
+        computed_balance: Money,
+    ) -> Result<()> {
+        conn.execute(
+            "UPDATE balance_adjustments SET old_balance = ?1, difference = new_balance - ?1
+             WHERE message_id = ?2 AND chat_id = ?3",
+            params![computed_balance, message_id, chat_id],
+        )?;
+        Ok(())
+    }
+}
+
+/// 工作单元，由 [`DatabaseOperations::unit_of_work`] 创建。
+///
+/// 所有操作都在同一个未提交的 SQLite 事务中执行。
+pub struct UnitOfWork<'a> {
+    conn: &'a Connection,
+}
+
+impl UnitOfWork<'_> {
+    pub fn get_or_create_wallet(&self, chat_id: i64, name: &str) -> Result<Wallet> {
+        DatabaseOperations::get_or_create_wallet_sync(self.conn, chat_id, name)
+    }
+
+    pub fn get_wallet_by_id(&self, wallet_id: i64) -> Result<Wallet> {
+        DatabaseOperations::get_wallet_by_id_sync(self.conn, wallet_id)
+    }
+
+    /// 记录一笔导入的交易，去重键已存在时返回 false
+    pub fn record_imported_transaction(
+        &self,
+        chat_id: i64,
+        wallet_id: i64,
+        row: &ImportedTransaction,
+    ) -> Result<bool> {
+        DatabaseOperations::record_imported_transaction_sync(self.conn, chat_id, wallet_id, row)
+    }
+
+    /// 去重键是否已经导入过
+    pub fn is_imported(&self, chat_id: i64, import_key: &str) -> Result<bool> {
+        DatabaseOperations::is_imported_sync(self.conn, chat_id, import_key)
+    }
+
+    pub fn update_wallet_balance(&self, chat_id: i64, name: &str, balance: Money) -> Result<()> {
+        DatabaseOperations::update_wallet_balance_sync(self.conn, chat_id, name, balance)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_transaction(
+        &self,
+        chat_id: i64,
+        wallet_name: &str,
+        transaction_type: &str,
+        amount: Money,
+        month: &str,
+        year: &str,
+        message_id: Option<i64>,
+    ) -> Result<()> {
+        DatabaseOperations::record_transaction_sync(
+            self.conn,
+            chat_id,
+            wallet_name,
+            transaction_type,
+            amount,
+            month,
+            year,
+            message_id,
+            None,
+            None,
+        )
+    }
+
+    /// 记录消息中的一笔交易明细
+    pub fn record_line_item(
+        &self,
+        chat_id: i64,
+        wallet_name: &str,
+        item: &LineItem,
+        month: &str,
+        year: &str,
+        message_id: i64,
+    ) -> Result<()> {
+        DatabaseOperations::record_transaction_sync(
+            self.conn,
+            chat_id,
+            wallet_name,
+            &item.transaction_type,
+            item.amount,
+            month,
+            year,
+            Some(message_id),
+            item.note.as_deref(),
+            item.category.as_deref(),
+        )
+    }
+
+    /// 记录一笔转账的两行交易
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_transfer(
+        &self,
+        chat_id: i64,
+        from_wallet: &str,
+        to_wallet: &str,
+        amount: Money,
+        month: &str,
+        year: &str,
+        message_id: i64,
+        note: Option<&str>,
+    ) -> Result<(i64, i64)> {
+        DatabaseOperations::record_transfer_sync(
+            self.conn,
+            chat_id,
+            from_wallet,
+            to_wallet,
+            amount,
+            month,
+            year,
+            Some(message_id),
+            note,
+        )
+    }
+
+    pub fn record_message(
+        &self,
+        message_id: i64,
+        chat_id: i64,
+        wallet_name: &str,
+        has_total: bool,
+        original_balance: Option<Money>,
+        new_balance: Option<Money>,
+    ) -> Result<()> {
+        DatabaseOperations::record_message_sync(
+            self.conn,
+            message_id,
+            chat_id,
+            wallet_name,
+            has_total,
+            original_balance,
+            new_balance,
+        )
+    }
+
+    pub fn get_message_transactions(
+        &self,
+        message_id: i64,
+        chat_id: i64,
+    ) -> Result<Vec<Transaction>> {
+        DatabaseOperations::get_message_transactions_sync(self.conn, message_id, chat_id)
+    }
+
+    pub fn get_wallet_by_name(&self, chat_id: i64, name: &str) -> Result<Wallet> {
+        DatabaseOperations::get_wallet_by_name_sync(self.conn, chat_id, name)
+    }
+
+    pub fn get_wallets(&self, chat_id: i64) -> Result<Vec<Wallet>> {
+        DatabaseOperations::get_wallets_sync(self.conn, chat_id)
+    }
+
+    pub fn get_wallet_ledger(&self, wallet_id: i64) -> Result<Vec<LedgerEntry>> {
+        DatabaseOperations::get_wallet_ledger_sync(self.conn, wallet_id)
+    }
+
+    pub fn record_adjustment(&self, adjustment: &BalanceAdjustment) -> Result<()> {
+        DatabaseOperations::record_adjustment_sync(self.conn, adjustment)
+    }
+
+    /// 删除某条消息产生的调整记录
+    pub fn delete_message_adjustments(&self, message_id: i64, chat_id: i64) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM balance_adjustments WHERE message_id = ?1 AND chat_id = ?2",
+            params![message_id, chat_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn rebase_message_adjustment(
+        &self,
+        message_id: i64,
+        chat_id: i64,
+        computed_balance: Money,
+    ) -> Result<()> {
+        DatabaseOperations::rebase_message_adjustment_sync(
+            self.conn,
+            message_id,
+            chat_id,
+            computed_balance,
+        )
+    }
+
+    /// 覆盖一条消息记录的余额快照
+    pub fn update_message_balances(
+        &self,
+        record_id: i64,
+        original_balance: Money,
+        new_balance: Money,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE messages SET original_balance = ?1, new_balance = ?2 WHERE id = ?3",
+            params![original_balance, new_balance, record_id],
+        )?;
+        Ok(())
+    }
+
+    /// 撤销一条消息：把它的交易标记为作废，并从之后的余额快照和钱包余额中减去它的影响。
+    ///
+    /// 转账消息的两行交易都会作废，转出和转入钱包的余额都会恢复。
+    pub fn void_message(&self, message_id: i64, chat_id: i64) -> Result<UndoneMessage> {
+        let conn = self.conn;
+        let record = DatabaseOperations::get_message_record_sync(conn, message_id, chat_id)?
+            .ok_or_else(|| {
+                anyhow::anyhow!("Message not found: {} in chat {}", message_id, chat_id)
+            })?;
+        if record.is_voided() {
+            return Err(anyhow::anyhow!(
+                "Message {} in chat {} is already voided",
+                message_id,
+                chat_id
+            ));
+        }
+        let record_id = record.id.unwrap();
+        let transactions =
+            DatabaseOperations::get_message_transactions_sync(conn, message_id, chat_id)?;
+
+        // 消息所属钱包的影响以余额快照为准，这样手动总额也能一并撤销
+        let original_balance = record.original_balance.unwrap_or(Money::ZERO);
+        let own_delta: Money = transactions
+            .iter()
+            .filter(|t| t.wallet_id == record.wallet_id)
+            .map(|t| signed_amount(&t.transaction_type, t.amount))
+            .sum();
+        let new_balance = record.new_balance.unwrap_or(original_balance + own_delta);
+        let mut removals = vec![(record.wallet_id, new_balance - original_balance)];
+        for transaction in &transactions {
+            if transaction.wallet_id == record.wallet_id {
+                continue;
+            }
+            let delta = signed_amount(&transaction.transaction_type, transaction.amount);
+            match removals
+                .iter_mut()
+                .find(|(id, _)| *id == transaction.wallet_id)
+            {
+                Some((_, total)) => *total += delta,
+                None => removals.push((transaction.wallet_id, delta)),
+            }
+        }
+
+        let now = Utc::now();
+        conn.execute(
+            "UPDATE transactions SET voided_at = ?1
+             WHERE message_id = ?2 AND chat_id = ?3 AND voided_at IS NULL",
+            params![now, message_id, chat_id],
+        )?;
+        conn.execute(
+            "UPDATE messages SET voided_at = ?1 WHERE id = ?2",
+            params![now, record_id],
+        )?;
+        self.delete_message_adjustments(message_id, chat_id)?;
+
+        let mut balances = Vec::with_capacity(removals.len());
+        for (wallet_id, removed) in removals {
+            let wallet = DatabaseOperations::get_wallet_by_id_sync(conn, wallet_id)?;
+            let mut balance = wallet.current_balance;
+            if DatabaseOperations::shift_later_balances_sync(conn, wallet_id, record_id, -removed)?
+            {
+                DatabaseOperations::add_to_wallet_balance_sync(conn, wallet_id, -removed)?;
+                balance -= removed;
+            }
+            balances.push(UndoneBalance {
+                wallet_name: wallet.name,
+                old_balance: wallet.current_balance,
+                new_balance: balance,
+            });
+        }
+
+        info!(
+            "Voided message {} in chat {} with {} transactions",
+            message_id,
+            chat_id,
+            transactions.len()
+        );
+        Ok(UndoneMessage {
+            message_id,
+            chat_id,
+            transactions,
+            balances,
+        })
+    }
+
+    /// 写回被编辑消息的账目变更，并重新计算之后的余额
+    pub fn apply_message_edit(&self, edit: &MessageEdit) -> Result<()> {
+        let conn = self.conn;
+        let record =
+            DatabaseOperations::get_message_record_sync(conn, edit.message_id, edit.chat_id)?
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Message not found: {} in chat {}",
+                        edit.message_id,
+                        edit.chat_id
+                    )
+                })?;
+        let record_id = record.id.unwrap();
+        let old_original_balance = record.original_balance.unwrap_or(Money::ZERO);
+        let old_new_balance = record.new_balance.unwrap_or(old_original_balance);
+
+        let wallet =
+            DatabaseOperations::get_wallet_by_name_sync(conn, edit.chat_id, &edit.wallet_name)?;
+        let wallet_id = wallet.id.unwrap();
+        let now = Utc::now();
+
+        // 按顺序原地更新这条消息未作废的交易，保留原来的编号和记录时间；
+        // 明细变多时按原消息的时间补记，变少时删除多出的交易。已作废的记录不受影响
+        let existing =
+            DatabaseOperations::get_message_transactions_sync(conn, edit.message_id, edit.chat_id)?;
+        let recorded_at = existing.first().and_then(|t| t.created_at).unwrap_or(now);
+        for (index, item) in edit.items.iter().enumerate() {
+            match existing.get(index) {
+                Some(transaction) => conn.execute(
+                    "UPDATE transactions
+                     SET wallet_id = ?1, transaction_type = ?2, amount = ?3, month = ?4, year = ?5, note = ?6, category = ?7
+                     WHERE id = ?8",
+                    params![wallet_id, item.transaction_type, item.amount, edit.month, edit.year, item.note, item.category, transaction.id],
+                )?,
+                None => conn.execute(
+                    "INSERT INTO transactions (wallet_id, transaction_type, amount, month, year, message_id, chat_id, created_at, note, category)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                    params![wallet_id, item.transaction_type, item.amount, edit.month, edit.year, Some(edit.message_id), Some(edit.chat_id), recorded_at, item.note, item.category],
+                )?,
+            };
+        }
+        for transaction in existing.iter().skip(edit.items.len()) {
+            conn.execute(
+                "DELETE FROM transactions WHERE id = ?1",
+                params![transaction.id],
+            )?;
+        }
+
+        if wallet_id == edit.old_wallet_id {
+            conn.execute(
+                "UPDATE messages SET has_total = ?1, original_balance = ?2, new_balance = ?3 WHERE id = ?4",
+                params![edit.has_total, edit.original_balance, edit.new_balance, record_id],
+            )?;
+
+            let delta = edit.new_balance - old_new_balance;
+            if DatabaseOperations::shift_later_balances_sync(conn, wallet_id, record_id, delta)? {
+                DatabaseOperations::add_to_wallet_balance_sync(conn, wallet_id, delta)?;
+            }
+        } else {
+            // 从原钱包中移除这条消息的影响
+            let removed = old_new_balance - old_original_balance;
+            if DatabaseOperations::shift_later_balances_sync(
+                conn,
+                edit.old_wallet_id,
+                record_id,
+                -removed,
+            )? {
+                DatabaseOperations::add_to_wallet_balance_sync(conn, edit.old_wallet_id, -removed)?;
+            }
+
+            // 作为最新的一条消息追加到目标钱包
+            conn.execute("DELETE FROM messages WHERE id = ?1", params![record_id])?;
+            conn.execute(
+                "INSERT INTO messages (message_id, chat_id, wallet_id, has_total, processed, original_balance, new_balance, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![edit.message_id, edit.chat_id, wallet_id, edit.has_total, true, edit.original_balance, edit.new_balance, now],
+            )?;
+            conn.execute(
+                "UPDATE wallets SET current_balance = ?1, updated_at = ?2 WHERE id = ?3",
+                params![edit.new_balance, now, wallet_id],
+            )?;
+        }
+
+        info!(
+            "Applied edit of message {} in chat {}: {} {} -> {}",
+            edit.message_id, edit.chat_id, edit.wallet_name, old_new_balance, edit.new_balance
+        );
+        Ok(())
+    }
+}
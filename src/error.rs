@@ -28,6 +28,9 @@ pub enum WalletBotError {
     #[allow(dead_code)]
     InvalidMessageFormat { message: String },
 
+    #[error("Database schema version {found} is newer than supported version {supported}")]
+    SchemaTooNew { found: i64, supported: i64 },
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -65,6 +68,10 @@ impl WalletBotError {
         }
     }
 
+    pub fn schema_too_new(found: i64, supported: i64) -> Self {
+        Self::SchemaTooNew { found, supported }
+    }
+
     /// 检查错误是否为可重试的类型
     #[allow(dead_code)]
     pub fn is_retryable(&self) -> bool {
@@ -85,6 +92,7 @@ impl WalletBotError {
             WalletBotError::BalanceCalculation { .. } => ErrorSeverity::High,
             WalletBotError::WalletNotFound { .. } => ErrorSeverity::Medium,
             WalletBotError::InvalidMessageFormat { .. } => ErrorSeverity::Low,
+            WalletBotError::SchemaTooNew { .. } => ErrorSeverity::Critical,
             WalletBotError::Io(_) => ErrorSeverity::Medium,
             WalletBotError::Env(_) => ErrorSeverity::Critical,
         }
@@ -0,0 +1,306 @@
+use crate::calculator::report::{ReportGenerator, ReportPeriod};
+use crate::database::models::TransactionFilter;
+use crate::database::operations::DatabaseOperations;
+use crate::money::Money;
+use crate::utils::{Formatter, PeriodArg};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use log::info;
+
+/// 交易记录 CSV 的表头
+pub const TRANSACTION_COLUMNS: [&str; 11] = [
+    "id",
+    "created_at",
+    "wallet",
+    "type",
+    "amount",
+    "category",
+    "note",
+    "month",
+    "year",
+    "message_id",
+    "message_link",
+];
+
+/// 钱包余额 CSV 的表头
+pub const BALANCE_COLUMNS: [&str; 6] = [
+    "wallet",
+    "period",
+    "opening_balance",
+    "closing_balance",
+    "transaction_count",
+    "updated_at",
+];
+
+/// 导出的内容
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportKind {
+    Transactions,
+    Balances,
+}
+
+impl ExportKind {
+    fn file_prefix(&self) -> &'static str {
+        match self {
+            ExportKind::Transactions => "transactions",
+            ExportKind::Balances => "balances",
+        }
+    }
+}
+
+/// 导出的筛选条件：钱包和时间范围，未设置时导出全部
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ExportQuery {
+    pub wallet_name: Option<String>,
+    pub period: Option<ReportPeriod>,
+}
+
+impl ExportQuery {
+    /// 从 "[钱包名] [月份] [年份]" 形式的参数解析筛选条件，只给出月份时使用今年
+    pub fn parse_args<'a, I>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut wallet_name = None;
+        let mut month = None;
+        let mut year = None;
+        for arg in args {
+            match PeriodArg::parse(arg) {
+                Some(PeriodArg::Month(m)) => month = Some(m),
+                Some(PeriodArg::Year(y)) => year = Some(y),
+                None if wallet_name.is_none() => {
+                    wallet_name = Some(arg.trim_start_matches('#').to_string())
+                }
+                None => return Err(anyhow!("Unexpected argument: {arg}")),
+            }
+        }
+
+        let period = match (year, month) {
+            (Some(year), month) => Some(ReportPeriod { year, month }),
+            (None, Some(month)) => Some(ReportPeriod::month(
+                ReportPeriod::current_month().year,
+                month,
+            )),
+            (None, None) => None,
+        };
+        Ok(Self {
+            wallet_name,
+            period,
+        })
+    }
+
+    pub fn filter(&self) -> TransactionFilter {
+        TransactionFilter {
+            wallet_name: self.wallet_name.clone(),
+            month: self.period.and_then(|p| p.month),
+            year: self.period.map(|p| p.year),
+            ..Default::default()
+        }
+    }
+
+    /// 导出文件名，例如 transactions_支付宝_2024-12.csv
+    pub fn file_name(&self, kind: ExportKind) -> String {
+        let mut parts = vec![kind.file_prefix().to_string()];
+        if let Some(name) = &self.wallet_name {
+            parts.push(name.replace(['/', '\\'], "_"));
+        }
+        match self.period {
+            Some(ReportPeriod {
+                year,
+                month: Some(month),
+            }) => parts.push(format!("{year}-{month:02}")),
+            Some(ReportPeriod { year, month: None }) => parts.push(year.to_string()),
+            None => {}
+        }
+        format!("{}.csv", parts.join("_"))
+    }
+
+    fn period_label(&self) -> String {
+        self.period
+            .map(|p| p.label())
+            .unwrap_or_else(|| "全部".to_string())
+    }
+}
+
+/// 一个导出的 CSV 文件
+#[derive(Debug, Clone)]
+pub struct CsvFile {
+    pub file_name: String,
+    pub content: String,
+    pub rows: usize, // 不含表头
+}
+
+/// 把交易记录和钱包余额导出为 CSV
+#[derive(Clone, Debug)]
+pub struct CsvExporter {
+    db: DatabaseOperations,
+    generator: ReportGenerator,
+}
+
+impl CsvExporter {
+    pub fn new(db: DatabaseOperations) -> Self {
+        Self {
+            generator: ReportGenerator::new(db.clone()),
+            db,
+        }
+    }
+
+    pub async fn export(
+        &self,
+        chat_id: i64,
+        query: &ExportQuery,
+        kind: ExportKind,
+    ) -> Result<CsvFile> {
+        match kind {
+            ExportKind::Transactions => self.transactions(chat_id, query).await,
+            ExportKind::Balances => self.balances(chat_id, query).await,
+        }
+    }
+
+    /// 符合条件的全部交易，按时间先后排列
+    pub async fn transactions(&self, chat_id: i64, query: &ExportQuery) -> Result<CsvFile> {
+        let transactions = self
+            .db
+            .get_wallet_transactions(chat_id, &query.filter())
+            .await?;
+        info!(
+            "📤 Exporting {} transactions of chat {chat_id}",
+            transactions.len()
+        );
+
+        let mut content = csv_row(TRANSACTION_COLUMNS);
+        for row in &transactions {
+            let t = &row.transaction;
+            let message_id = t.message_id.map(|id| id.to_string()).unwrap_or_default();
+            let link = t
+                .message_id
+                .and_then(|id| Formatter::message_link(chat_id, id))
+                .unwrap_or_default();
+            content.push_str(&csv_row([
+                t.id.map(|id| id.to_string()).unwrap_or_default().as_str(),
+                format_time(t.created_at).as_str(),
+                row.wallet_name.as_str(),
+                t.transaction_type.as_str(),
+                t.amount.to_string().as_str(),
+                t.category.as_deref().unwrap_or_default(),
+                t.note.as_deref().unwrap_or_default(),
+                t.month.as_str(),
+                t.year.as_str(),
+                message_id.as_str(),
+                link.as_str(),
+            ]));
+        }
+
+        Ok(CsvFile {
+            file_name: query.file_name(ExportKind::Transactions),
+            content,
+            rows: transactions.len(),
+        })
+    }
+
+    /// 每个钱包的余额。
+    ///
+    /// 指定时间范围时是该范围的期初、期末余额和交易笔数，否则是从零开始到当前的余额
+    pub async fn balances(&self, chat_id: i64, query: &ExportQuery) -> Result<CsvFile> {
+        let mut summaries = self.db.get_wallet_summaries(chat_id).await?;
+        if let Some(name) = &query.wallet_name {
+            summaries.retain(|s| &s.name == name);
+            if summaries.is_empty() {
+                return Err(anyhow!("Wallet not found: {name}"));
+            }
+        }
+
+        let period = query.period_label();
+        let mut content = csv_row(BALANCE_COLUMNS);
+        for summary in &summaries {
+            let (opening, closing, count) = match query.period {
+                Some(p) => {
+                    let report = self
+                        .generator
+                        .wallet_report(chat_id, &summary.name, p)
+                        .await?;
+                    (
+                        report.opening_balance,
+                        report.closing_balance,
+                        report.transaction_count,
+                    )
+                }
+                None => (Money::ZERO, summary.balance, summary.transaction_count),
+            };
+            content.push_str(&csv_row([
+                summary.name.as_str(),
+                period.as_str(),
+                opening.to_string().as_str(),
+                closing.to_string().as_str(),
+                count.to_string().as_str(),
+                format_time(summary.updated_at).as_str(),
+            ]));
+        }
+
+        Ok(CsvFile {
+            file_name: query.file_name(ExportKind::Balances),
+            content,
+            rows: summaries.len(),
+        })
+    }
+}
+
+/// 时间统一使用 UTC，便于表格软件识别
+fn format_time(time: Option<DateTime<Utc>>) -> String {
+    time.map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+/// 按 RFC 4180 拼接一行 CSV，包含逗号、引号或换行的字段加引号
+pub fn csv_row<'a, I>(fields: I) -> String
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let fields: Vec<String> = fields
+        .into_iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect();
+    format!("{}\r\n", fields.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_row_escaping() {
+        assert_eq!(csv_row(["a", "b c", ""]), "a,b c,\r\n");
+        assert_eq!(
+            csv_row(["午饭, 晚饭", "说 \"好\"", "两\n行"]),
+            "\"午饭, 晚饭\",\"说 \"\"好\"\"\",\"两\n行\"\r\n"
+        );
+    }
+
+    #[test]
+    fn test_export_query() {
+        let query = ExportQuery::parse_args(["#支付宝", "12月", "2024年"]).unwrap();
+        assert_eq!(query.wallet_name.as_deref(), Some("支付宝"));
+        assert_eq!(query.period, Some(ReportPeriod::month(2024, 12)));
+        assert_eq!(
+            query.file_name(ExportKind::Transactions),
+            "transactions_支付宝_2024-12.csv"
+        );
+        assert_eq!(query.filter().year, Some(2024));
+
+        let query = ExportQuery::parse_args(["2024"]).unwrap();
+        assert_eq!(query.period, Some(ReportPeriod::year(2024)));
+        assert_eq!(query.file_name(ExportKind::Balances), "balances_2024.csv");
+
+        let query = ExportQuery::parse_args([]).unwrap();
+        assert_eq!(query, ExportQuery::default());
+        assert_eq!(query.filter(), TransactionFilter::default());
+
+        assert!(ExportQuery::parse_args(["支付宝", "微信"]).is_err());
+    }
+}
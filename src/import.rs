@@ -0,0 +1,544 @@
+use crate::calculator::balance::{BalanceCalculator, LedgerReplay};
+use crate::database::models::{signed_amount, ImportedTransaction, WalletReconciliation};
+use crate::database::operations::DatabaseOperations;
+use crate::money::Money;
+use crate::utils::Validator;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use log::info;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+/// 可以导入的交易类型，转账需要两侧交易互相链接，不支持导入
+pub const IMPORT_TRANSACTION_TYPES: [&str; 4] = ["出账", "入账", "支出", "收入"];
+
+/// 导入文件的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Csv,
+    Json,
+}
+
+impl ImportFormat {
+    /// 按文件扩展名判断格式，没有扩展名时按内容判断
+    pub fn detect(file_name: Option<&str>, content: &str) -> Self {
+        let extension = file_name
+            .and_then(|name| name.rsplit_once('.'))
+            .map(|(_, ext)| ext.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("json") => ImportFormat::Json,
+            Some("csv") => ImportFormat::Csv,
+            _ if content
+                .trim_start_matches('\u{feff}')
+                .trim_start()
+                .starts_with('[') =>
+            {
+                ImportFormat::Json
+            }
+            _ => ImportFormat::Csv,
+        }
+    }
+}
+
+/// 文件中的一条原始记录，字段名已统一为英文列名
+#[derive(Debug, Clone)]
+struct Record {
+    line: usize, // CSV 中的行号，JSON 中的序号，从 1 开始
+    fields: HashMap<String, String>,
+}
+
+impl Record {
+    fn get(&self, column: &str) -> Option<&str> {
+        self.fields
+            .get(column)
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+    }
+
+    fn require(&self, column: &str) -> std::result::Result<&str, String> {
+        self.get(column).ok_or_else(|| format!("缺少 {column}"))
+    }
+}
+
+/// 校验失败的一行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// 导入涉及的一个钱包
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalletImport {
+    pub name: String,
+    pub created: bool, // 导入前不存在
+    pub transactions: usize,
+    pub delta: Money,
+    pub balance: Option<Money>, // 导入后的余额，预览时为 None
+    pub drift: Money,           // 导入前余额与账本之间已有的偏差，导入不会修正
+    pub stale_snapshots: usize, // 导入前余额快照与账本不一致的消息数
+}
+
+impl WalletImport {
+    /// 导入前钱包是否已经与账本不一致
+    pub fn has_drift(&self) -> bool {
+        !self.drift.is_zero() || self.stale_snapshots > 0
+    }
+}
+
+/// 一次导入（或预览）的结果
+#[derive(Debug, Clone, Default)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub rows: usize,
+    pub imported: usize,
+    pub duplicates: usize, // 去重键已经导入过的行
+    pub errors: Vec<RowError>,
+    pub wallets: Vec<WalletImport>,
+}
+
+impl ImportReport {
+    /// 有任何一行校验失败时整个文件都不导入
+    pub fn is_rejected(&self) -> bool {
+        !self.errors.is_empty()
+    }
+}
+
+/// 把表格或 JSON 中的历史交易导入到一个聊天
+#[derive(Clone, Debug)]
+pub struct Importer {
+    db: DatabaseOperations,
+}
+
+impl Importer {
+    pub fn new(db: DatabaseOperations) -> Self {
+        Self { db }
+    }
+
+    /// 校验并导入全部记录。
+    ///
+    /// 任何一行校验失败时不写入任何数据；dry_run 时只预览结果。
+    /// 每行都有去重键，重复导入同一个文件不会产生新的交易。
+    pub async fn import(
+        &self,
+        chat_id: i64,
+        content: &str,
+        format: ImportFormat,
+        dry_run: bool,
+    ) -> Result<ImportReport> {
+        let records = parse_records(content, format)?;
+        let mut report = ImportReport {
+            dry_run,
+            rows: records.len(),
+            ..Default::default()
+        };
+
+        let mut rows = Vec::with_capacity(records.len());
+        let mut occurrences: HashMap<String, usize> = HashMap::new();
+        for record in &records {
+            match validate(record, &mut occurrences) {
+                Ok(row) => rows.push(row),
+                Err(message) => report.errors.push(RowError {
+                    line: record.line,
+                    message,
+                }),
+            }
+        }
+        if report.is_rejected() {
+            return Ok(report);
+        }
+
+        info!(
+            "📥 Importing {} rows into chat {chat_id} (dry run: {dry_run})",
+            rows.len()
+        );
+        self.db
+            .unit_of_work(|uow| {
+                // 导入前先按账本重放已有的钱包，只应用导入带来的变化，已有的偏差原样报告
+                let mut existing: HashMap<String, (WalletReconciliation, LedgerReplay)> =
+                    HashMap::new();
+                for wallet in uow.get_wallets(chat_id)? {
+                    if !rows.iter().any(|row| row.wallet_name == wallet.name) {
+                        continue;
+                    }
+                    let wallet_id = wallet
+                        .id
+                        .ok_or_else(|| anyhow!("Wallet {} has no id", wallet.name))?;
+                    let reconciliation = BalanceCalculator::reconcile(uow, &wallet, false)?;
+                    let replay = BalanceCalculator::replay_ledger(uow, wallet_id)?;
+                    existing.insert(wallet.name, (reconciliation, replay));
+                }
+                let mut seen = HashSet::new();
+
+                for row in &rows {
+                    if !seen.insert(row.import_key.as_str())
+                        || uow.is_imported(chat_id, &row.import_key)?
+                    {
+                        report.duplicates += 1;
+                        continue;
+                    }
+                    if !dry_run {
+                        let wallet = uow.get_or_create_wallet(chat_id, &row.wallet_name)?;
+                        let wallet_id = wallet
+                            .id
+                            .ok_or_else(|| anyhow!("Wallet {} has no id", wallet.name))?;
+                        uow.record_imported_transaction(chat_id, wallet_id, row)?;
+                    }
+
+                    report.imported += 1;
+                    let index = match report
+                        .wallets
+                        .iter()
+                        .position(|w| w.name == row.wallet_name)
+                    {
+                        Some(index) => index,
+                        None => {
+                            let reconciliation = existing.get(&row.wallet_name).map(|(r, _)| r);
+                            report.wallets.push(WalletImport {
+                                name: row.wallet_name.clone(),
+                                created: reconciliation.is_none(),
+                                transactions: 0,
+                                delta: Money::ZERO,
+                                balance: None,
+                                drift: reconciliation.map_or(Money::ZERO, |r| r.drift()),
+                                stale_snapshots: reconciliation.map_or(0, |r| r.stale_snapshots),
+                            });
+                            report.wallets.len() - 1
+                        }
+                    };
+                    let wallet = &mut report.wallets[index];
+                    wallet.transactions += 1;
+                    wallet.delta += signed_amount(&row.transaction_type, row.amount);
+                }
+
+                // 历史交易可能早于已有的消息，之后的余额快照和钱包余额随之变化
+                if !dry_run {
+                    for imported in &mut report.wallets {
+                        let wallet = uow.get_wallet_by_name(chat_id, &imported.name)?;
+                        let before = existing
+                            .get(&imported.name)
+                            .map(|(_, replay)| replay.clone())
+                            .unwrap_or_default();
+                        imported.balance = Some(BalanceCalculator::apply_ledger_change(
+                            uow, &wallet, &before,
+                        )?);
+                    }
+                }
+                Ok(())
+            })
+            .await?;
+
+        Ok(report)
+    }
+}
+
+/// 统一列名，支持导出文件的英文列名和常用的中文列名
+fn column_name(header: &str) -> String {
+    let header = header.trim().trim_start_matches('\u{feff}');
+    match header {
+        "钱包" | "钱包名" => "wallet",
+        "类型" => "type",
+        "金额" => "amount",
+        "月份" => "month",
+        "年份" => "year",
+        "时间" | "日期" => "created_at",
+        "分类" => "category",
+        "备注" => "note",
+        "去重键" => "key",
+        other => return other.to_ascii_lowercase(),
+    }
+    .to_string()
+}
+
+fn parse_records(content: &str, format: ImportFormat) -> Result<Vec<Record>> {
+    match format {
+        ImportFormat::Csv => parse_csv_records(content),
+        ImportFormat::Json => parse_json_records(content),
+    }
+}
+
+fn parse_csv_records(content: &str) -> Result<Vec<Record>> {
+    let mut rows = read_csv(content.trim_start_matches('\u{feff}'))?.into_iter();
+    let Some((_, header)) = rows.next() else {
+        return Ok(Vec::new());
+    };
+    let columns: Vec<String> = header.iter().map(|h| column_name(h)).collect();
+
+    Ok(rows
+        .filter(|(_, fields)| fields.iter().any(|f| !f.trim().is_empty()))
+        .map(|(line, fields)| Record {
+            line,
+            fields: columns.iter().cloned().zip(fields).collect(),
+        })
+        .collect())
+}
+
+fn parse_json_records(content: &str) -> Result<Vec<Record>> {
+    let value: Value = serde_json::from_str(content.trim_start_matches('\u{feff}'))?;
+    let Value::Array(items) = value else {
+        return Err(anyhow!("JSON import must be an array of objects"));
+    };
+
+    items
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let Value::Object(object) = item else {
+                return Err(anyhow!("JSON item {} is not an object", index + 1));
+            };
+            let fields = object
+                .into_iter()
+                .map(|(key, value)| {
+                    let value = match value {
+                        Value::String(s) => s,
+                        Value::Null => String::new(),
+                        other => other.to_string(),
+                    };
+                    (column_name(&key), value)
+                })
+                .collect();
+            Ok(Record {
+                line: index + 1,
+                fields,
+            })
+        })
+        .collect()
+}
+
+/// 按 RFC 4180 读取 CSV，返回每条记录的起始行号和字段
+fn read_csv(content: &str) -> Result<Vec<(usize, Vec<String>)>> {
+    let mut rows = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut row_start = 1;
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    in_quotes = false;
+                }
+            }
+            '"' if field.is_empty() => in_quotes = true,
+            '\n' if in_quotes => {
+                line += 1;
+                field.push(c);
+            }
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+            '\r' if !in_quotes && chars.peek() == Some(&'\n') => {}
+            '\n' if !in_quotes => {
+                fields.push(std::mem::take(&mut field));
+                rows.push((row_start, std::mem::take(&mut fields)));
+                line += 1;
+                row_start = line;
+            }
+            c => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(anyhow!(
+            "Unterminated quoted field starting on line {row_start}"
+        ));
+    }
+    if !field.is_empty() || !fields.is_empty() {
+        fields.push(field);
+        rows.push((row_start, fields));
+    }
+    Ok(rows)
+}
+
+/// 校验一条记录并生成去重键。
+///
+/// 记录中有 key 列时直接使用，否则由各字段拼成，
+/// 同一文件中完全相同的记录按出现次数区分。
+fn validate(
+    record: &Record,
+    occurrences: &mut HashMap<String, usize>,
+) -> std::result::Result<ImportedTransaction, String> {
+    let wallet_name = record
+        .require("wallet")?
+        .trim_start_matches('#')
+        .to_string();
+    if !Validator::is_valid_wallet_name(&wallet_name) {
+        return Err(format!("钱包名无效：{wallet_name}"));
+    }
+
+    let transaction_type = record.require("type")?.trim_start_matches('#').to_string();
+    if !IMPORT_TRANSACTION_TYPES.contains(&transaction_type.as_str()) {
+        return Err(format!("不支持的交易类型：{transaction_type}"));
+    }
+
+    let raw_amount = record.require("amount")?;
+    let amount_text = raw_amount.trim_end_matches('元').trim();
+    let amount = amount_text
+        .parse::<f64>()
+        .ok()
+        .filter(|amount| Validator::is_valid_amount(*amount))
+        .and_then(|_| amount_text.parse::<Money>().ok())
+        .ok_or_else(|| format!("金额无效：{raw_amount}"))?;
+
+    let raw_month = record.require("month")?;
+    let month = raw_month.trim_start_matches('#').trim_end_matches('月');
+    if !Validator::is_valid_month(month) {
+        return Err(format!("月份无效：{raw_month}"));
+    }
+
+    let raw_year = record.require("year")?;
+    let year = raw_year.trim_start_matches('#').trim_end_matches('年');
+    if !Validator::is_valid_year(year) {
+        return Err(format!("年份无效：{raw_year}"));
+    }
+
+    let created_at = match record.get("created_at") {
+        Some(raw) => parse_time(raw).ok_or_else(|| format!("时间无效：{raw}"))?,
+        // 没有时间时记在当月第一天，账本按时间排序时仍然落在正确的月份
+        None => first_day_of_month(year, month).ok_or_else(|| "月份无效".to_string())?,
+    };
+
+    let note = record.get("note").map(str::to_string);
+    let category = record
+        .get("category")
+        .map(|c| c.trim_start_matches('#').to_string())
+        .filter(|c| !c.is_empty());
+
+    let import_key = match record.get("key") {
+        Some(key) => format!("key:{key}"),
+        None => {
+            let base = format!(
+                "{wallet_name}|{transaction_type}|{amount}|{month}|{year}|{}|{}|{}",
+                record.get("created_at").unwrap_or_default(),
+                category.as_deref().unwrap_or_default(),
+                note.as_deref().unwrap_or_default(),
+            );
+            let count = occurrences.entry(base.clone()).or_default();
+            *count += 1;
+            format!("{base}#{count}")
+        }
+    };
+
+    Ok(ImportedTransaction {
+        wallet_name,
+        transaction_type,
+        amount,
+        month: format!("{month}月"),
+        year: format!("{year}年"),
+        created_at,
+        note,
+        category,
+        import_key,
+    })
+}
+
+/// 支持导出文件中的 "2024-12-01 10:00:00"、RFC 3339 和只有日期的格式，时间按 UTC 处理
+fn parse_time(raw: &str) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(raw) {
+        return Some(time.with_timezone(&Utc));
+    }
+    NaiveDateTime::parse_from_str(raw, "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(raw, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .map(|time| Utc.from_utc_datetime(&time))
+}
+
+fn first_day_of_month(year: &str, month: &str) -> Option<DateTime<Utc>> {
+    let date = NaiveDate::from_ymd_opt(year.parse().ok()?, month.parse().ok()?, 1)?;
+    Some(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_csv() {
+        let rows = read_csv("a,b\r\n\"x, y\",\"说 \"\"好\"\"\"\n\"两\n行\",z").unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (1, vec!["a".to_string(), "b".to_string()]),
+                (2, vec!["x, y".to_string(), "说 \"好\"".to_string()]),
+                (3, vec!["两\n行".to_string(), "z".to_string()]),
+            ]
+        );
+        assert!(read_csv("a,\"b").is_err());
+    }
+
+    #[test]
+    fn test_validate_record() {
+        let record = |pairs: &[(&str, &str)]| Record {
+            line: 2,
+            fields: pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        };
+        let mut occurrences = HashMap::new();
+        let row = validate(
+            &record(&[
+                ("wallet", "#支付宝"),
+                ("type", "出账"),
+                ("amount", "30.5元"),
+                ("month", "12月"),
+                ("year", "2024"),
+                ("category", "#餐饮"),
+            ]),
+            &mut occurrences,
+        )
+        .unwrap();
+        assert_eq!(row.wallet_name, "支付宝");
+        assert_eq!(row.amount, Money::from_cents(3050));
+        assert_eq!((row.month.as_str(), row.year.as_str()), ("12月", "2024年"));
+        assert_eq!(row.created_at, parse_time("2024-12-01").unwrap());
+        assert_eq!(row.category.as_deref(), Some("餐饮"));
+        assert!(row.import_key.ends_with("#1"));
+
+        let invalid = [
+            ("amount", "-5", "金额无效：-5"),
+            ("amount", "1.234", "金额无效：1.234"),
+            ("month", "13", "月份无效：13"),
+            ("year", "1999", "年份无效：1999"),
+            ("type", "转出", "不支持的交易类型：转出"),
+            ("created_at", "昨天", "时间无效：昨天"),
+        ];
+        for (column, value, message) in invalid {
+            let mut fields = vec![
+                ("wallet", "支付宝"),
+                ("type", "出账"),
+                ("amount", "1"),
+                ("month", "1"),
+                ("year", "2024"),
+            ];
+            fields.retain(|(k, _)| *k != column);
+            fields.push((column, value));
+            assert_eq!(
+                validate(&record(&fields), &mut occurrences),
+                Err(message.to_string())
+            );
+        }
+        assert_eq!(
+            validate(&record(&[("wallet", "支付宝")]), &mut occurrences),
+            Err("缺少 type".to_string())
+        );
+    }
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(ImportFormat::detect(Some("a.JSON"), ""), ImportFormat::Json);
+        assert_eq!(ImportFormat::detect(Some("a.csv"), "[]"), ImportFormat::Csv);
+        assert_eq!(
+            ImportFormat::detect(None, "\u{feff} [{}]"),
+            ImportFormat::Json
+        );
+        assert_eq!(ImportFormat::detect(None, "wallet,type"), ImportFormat::Csv);
+    }
+}
@@ -1,10 +1,15 @@
 // 公开内部模块以便测试
 pub mod bot;
 pub mod calculator;
+pub mod cli;
 pub mod config;
 pub mod database;
 pub mod error;
+pub mod export;
+pub mod import;
+pub mod money;
 pub mod parser;
+pub mod replay;
 pub mod retry;
 pub mod utils;
 
@@ -14,4 +19,5 @@ pub use calculator::balance::BalanceCalculator;
 pub use config::Settings;
 pub use database::{models, DatabaseOperations};
 pub use error::WalletBotError;
+pub use money::Money;
 pub use parser::message::MessageParser;
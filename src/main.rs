@@ -1,17 +1,24 @@
 mod bot;
 mod calculator;
+mod cli;
 mod config;
 mod database;
 mod error;
+mod export;
+mod import;
+mod money;
 mod parser;
+mod replay;
 mod retry;
 mod utils;
 
 use anyhow::Result;
 use dotenv::dotenv;
-use log::info;
+use log::{info, warn};
+use std::time::Duration;
 
 use bot::{start_bot, MessageHandler};
+use cli::CliCommand;
 use config::Settings;
 use database::DatabaseOperations;
 use utils::Logger;
@@ -24,6 +31,26 @@ async fn main() -> Result<()> {
     // 初始化日志
     env_logger::init();
 
+    // 解析命令行子命令，除启动机器人外都只需要数据库
+    let command = match CliCommand::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{e}\n\n{}", cli::USAGE);
+            return Err(e);
+        }
+    };
+    match command {
+        CliCommand::Run => {}
+        CliCommand::Help => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+        command => {
+            let db = DatabaseOperations::new(&Settings::database_url_from_env()).await?;
+            return cli::execute(command, db).await;
+        }
+    }
+
     Logger::log_operation_start("WalletBot", "Initializing application");
 
     // 加载配置
@@ -57,7 +84,9 @@ async fn main() -> Result<()> {
     };
 
     // 初始化消息处理器
-    let message_handler = MessageHandler::new(db);
+    let message_handler = MessageHandler::new(db)
+        .with_admins(settings.admin_user_ids.clone())
+        .with_confirmation_timeout(Duration::from_secs(settings.confirmation_timeout));
     Logger::log_operation_success("MessageHandler", "Handler initialized successfully");
 
     info!("🤖 WalletBot initialized successfully!");
@@ -66,10 +95,17 @@ async fn main() -> Result<()> {
     info!("  - Bot Name: {}", settings.bot_name);
     info!("  - Max Retry Attempts: {}", settings.max_retry_attempts);
     info!("  - Processing Timeout: {}s", settings.processing_timeout);
+    if let Some(channel) = settings.target_channel_id {
+        if !settings.chat_allowlist().contains(&channel) {
+            warn!(
+                "TARGET_CHANNEL_ID {channel} does not restrict which chats can use the bot; add it to ALLOWED_CHAT_IDS if that was intended"
+            );
+        }
+    }
 
     // 启动机器人
     info!("🚀 Starting WalletBot...");
-    match start_bot(&settings.telegram_bot_token, message_handler).await {
+    match start_bot(&settings, message_handler).await {
         Ok(()) => {
             Logger::log_operation_success("WalletBot", "Bot stopped gracefully");
         }
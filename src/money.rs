@@ -0,0 +1,227 @@
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// 精确到分的金额，内部以整数“分”存储，避免浮点累积误差。
+///
+/// 加减运算在溢出时停在 i64 的上下限，不会 panic。
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Money(i64);
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Invalid money amount: {0}")]
+pub struct ParseMoneyError(String);
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    pub const fn from_cents(cents: i64) -> Self {
+        Self(cents)
+    }
+
+    #[allow(dead_code)]
+    pub const fn from_yuan(yuan: i64) -> Self {
+        Self(yuan.saturating_mul(100))
+    }
+
+    /// 从浮点数换算（四舍五入到分），仅用于旧数据和外部输入的边界转换
+    pub fn from_f64(value: f64) -> Option<Self> {
+        let cents = (value * 100.0).round();
+        if !cents.is_finite() || cents.abs() > i64::MAX as f64 {
+            return None;
+        }
+        Some(Self(cents as i64))
+    }
+
+    pub const fn cents(self) -> i64 {
+        self.0
+    }
+
+    pub const fn abs(self) -> Self {
+        Self(self.0.saturating_abs())
+    }
+
+    pub const fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl FromStr for Money {
+    type Err = ParseMoneyError;
+
+    /// 解析形如 `150`、`150.5`、`-0.10` 的金额，最多两位小数
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseMoneyError(s.to_string());
+        let trimmed = s.trim();
+        let (negative, digits) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed),
+        };
+
+        let (integer, fraction) = match digits.split_once('.') {
+            Some((integer, fraction)) => (integer, fraction),
+            None => (digits, ""),
+        };
+        if integer.is_empty()
+            || fraction.len() > 2
+            || !integer.bytes().all(|b| b.is_ascii_digit())
+            || !fraction.bytes().all(|b| b.is_ascii_digit())
+            || (digits.contains('.') && fraction.is_empty())
+        {
+            return Err(err());
+        }
+
+        let yuan: i64 = integer.parse().map_err(|_| err())?;
+        let cents: i64 = match fraction.len() {
+            0 => 0,
+            1 => fraction.parse::<i64>().map_err(|_| err())? * 10,
+            _ => fraction.parse().map_err(|_| err())?,
+        };
+        let total = yuan
+            .checked_mul(100)
+            .and_then(|v| v.checked_add(cents))
+            .ok_or_else(err)?;
+
+        Ok(Self(if negative { -total } else { total }))
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        write!(f, "{sign}{}.{:02}", abs / 100, abs % 100)
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, rhs: Money) -> Money {
+        Money(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, rhs: Money) -> Money {
+        Money(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Money(self.0.saturating_neg())
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, rhs: Money) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign for Money {
+    fn sub_assign(&mut self, rhs: Money) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        iter.fold(Money::ZERO, Add::add)
+    }
+}
+
+impl<'a> Sum<&'a Money> for Money {
+    fn sum<I: Iterator<Item = &'a Money>>(iter: I) -> Money {
+        iter.copied().sum()
+    }
+}
+
+impl ToSql for Money {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.0))
+    }
+}
+
+impl FromSql for Money {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Integer(cents) => Ok(Money(cents)),
+            // 兼容尚未迁移的 REAL 数据（单位为元）
+            ValueRef::Real(yuan) => Money::from_f64(yuan).ok_or(FromSqlError::InvalidType),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_money() {
+        assert_eq!("150".parse::<Money>().unwrap(), Money::from_cents(15000));
+        assert_eq!("150.5".parse::<Money>().unwrap(), Money::from_cents(15050));
+        assert_eq!("0.10".parse::<Money>().unwrap(), Money::from_cents(10));
+        assert_eq!("-20.05".parse::<Money>().unwrap(), Money::from_cents(-2005));
+
+        assert!("".parse::<Money>().is_err());
+        assert!("abc".parse::<Money>().is_err());
+        assert!("1.234".parse::<Money>().is_err());
+        assert!("1.".parse::<Money>().is_err());
+        assert!(".5".parse::<Money>().is_err());
+    }
+
+    #[test]
+    fn test_display_money() {
+        assert_eq!(Money::from_cents(123456).to_string(), "1234.56");
+        assert_eq!(Money::from_cents(5).to_string(), "0.05");
+        assert_eq!(Money::from_cents(-5).to_string(), "-0.05");
+        assert_eq!(Money::ZERO.to_string(), "0.00");
+    }
+
+    #[test]
+    fn test_money_has_no_rounding_drift() {
+        let dime: Money = "0.10".parse().unwrap();
+        let total: Money = std::iter::repeat_n(dime, 1000).sum();
+        assert_eq!(total, Money::from_yuan(100));
+
+        // 旧的 REAL 值换算时四舍五入到分
+        assert_eq!(
+            Money::from_f64(1234.5699999),
+            Some(Money::from_cents(123457))
+        );
+        assert_eq!(Money::from_f64(f64::NAN), None);
+    }
+
+    #[test]
+    fn test_money_arithmetic_saturates() {
+        let max = Money::from_cents(i64::MAX);
+        let min = Money::from_cents(i64::MIN);
+        assert_eq!(max + Money::from_cents(1), max);
+        assert_eq!(min - Money::from_cents(1), min);
+        assert_eq!(-min, max);
+        assert_eq!(min.abs(), max);
+        assert_eq!([max, max].iter().sum::<Money>(), max);
+
+        let mut balance = max;
+        balance += Money::from_yuan(1);
+        assert_eq!(balance, max);
+        balance -= max;
+        balance -= max;
+        balance -= max;
+        assert_eq!(balance, min);
+    }
+}
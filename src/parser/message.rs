@@ -1,7 +1,11 @@
-use crate::database::models::ParsedMessage;
+use crate::database::models::{LineItem, ParsedMessage, ParsedTransfer};
+use crate::money::Money;
 use crate::parser::regex::RegexPatterns;
 use log::debug;
 
+/// /undo 在原消息中代替 #总额 的标记
+pub const UNDONE_MARK: &str = "#已撤销";
+
 #[derive(Clone, Debug)]
 pub struct MessageParser {
     patterns: &'static RegexPatterns,
@@ -27,19 +31,12 @@ impl MessageParser {
             .to_string();
         debug!("Wallet name: {wallet_name}");
 
-        // 解析交易类型
-        let transaction_type = self
-            .patterns
-            .transaction_regex
-            .captures(text)?
-            .get(1)?
-            .as_str()
-            .to_string();
-        debug!("Transaction type: {transaction_type}");
-
-        // 解析金额 - 需要找到交易金额，而不是总额
-        let amount = self.parse_transaction_amount(text)?;
-        debug!("Transaction amount: {amount}");
+        // 逐行解析交易明细，总额所在的金额不计入；有金额无法解析（例如超过两位小数）时拒绝整条消息
+        let items = self.parse_line_items(text, &wallet_name)?;
+        if items.is_empty() {
+            return None;
+        }
+        debug!("Line items: {items:?}");
 
         // 解析时间
         let time_captures = self.patterns.time_regex.captures(text)?;
@@ -47,16 +44,18 @@ impl MessageParser {
         let year = time_captures.get(2)?.as_str().to_string();
         debug!("Time: {month}月 {year}年");
 
-        // 解析总额（如果存在）
+        // 解析总额（如果存在），总额无法解析时同样拒绝，不能当作没有总额
         let total_amount = self.parse_total_amount(text);
         if let Some(total) = total_amount {
             debug!("Total amount found: {total}");
+        } else if self.has_total(text) {
+            debug!("Invalid total amount");
+            return None;
         }
 
         Some(ParsedMessage {
             wallet_name,
-            transaction_type,
-            amount,
+            items,
             month,
             year,
             total_amount,
@@ -64,38 +63,176 @@ impl MessageParser {
         })
     }
 
-    fn parse_transaction_amount(&self, text: &str) -> Option<f64> {
-        // 找到所有金额，排除总额
-        let mut amounts = Vec::new();
-        for cap in self.patterns.amount_regex.captures_iter(text) {
-            if let Some(amount_match) = cap.get(0) {
-                let amount_str = amount_match.as_str();
-                // 检查这个金额是否是总额
-                if !self.is_total_amount(text, amount_match.start()) {
-                    if let Ok(amount) = amount_str.trim_end_matches("元").parse::<f64>() {
-                        amounts.push(amount);
-                    }
-                }
+    /// 解析转账消息，例如：
+    ///
+    /// ```text
+    /// #转账 #支付宝 → #银行卡 #12月 #2024年
+    /// 500.00元 还信用卡
+    /// ```
+    ///
+    /// 只取第一个金额；除标签和金额以外的文字作为备注。
+    pub fn parse_transfer(&self, text: &str) -> Option<ParsedTransfer> {
+        debug!("Parsing transfer: {text}");
+
+        let captures = self.patterns.transfer_regex.captures(text)?;
+        let from_wallet = captures.get(1)?.as_str().to_string();
+        let to_wallet = captures.get(2)?.as_str().to_string();
+        // 钱包名不能是月份或年份标签，也不能转给自己
+        if [&from_wallet, &to_wallet]
+            .iter()
+            .any(|name| name.ends_with('月') || name.ends_with('年'))
+            || from_wallet == to_wallet
+        {
+            return None;
+        }
+        debug!("Transfer: {from_wallet} -> {to_wallet}");
+
+        // 金额无法解析或为零时拒绝整条消息
+        let amount = self
+            .patterns
+            .amount_regex
+            .captures(text)?
+            .get(1)?
+            .as_str()
+            .parse::<Money>()
+            .ok()
+            .filter(|amount| !amount.is_zero())?;
+
+        let time_captures = self.patterns.time_regex.captures(text)?;
+        let month = time_captures.get(1)?.as_str().to_string();
+        let year = time_captures.get(2)?.as_str().to_string();
+
+        let note = self
+            .patterns
+            .transfer_regex
+            .replace(text, " ")
+            .lines()
+            .filter_map(|line| self.line_note(line))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        Some(ParsedTransfer {
+            from_wallet,
+            to_wallet,
+            amount,
+            month,
+            year,
+            note: (!note.is_empty()).then_some(note),
+            original_text: text.to_string(),
+        })
+    }
+
+    /// 检查消息是否是转账格式
+    pub fn is_transfer_message(&self, text: &str) -> bool {
+        self.patterns.transfer_regex.is_match(text) && self.patterns.amount_regex.is_match(text)
+    }
+
+    /// 逐行解析交易明细。
+    ///
+    /// 每个金额是一笔明细，交易类型取同一行的标签，没有标签时沿用上一行的类型；
+    /// 去掉标签、金额和总额后，该行剩下的文字作为备注。
+    /// 钱包、时间、交易类型以外的标签是分类：明细行自己的分类优先，
+    /// 否则使用没有金额的行（通常是标题行）中的分类。
+    /// 任何一个金额无法解析时返回 None，不会悄悄跳过这笔明细。
+    fn parse_line_items(&self, text: &str, wallet_name: &str) -> Option<Vec<LineItem>> {
+        let mut current_type = self
+            .patterns
+            .transaction_regex
+            .captures(text)
+            .map(|cap| cap[1].to_string());
+        let default_category = text
+            .lines()
+            .filter(|line| !self.patterns.amount_regex.is_match(line))
+            .find_map(|line| self.line_category(line, wallet_name));
+        let mut items = Vec::new();
+
+        for line in text.lines() {
+            if let Some(cap) = self.patterns.transaction_regex.captures(line) {
+                current_type = Some(cap[1].to_string());
+            }
+            let Some(transaction_type) = &current_type else {
+                continue;
+            };
+
+            let amounts = self
+                .patterns
+                .amount_regex
+                .captures_iter(line)
+                .filter_map(|cap| cap.get(0))
+                .filter(|m| !self.is_total_amount(line, m.start()))
+                .map(|m| m.as_str().trim_end_matches("元").parse::<Money>().ok())
+                .collect::<Option<Vec<_>>>()?;
+            if amounts.is_empty() {
+                continue;
+            }
+
+            let note = self.line_note(line);
+            let category = self
+                .line_category(line, wallet_name)
+                .or_else(|| default_category.clone());
+            for amount in amounts {
+                items.push(LineItem {
+                    transaction_type: transaction_type.clone(),
+                    amount,
+                    note: note.clone(),
+                    category: category.clone(),
+                });
             }
         }
 
-        // 返回第一个非总额的金额
-        amounts.into_iter().next()
+        Some(items)
     }
 
-    fn is_total_amount(&self, text: &str, amount_pos: usize) -> bool {
-        // 检查金额前面是否有 #总额
-        let prefix = &text[..amount_pos];
+    /// 一行中的第一个分类标签
+    fn line_category(&self, line: &str, wallet_name: &str) -> Option<String> {
+        let line = self.patterns.total_regex.replace_all(line, " ");
+        self.patterns
+            .hashtag_regex
+            .find_iter(&line)
+            .map(|m| m.as_str().trim_start_matches('#'))
+            .find(|tag| !Self::is_reserved_tag(tag, wallet_name))
+            .map(str::to_string)
+    }
+
+    /// 钱包名、月份、年份、交易类型和转账等有固定含义的标签不是分类
+    fn is_reserved_tag(tag: &str, wallet_name: &str) -> bool {
+        let is_time = |suffix: char| {
+            tag.strip_suffix(suffix)
+                .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+        };
+        tag == wallet_name
+            || is_time('月')
+            || is_time('年')
+            || matches!(tag, "出账" | "入账" | "收入" | "支出" | "总额" | "转账")
+    }
+
+    fn line_note(&self, line: &str) -> Option<String> {
+        let line = self.patterns.total_regex.replace_all(line, " ");
+        let line = self.patterns.amount_regex.replace_all(&line, " ");
+        let line = self.patterns.hashtag_regex.replace_all(&line, " ");
+        let note = line
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .trim_matches(|c: char| {
+                matches!(c, ':' | '：' | ',' | '，' | '-' | '—') || c.is_whitespace()
+            })
+            .to_string();
+        (!note.is_empty()).then_some(note)
+    }
+
+    fn is_total_amount(&self, line: &str, amount_pos: usize) -> bool {
+        // 检查同一行中金额前面是否有 #总额
+        let prefix = &line[..amount_pos];
         prefix.contains("#总额")
     }
 
-    fn parse_total_amount(&self, text: &str) -> Option<f64> {
-        self.patterns
-            .total_regex
-            .captures(text)?
-            .get(1)?
+    fn parse_total_amount(&self, text: &str) -> Option<Money> {
+        let cap = self.patterns.total_regex.captures(text)?;
+        cap.get(1)
+            .or_else(|| cap.get(2))?
             .as_str()
-            .parse::<f64>()
+            .parse::<Money>()
             .ok()
     }
 
@@ -103,9 +240,26 @@ impl MessageParser {
         self.patterns.total_regex.is_match(text)
     }
 
-    #[allow(dead_code)]
-    pub fn extract_total_amount(&self, text: &str) -> Option<f64> {
-        self.parse_total_amount(text)
+    /// 去掉消息中的 #总额，得到用户原始的交易内容
+    pub fn strip_total(&self, text: &str) -> String {
+        text.lines()
+            .filter_map(|line| {
+                if !self.patterns.total_regex.is_match(line) {
+                    return Some(line.to_string());
+                }
+                let rest = self.patterns.total_regex.replace_all(line, "");
+                let rest = rest.trim_end();
+                (!rest.trim().is_empty()).then(|| rest.to_string())
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim_end()
+            .to_string()
+    }
+
+    /// 消息是否带着 /undo 留下的撤销标记
+    pub fn is_undone(&self, text: &str) -> bool {
+        text.lines().any(|line| line.trim() == UNDONE_MARK)
     }
 
     /// 检查消息是否符合钱包操作格式
@@ -114,42 +268,26 @@ impl MessageParser {
             && self.patterns.transaction_regex.is_match(text)
             && self.patterns.amount_regex.is_match(text)
     }
-}
-
-#[derive(Debug, Clone)]
-#[allow(dead_code)]
-pub struct Transaction {
-    pub transaction_type: String,
-    pub amount: f64,
-    pub description: String,
-}
-
-impl MessageParser {
-    #[allow(dead_code)]
-    pub fn parse_transaction(&self, text: &str) -> Result<Transaction, anyhow::Error> {
-        // 简化的交易解析，适用于"收入 100 工作收入"这样的格式
-        let parts: Vec<&str> = text.split_whitespace().collect();
 
-        if parts.len() < 3 {
-            return Err(anyhow::Error::msg("Invalid transaction format"));
-        }
-
-        let transaction_type = parts[0].to_string();
-        let amount = parts[1]
-            .parse::<f64>()
-            .map_err(|_| anyhow::Error::msg("Invalid amount"))?;
-        let description = parts[2..].join(" ");
+    /// 除了没有写钱包以外符合记账格式的消息，例如 "#12月 #2024年\n#出账 30元"
+    pub fn is_walletless_message(&self, text: &str) -> bool {
+        !self.patterns.wallet_regex.is_match(text)
+            && !self.is_transfer_message(text)
+            && self.patterns.time_regex.is_match(text)
+            && self.patterns.transaction_regex.is_match(text)
+            && self.patterns.amount_regex.is_match(text)
+    }
 
-        // 验证交易类型
-        if transaction_type != "收入" && transaction_type != "支出" {
-            return Err(anyhow::Error::msg("Invalid transaction type"));
+    /// 在时间标签前补上钱包标签，没有时间标签时原样返回
+    pub fn with_wallet(&self, text: &str, wallet_name: &str) -> String {
+        match self.patterns.time_regex.find(text) {
+            Some(time) => format!(
+                "{}#{wallet_name} {}",
+                &text[..time.start()],
+                &text[time.start()..]
+            ),
+            None => text.to_string(),
         }
-
-        Ok(Transaction {
-            transaction_type,
-            amount,
-            description,
-        })
     }
 }
 
@@ -8,6 +8,8 @@ pub struct RegexPatterns {
     pub amount_regex: Regex,
     pub time_regex: Regex,
     pub total_regex: Regex,
+    pub hashtag_regex: Regex,
+    pub transfer_regex: Regex,
 }
 
 impl RegexPatterns {
@@ -21,8 +23,15 @@ impl RegexPatterns {
             amount_regex: Regex::new(r"(\d+(?:\.\d+)?)元").unwrap(),
             // 匹配时间 #数字月 #数字年 - 捕获完整的月份和年份
             time_regex: Regex::new(r"#(\d+月)\s+#(\d+年)").unwrap(),
-            // 匹配总额 #总额 数字元
-            total_regex: Regex::new(r"#总额\s+(\d+(?:\.\d+)?)元").unwrap(),
+            // 匹配总额 #总额 数字元，或按聊天货币单位写作 #总额 $数字（余额可能为负）
+            total_regex: Regex::new(
+                r"#总额\s+(?:(?:HK\$|[¥$€£])(-?\d+(?:\.\d+)?)|(-?\d+(?:\.\d+)?)元)",
+            )
+            .unwrap(),
+            // 匹配任意话题标签 #标签
+            hashtag_regex: Regex::new(r"#[^#\s]+").unwrap(),
+            // 匹配转账 #转账 #转出钱包 → #转入钱包（箭头可写作 -> / → / 到，也可省略）
+            transfer_regex: Regex::new(r"#转账\s+#([^#\s]+?)\s*(?:->|→|到)?\s*#([^#\s]+)").unwrap(),
         }
     }
 
@@ -0,0 +1,338 @@
+use crate::calculator::balance::BalanceCalculator;
+use crate::database::operations::DatabaseOperations;
+use crate::parser::message::MessageParser;
+use anyhow::Result;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use log::{debug, info, warn};
+use serde::Deserialize;
+
+/// Telegram Desktop 导出的聊天记录（result.json）
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatExport {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(rename = "type")]
+    pub chat_type: String,
+    pub id: i64,
+    #[serde(default)]
+    pub messages: Vec<ExportedMessage>,
+}
+
+impl ChatExport {
+    pub fn from_json(content: &str) -> Result<Self> {
+        Ok(serde_json::from_str(
+            content.trim_start_matches('\u{feff}'),
+        )?)
+    }
+
+    /// Bot API 使用的聊天 ID：导出文件中频道和超级群组的 ID 不带 -100 前缀，普通群组不带负号
+    pub fn bot_chat_id(&self) -> i64 {
+        match self.chat_type.as_str() {
+            "private_channel" | "public_channel" | "private_supergroup" | "public_supergroup" => {
+                -(1_000_000_000_000 + self.id)
+            }
+            "private_group" => -self.id,
+            _ => self.id,
+        }
+    }
+}
+
+/// 导出文件中的一条消息
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportedMessage {
+    pub id: i64,
+    #[serde(rename = "type")]
+    pub kind: String, // "message" 或 "service"
+    #[serde(default)]
+    pub date: String, // "2024-12-01T10:00:00"，导出电脑的当地时间
+    #[serde(default)]
+    date_unixtime: Option<String>, // "1733047200"，较新的导出才有
+    #[serde(default)]
+    text: ExportedText,
+}
+
+impl ExportedMessage {
+    /// 消息的发送时间。
+    ///
+    /// 优先使用 date_unixtime；旧的导出只有不带时区的 date，按聊天设置的时区（相对 UTC 的小时数）换算。
+    pub fn time(&self, utc_offset: i32) -> Option<DateTime<Utc>> {
+        if let Some(seconds) = self.date_unixtime.as_deref() {
+            return DateTime::from_timestamp(seconds.parse().ok()?, 0);
+        }
+        NaiveDateTime::parse_from_str(&self.date, "%Y-%m-%dT%H:%M:%S")
+            .ok()
+            .map(|time| time.and_utc() - chrono::Duration::hours(utc_offset.into()))
+    }
+
+    /// 消息的纯文本，带格式的片段拼接在一起
+    pub fn text(&self) -> String {
+        match &self.text {
+            ExportedText::Plain(text) => text.clone(),
+            ExportedText::Parts(parts) => parts
+                .iter()
+                .map(|part| match part {
+                    TextPart::Plain(text) => text.as_str(),
+                    TextPart::Entity { text } => text.as_str(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// 消息文本是字符串，或者是纯文本与 {"type": "hashtag", "text": "#支付宝"} 这样的片段组成的数组
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum ExportedText {
+    Plain(String),
+    Parts(Vec<TextPart>),
+}
+
+impl Default for ExportedText {
+    fn default() -> Self {
+        ExportedText::Plain(String::new())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum TextPart {
+    Plain(String),
+    Entity { text: String },
+}
+
+/// 重放失败的一条消息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayFailure {
+    pub message_id: i64,
+    pub reason: String,
+}
+
+/// 重放结果
+#[derive(Debug, Clone, Default)]
+pub struct ReplayReport {
+    pub total: usize,
+    pub parsed: usize,            // 成功记账的消息
+    pub already_processed: usize, // 之前已经处理过的消息，包括被 /undo 撤销的
+    pub undone: usize,            // 导出时已经带着 #已撤销 标记的消息
+    pub ignored: usize,           // 服务消息和不是记账格式的消息
+    pub failed: Vec<ReplayFailure>,
+}
+
+impl ReplayReport {
+    pub fn skipped(&self) -> usize {
+        self.already_processed + self.undone + self.ignored
+    }
+}
+
+/// 按时间顺序把导出的消息交给解析器和余额计算器，补记机器人离线期间漏掉的消息。
+///
+/// 使用消息的原始 ID，已经处理过的消息（包括被 /undo 撤销的）和带着 #已撤销 标记的消息
+/// 会被跳过，重复重放不会重复记账，也不会恢复撤销过的记账。
+/// 补记的金额直接加到当前余额上，因此钱包在该消息之后已经有手动总额时拒绝补记，
+/// 否则总额中已经包含的金额会被再算一次；带 #总额 的消息之后已有其他记账时同样拒绝。
+/// 重放只写数据库，不会修改 Telegram 中的消息。
+#[derive(Clone, Debug)]
+pub struct Replayer {
+    parser: MessageParser,
+    calculator: BalanceCalculator,
+    db: DatabaseOperations,
+}
+
+impl Replayer {
+    pub fn new(db: DatabaseOperations) -> Self {
+        Self {
+            parser: MessageParser::new(),
+            calculator: BalanceCalculator::new(db.clone()),
+            db,
+        }
+    }
+
+    pub async fn replay(&self, chat_id: i64, export: &ChatExport) -> Result<ReplayReport> {
+        let utc_offset = self.db.get_chat_settings(chat_id).await?.utc_offset;
+        let mut messages: Vec<&ExportedMessage> = export.messages.iter().collect();
+        messages.sort_by_key(|message| (message.time(utc_offset), message.id));
+        info!(
+            "⏪ Replaying {} exported messages into chat {chat_id}",
+            messages.len()
+        );
+
+        let mut report = ReplayReport {
+            total: messages.len(),
+            ..Default::default()
+        };
+        for message in messages {
+            let text = message.text();
+            let is_transfer = self.parser.is_transfer_message(&text);
+            if message.kind != "message" || !(is_transfer || self.parser.is_wallet_message(&text)) {
+                report.ignored += 1;
+                continue;
+            }
+            // 被撤销的消息仍有记录，同样跳过
+            if self.db.is_message_processed(message.id, chat_id).await? {
+                debug!("Message {} already processed, skipping", message.id);
+                report.already_processed += 1;
+                continue;
+            }
+            if self.parser.is_undone(&text) {
+                debug!(
+                    "Message {} was undone before the export, skipping",
+                    message.id
+                );
+                report.undone += 1;
+                continue;
+            }
+
+            match self
+                .replay_message(chat_id, message, utc_offset, &text, is_transfer)
+                .await
+            {
+                Ok(()) => report.parsed += 1,
+                Err(reason) => {
+                    warn!("Failed to replay message {}: {reason}", message.id);
+                    report.failed.push(ReplayFailure {
+                        message_id: message.id,
+                        reason,
+                    });
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    async fn replay_message(
+        &self,
+        chat_id: i64,
+        message: &ExportedMessage,
+        utc_offset: i32,
+        text: &str,
+        is_transfer: bool,
+    ) -> std::result::Result<(), String> {
+        let time = message
+            .time(utc_offset)
+            .ok_or_else(|| format!("消息时间无效：{}", message.date))?;
+        if is_transfer {
+            let transfer = self
+                .parser
+                .parse_transfer(text)
+                .ok_or_else(|| "转账格式不正确".to_string())?;
+            for wallet_name in [&transfer.from_wallet, &transfer.to_wallet] {
+                self.check_backfill(chat_id, wallet_name, time, false)
+                    .await?;
+            }
+            self.calculator
+                .process_transfer(chat_id, message.id, &transfer)
+                .await
+                .map_err(|e| e.to_string())?;
+        } else {
+            let parsed = self
+                .parser
+                .parse(text)
+                .ok_or_else(|| "消息格式不正确".to_string())?;
+            self.check_backfill(
+                chat_id,
+                &parsed.wallet_name,
+                time,
+                parsed.total_amount.is_some(),
+            )
+            .await?;
+            self.calculator
+                .process_message(chat_id, message.id, &parsed)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// 检查把 time 时的消息补记到当前余额上是否会算错，会算错时返回原因
+    async fn check_backfill(
+        &self,
+        chat_id: i64,
+        wallet_name: &str,
+        time: DateTime<Utc>,
+        has_total: bool,
+    ) -> std::result::Result<(), String> {
+        let later = self
+            .db
+            .unit_of_work(|uow| {
+                let Some(wallet_id) = uow
+                    .get_wallets(chat_id)?
+                    .into_iter()
+                    .find(|wallet| wallet.name == wallet_name)
+                    .and_then(|wallet| wallet.id)
+                else {
+                    return Ok(Vec::new());
+                };
+                Ok(uow
+                    .get_wallet_ledger(wallet_id)?
+                    .into_iter()
+                    .filter(|entry| entry.created_at().is_some_and(|created| created > time))
+                    .collect::<Vec<_>>())
+            })
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if let Some(checkpoint) = later.iter().find(|entry| entry.is_checkpoint()) {
+            let message_id = checkpoint.message.as_ref().map_or(0, |m| m.message_id);
+            return Err(format!(
+                "{wallet_name} 之后已有手动总额（消息 {message_id}），补记会重复计算，请核对后手动记账"
+            ));
+        }
+        if has_total && !later.is_empty() {
+            return Err(format!(
+                "消息带有 #总额，但 {wallet_name} 之后已有 {} 笔记账，补记会覆盖之后的余额",
+                later.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chat_export() {
+        let export = ChatExport::from_json(
+            r##"{
+                "name": "账本",
+                "type": "private_channel",
+                "id": 1234567890,
+                "messages": [
+                    {"id": 1, "type": "service", "date": "2024-12-01T09:00:00", "action": "create_channel"},
+                    {"id": 2, "type": "message", "date": "2024-12-01T10:00:00",
+                     "text": [{"type": "hashtag", "text": "#支付宝"}, " ", {"type": "hashtag", "text": "#12月"}, "\n#出账 30元"]},
+                    {"id": 3, "type": "message", "date": "2024-12-01T11:00:00", "text": "你好"}
+                ]
+            }"##,
+        )
+        .unwrap();
+        assert_eq!(export.bot_chat_id(), -1001234567890);
+        assert_eq!(export.messages[0].text(), "");
+        assert_eq!(export.messages[1].text(), "#支付宝 #12月\n#出账 30元");
+        assert_eq!(export.messages[2].text(), "你好");
+    }
+
+    #[test]
+    fn test_exported_message_time() {
+        let message = |json: &str| -> ExportedMessage { serde_json::from_str(json).unwrap() };
+        let utc = |s: &str| {
+            NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S")
+                .unwrap()
+                .and_utc()
+        };
+
+        // 有 date_unixtime 时不受导出电脑时区的影响
+        let exported = message(
+            r#"{"id": 1, "type": "message", "date": "2024-12-01T08:30:00", "date_unixtime": "1733013000"}"#,
+        );
+        assert_eq!(exported.time(8), Some(utc("2024-12-01T00:30:00")));
+        assert_eq!(exported.time(0), Some(utc("2024-12-01T00:30:00")));
+
+        // 旧的导出按聊天时区换算，UTC+8 的 12 月 1 日早上仍是 UTC 的 11 月 30 日
+        let exported = message(r#"{"id": 2, "type": "message", "date": "2024-12-01T06:00:00"}"#);
+        assert_eq!(exported.time(8), Some(utc("2024-11-30T22:00:00")));
+        assert_eq!(exported.time(0), Some(utc("2024-12-01T06:00:00")));
+        assert_eq!(message(r#"{"id": 3, "type": "message"}"#).time(0), None);
+    }
+}
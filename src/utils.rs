@@ -1,8 +1,19 @@
+use crate::bot::confirmation::{LargeAmount, LargeAmountReason};
+use crate::calculator::report::{ChatReport, PeriodReport};
+use crate::database::models::{
+    AccessPolicy, BalanceUpdate, CategoryTotal, ChatPermission, ChatSettings, Language, LineItem,
+    MonthlyAdjustment, OutputMode, ParsedMessage, Transaction, TransactionPage, TransferUpdate,
+    UndoneMessage, WalletReconciliation, WalletSummary,
+};
 use crate::error::Result;
+use crate::import::ImportReport;
+use crate::money::Money;
+use crate::replay::ReplayReport;
 use chrono::{DateTime, Utc};
 use log::{error, info, warn};
 use std::fs;
 use std::path::Path;
+use std::time::Duration;
 
 /// 日志记录工具
 pub struct Logger;
@@ -46,8 +57,15 @@ impl Logger {
     }
 }
 
+/// 记账确认消息的种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confirmation {
+    Recorded,    // 新记录的交易
+    ManualTotal, // 按消息中的 #总额 更新余额
+    Updated,     // 消息编辑后重新计算
+}
+
 /// 格式化工具
-#[allow(dead_code)]
 pub struct Formatter;
 
 impl Formatter {
@@ -74,32 +92,1089 @@ impl Formatter {
         }
     }
 
+    /// 格式化一条消息中的多笔交易明细，单笔交易时返回 None
+    pub fn format_line_items(items: &[LineItem], settings: &ChatSettings) -> Option<String> {
+        if items.len() < 2 {
+            return None;
+        }
+
+        let english = settings.language == Language::English;
+        let mut lines = vec![if english {
+            "🧾 Items:"
+        } else {
+            "🧾 明细："
+        }
+        .to_string()];
+        for item in items {
+            let mut line = format!(
+                "• {} {}",
+                item.transaction_type,
+                settings.money(item.amount)
+            );
+            if let Some(category) = &item.category {
+                line.push_str(&format!(" #{category}"));
+            }
+            if let Some(note) = &item.note {
+                line.push(' ');
+                line.push_str(note);
+            }
+            lines.push(line);
+        }
+
+        let delta: Money = items.iter().map(LineItem::signed_amount).sum();
+        let sign = if delta > Money::ZERO { "+" } else { "" };
+        let label = if english {
+            "📐 Net: "
+        } else {
+            "📐 合计："
+        };
+        lines.push(format!("{label}{sign}{}", settings.money(delta)));
+        Some(lines.join("\n"))
+    }
+
+    /// 记账确认消息，按聊天的语言和货币单位显示，多笔明细时附上逐行明细
+    pub fn format_confirmation(
+        settings: &ChatSettings,
+        kind: Confirmation,
+        parsed: &ParsedMessage,
+        update: &BalanceUpdate,
+    ) -> String {
+        let english = settings.language == Language::English;
+        let title = match (kind, english) {
+            (Confirmation::Recorded, false) => "✅ 交易已记录",
+            (Confirmation::Recorded, true) => "✅ Transaction recorded",
+            (Confirmation::ManualTotal, false) => "✅ 余额已更新（手动总额）",
+            (Confirmation::ManualTotal, true) => "✅ Balance updated (manual total)",
+            (Confirmation::Updated, false) => "✏️ 交易已更新",
+            (Confirmation::Updated, true) => "✏️ Transaction updated",
+        };
+        let balance = match (kind, english) {
+            (Confirmation::Updated, false) => format!(
+                "💰 余额：{} → {}",
+                settings.money(update.old_balance),
+                settings.money(update.new_balance)
+            ),
+            (Confirmation::Updated, true) => format!(
+                "💰 Balance: {} → {}",
+                settings.money(update.old_balance),
+                settings.money(update.new_balance)
+            ),
+            (_, false) => format!("💰 当前余额：{}", settings.money(update.new_balance)),
+            (_, true) => format!("💰 Balance: {}", settings.money(update.new_balance)),
+        };
+        let wallet = if english {
+            "📊 Wallet: "
+        } else {
+            "📊 钱包："
+        };
+
+        let text = format!("{title}\n{wallet}{}\n{balance}", parsed.wallet_name);
+        match Self::format_line_items(&parsed.items, settings) {
+            Some(details) => format!("{text}\n{details}"),
+            None => text,
+        }
+    }
+
+    /// 转账确认消息，按聊天的语言和货币单位显示
+    pub fn format_transfer_confirmation(
+        settings: &ChatSettings,
+        amount: Money,
+        update: &TransferUpdate,
+    ) -> String {
+        let (title, separator) = match settings.language {
+            Language::Chinese => ("🔁 转账已记录：", "："),
+            Language::English => ("🔁 Transfer recorded: ", ": "),
+        };
+        let line = |icon: &str, balance: &BalanceUpdate| {
+            format!(
+                "{icon} {}{separator}{} → {}",
+                balance.wallet_name,
+                settings.money(balance.old_balance),
+                settings.money(balance.new_balance)
+            )
+        };
+        format!(
+            "{title}{}\n{}\n{}",
+            settings.money(amount),
+            line("📤", &update.from),
+            line("📥", &update.to)
+        )
+    }
+
+    /// 重复处理同一条消息时的提示
+    pub fn format_already_processed(settings: &ChatSettings) -> &'static str {
+        match settings.language {
+            Language::Chinese => "⚠️ 这条消息已经被处理过了，不会重复记录交易。",
+            Language::English => {
+                "⚠️ This message has already been processed and will not be recorded again."
+            }
+        }
+    }
+
+    /// 格式化余额核对结果，每个钱包一行
+    pub fn format_reconciliation(
+        settings: &ChatSettings,
+        results: &[WalletReconciliation],
+    ) -> String {
+        let english = settings.language == Language::English;
+        if results.is_empty() {
+            return if english {
+                "🔍 No wallets to reconcile"
+            } else {
+                "🔍 没有需要核对的钱包"
+            }
+            .to_string();
+        }
+
+        let mut lines = vec![if english {
+            "🔍 Reconciliation"
+        } else {
+            "🔍 余额核对结果"
+        }
+        .to_string()];
+        for result in results {
+            let ledger_balance = settings.money(result.ledger_balance);
+            if result.is_consistent() {
+                lines.push(if english {
+                    format!(
+                        "✅ {}: {ledger_balance} ({} transactions, {} manual totals)",
+                        result.wallet_name, result.transaction_count, result.checkpoint_count
+                    )
+                } else {
+                    format!(
+                        "✅ {}：{ledger_balance}（{} 笔交易，{} 个总额检查点）",
+                        result.wallet_name, result.transaction_count, result.checkpoint_count
+                    )
+                });
+                continue;
+            }
+
+            let stored_balance = settings.money(result.stored_balance);
+            let drift = settings.money(result.drift());
+            let mut line = if english {
+                format!(
+                    "⚠️ {}: stored {stored_balance}, ledger {ledger_balance}, drift {drift}",
+                    result.wallet_name
+                )
+            } else {
+                format!(
+                    "⚠️ {}：记录 {stored_balance}，账本 {ledger_balance}，差额 {drift}",
+                    result.wallet_name
+                )
+            };
+            if result.stale_snapshots > 0 {
+                line.push_str(&if english {
+                    format!(", {} stale message snapshots", result.stale_snapshots)
+                } else {
+                    format!("，{} 条消息快照不一致", result.stale_snapshots)
+                });
+            }
+            if result.fixed {
+                line.push_str(if english {
+                    " → fixed"
+                } else {
+                    " → 已修正"
+                });
+            }
+            lines.push(line);
+        }
+
+        lines.join("\n")
+    }
+
+    /// 格式化聊天的访问策略和单独授权的成员
+    pub fn format_access(policy: &AccessPolicy, permissions: &[ChatPermission]) -> String {
+        let mut lines = vec![
+            "🔐 权限设置".to_string(),
+            if policy.restricted {
+                "• 受限：只有授权的成员可以记账和查询".to_string()
+            } else {
+                "• 开放：所有成员都可以记账".to_string()
+            },
+            if policy.reject_unauthorized {
+                "• 未授权的交易消息：回复提示".to_string()
+            } else {
+                "• 未授权的交易消息：忽略".to_string()
+            },
+            "• 群组管理员和全局管理员始终拥有管理员权限".to_string(),
+        ];
+        if permissions.is_empty() {
+            lines.push("👥 没有单独授权的成员".to_string());
+        } else {
+            lines.push("👥 授权成员：".to_string());
+            for permission in permissions {
+                lines.push(format!(
+                    "• {}：{}",
+                    permission.user_id,
+                    permission.role.label()
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// 格式化 /undo 的结果：作废的交易和恢复后的余额
+    pub fn format_undo(settings: &ChatSettings, undone: &UndoneMessage) -> String {
+        let english = settings.language == Language::English;
+        let link = Self::message_link(undone.chat_id, undone.message_id);
+        let mut lines = vec![match (link, english) {
+            (Some(link), false) => format!("↩️ 已撤销这条消息的记账：{link}"),
+            (Some(link), true) => format!("↩️ Undid the transactions of this message: {link}"),
+            (None, false) => format!("↩️ 已撤销消息 {} 的记账", undone.message_id),
+            (None, true) => format!("↩️ Undid the transactions of message {}", undone.message_id),
+        }];
+        lines.extend(
+            undone
+                .transactions
+                .iter()
+                .map(|transaction| Self::transaction_line(settings, transaction)),
+        );
+        let separator = if english { ": " } else { "：" };
+        for balance in &undone.balances {
+            lines.push(format!(
+                "📊 {}{separator}{} → {}",
+                balance.wallet_name,
+                settings.money(balance.old_balance),
+                settings.money(balance.new_balance)
+            ));
+        }
+        lines.join("\n")
+    }
+
+    /// 格式化 /reprocess 前后的交易和余额变化
+    pub fn format_reprocess(
+        settings: &ChatSettings,
+        before: &[Transaction],
+        after: &[Transaction],
+        wallets_before: &[WalletSummary],
+        wallets_after: &[WalletSummary],
+    ) -> String {
+        let english = settings.language == Language::English;
+        let mut lines = vec![if english {
+            "🔄 Reprocessed"
+        } else {
+            "🔄 重新处理结果"
+        }
+        .to_string()];
+        let sections = if english {
+            [("Before", before), ("After", after)]
+        } else {
+            [("之前", before), ("之后", after)]
+        };
+        for (title, transactions) in sections {
+            if transactions.is_empty() {
+                lines.push(if english {
+                    format!("{title}: nothing recorded")
+                } else {
+                    format!("{title}：没有记账")
+                });
+            } else {
+                lines.push(if english {
+                    format!("{title}:")
+                } else {
+                    format!("{title}：")
+                });
+                lines.extend(
+                    transactions
+                        .iter()
+                        .map(|transaction| Self::transaction_line(settings, transaction)),
+                );
+            }
+        }
+
+        let separator = if english { ": " } else { "：" };
+        let changes: Vec<String> = wallets_after
+            .iter()
+            .filter_map(|wallet| {
+                let old_balance = wallets_before
+                    .iter()
+                    .find(|w| w.name == wallet.name)
+                    .map_or(Money::ZERO, |w| w.balance);
+                (old_balance != wallet.balance).then(|| {
+                    format!(
+                        "• {}{separator}{} → {}",
+                        wallet.name,
+                        settings.money(old_balance),
+                        settings.money(wallet.balance)
+                    )
+                })
+            })
+            .collect();
+        if changes.is_empty() {
+            lines.push(
+                if english {
+                    "💰 Balances unchanged"
+                } else {
+                    "💰 余额没有变化"
+                }
+                .to_string(),
+            );
+        } else {
+            lines.push(
+                if english {
+                    "💰 Balance changes:"
+                } else {
+                    "💰 余额变化："
+                }
+                .to_string(),
+            );
+            lines.extend(changes);
+        }
+        lines.join("\n")
+    }
+
+    /// 一笔交易的类型、金额、分类和备注
+    fn transaction_line(settings: &ChatSettings, transaction: &Transaction) -> String {
+        let mut line = format!(
+            "• {} {}",
+            transaction.transaction_type,
+            settings.money(transaction.amount)
+        );
+        if let Some(category) = &transaction.category {
+            line.push_str(&format!(" #{category}"));
+        }
+        if let Some(note) = &transaction.note {
+            line.push(' ');
+            line.push_str(note);
+        }
+        line
+    }
+
+    /// 格式化聊天当前的输出方式和可选项
+    pub fn format_output_mode(settings: &ChatSettings) -> String {
+        let mut current = format!("📤 记账结果的输出方式：{}", settings.output_mode.label());
+        if settings.output_mode == OutputMode::TemporaryConfirm {
+            current.push_str(&format!("（{} 秒后删除）", settings.confirmation_ttl));
+        }
+        let options = OutputMode::ALL
+            .iter()
+            .map(|mode| format!("• {}：{}", mode.as_str(), mode.label()))
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!("{current}\n\n使用 /output <方式> 修改：\n{options}")
+    }
+
+    /// 格式化聊天的设置，配合 /settings 的按钮使用
+    pub fn format_chat_settings(settings: &ChatSettings) -> String {
+        let mut output = settings.output_mode.label().to_string();
+        if settings.output_mode == OutputMode::TemporaryConfirm {
+            output.push_str(&format!("（{} 秒后删除）", settings.confirmation_ttl));
+        }
+        [
+            "⚙️ 聊天设置".to_string(),
+            format!("📤 输出方式：{output}"),
+            format!("💱 货币单位：{}", settings.currency),
+            format!(
+                "👛 默认钱包：{}",
+                settings.default_wallet.as_deref().unwrap_or("未设置")
+            ),
+            format!("🕒 时区：{}", settings.timezone_label()),
+            format!("🌐 语言：{}", settings.language.label()),
+            format!(
+                "🔔 大额确认：{}（使用 /threshold 修改）",
+                Self::format_large_amount_threshold(settings)
+            ),
+            String::new(),
+            "点击下面的按钮修改设置（需要管理员权限）。".to_string(),
+        ]
+        .join("\n")
+    }
+
+    /// 需要确认的金额阈值，例如“超过 1000.00元 或平时金额的 5 倍”
+    pub fn format_large_amount_threshold(settings: &ChatSettings) -> String {
+        let english = settings.language == Language::English;
+        let above = settings.confirm_above.map(|limit| {
+            let limit = settings.money(limit);
+            settings.text(format!("超过 {limit}"), format!("above {limit}"))
+        });
+        let ratio = settings.confirm_ratio.map(|ratio| {
+            settings.text(
+                format!("平时金额的 {ratio} 倍"),
+                format!("{ratio}x the usual amount"),
+            )
+        });
+        match (above, ratio) {
+            (Some(above), Some(ratio)) if english => format!("{above} or above {ratio}"),
+            (Some(above), Some(ratio)) => format!("{above} 或{ratio}"),
+            (Some(above), None) => above,
+            (None, Some(ratio)) if english => format!("above {ratio}"),
+            (None, Some(ratio)) => format!("超过{ratio}"),
+            (None, None) => settings.text("未开启", "off").to_string(),
+        }
+    }
+
+    /// 大额交易的确认提示
+    pub fn format_large_amount_prompt(
+        settings: &ChatSettings,
+        large: &LargeAmount,
+        timeout: Duration,
+    ) -> String {
+        let english = settings.language == Language::English;
+        let reason = match large.reason {
+            LargeAmountReason::Above(limit) => {
+                let limit = settings.money(limit);
+                settings.text(format!("超过 {limit}"), format!("above {limit}"))
+            }
+            LargeAmountReason::Ratio { typical, ratio } => {
+                let typical = settings.money(typical);
+                settings.text(
+                    format!("超过平时金额 {typical} 的 {ratio} 倍"),
+                    format!("over {ratio}x the usual {typical}"),
+                )
+            }
+        };
+        let seconds = timeout.as_secs();
+        let timeout = match (seconds.is_multiple_of(60), english) {
+            (true, false) => format!("{} 分钟", seconds / 60),
+            (true, true) => format!("{} minutes", seconds / 60),
+            (false, false) => format!("{seconds} 秒"),
+            (false, true) => format!("{seconds} seconds"),
+        };
+        let amount = settings.money(large.amount);
+        if english {
+            format!(
+                "⚠️ This is a large amount and needs confirmation before it is recorded\n📊 Wallet: {}\n💰 Amount: {amount} ({reason})\n⏳ It will not be recorded unless confirmed within {timeout}",
+                large.wallet_name
+            )
+        } else {
+            format!(
+                "⚠️ 这笔金额较大，确认后才会记账\n📊 钱包：{}\n💰 金额：{amount}（{reason}）\n⏳ {timeout}内没有确认将不会记账",
+                large.wallet_name
+            )
+        }
+    }
+
+    /// /start 的欢迎语
+    pub fn format_welcome(settings: &ChatSettings) -> &'static str {
+        settings.text(
+            "欢迎使用 WalletBot！\n\n我可以帮助你管理钱包交易记录。\n\n支持的消息格式：\n#钱包名称 #月份 #年份\n#出账/入账 金额元\n\n输入 /help 查看更多命令。",
+            "Welcome to WalletBot!\n\nI keep track of your wallet transactions.\n\nMessage format:\n#wallet #month #year\n#出账/入账 amount元\n\nSend /help to see all commands.",
+        )
+    }
+
+    /// /help 的命令列表和消息格式说明
+    pub fn format_help(settings: &ChatSettings) -> &'static str {
+        settings.text(
+            "WalletBot 帮助\n\n支持的命令：\n/start - 开始使用\n/help - 显示帮助\n/reprocess - 重新处理消息\n/undo - 撤销最近一笔记账（回复消息时撤销该消息）\n/status - 查看状态\n/history <钱包名> [月份] [年份] [类型] - 分页查看交易记录\n/balance [钱包名] - 查看余额\n/wallets - 列出所有钱包\n/reconcile [钱包名] [fix] - 按账本核对余额\n/adjustments - 按月查看手动总额造成的余额调整\n/categories [月份] [年份] - 按分类汇总收支\n/report [钱包名] [月份] [年份] - 月度/年度报表\n/summary [on [日] [时] | off] - 每月自动发送上个月的总结\n/export [钱包名] [月份] [年份] - 导出 CSV\n/import [dry] - 导入 CSV/JSON 文件（回复文件或作为文件说明）\n/access - 查看或设置谁可以记账和查询\n/output [edit|confirm|temporary [秒]|discussion] - 设置记账结果的输出方式\n/settings - 查看和修改本聊天的设置（货币、默认钱包、时区、语言等）\n/threshold [金额] [倍数x] | off - 大额交易先确认再记账\n\n消息格式：\n#钱包名称 #月份 #年份\n#出账 1000.00元\n\n额外的标签会作为分类，例如 #餐饮，其余文字作为备注。\n\n或者：\n#钱包名称 #月份 #年份\n#入账 500.00元\n\n转账：\n#转账 #支付宝 → #银行卡 #月份 #年份\n500.00元\n\n我会自动计算并添加 #总额 信息。",
+            "WalletBot help\n\nCommands:\n/start - Get started\n/help - Show this help\n/reprocess - Reprocess a message\n/undo - Undo the latest entry (or the replied-to message)\n/status - Show status\n/history <wallet> [month] [year] [type] - Browse transactions page by page\n/balance [wallet] - Show balances\n/wallets - List all wallets\n/reconcile [wallet] [fix] - Check balances against the ledger\n/adjustments - Monthly balance adjustments caused by manual totals\n/categories [month] [year] - Totals by category\n/report [wallet] [month] [year] - Monthly or yearly report\n/summary [on [day] [hour] | off] - Post last month's summary every month\n/export [wallet] [month] [year] - Export CSV\n/import [dry] - Import a CSV/JSON file (reply to the file or use as its caption)\n/access - Show or set who can record and query\n/output [edit|confirm|temporary [seconds]|discussion] - How results are posted\n/settings - Show and change this chat's settings (currency, default wallet, timezone, language…)\n/threshold [amount] [ratio x] | off - Confirm large transactions before recording\n\nMessage format:\n#wallet #month #year\n#出账 1000.00元\n\nOther tags become the category, e.g. #餐饮; remaining text becomes the note.\n\nOr:\n#wallet #month #year\n#入账 500.00元\n\nTransfers:\n#转账 #支付宝 → #银行卡 #month #year\n500.00元\n\nI calculate the balance and append the #总额 line.",
+        )
+    }
+
+    /// 钱包消息格式不正确时的说明
+    pub fn format_message_help(settings: &ChatSettings) -> &'static str {
+        settings.text(
+            "❌ 消息格式不正确\n\n📋 正确格式：\n#钱包名称 #月份 #年份\n#出账/入账 金额元\n\n💡 示例：\n#支付宝 #12月 #2024年\n#出账 150.00元\n\n或者：\n#微信 #01月 #2024年\n#入账 200.00元\n\n❓ 需要帮助请输入 /help",
+            "❌ Invalid message format\n\n📋 Expected format:\n#wallet #month #year\n#出账/入账 amount元\n\n💡 Example:\n#支付宝 #12月 #2024年\n#出账 150.00元\n\nOr:\n#微信 #01月 #2024年\n#入账 200.00元\n\n❓ Send /help for more",
+        )
+    }
+
+    /// 转账消息格式不正确时的说明
+    pub fn format_transfer_help(settings: &ChatSettings) -> &'static str {
+        settings.text(
+            "❌ 转账格式不正确\n\n📋 正确格式：\n#转账 #转出钱包 → #转入钱包 #月份 #年份\n金额元 备注\n\n💡 示例：\n#转账 #支付宝 → #银行卡 #12月 #2024年\n500.00元 还信用卡\n\n⚠️ 转出和转入不能是同一个钱包",
+            "❌ Invalid transfer format\n\n📋 Expected format:\n#转账 #from_wallet → #to_wallet #month #year\namount元 note\n\n💡 Example:\n#转账 #支付宝 → #银行卡 #12月 #2024年\n500.00元 还信用卡\n\n⚠️ The two wallets must be different",
+        )
+    }
+
+    /// 格式化导入结果，校验失败时只列出出错的行
+    pub fn format_import_report(settings: &ChatSettings, report: &ImportReport) -> String {
+        const MAX_ERRORS: usize = 10;
+        let english = settings.language == Language::English;
+        if report.is_rejected() {
+            let mut lines = vec![if english {
+                format!(
+                    "❌ Import failed: {} of {} rows are invalid, nothing was imported",
+                    report.errors.len(),
+                    report.rows
+                )
+            } else {
+                format!(
+                    "❌ 导入失败：{} 行中有 {} 行无效，没有导入任何数据",
+                    report.rows,
+                    report.errors.len()
+                )
+            }];
+            for error in report.errors.iter().take(MAX_ERRORS) {
+                lines.push(if english {
+                    format!("• Line {}: {}", error.line, error.message)
+                } else {
+                    format!("• 第 {} 行：{}", error.line, error.message)
+                });
+            }
+            if report.errors.len() > MAX_ERRORS {
+                let more = report.errors.len() - MAX_ERRORS;
+                lines.push(if english {
+                    format!("… and {more} more")
+                } else {
+                    format!("… 另有 {more} 行")
+                });
+            }
+            return lines.join("\n");
+        }
+
+        let title = match (report.dry_run, english) {
+            (true, false) => "🔎 导入预览（未写入）",
+            (true, true) => "🔎 Import preview (nothing written)",
+            (false, false) => "📥 导入完成",
+            (false, true) => "📥 Import finished",
+        };
+        let mut lines = vec![if english {
+            format!(
+                "{title}: {} rows, {} new transactions, {} already imported",
+                report.rows, report.imported, report.duplicates
+            )
+        } else {
+            format!(
+                "{title}：共 {} 行，新增 {} 笔，跳过 {} 笔已导入的交易",
+                report.rows, report.imported, report.duplicates
+            )
+        }];
+        for wallet in &report.wallets {
+            let delta = settings.money(wallet.delta);
+            let mut line = match (wallet.created, english) {
+                (true, false) => format!(
+                    "• {}（新钱包）：{} 笔，合计 {delta}",
+                    wallet.name, wallet.transactions
+                ),
+                (false, false) => {
+                    format!(
+                        "• {}：{} 笔，合计 {delta}",
+                        wallet.name, wallet.transactions
+                    )
+                }
+                (true, true) => format!(
+                    "• {} (new wallet): {} transactions, net {delta}",
+                    wallet.name, wallet.transactions
+                ),
+                (false, true) => format!(
+                    "• {}: {} transactions, net {delta}",
+                    wallet.name, wallet.transactions
+                ),
+            };
+            if let Some(balance) = wallet.balance {
+                line.push_str(&if english {
+                    format!(", balance {}", settings.money(balance))
+                } else {
+                    format!("，余额 {}", settings.money(balance))
+                });
+            }
+            lines.push(line);
+            if wallet.has_drift() {
+                let drift = settings.money(wallet.drift);
+                lines.push(if english {
+                    format!(
+                        "  ⚠️ Balance was already {drift} off the ledger before the import, with {} stale message snapshots; not fixed automatically, use /reconcile {} fix",
+                        wallet.stale_snapshots, wallet.name
+                    )
+                } else {
+                    format!(
+                        "  ⚠️ 导入前余额与账本相差 {drift}，{} 条消息的余额快照不一致，未自动修正，可用 /reconcile {} fix 修正",
+                        wallet.stale_snapshots, wallet.name
+                    )
+                });
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// 格式化聊天记录重放结果，列出失败的消息
+    pub fn format_replay_report(report: &ReplayReport) -> String {
+        let mut lines = vec![
+            format!("⏪ 重放完成：共 {} 条消息", report.total),
+            format!("✅ 已记账：{}", report.parsed),
+            format!(
+                "⏭️ 跳过：{}（已处理 {}，已撤销 {}，非记账消息 {}）",
+                report.skipped(),
+                report.already_processed,
+                report.undone,
+                report.ignored
+            ),
+            format!("❌ 失败：{}", report.failed.len()),
+        ];
+        for failure in &report.failed {
+            lines.push(format!("• 消息 {}：{}", failure.message_id, failure.reason));
+        }
+        lines.join("\n")
+    }
+
+    /// 格式化每月未被交易解释的余额变化
+    pub fn format_monthly_adjustments(
+        settings: &ChatSettings,
+        months: &[MonthlyAdjustment],
+    ) -> String {
+        let english = settings.language == Language::English;
+        if months.is_empty() {
+            return if english {
+                "📒 No balance adjustments, every balance change is explained by transactions"
+            } else {
+                "📒 没有余额调整记录，所有余额变化都能由交易解释"
+            }
+            .to_string();
+        }
+
+        let mut lines = vec![if english {
+            "📒 Balance changes not explained by transactions (by month)"
+        } else {
+            "📒 未被交易解释的余额变化（按月）"
+        }
+        .to_string()];
+        for month in months {
+            let period = Self::format_month(settings, &month.month, &month.year);
+            let (gained, lost, net) = (
+                settings.money(month.gained),
+                settings.money(month.lost),
+                settings.money(month.net()),
+            );
+            lines.push(if english {
+                format!(
+                    "{period}: gained {gained}, lost {lost}, net {net} ({} adjustments)",
+                    month.count
+                )
+            } else {
+                format!(
+                    "{period}：多出 {gained}，消失 {lost}，净变化 {net}（{} 次调整）",
+                    month.count
+                )
+            });
+        }
+        lines.join("\n")
+    }
+
+    /// 格式化钱包余额及最后更新时间，多个钱包时附上合计
+    pub fn format_balances(settings: &ChatSettings, wallets: &[WalletSummary]) -> String {
+        let english = settings.language == Language::English;
+        if wallets.is_empty() {
+            return Self::format_no_wallets(settings);
+        }
+
+        let mut lines = vec![if english {
+            "💰 Balances"
+        } else {
+            "💰 钱包余额"
+        }
+        .to_string()];
+        for wallet in wallets {
+            let balance = settings.money(wallet.balance);
+            let mut line = if english {
+                format!("• {}: {balance}", wallet.name)
+            } else {
+                format!("• {}：{balance}", wallet.name)
+            };
+            if let Some(updated_at) = wallet.updated_at {
+                let updated_at = Self::format_timestamp(updated_at);
+                line.push_str(&if english {
+                    format!(" (updated {updated_at})")
+                } else {
+                    format!("（更新于 {updated_at}）")
+                });
+            }
+            lines.push(line);
+        }
+        if wallets.len() > 1 {
+            let total = settings.money(wallets.iter().map(|w| w.balance).sum());
+            lines.push(if english {
+                format!("📐 Total: {total}")
+            } else {
+                format!("📐 合计：{total}")
+            });
+        }
+        lines.join("\n")
+    }
+
+    /// 格式化钱包列表及交易笔数
+    pub fn format_wallets(settings: &ChatSettings, wallets: &[WalletSummary]) -> String {
+        let english = settings.language == Language::English;
+        if wallets.is_empty() {
+            return Self::format_no_wallets(settings);
+        }
+
+        let mut lines = vec![if english {
+            format!("👛 {} wallets", wallets.len())
+        } else {
+            format!("👛 共 {} 个钱包", wallets.len())
+        }];
+        for wallet in wallets {
+            let balance = settings.money(wallet.balance);
+            lines.push(if english {
+                format!(
+                    "• {}: {} transactions, balance {balance}",
+                    wallet.name, wallet.transaction_count
+                )
+            } else {
+                format!(
+                    "• {}：{} 笔交易，余额 {balance}",
+                    wallet.name, wallet.transaction_count
+                )
+            });
+        }
+        lines.join("\n")
+    }
+
+    /// 聊天中还没有钱包时的提示
+    fn format_no_wallets(settings: &ChatSettings) -> String {
+        match settings.language {
+            Language::Chinese => "👛 这个聊天还没有钱包，发送一条交易消息即可创建",
+            Language::English => {
+                "👛 This chat has no wallets yet, send a transaction message to create one"
+            }
+        }
+        .to_string()
+    }
+
+    /// 原始 Telegram 消息的链接。
+    ///
+    /// t.me/c 链接只适用于超级群组和频道（ID 以 -100 开头），其他聊天返回 None。
+    pub fn message_link(chat_id: i64, message_id: i64) -> Option<String> {
+        let internal_id = chat_id.to_string().strip_prefix("-100")?.to_string();
+        Some(format!("https://t.me/c/{internal_id}/{message_id}"))
+    }
+
+    /// 格式化一页交易记录，每笔交易附上原消息链接
+    pub fn format_history(settings: &ChatSettings, title: &str, page: &TransactionPage) -> String {
+        let english = settings.language == Language::English;
+        if page.transactions.is_empty() {
+            return if english {
+                format!("📜 {title}: no matching transactions")
+            } else {
+                format!("📜 {title}：没有符合条件的交易")
+            };
+        }
+
+        let mut lines = vec![if english {
+            format!(
+                "📜 {title} (page {}/{}, {} transactions)",
+                page.page + 1,
+                page.page_count(),
+                page.total
+            )
+        } else {
+            format!(
+                "📜 {title}（第 {}/{} 页，共 {} 笔）",
+                page.page + 1,
+                page.page_count(),
+                page.total
+            )
+        }];
+        for transaction in &page.transactions {
+            let mut line = format!(
+                "• {} {} {}",
+                Self::format_month(settings, &transaction.month, &transaction.year),
+                transaction.transaction_type,
+                settings.money(transaction.amount)
+            );
+            if let Some(category) = &transaction.category {
+                line.push_str(&format!(" #{category}"));
+            }
+            if let Some(note) = &transaction.note {
+                line.push(' ');
+                line.push_str(note);
+            }
+            let link = transaction
+                .chat_id
+                .zip(transaction.message_id)
+                .and_then(|(chat_id, message_id)| Self::message_link(chat_id, message_id));
+            if let Some(link) = link {
+                line.push_str(&format!("\n  🔗 {link}"));
+            }
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+
+    /// 格式化单个钱包或聊天合计的报表
+    pub fn format_report(settings: &ChatSettings, report: &PeriodReport) -> String {
+        Self::format_period_report(settings, &report.name, report)
+    }
+
+    fn format_period_report(settings: &ChatSettings, name: &str, report: &PeriodReport) -> String {
+        let english = settings.language == Language::English;
+        let money = |amount: Money| settings.money(amount);
+        let signed = |amount: Money| {
+            let sign = if amount > Money::ZERO { "+" } else { "" };
+            format!("{sign}{}", money(amount))
+        };
+        let period = Self::format_period(settings, report.period.month, Some(report.period.year));
+
+        let mut lines = if english {
+            vec![
+                format!("📊 {name} report for {period}"),
+                format!("🔓 Opening balance: {}", money(report.opening_balance)),
+                format!("📥 Income: {}", money(report.income)),
+                format!("📤 Expense: {}", money(report.expense)),
+            ]
+        } else {
+            vec![
+                format!("📊 {name} {period} 报表"),
+                format!("🔓 期初余额：{}", money(report.opening_balance)),
+                format!("📥 收入：{}", money(report.income)),
+                format!("📤 支出：{}", money(report.expense)),
+            ]
+        };
+        if !report.transfer_in.is_zero() || !report.transfer_out.is_zero() {
+            let (transfer_in, transfer_out) =
+                (money(report.transfer_in), money(report.transfer_out));
+            lines.push(if english {
+                format!("🔁 Transferred in {transfer_in}, out {transfer_out}")
+            } else {
+                format!("🔁 转入 {transfer_in}，转出 {transfer_out}")
+            });
+        }
+        if !report.unexplained().is_zero() {
+            let unexplained = signed(report.unexplained());
+            lines.push(if english {
+                format!("📐 Manual total adjustments: {unexplained}")
+            } else {
+                format!("📐 手动总额调整：{unexplained}")
+            });
+        }
+        let net_change = signed(report.net_change());
+        let closing_balance = money(report.closing_balance);
+        if english {
+            lines.push(format!("📈 Net change: {net_change}"));
+            lines.push(format!(
+                "🔒 Closing balance: {closing_balance} ({} transactions)",
+                report.transaction_count
+            ));
+        } else {
+            lines.push(format!("📈 净变化：{net_change}"));
+            lines.push(format!(
+                "🔒 期末余额：{closing_balance}（{} 笔交易）",
+                report.transaction_count
+            ));
+        }
+
+        if !report.largest.is_empty() {
+            lines.push(String::new());
+            lines.push(
+                if english {
+                    "💸 Largest transactions:"
+                } else {
+                    "💸 最大的几笔交易："
+                }
+                .to_string(),
+            );
+            for item in &report.largest {
+                let transaction = &item.transaction;
+                let mut line = format!(
+                    "• {} {} {}",
+                    item.wallet_name,
+                    transaction.transaction_type,
+                    money(transaction.amount)
+                );
+                if let Some(category) = &transaction.category {
+                    line.push_str(&format!(" #{category}"));
+                }
+                if let Some(note) = &transaction.note {
+                    line.push(' ');
+                    line.push_str(note);
+                }
+                lines.push(line);
+            }
+        }
+
+        if report.categories.iter().any(|c| c.category.is_some()) {
+            lines.push(String::new());
+            lines.push(
+                if english {
+                    "🏷️ Categories:"
+                } else {
+                    "🏷️ 分类："
+                }
+                .to_string(),
+            );
+            lines.extend(
+                report
+                    .categories
+                    .iter()
+                    .map(|total| Self::category_line(settings, total)),
+            );
+        }
+
+        lines.join("\n")
+    }
+
+    /// 格式化整个聊天的报表：合计在前，之后每个钱包一行
+    pub fn format_chat_report(settings: &ChatSettings, report: &ChatReport) -> String {
+        if report.wallets.is_empty() {
+            return Self::format_no_wallets(settings);
+        }
+
+        let english = settings.language == Language::English;
+        let name = if english {
+            "All wallets"
+        } else {
+            &report.total.name
+        };
+        let mut text = Self::format_period_report(settings, name, &report.total);
+        text.push_str(if english {
+            "\n\n👛 Wallets:"
+        } else {
+            "\n\n👛 各钱包："
+        });
+        for wallet in &report.wallets {
+            let (opening, closing, income, expense) = (
+                settings.money(wallet.opening_balance),
+                settings.money(wallet.closing_balance),
+                settings.money(wallet.income),
+                settings.money(wallet.expense),
+            );
+            text.push_str(&if english {
+                format!(
+                    "\n• {}: {opening} → {closing} (income {income}, expense {expense})",
+                    wallet.name
+                )
+            } else {
+                format!(
+                    "\n• {}：{opening} → {closing}（收入 {income}，支出 {expense}）",
+                    wallet.name
+                )
+            });
+        }
+        text
+    }
+
+    /// 格式化分类汇总，每个分类一行
+    pub fn format_category_totals(
+        settings: &ChatSettings,
+        totals: &[CategoryTotal],
+        period: &str,
+    ) -> String {
+        let english = settings.language == Language::English;
+        if totals.is_empty() {
+            return if english {
+                format!("🏷️ No transactions in {period}")
+            } else {
+                format!("🏷️ {period}没有交易记录")
+            };
+        }
+
+        let mut lines = vec![if english {
+            format!("🏷️ Categories ({period})")
+        } else {
+            format!("🏷️ 分类汇总（{period}）")
+        }];
+        for total in totals {
+            let line = Self::category_line(settings, total);
+            lines.push(if english {
+                format!("{line} ({} transactions)", total.count)
+            } else {
+                format!("{line}（{} 笔）", total.count)
+            });
+        }
+
+        let expense = settings.money(totals.iter().map(|t| t.expense).sum());
+        let income = settings.money(totals.iter().map(|t| t.income).sum());
+        lines.push(if english {
+            format!("📐 Total: expense {expense}, income {income}")
+        } else {
+            format!("📐 合计：支出 {expense}，收入 {income}")
+        });
+        lines.join("\n")
+    }
+
+    fn category_line(settings: &ChatSettings, total: &CategoryTotal) -> String {
+        let english = settings.language == Language::English;
+        let name = total.category.as_deref().unwrap_or(if english {
+            "Uncategorized"
+        } else {
+            "未分类"
+        });
+        let mut amounts = Vec::new();
+        if !total.expense.is_zero() {
+            let expense = settings.money(total.expense);
+            amounts.push(if english {
+                format!("expense {expense}")
+            } else {
+                format!("支出 {expense}")
+            });
+        }
+        if !total.income.is_zero() {
+            let income = settings.money(total.income);
+            amounts.push(if english {
+                format!("income {income}")
+            } else {
+                format!("收入 {income}")
+            });
+        }
+        if english {
+            format!("• {name}: {}", amounts.join(", "))
+        } else {
+            format!("• {name}：{}", amounts.join("，"))
+        }
+    }
+
+    /// 查询的时间范围，例如“2024年12月”，英文显示为 2024-12
+    pub fn format_period(settings: &ChatSettings, month: Option<u32>, year: Option<u32>) -> String {
+        match (settings.language, year, month) {
+            (Language::Chinese, Some(y), Some(m)) => format!("{y}年{m}月"),
+            (Language::Chinese, Some(y), None) => format!("{y}年"),
+            (Language::Chinese, None, Some(m)) => format!("{m}月"),
+            (Language::Chinese, None, None) => "全部".to_string(),
+            (Language::English, Some(y), Some(m)) => format!("{y}-{m:02}"),
+            (Language::English, Some(y), None) => y.to_string(),
+            (Language::English, None, Some(m)) => format!("month {m}"),
+            (Language::English, None, None) => "all time".to_string(),
+        }
+    }
+
+    /// 交易中 "12月"、"2024年" 形式的月份和年份，英文显示为 2024-12
+    fn format_month(settings: &ChatSettings, month: &str, year: &str) -> String {
+        let number = |s: &str| s.trim_end_matches(['月', '年']).parse::<u32>().ok();
+        match (settings.language, number(month), number(year)) {
+            (Language::English, Some(month), Some(year)) => format!("{year}-{month:02}"),
+            _ => format!("{year}{month}"),
+        }
+    }
+
     /// 格式化时间戳
-    #[allow(dead_code)]
     pub fn format_timestamp(timestamp: DateTime<Utc>) -> String {
         timestamp.format("%Y-%m-%d %H:%M:%S UTC").to_string()
     }
 }
 
+/// 命令参数中的一个时间参数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeriodArg {
+    Month(u32),
+    Year(u32),
+}
+
+impl PeriodArg {
+    /// 解析单个月份或年份参数，例如 "12月"、"#2024年"、"12"、"2024"
+    pub fn parse(arg: &str) -> Option<Self> {
+        let arg = arg.trim_start_matches('#');
+        if let Some(m) = arg.strip_suffix('月') {
+            m.parse::<u32>()
+                .ok()
+                .filter(|m| (1..=12).contains(m))
+                .map(Self::Month)
+        } else if let Some(y) = arg.strip_suffix('年') {
+            y.parse::<u32>().ok().map(Self::Year)
+        } else {
+            match arg.parse::<u32>().ok()? {
+                m @ 1..=12 => Some(Self::Month(m)),
+                y if y >= 1000 => Some(Self::Year(y)),
+                _ => None,
+            }
+        }
+    }
+}
+
 /// 验证工具
-#[allow(dead_code)]
 pub struct Validator;
 
 impl Validator {
     /// 验证钱包名称
-    #[allow(dead_code)]
     pub fn is_valid_wallet_name(name: &str) -> bool {
         !name.is_empty() && name.len() <= 100 && !name.contains('\n')
     }
 
     /// 验证金额
-    #[allow(dead_code)]
     pub fn is_valid_amount(amount: f64) -> bool {
         (0.0..=999_999_999.99).contains(&amount) && !amount.is_nan() && !amount.is_infinite()
     }
 
     /// 验证月份
-    #[allow(dead_code)]
     pub fn is_valid_month(month: &str) -> bool {
         if let Ok(m) = month.parse::<u32>() {
             (1..=12).contains(&m)
@@ -109,7 +1184,6 @@ impl Validator {
     }
 
     /// 验证年份
-    #[allow(dead_code)]
     pub fn is_valid_year(year: &str) -> bool {
         if let Ok(y) = year.parse::<u32>() {
             (2000..=2100).contains(&y)
@@ -223,6 +1297,44 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_format_line_items() {
+        let item = |transaction_type: &str, cents: i64, note: Option<&str>| LineItem {
+            transaction_type: transaction_type.to_string(),
+            amount: Money::from_cents(cents),
+            note: note.map(str::to_string),
+            category: None,
+        };
+        let settings = ChatSettings::new(0);
+
+        assert_eq!(
+            Formatter::format_line_items(&[item("出账", 3000, None)], &settings),
+            None
+        );
+        assert_eq!(
+            Formatter::format_line_items(
+                &[item("出账", 3000, Some("午饭")), item("入账", 5050, None)],
+                &settings
+            )
+            .unwrap(),
+            "🧾 明细：\n• 出账 30.00元 午饭\n• 入账 50.50元\n📐 合计：+20.50元"
+        );
+
+        let settings = ChatSettings {
+            currency: "$".to_string(),
+            language: Language::English,
+            ..settings
+        };
+        assert_eq!(
+            Formatter::format_line_items(
+                &[item("出账", 3000, None), item("出账", 1000, None)],
+                &settings
+            )
+            .unwrap(),
+            "🧾 Items:\n• 出账 $30.00\n• 出账 $10.00\n📐 Net: $-40.00"
+        );
+    }
+
     #[test]
     fn test_validators() {
         // 钱包名称验证
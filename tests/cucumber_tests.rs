@@ -5,8 +5,8 @@ use cucumber::{given, then, when, World};
 
 use std::sync::Arc;
 use teloxide::types::{
-    Chat, ChatId, MediaKind, MediaText, Message, MessageCommon, MessageId, MessageKind, User,
-    UserId,
+    Chat, ChatId, InlineKeyboardMarkup, MediaKind, MediaText, Message, MessageCommon, MessageId,
+    MessageKind, User, UserId,
 };
 use teloxide::RequestError;
 use tokio::sync::Mutex;
@@ -17,7 +17,8 @@ use walletbot::bot::traits::BotApi;
 use walletbot::database::models::ParsedMessage;
 use walletbot::database::operations::DatabaseOperations;
 use walletbot::error::WalletBotError;
-use walletbot::parser::message::{MessageParser, Transaction};
+use walletbot::money::Money;
+use walletbot::parser::message::MessageParser;
 
 // 动态管理多个chat_id，不再使用固定值
 // const TEST_CHAT_ID: i64 = 12345; // 已移除
@@ -207,6 +208,16 @@ impl BotApi for MockBotApi {
         Ok(())
     }
 
+    async fn delete_message_later(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        _delay: std::time::Duration,
+    ) {
+        // mock 不等待，直接删除
+        let _ = self.delete_message(chat_id, message_id).await;
+    }
+
     async fn reply_to_message(
         &self,
         message: &Message,
@@ -228,6 +239,57 @@ impl BotApi for MockBotApi {
 
         Ok(Self::create_mock_message(message.chat.id, message_id, text))
     }
+
+    async fn send_message_with_keyboard(
+        &self,
+        chat_id: ChatId,
+        text: &str,
+        _keyboard: Option<InlineKeyboardMarkup>,
+    ) -> Result<Message, RequestError> {
+        self.send_message(chat_id, text).await
+    }
+
+    async fn edit_message_with_keyboard(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        text: &str,
+        _keyboard: Option<InlineKeyboardMarkup>,
+    ) -> Result<Message, RequestError> {
+        self.edit_message_text(chat_id, message_id, text).await
+    }
+
+    async fn answer_callback_query(&self, _query_id: &str) -> Result<(), RequestError> {
+        Ok(())
+    }
+
+    async fn send_document(
+        &self,
+        chat_id: ChatId,
+        _file_name: &str,
+        _content: Vec<u8>,
+        caption: &str,
+    ) -> Result<Message, RequestError> {
+        self.send_message(chat_id, caption).await
+    }
+
+    async fn download_file(&self, file_id: &str) -> Result<Vec<u8>, RequestError> {
+        Err(RequestError::Api(teloxide::ApiError::Unknown(format!(
+            "File not found: {file_id}"
+        ))))
+    }
+
+    async fn is_chat_admin(
+        &self,
+        _chat_id: ChatId,
+        _user_id: UserId,
+    ) -> Result<bool, RequestError> {
+        Ok(false)
+    }
+
+    async fn leave_chat(&self, _chat_id: ChatId) -> Result<(), RequestError> {
+        Ok(())
+    }
 }
 
 // World结构，管理测试状态
@@ -247,7 +309,6 @@ pub struct WalletBotWorld {
     pub last_error: Option<String>,
     pub last_result: Option<Result<(), WalletBotError>>,
     pub parse_result: Option<ParsedMessage>,
-    pub simple_parse_result: Option<Transaction>,
 }
 
 impl WalletBotWorld {
@@ -266,7 +327,6 @@ impl WalletBotWorld {
             last_error: None,
             last_result: None,
             parse_result: None,
-            simple_parse_result: None,
         }
     }
 
@@ -462,7 +522,7 @@ async fn initial_balance_should_be(world: &mut WalletBotWorld, expected_balance:
         .get_balance(world.current_chat_id.0, &world.current_user)
         .await
         .unwrap();
-    assert_eq!(balance, expected_balance as f64);
+    assert_eq!(balance, Money::from_yuan(expected_balance as i64));
 }
 
 #[given(expr = "用户 {string} 已经有钱包")]
@@ -483,7 +543,7 @@ async fn record_income_transaction(world: &mut WalletBotWorld, amount: i32, desc
             world.current_chat_id.0,
             &world.current_user,
             "收入",
-            amount as f64,
+            Money::from_yuan(amount as i64),
             &description,
             &format!("tx_{}", rand::random::<u32>()),
         )
@@ -513,7 +573,7 @@ async fn wallet_balance_should_increase(world: &mut WalletBotWorld, amount: i32)
         .get_balance(world.current_chat_id.0, &world.current_user)
         .await
         .unwrap();
-    assert_eq!(balance, amount as f64);
+    assert_eq!(balance, Money::from_yuan(amount as i64));
 }
 
 #[given(expr = "用户 {string} 已经有钱包 余额为 {int}")]
@@ -529,7 +589,7 @@ async fn user_has_wallet_with_balance(world: &mut WalletBotWorld, username: Stri
             world.current_chat_id.0,
             &username,
             "收入",
-            balance as f64,
+            Money::from_yuan(balance as i64),
             "初始余额",
             &format!("tx_{}", rand::random::<u32>()),
         )
@@ -544,7 +604,7 @@ async fn record_expense_transaction(world: &mut WalletBotWorld, amount: i32, des
             world.current_chat_id.0,
             &world.current_user,
             "支出",
-            -(amount as f64),
+            -Money::from_yuan(amount as i64),
             &description,
             &format!("tx_{}", rand::random::<u32>()),
         )
@@ -559,7 +619,7 @@ async fn wallet_balance_should_decrease(world: &mut WalletBotWorld, amount: i32)
         .get_balance(world.current_chat_id.0, &world.current_user)
         .await
         .unwrap();
-    assert_eq!(balance, 200.0 - amount as f64); // 假设初始余额为200
+    assert_eq!(balance, Money::from_yuan(200 - amount as i64)); // 假设初始余额为200
 }
 
 #[given(expr = "有一笔收入交易 金额为 {int} 描述为 {string}")]
@@ -570,7 +630,7 @@ async fn has_income_transaction(world: &mut WalletBotWorld, amount: i32, descrip
             world.current_chat_id.0,
             "收入",
             &world.current_user,
-            amount as f64,
+            Money::from_yuan(amount as i64),
             &format!("tx_{}", rand::random::<u32>()),
             &description,
         )
@@ -585,7 +645,7 @@ async fn has_expense_transaction(world: &mut WalletBotWorld, amount: i32, descri
             world.current_chat_id.0,
             "支出",
             &world.current_user,
-            -(amount as f64),
+            -Money::from_yuan(amount as i64),
             &format!("tx_{}", rand::random::<u32>()),
             &description,
         )
@@ -600,7 +660,7 @@ async fn calculate_wallet_balance(world: &mut WalletBotWorld) {
         .await
         .unwrap();
     // 余额已经在数据库中计算好了
-    assert!(balance >= 0.0);
+    assert!(balance >= Money::ZERO);
 }
 
 #[when(expr = "我计算钱包余额 钱包名称为 {string}")]
@@ -610,9 +670,9 @@ async fn calculate_wallet_balance_with_name(world: &mut WalletBotWorld, wallet_n
     let balance = database
         .get_balance(world.current_chat_id.0, &wallet_name)
         .await
-        .unwrap_or(0.0);
+        .unwrap_or(Money::ZERO);
     // 余额已经在数据库中计算好了
-    assert!(balance >= 0.0);
+    assert!(balance >= Money::ZERO);
 }
 
 #[then(expr = "余额应该是 {int}")]
@@ -622,7 +682,7 @@ async fn balance_should_be(world: &mut WalletBotWorld, expected_balance: i32) {
         .get_balance(world.current_chat_id.0, &world.current_user)
         .await
         .unwrap();
-    assert_eq!(balance, expected_balance as f64);
+    assert_eq!(balance, Money::from_yuan(expected_balance as i64));
 }
 
 #[when(expr = "我获取钱包的交易记录")]
@@ -681,30 +741,18 @@ async fn received_message(world: &mut WalletBotWorld, message: String) {
 
 #[when(expr = "我解析这条消息")]
 async fn parse_message(world: &mut WalletBotWorld) {
-    // 先尝试简化的交易解析格式（"收入 100 工作收入"）
-    if let Ok(transaction) = world
-        .message_parser
-        .parse_transaction(&world.current_message_text)
-    {
-        world.simple_parse_result = Some(transaction);
-    } else {
-        // 如果简化解析失败，尝试完整的钱包消息解析
-        let result = world.message_parser.parse(&world.current_message_text);
-        world.parse_result = result;
-    }
+    world.parse_result = world.message_parser.parse(&world.current_message_text);
 }
 
 #[then(expr = "解析结果应该是成功的")]
 async fn parse_result_should_be_success(world: &mut WalletBotWorld) {
-    assert!(world.parse_result.is_some() || world.simple_parse_result.is_some());
+    assert!(world.parse_result.is_some());
 }
 
 #[then(expr = "交易类型应该是 {string}")]
 async fn transaction_type_should_be(world: &mut WalletBotWorld, expected_type: String) {
-    if let Some(result) = &world.simple_parse_result {
-        assert_eq!(result.transaction_type, expected_type);
-    } else if let Some(result) = &world.parse_result {
-        assert_eq!(result.transaction_type, expected_type);
+    if let Some(result) = &world.parse_result {
+        assert_eq!(result.items[0].transaction_type, expected_type);
     } else {
         panic!("No parse result available");
     }
@@ -712,27 +760,19 @@ async fn transaction_type_should_be(world: &mut WalletBotWorld, expected_type: S
 
 #[then(expr = "金额应该是 {float}")]
 async fn amount_should_be(world: &mut WalletBotWorld, expected_amount: f64) {
-    if let Some(result) = &world.simple_parse_result {
-        assert_eq!(result.amount, expected_amount);
-    } else if let Some(result) = &world.parse_result {
-        assert_eq!(result.amount, expected_amount);
+    if let Some(result) = &world.parse_result {
+        assert_eq!(
+            result.items[0].amount,
+            Money::from_f64(expected_amount).unwrap()
+        );
     } else {
         panic!("No parse result available");
     }
 }
 
-#[then(expr = "描述应该是 {string}")]
-async fn description_should_be(world: &mut WalletBotWorld, expected_description: String) {
-    if let Some(result) = &world.simple_parse_result {
-        assert_eq!(result.description, expected_description);
-    } else {
-        panic!("No simple parse result available for description check");
-    }
-}
-
 #[then(expr = "解析结果应该是失败的")]
 async fn parse_result_should_be_failure(world: &mut WalletBotWorld) {
-    assert!(world.parse_result.is_none() && world.simple_parse_result.is_none());
+    assert!(world.parse_result.is_none());
 }
 
 // 错误处理步骤实现
@@ -744,28 +784,18 @@ async fn system_initialized(world: &mut WalletBotWorld) {
 
 #[when(expr = "我尝试解析这条消息")]
 async fn try_parse_message(world: &mut WalletBotWorld) {
-    // 先尝试简化的交易解析格式
-    if let Ok(transaction) = world
-        .message_parser
-        .parse_transaction(&world.current_message_text)
-    {
-        world.simple_parse_result = Some(transaction);
-    } else {
-        // 如果简化解析失败，尝试完整的钱包消息解析
-        let result = world.message_parser.parse(&world.current_message_text);
-        world.parse_result = result;
-    }
+    world.parse_result = world.message_parser.parse(&world.current_message_text);
 }
 
 #[then(expr = "应该返回解析错误")]
 async fn should_return_parse_error(world: &mut WalletBotWorld) {
-    assert!(world.parse_result.is_none() && world.simple_parse_result.is_none());
+    assert!(world.parse_result.is_none());
 }
 
 #[then(expr = "错误信息应该包含 {string}")]
 async fn error_message_should_contain(world: &mut WalletBotWorld, _expected_message: String) {
     // 这里可以根据实际的错误处理机制来实现
-    assert!(world.parse_result.is_none() && world.simple_parse_result.is_none());
+    assert!(world.parse_result.is_none());
 }
 
 #[given(expr = "用户 {string} 不存在")]
@@ -869,8 +899,8 @@ async fn process_message(world: &mut WalletBotWorld) {
             .add_transaction(
                 world.current_chat_id.0,
                 &parsed.wallet_name,
-                &parsed.transaction_type,
-                parsed.amount,
+                &parsed.items[0].transaction_type,
+                parsed.items[0].amount,
                 "从消息解析的交易",
                 &transaction_id,
             )
@@ -880,7 +910,7 @@ async fn process_message(world: &mut WalletBotWorld) {
         let balance = database
             .get_balance(world.current_chat_id.0, &parsed.wallet_name)
             .await
-            .unwrap_or(0.0);
+            .unwrap_or(Money::ZERO);
         let confirmation_message = format!("交易已记录，当前余额: {balance:.2}元");
         let _ = world
             .bot_api
@@ -898,12 +928,10 @@ async fn process_message(world: &mut WalletBotWorld) {
 
 #[then(expr = "消息应该解析成功")]
 async fn message_should_parse_successfully(world: &mut WalletBotWorld) {
-    // 检查任一解析结果是否成功
-    let simple_result = world
+    assert!(world
         .message_parser
-        .parse_transaction(&world.current_message_text);
-    let full_result = world.message_parser.parse(&world.current_message_text);
-    assert!(simple_result.is_ok() || full_result.is_some());
+        .parse(&world.current_message_text)
+        .is_some());
 }
 
 #[then(expr = "交易应该记录到数据库")]
@@ -945,7 +973,7 @@ async fn user_wallet_balance_is(world: &mut WalletBotWorld, balance: i32) {
             world.current_chat_id.0,
             &world.current_user,                      // wallet_name
             "入账",                                   // transaction_type (使用标准类型)
-            balance as f64,                           // amount
+            Money::from_yuan(balance as i64),         // amount
             "初始余额",                               // description
             &format!("tx_{}", rand::random::<u32>()), // transaction_id
         )
@@ -977,7 +1005,7 @@ async fn user_has_transaction_records(world: &mut WalletBotWorld, count: i32) {
                 world.current_chat_id.0,
                 &world.current_user,
                 "收入",
-                i as f64,
+                Money::from_yuan(i as i64),
                 &format!("交易{i}"),
                 &format!("tx_{}_{}", i, rand::random::<u32>()),
             )
@@ -1150,7 +1178,7 @@ async fn record_income_transaction_with_details(
             world.current_chat_id.0,
             &wallet_name,
             "收入",
-            amount,
+            Money::from_f64(amount).unwrap(),
             "测试收入交易",
             "test_tx_id",
         )
@@ -1180,7 +1208,7 @@ async fn record_expense_transaction_with_details(
             world.current_chat_id.0,
             &wallet_name,
             "支出",
-            amount,
+            Money::from_f64(amount).unwrap(),
             "测试支出交易",
             "test_tx_id",
         )
@@ -1210,7 +1238,7 @@ async fn record_outgoing_transaction_with_details(
             world.current_chat_id.0,
             &wallet_name,
             "出账",
-            amount,
+            Money::from_f64(amount).unwrap(),
             "测试出账交易",
             "test_tx_id",
         )
@@ -1240,7 +1268,7 @@ async fn record_incoming_transaction_with_details(
             world.current_chat_id.0,
             &wallet_name,
             "入账",
-            amount,
+            Money::from_f64(amount).unwrap(),
             "测试入账交易",
             "test_tx_id",
         )
@@ -1268,7 +1296,7 @@ async fn has_income_transaction_with_details(
             world.current_chat_id.0,
             &wallet_name,
             "收入",
-            amount,
+            Money::from_f64(amount).unwrap(),
             "测试收入交易",
             "test_tx_income",
         )
@@ -1296,7 +1324,7 @@ async fn has_expense_transaction_with_details(
             world.current_chat_id.0,
             &wallet_name,
             "支出",
-            amount,
+            Money::from_f64(amount).unwrap(),
             "测试支出交易",
             "test_tx_expense",
         )
@@ -1323,11 +1351,11 @@ async fn user_has_wallet_with_balance_float(
         let _ = database
             .add_transaction(
                 world.current_chat_id.0,
-                &username,         // wallet_name
-                "入账",            // transaction_type
-                balance,           // amount
-                "初始余额设置",    // description
-                "initial_balance", // transaction_id
+                &username,                         // wallet_name
+                "入账",                            // transaction_type
+                Money::from_f64(balance).unwrap(), // amount
+                "初始余额设置",                    // description
+                "initial_balance",                 // transaction_id
             )
             .await;
     }
@@ -1354,11 +1382,11 @@ async fn user_wallet_balance_is_float(world: &mut WalletBotWorld, balance: f64)
         let _ = database
             .add_transaction(
                 world.current_chat_id.0,
-                wallet_name,             // wallet_name
-                "入账",                  // transaction_type
-                balance,                 // amount
-                "初始余额设置",          // description
-                "initial_balance_float", // transaction_id
+                wallet_name,                       // wallet_name
+                "入账",                            // transaction_type
+                Money::from_f64(balance).unwrap(), // amount
+                "初始余额设置",                    // description
+                "initial_balance_float",           // transaction_id
             )
             .await;
     }
@@ -1389,8 +1417,8 @@ async fn balance_should_be_float(world: &mut WalletBotWorld, expected_balance: f
     let balance = database
         .get_balance(world.current_chat_id.0, wallet_name)
         .await
-        .unwrap_or(0.0);
-    assert_eq!(balance, expected_balance);
+        .unwrap_or(Money::ZERO);
+    assert_eq!(balance, Money::from_f64(expected_balance).unwrap());
 }
 
 #[then(expr = "初始余额应该是 {float}")]
@@ -1404,8 +1432,8 @@ async fn initial_balance_should_be_float(world: &mut WalletBotWorld, expected_ba
     let balance = database
         .get_balance(world.current_chat_id.0, wallet_name)
         .await
-        .unwrap_or(0.0);
-    assert_eq!(balance, expected_balance);
+        .unwrap_or(Money::ZERO);
+    assert_eq!(balance, Money::from_f64(expected_balance).unwrap());
 }
 
 // 新的步骤函数：支持频道相关的测试
@@ -1460,7 +1488,7 @@ async fn channel_has_wallet_with_balance(
                 chat_id_val,
                 &wallet_name,
                 "入账", // 使用标准的交易类型
-                balance,
+                Money::from_f64(balance).unwrap(),
                 "初始余额设置",
                 "initial_balance",
             )
@@ -1536,8 +1564,8 @@ async fn when_user_sends_message_in_channel(
             .add_transaction(
                 chat_id_val,
                 &parsed.wallet_name,
-                &parsed.transaction_type,
-                parsed.amount,
+                &parsed.items[0].transaction_type,
+                parsed.items[0].amount,
                 "从频道消息解析的交易",
                 &transaction_id,
             )
@@ -1557,8 +1585,8 @@ async fn channel_wallet_balance_should_be(
     let balance = database
         .get_balance(chat_id_val, &wallet_name)
         .await
-        .unwrap_or(0.0);
-    assert_eq!(balance, expected_balance);
+        .unwrap_or(Money::ZERO);
+    assert_eq!(balance, Money::from_f64(expected_balance).unwrap());
 }
 
 #[tokio::main]
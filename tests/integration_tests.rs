@@ -1,13 +1,18 @@
 use anyhow::Result;
 use async_trait::async_trait;
 use serial_test::serial;
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
 // 导入我们需要测试的模块
 use walletbot::bot::handler::MessageHandler;
 use walletbot::bot::traits::BotApi;
+use walletbot::calculator::balance::BalanceCalculator;
+use walletbot::database::migrations;
 use walletbot::database::operations::DatabaseOperations;
+use walletbot::error::WalletBotError;
+use walletbot::money::Money;
 use walletbot::parser::message::MessageParser;
 
 // 测试用的常量
@@ -15,8 +20,8 @@ const TEST_CHAT_ID: i64 = 12345;
 
 use chrono::Utc;
 use teloxide::types::{
-    Chat, ChatId, MediaKind, MediaText, Message, MessageCommon, MessageId, MessageKind, User,
-    UserId,
+    Chat, ChatId, InlineKeyboardMarkup, MediaKind, MediaText, Message, MessageCommon, MessageId,
+    MessageKind, User, UserId,
 };
 use teloxide::RequestError;
 
@@ -26,6 +31,11 @@ pub struct MockBotApi {
     pub sent_messages: Arc<Mutex<Vec<MockSentMessage>>>,
     pub edited_messages: Arc<Mutex<Vec<MockEditedMessage>>>,
     pub deleted_messages: Arc<Mutex<Vec<MockDeletedMessage>>>,
+    pub sent_documents: Arc<Mutex<Vec<MockSentDocument>>>,
+    pub answered_callbacks: Arc<Mutex<Vec<String>>>,
+    pub files: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    pub chat_admins: Arc<Mutex<Vec<(ChatId, UserId)>>>,
+    pub left_chats: Arc<Mutex<Vec<ChatId>>>,
     pub should_fail: Arc<Mutex<bool>>,
 }
 
@@ -34,6 +44,7 @@ pub struct MockSentMessage {
     pub chat_id: ChatId,
     pub text: String,
     pub reply_to_message_id: Option<MessageId>,
+    pub keyboard: Option<InlineKeyboardMarkup>,
 }
 
 #[derive(Debug, Clone)]
@@ -49,6 +60,14 @@ pub struct MockDeletedMessage {
     pub message_id: MessageId,
 }
 
+#[derive(Debug, Clone)]
+pub struct MockSentDocument {
+    pub chat_id: ChatId,
+    pub file_name: String,
+    pub content: Vec<u8>,
+    pub caption: String,
+}
+
 impl Default for MockBotApi {
     fn default() -> Self {
         Self::new()
@@ -61,6 +80,11 @@ impl MockBotApi {
             sent_messages: Arc::new(Mutex::new(Vec::new())),
             edited_messages: Arc::new(Mutex::new(Vec::new())),
             deleted_messages: Arc::new(Mutex::new(Vec::new())),
+            sent_documents: Arc::new(Mutex::new(Vec::new())),
+            answered_callbacks: Arc::new(Mutex::new(Vec::new())),
+            files: Arc::new(Mutex::new(HashMap::new())),
+            chat_admins: Arc::new(Mutex::new(Vec::new())),
+            left_chats: Arc::new(Mutex::new(Vec::new())),
             should_fail: Arc::new(Mutex::new(false)),
         }
     }
@@ -81,10 +105,55 @@ impl MockBotApi {
         self.deleted_messages.lock().await.clone()
     }
 
+    pub async fn get_sent_documents(&self) -> Vec<MockSentDocument> {
+        self.sent_documents.lock().await.clone()
+    }
+
+    /// 登记一个可以通过 download_file 下载的文件
+    pub async fn add_file(&self, file_id: &str, content: &[u8]) {
+        self.files
+            .lock()
+            .await
+            .insert(file_id.to_string(), content.to_vec());
+    }
+
+    /// 把用户设为群组管理员
+    pub async fn add_chat_admin(&self, chat_id: ChatId, user_id: UserId) {
+        self.chat_admins.lock().await.push((chat_id, user_id));
+    }
+
     pub async fn clear_all(&self) {
         self.sent_messages.lock().await.clear();
         self.edited_messages.lock().await.clear();
         self.deleted_messages.lock().await.clear();
+        self.sent_documents.lock().await.clear();
+        self.answered_callbacks.lock().await.clear();
+    }
+
+    async fn check_should_fail(&self) -> Result<(), RequestError> {
+        if *self.should_fail.lock().await {
+            return Err(RequestError::Api(teloxide::ApiError::Unknown(
+                "Mock error".to_string(),
+            )));
+        }
+        Ok(())
+    }
+
+    /// 群组中某个成员发送的消息
+    fn create_group_message(
+        chat_id: ChatId,
+        message_id: MessageId,
+        user_id: u64,
+        text: &str,
+    ) -> Message {
+        serde_json::from_value(serde_json::json!({
+            "message_id": message_id.0,
+            "date": Utc::now().timestamp(),
+            "chat": {"id": chat_id.0, "type": "supergroup", "title": "记账群"},
+            "from": {"id": user_id, "is_bot": false, "first_name": "成员"},
+            "text": text
+        }))
+        .expect("valid group message")
     }
 
     fn create_mock_message(chat_id: ChatId, message_id: MessageId, text: &str) -> Message {
@@ -158,6 +227,7 @@ impl BotApi for MockBotApi {
             chat_id,
             text: text.to_string(),
             reply_to_message_id: None,
+            keyboard: None,
         });
 
         Ok(Self::create_mock_message(chat_id, message_id, text))
@@ -205,6 +275,16 @@ impl BotApi for MockBotApi {
         Ok(())
     }
 
+    async fn delete_message_later(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        _delay: std::time::Duration,
+    ) {
+        // mock 不等待，直接删除
+        let _ = self.delete_message(chat_id, message_id).await;
+    }
+
     async fn reply_to_message(
         &self,
         message: &Message,
@@ -222,10 +302,94 @@ impl BotApi for MockBotApi {
             chat_id: message.chat.id,
             text: text.to_string(),
             reply_to_message_id: Some(message.id),
+            keyboard: None,
         });
 
         Ok(Self::create_mock_message(message.chat.id, message_id, text))
     }
+
+    async fn send_message_with_keyboard(
+        &self,
+        chat_id: ChatId,
+        text: &str,
+        keyboard: Option<InlineKeyboardMarkup>,
+    ) -> Result<Message, RequestError> {
+        self.check_should_fail().await?;
+
+        let message_id = MessageId(rand::random::<i32>().abs());
+        self.sent_messages.lock().await.push(MockSentMessage {
+            chat_id,
+            text: text.to_string(),
+            reply_to_message_id: None,
+            keyboard,
+        });
+
+        Ok(Self::create_mock_message(chat_id, message_id, text))
+    }
+
+    async fn edit_message_with_keyboard(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        text: &str,
+        _keyboard: Option<InlineKeyboardMarkup>,
+    ) -> Result<Message, RequestError> {
+        self.edit_message_text(chat_id, message_id, text).await
+    }
+
+    async fn answer_callback_query(&self, query_id: &str) -> Result<(), RequestError> {
+        self.check_should_fail().await?;
+        self.answered_callbacks
+            .lock()
+            .await
+            .push(query_id.to_string());
+        Ok(())
+    }
+
+    async fn send_document(
+        &self,
+        chat_id: ChatId,
+        file_name: &str,
+        content: Vec<u8>,
+        caption: &str,
+    ) -> Result<Message, RequestError> {
+        self.check_should_fail().await?;
+
+        let message_id = MessageId(rand::random::<i32>().abs());
+        self.sent_documents.lock().await.push(MockSentDocument {
+            chat_id,
+            file_name: file_name.to_string(),
+            content,
+            caption: caption.to_string(),
+        });
+
+        Ok(Self::create_mock_message(chat_id, message_id, caption))
+    }
+
+    async fn is_chat_admin(&self, chat_id: ChatId, user_id: UserId) -> Result<bool, RequestError> {
+        self.check_should_fail().await?;
+        Ok(self.chat_admins.lock().await.contains(&(chat_id, user_id)))
+    }
+
+    async fn leave_chat(&self, chat_id: ChatId) -> Result<(), RequestError> {
+        self.check_should_fail().await?;
+        self.left_chats.lock().await.push(chat_id);
+        Ok(())
+    }
+
+    async fn download_file(&self, file_id: &str) -> Result<Vec<u8>, RequestError> {
+        self.check_should_fail().await?;
+        self.files
+            .lock()
+            .await
+            .get(file_id)
+            .cloned()
+            .ok_or_else(|| {
+                RequestError::Api(teloxide::ApiError::Unknown(format!(
+                    "File not found: {file_id}"
+                )))
+            })
+    }
 }
 
 // 测试辅助函数
@@ -253,8 +417,9 @@ async fn test_message_parser() -> Result<()> {
     assert!(parsed.is_some());
     let parsed = parsed.unwrap();
     assert_eq!(parsed.wallet_name, "支付宝");
-    assert_eq!(parsed.transaction_type, "出账");
-    assert_eq!(parsed.amount, 150.0);
+    assert_eq!(parsed.items.len(), 1);
+    assert_eq!(parsed.items[0].transaction_type, "出账");
+    assert_eq!(parsed.items[0].amount, Money::from_yuan(150));
     assert_eq!(parsed.month, "12月");
     assert_eq!(parsed.year, "2024年");
 
@@ -266,10 +431,39 @@ async fn test_message_parser() -> Result<()> {
     let message_with_total = "#支付宝 #12月 #2024年\n#出账 150.00元\n#总额 1000.00元";
     assert!(parser.has_total(message_with_total));
     assert_eq!(
-        parser.extract_total_amount(message_with_total),
-        Some(1000.0)
+        parser.parse(message_with_total).unwrap().total_amount,
+        Some(Money::from_yuan(1000))
     );
 
+    // 按聊天货币单位追加的总额
+    for (total, expected) in [
+        ("#总额 ¥1000.00", 100000),
+        ("#总额 $-5.50", -550),
+        ("#总额 HK$12.00", 1200),
+    ] {
+        let text = format!("#支付宝 #12月 #2024年\n#出账 150.00元\n{total}");
+        assert_eq!(
+            parser.parse(&text).unwrap().total_amount,
+            Some(Money::from_cents(expected))
+        );
+        assert_eq!(
+            parser.strip_total(&text),
+            "#支付宝 #12月 #2024年\n#出账 150.00元"
+        );
+    }
+    assert!(!parser.has_total("#支付宝 #12月 #2024年\n#出账 150.00元\n#总额 1000"));
+
+    // 金额超过两位小数时拒绝整条消息，不能只记其余的明细
+    assert!(parser
+        .parse("#支付宝 #12月 #2024年\n#出账 10.555元\n#出账 20元")
+        .is_none());
+    assert!(parser
+        .parse("#支付宝 #12月 #2024年\n#出账 20元\n#总额 100.555元")
+        .is_none());
+    assert!(parser
+        .parse_transfer("#转账 #支付宝 → #银行卡 #12月 #2024年\n500.555元")
+        .is_none());
+
     println!("✅ 消息解析器测试通过");
     Ok(())
 }
@@ -283,20 +477,20 @@ async fn test_database_operations() -> Result<()> {
     // 测试创建钱包
     let wallet = db.get_or_create_wallet(TEST_CHAT_ID, "测试钱包").await?;
     assert_eq!(wallet.name, "测试钱包");
-    assert_eq!(wallet.current_balance, 0.0);
+    assert_eq!(wallet.current_balance, Money::ZERO);
 
     // 测试更新余额
-    db.update_wallet_balance(TEST_CHAT_ID, "测试钱包", 1000.0)
+    db.update_wallet_balance(TEST_CHAT_ID, "测试钱包", Money::from_yuan(1000))
         .await?;
     let updated_wallet = db.get_or_create_wallet(TEST_CHAT_ID, "测试钱包").await?;
-    assert_eq!(updated_wallet.current_balance, 1000.0);
+    assert_eq!(updated_wallet.current_balance, Money::from_yuan(1000));
 
     // 测试记录交易
     db.record_transaction(
         TEST_CHAT_ID,
         "测试钱包",
         "出账",
-        150.0,
+        Money::from_yuan(150),
         "12",
         "2024",
         Some(456),
@@ -309,8 +503,8 @@ async fn test_database_operations() -> Result<()> {
         TEST_CHAT_ID,
         "测试钱包",
         true,
-        Some(1000.0),
-        Some(850.0),
+        Some(Money::from_yuan(1000)),
+        Some(Money::from_yuan(850)),
     )
     .await?;
 
@@ -408,8 +602,15 @@ async fn test_duplicate_message_handling() -> Result<()> {
     let _wallet = db.get_or_create_wallet(TEST_CHAT_ID, "支付宝").await?;
 
     // 记录一条消息
-    db.record_message(123, TEST_CHAT_ID, "支付宝", true, Some(1000.0), Some(850.0))
-        .await?;
+    db.record_message(
+        123,
+        TEST_CHAT_ID,
+        "支付宝",
+        true,
+        Some(Money::from_yuan(1000)),
+        Some(Money::from_yuan(850)),
+    )
+    .await?;
 
     // 检查是否已处理
     let is_processed = db.is_message_processed(123, TEST_CHAT_ID).await?;
@@ -436,14 +637,19 @@ async fn test_complete_message_flow() -> Result<()> {
             "#支付宝 #12月 #2024年\n#出账 150.00元",
             "支付宝",
             "出账",
-            150.0,
+            Money::from_yuan(150),
+        ),
+        (
+            "#微信 #12月 #2024年\n#入账 200.00元",
+            "微信",
+            "入账",
+            Money::from_yuan(200),
         ),
-        ("#微信 #12月 #2024年\n#入账 200.00元", "微信", "入账", 200.0),
         (
             "#支付宝 #12月 #2024年\n#入账 50.00元",
             "支付宝",
             "入账",
-            50.0,
+            Money::from_yuan(50),
         ),
     ];
 
@@ -455,8 +661,8 @@ async fn test_complete_message_flow() -> Result<()> {
 
         let parsed = parsed.unwrap();
         assert_eq!(parsed.wallet_name, wallet_name);
-        assert_eq!(parsed.transaction_type, transaction_type);
-        assert_eq!(parsed.amount, amount);
+        assert_eq!(parsed.items[0].transaction_type, transaction_type);
+        assert_eq!(parsed.items[0].amount, amount);
 
         // 验证数据库操作
         let wallet = db.get_or_create_wallet(TEST_CHAT_ID, wallet_name).await?;
@@ -495,7 +701,7 @@ async fn test_performance() -> Result<()> {
             TEST_CHAT_ID,
             "性能测试钱包",
             "出账",
-            100.0,
+            Money::from_yuan(100),
             "12",
             "2024",
             Some(12345),
@@ -562,23 +768,39 @@ async fn test_multi_chat_wallet_isolation() -> Result<()> {
     assert_eq!(wallet_2.chat_id, chat_id_2);
 
     // 在不同聊天中添加不同余额
-    db.update_wallet_balance(chat_id_1, wallet_name, 100.0)
+    db.update_wallet_balance(chat_id_1, wallet_name, Money::from_yuan(100))
         .await?;
-    db.update_wallet_balance(chat_id_2, wallet_name, 200.0)
+    db.update_wallet_balance(chat_id_2, wallet_name, Money::from_yuan(200))
         .await?;
 
     // 验证余额隔离
     let balance_1 = db.get_balance(chat_id_1, wallet_name).await?;
     let balance_2 = db.get_balance(chat_id_2, wallet_name).await?;
 
-    assert_eq!(balance_1, 100.0);
-    assert_eq!(balance_2, 200.0);
+    assert_eq!(balance_1, Money::from_yuan(100));
+    assert_eq!(balance_2, Money::from_yuan(200));
 
     // 在不同聊天中添加交易
-    db.record_transaction(chat_id_1, wallet_name, "入账", 50.0, "12", "2024", None)
-        .await?;
-    db.record_transaction(chat_id_2, wallet_name, "出账", 30.0, "12", "2024", None)
-        .await?;
+    db.record_transaction(
+        chat_id_1,
+        wallet_name,
+        "入账",
+        Money::from_yuan(50),
+        "12",
+        "2024",
+        None,
+    )
+    .await?;
+    db.record_transaction(
+        chat_id_2,
+        wallet_name,
+        "出账",
+        Money::from_yuan(30),
+        "12",
+        "2024",
+        None,
+    )
+    .await?;
 
     // 验证交易隔离
     let transactions_1 = db.get_transactions(chat_id_1, wallet_name).await?;
@@ -611,7 +833,7 @@ async fn test_same_wallet_different_chats() -> Result<()> {
             assert_eq!(wallet.name, *wallet_name);
 
             // 设置不同的余额以区分
-            let initial_balance = (*chat_id as f64) / 1000.0; // 11.111, 22.222, 33.333
+            let initial_balance = Money::from_cents(*chat_id / 10); // 11.11, 22.22, 33.33
             db.update_wallet_balance(*chat_id, wallet_name, initial_balance)
                 .await?;
         }
@@ -621,7 +843,7 @@ async fn test_same_wallet_different_chats() -> Result<()> {
     for chat_id in &chat_ids {
         for wallet_name in &wallet_names {
             let balance = db.get_balance(*chat_id, wallet_name).await?;
-            let expected_balance = (*chat_id as f64) / 1000.0;
+            let expected_balance = Money::from_cents(*chat_id / 10);
             assert_eq!(balance, expected_balance);
 
             // 验证钱包存在性
@@ -631,10 +853,24 @@ async fn test_same_wallet_different_chats() -> Result<()> {
     }
 
     // 在不同聊天中操作同名钱包，验证互不干扰
-    db.add_transaction(chat_ids[0], "微信", "入账", 100.0, "测试交易", "tx1")
-        .await?;
-    db.add_transaction(chat_ids[1], "微信", "出账", 50.0, "测试交易", "tx2")
-        .await?;
+    db.add_transaction(
+        chat_ids[0],
+        "微信",
+        "入账",
+        Money::from_yuan(100),
+        "测试交易",
+        "tx1",
+    )
+    .await?;
+    db.add_transaction(
+        chat_ids[1],
+        "微信",
+        "出账",
+        Money::from_yuan(50),
+        "测试交易",
+        "tx2",
+    )
+    .await?;
 
     let balance_0 = db.get_balance(chat_ids[0], "微信").await?;
     let balance_1 = db.get_balance(chat_ids[1], "微信").await?;
@@ -645,3 +881,2853 @@ async fn test_same_wallet_different_chats() -> Result<()> {
     println!("✅ 不同聊天环境下相同钱包名称处理测试通过");
     Ok(())
 }
+
+// 按处理器的流程记账一条新消息
+async fn process_wallet_message(
+    db: &DatabaseOperations,
+    chat_id: i64,
+    message_id: i64,
+    text: &str,
+) -> Result<Money> {
+    let parser = MessageParser::new();
+    let calculator = BalanceCalculator::new(db.clone());
+    let parsed = parser.parse(text).expect("test message should parse");
+
+    let update = calculator
+        .process_message(chat_id, message_id, &parsed)
+        .await?;
+    Ok(update.new_balance)
+}
+
+#[tokio::test]
+async fn test_edited_message_reapplies_transaction() -> Result<()> {
+    let db = create_test_db().await?;
+    let parser = MessageParser::new();
+    let calculator = BalanceCalculator::new(db.clone());
+
+    db.get_or_create_wallet(TEST_CHAT_ID, "支付宝").await?;
+    db.update_wallet_balance(TEST_CHAT_ID, "支付宝", Money::from_yuan(1000))
+        .await?;
+
+    process_wallet_message(
+        &db,
+        TEST_CHAT_ID,
+        1,
+        "#支付宝 #12月 #2024年\n#出账 150.00元",
+    )
+    .await?;
+    process_wallet_message(&db, TEST_CHAT_ID, 2, "#支付宝 #12月 #2024年\n#入账 50.00元").await?;
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(900)
+    );
+
+    // 修正第一条消息的金额，保留机器人追加的旧总额
+    let edited_text = "#支付宝 #12月 #2024年\n#出账 15.00元\n#总额 850.00元";
+    let record = db.get_message_record(1, TEST_CHAT_ID).await?.unwrap();
+    let parsed = parser.parse(edited_text).unwrap();
+    let update = calculator
+        .recalculate_edited_message(TEST_CHAT_ID, &record, &parsed)
+        .await?;
+
+    assert_eq!(update.old_balance, Money::from_yuan(850));
+    assert_eq!(update.new_balance, Money::from_yuan(985));
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(1035)
+    );
+    assert_eq!(
+        parser.strip_total(edited_text),
+        "#支付宝 #12月 #2024年\n#出账 15.00元"
+    );
+
+    let transactions = db.get_message_transactions(1, TEST_CHAT_ID).await?;
+    assert_eq!(transactions.len(), 1);
+    assert_eq!(transactions[0].amount, Money::from_yuan(15));
+
+    // 之后消息的余额快照也随之更新
+    let later = db.get_message_record(2, TEST_CHAT_ID).await?.unwrap();
+    assert_eq!(later.original_balance, Some(Money::from_yuan(985)));
+    assert_eq!(later.new_balance, Some(Money::from_yuan(1035)));
+
+    // 手动总额之后的余额不受更早消息编辑的影响
+    process_wallet_message(
+        &db,
+        TEST_CHAT_ID,
+        3,
+        "#支付宝 #12月 #2024年\n#入账 10.00元\n#总额 2000.00元",
+    )
+    .await?;
+    let record = db.get_message_record(2, TEST_CHAT_ID).await?.unwrap();
+    let parsed = parser
+        .parse("#支付宝 #12月 #2024年\n#入账 500.00元\n#总额 1035.00元")
+        .unwrap();
+    calculator
+        .recalculate_edited_message(TEST_CHAT_ID, &record, &parsed)
+        .await?;
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(2000)
+    );
+
+    println!("✅ 编辑消息重新记账测试通过");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_edited_message_moves_to_other_wallet() -> Result<()> {
+    let db = create_test_db().await?;
+    let parser = MessageParser::new();
+    let calculator = BalanceCalculator::new(db.clone());
+
+    process_wallet_message(
+        &db,
+        TEST_CHAT_ID,
+        1,
+        "#支付宝 #12月 #2024年\n#出账 100.00元",
+    )
+    .await?;
+    process_wallet_message(&db, TEST_CHAT_ID, 2, "#支付宝 #12月 #2024年\n#出账 20.00元").await?;
+
+    let record = db.get_message_record(1, TEST_CHAT_ID).await?.unwrap();
+    let parsed = parser
+        .parse("#微信 #12月 #2024年\n#出账 100.00元\n#总额 -100.00元")
+        .unwrap();
+    let update = calculator
+        .recalculate_edited_message(TEST_CHAT_ID, &record, &parsed)
+        .await?;
+
+    assert_eq!(update.new_balance, Money::from_yuan(-100));
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(-20)
+    );
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "微信").await?,
+        Money::from_yuan(-100)
+    );
+
+    println!("✅ 编辑消息更换钱包测试通过");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_edited_message_keeps_transaction_history() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("edits.db");
+    let db = DatabaseOperations::new(path.to_str().unwrap()).await?;
+    let handler = MessageHandler::new(db.clone());
+    let bot = MockBotApi::new();
+    let parser = MessageParser::new();
+    let calculator = BalanceCalculator::new(db.clone());
+
+    let text = "#支付宝 #12月 #2024年\n#出账 30.00元";
+    let message = MockBotApi::create_mock_message(ChatId(TEST_CHAT_ID), MessageId(1), text);
+    handler.handle_message(&bot, &message).await?;
+    // /reprocess 会把旧记录标记为作废并重新记账
+    let processed = MockBotApi::create_mock_message(
+        ChatId(TEST_CHAT_ID),
+        MessageId(1),
+        &format!("{text}\n#总额 -30.00元"),
+    );
+    handler.reprocess_message(&bot, &processed).await?;
+    let before = db.get_message_transactions(1, TEST_CHAT_ID).await?;
+    assert_eq!(before.len(), 1);
+
+    // 编辑时原地更新未作废的交易，新增的明细沿用原消息的记录时间
+    let record = db.get_message_record(1, TEST_CHAT_ID).await?.unwrap();
+    let parsed = parser
+        .parse("#支付宝 #12月 #2024年\n#出账 20.00元\n#出账 5.00元\n#总额 -30.00元")
+        .unwrap();
+    calculator
+        .recalculate_edited_message(TEST_CHAT_ID, &record, &parsed)
+        .await?;
+    let after = db.get_message_transactions(1, TEST_CHAT_ID).await?;
+    assert_eq!(after.len(), 2);
+    assert_eq!(after[0].id, before[0].id);
+    assert_eq!(after[0].created_at, before[0].created_at);
+    assert_eq!(after[0].amount, Money::from_yuan(20));
+    assert_eq!(after[1].created_at, before[0].created_at);
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(-25)
+    );
+
+    // 明细变少时只删除多出的交易
+    let record = db.get_message_record(1, TEST_CHAT_ID).await?.unwrap();
+    let parsed = parser
+        .parse("#支付宝 #12月 #2024年\n#出账 10.00元\n#总额 -25.00元")
+        .unwrap();
+    calculator
+        .recalculate_edited_message(TEST_CHAT_ID, &record, &parsed)
+        .await?;
+    let after = db.get_message_transactions(1, TEST_CHAT_ID).await?;
+    assert_eq!(after.len(), 1);
+    assert_eq!(after[0].id, before[0].id);
+    drop(db);
+
+    // 作废的审计记录仍然保留
+    let conn = rusqlite::Connection::open(&path)?;
+    let voided: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM transactions WHERE message_id = 1 AND voided_at IS NOT NULL",
+        [],
+        |row| row.get(0),
+    )?;
+    assert_eq!(voided, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_process_message_is_atomic() -> Result<()> {
+    let db = create_test_db().await?;
+
+    let balance = process_wallet_message(
+        &db,
+        TEST_CHAT_ID,
+        1,
+        "#支付宝 #12月 #2024年\n#入账 200.00元",
+    )
+    .await?;
+    assert_eq!(balance, Money::from_yuan(200));
+    assert!(db.is_message_processed(1, TEST_CHAT_ID).await?);
+    assert_eq!(db.get_transactions(TEST_CHAT_ID, "支付宝").await?.len(), 1);
+
+    let record = db.get_message_record(1, TEST_CHAT_ID).await?.unwrap();
+    assert_eq!(record.original_balance, Some(Money::ZERO));
+    assert_eq!(record.new_balance, Some(Money::from_yuan(200)));
+    assert!(!record.has_total);
+
+    // 工作单元中途失败时，之前的写操作全部回滚
+    let result: Result<()> = db
+        .unit_of_work(|uow| {
+            uow.update_wallet_balance(TEST_CHAT_ID, "支付宝", Money::from_yuan(50))?;
+            uow.record_transaction(
+                TEST_CHAT_ID,
+                "支付宝",
+                "出账",
+                Money::from_yuan(150),
+                "12月",
+                "2024年",
+                Some(2),
+            )?;
+            uow.record_message(
+                2,
+                TEST_CHAT_ID,
+                "不存在的钱包",
+                false,
+                Some(Money::from_yuan(200)),
+                Some(Money::from_yuan(50)),
+            )
+        })
+        .await;
+    assert!(result.is_err());
+
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(200)
+    );
+    assert_eq!(db.get_transactions(TEST_CHAT_ID, "支付宝").await?.len(), 1);
+    assert!(!db.is_message_processed(2, TEST_CHAT_ID).await?);
+
+    println!("✅ 消息原子处理测试通过");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_legacy_real_balances_are_converted_to_cents() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("legacy.db");
+
+    // 模拟旧版本以 REAL（元）存储金额的数据库
+    {
+        let conn = rusqlite::Connection::open(&path)?;
+        conn.execute_batch(
+            "CREATE TABLE wallets (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                chat_id INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                current_balance REAL NOT NULL DEFAULT 0.0,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                UNIQUE(chat_id, name)
+            );
+            CREATE TABLE transactions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                wallet_id INTEGER NOT NULL,
+                transaction_type TEXT NOT NULL,
+                amount REAL NOT NULL,
+                month TEXT NOT NULL,
+                year TEXT NOT NULL,
+                message_id INTEGER,
+                chat_id INTEGER,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (wallet_id) REFERENCES wallets(id)
+            );
+            CREATE TABLE messages (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                message_id INTEGER NOT NULL,
+                chat_id INTEGER NOT NULL,
+                wallet_id INTEGER NOT NULL,
+                has_total BOOLEAN DEFAULT FALSE,
+                processed BOOLEAN DEFAULT FALSE,
+                original_balance REAL,
+                new_balance REAL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (wallet_id) REFERENCES wallets(id),
+                UNIQUE(message_id, chat_id)
+            );
+            INSERT INTO wallets (chat_id, name, current_balance) VALUES (12345, '支付宝', 1234.5699999);
+            INSERT INTO transactions (wallet_id, transaction_type, amount, month, year, message_id, chat_id)
+                VALUES (1, '入账', 0.1, '12月', '2024年', 1, 12345);
+            INSERT INTO messages (message_id, chat_id, wallet_id, has_total, processed, original_balance, new_balance)
+                VALUES (1, 12345, 1, TRUE, TRUE, 1234.4699999, 1234.5699999);",
+        )?;
+    }
+
+    let db = DatabaseOperations::new(path.to_str().unwrap()).await?;
+    assert_eq!(db.schema_version().await?, migrations::latest_version());
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_cents(123457)
+    );
+
+    let transactions = db.get_transactions(TEST_CHAT_ID, "支付宝").await?;
+    assert_eq!(transactions.len(), 1);
+    assert_eq!(transactions[0].amount, Money::from_cents(10));
+
+    let record = db.get_message_record(1, TEST_CHAT_ID).await?.unwrap();
+    assert_eq!(record.original_balance, Some(Money::from_cents(123447)));
+    assert_eq!(record.new_balance, Some(Money::from_cents(123457)));
+
+    // 再次打开时不会重复换算
+    drop(db);
+    let db = DatabaseOperations::new(path.to_str().unwrap()).await?;
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_cents(123457)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_schema_migrations_are_versioned() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("versioned.db");
+    let path = path.to_str().unwrap();
+
+    let db = DatabaseOperations::new(path).await?;
+    assert_eq!(db.schema_version().await?, migrations::latest_version());
+    db.get_or_create_wallet(TEST_CHAT_ID, "支付宝").await?;
+    db.update_wallet_balance(TEST_CHAT_ID, "支付宝", Money::from_cents(1050))
+        .await?;
+    drop(db);
+
+    // 重复启动不会重新执行迁移，也不会改动已有数据，外键检查仍然开启
+    let db = DatabaseOperations::new(path).await?;
+    assert_eq!(db.schema_version().await?, migrations::latest_version());
+    assert!(db.foreign_keys_enabled().await?);
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_cents(1050)
+    );
+    drop(db);
+
+    // 迁移结束后恢复连接原来的外键设置
+    {
+        let conn = rusqlite::Connection::open(dir.path().join("fresh.db"))?;
+        conn.pragma_update(None, "foreign_keys", false)?;
+        migrations::run(&conn)?;
+        let foreign_keys: bool = conn.query_row("PRAGMA foreign_keys", [], |row| row.get(0))?;
+        assert!(!foreign_keys);
+    }
+
+    // 模拟由更新版本的程序升级过的数据库
+    {
+        let conn = rusqlite::Connection::open(path)?;
+        conn.execute(
+            "INSERT INTO schema_version (version, description) VALUES (?1, 'from the future')",
+            [migrations::latest_version() + 1],
+        )?;
+    }
+
+    let err = DatabaseOperations::new(path).await.unwrap_err();
+    match err.downcast_ref::<WalletBotError>() {
+        Some(WalletBotError::SchemaTooNew { found, supported }) => {
+            assert_eq!(*found, migrations::latest_version() + 1);
+            assert_eq!(*supported, migrations::latest_version());
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reconcile_replays_ledger_and_fixes_drift() -> Result<()> {
+    let db = create_test_db().await?;
+    let calculator = BalanceCalculator::new(db.clone());
+
+    process_wallet_message(
+        &db,
+        TEST_CHAT_ID,
+        1,
+        "#支付宝 #12月 #2024年\n#入账 200.00元",
+    )
+    .await?;
+    process_wallet_message(&db, TEST_CHAT_ID, 2, "#支付宝 #12月 #2024年\n#出账 50.00元").await?;
+    // 手动总额检查点：余额直接以总额为准
+    process_wallet_message(
+        &db,
+        TEST_CHAT_ID,
+        3,
+        "#支付宝 #12月 #2024年\n#出账 10.00元\n#总额 500.00元",
+    )
+    .await?;
+    let balance =
+        process_wallet_message(&db, TEST_CHAT_ID, 4, "#支付宝 #12月 #2024年\n#收入 20.00元")
+            .await?;
+    assert_eq!(balance, Money::from_yuan(520));
+    db.add_transaction(TEST_CHAT_ID, "支付宝", "入账", Money::from_yuan(30), "", "")
+        .await?;
+    process_wallet_message(&db, TEST_CHAT_ID, 5, "#微信 #12月 #2024年\n#入账 8.50元").await?;
+
+    let result = calculator
+        .reconcile_wallet(TEST_CHAT_ID, "支付宝", false)
+        .await?;
+    assert!(result.is_consistent());
+    assert_eq!(result.ledger_balance, Money::from_yuan(550));
+    assert_eq!(result.transaction_count, 5);
+    assert_eq!(result.checkpoint_count, 1);
+
+    // 模拟被错误覆盖的余额
+    db.update_wallet_balance(TEST_CHAT_ID, "支付宝", Money::from_yuan(999))
+        .await?;
+
+    let results = calculator.reconcile_chat(TEST_CHAT_ID, false).await?;
+    assert_eq!(results.len(), 2);
+    let alipay = results.iter().find(|r| r.wallet_name == "支付宝").unwrap();
+    assert_eq!(alipay.stored_balance, Money::from_yuan(999));
+    assert_eq!(alipay.ledger_balance, Money::from_yuan(550));
+    assert_eq!(alipay.drift(), -Money::from_yuan(449));
+    assert!(!alipay.fixed);
+    let wechat = results.iter().find(|r| r.wallet_name == "微信").unwrap();
+    assert!(wechat.is_consistent());
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(999)
+    );
+
+    let results = calculator.reconcile_chat(TEST_CHAT_ID, true).await?;
+    assert!(results.iter().any(|r| r.fixed));
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(550)
+    );
+
+    let result = calculator
+        .reconcile_wallet(TEST_CHAT_ID, "支付宝", false)
+        .await?;
+    assert!(result.is_consistent());
+
+    // 不存在的钱包报错
+    assert!(calculator
+        .reconcile_wallet(TEST_CHAT_ID, "不存在的钱包", false)
+        .await
+        .is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_manual_totals_record_balance_adjustments() -> Result<()> {
+    let db = create_test_db().await?;
+    let parser = MessageParser::new();
+    let calculator = BalanceCalculator::new(db.clone());
+
+    process_wallet_message(
+        &db,
+        TEST_CHAT_ID,
+        1,
+        "#支付宝 #12月 #2024年\n#入账 200.00元",
+    )
+    .await?;
+    // 按交易应为 190，手动总额 150：凭空消失 40
+    process_wallet_message(
+        &db,
+        TEST_CHAT_ID,
+        2,
+        "#支付宝 #12月 #2024年\n#出账 10.00元\n#总额 150.00元",
+    )
+    .await?;
+    // 按交易应为 155，手动总额 200：凭空多出 45
+    process_wallet_message(
+        &db,
+        TEST_CHAT_ID,
+        3,
+        "#支付宝 #01月 #2025年\n#入账 5.00元\n#总额 200.00元",
+    )
+    .await?;
+    // 总额与交易一致时不产生调整
+    process_wallet_message(
+        &db,
+        TEST_CHAT_ID,
+        4,
+        "#支付宝 #01月 #2025年\n#入账 1.00元\n#总额 201.00元",
+    )
+    .await?;
+
+    let adjustments = db.get_balance_adjustments(TEST_CHAT_ID).await?;
+    assert_eq!(adjustments.len(), 2);
+    assert_eq!(adjustments[0].message_id, Some(2));
+    assert_eq!(adjustments[0].old_balance, Money::from_yuan(190));
+    assert_eq!(adjustments[0].new_balance, Money::from_yuan(150));
+    assert_eq!(adjustments[0].difference, -Money::from_yuan(40));
+    assert_eq!(adjustments[0].month, "12月");
+    assert_eq!(adjustments[0].year, "2024年");
+    assert_eq!(adjustments[1].difference, Money::from_yuan(45));
+
+    let months = db.get_monthly_adjustments(TEST_CHAT_ID).await?;
+    assert_eq!(months.len(), 2);
+    assert_eq!(
+        (months[0].year.as_str(), months[0].month.as_str()),
+        ("2024年", "12月")
+    );
+    assert_eq!(months[0].lost, Money::from_yuan(40));
+    assert_eq!(months[0].net(), -Money::from_yuan(40));
+    assert_eq!(
+        (months[1].year.as_str(), months[1].month.as_str()),
+        ("2025年", "01月")
+    );
+    assert_eq!(months[1].gained, Money::from_yuan(45));
+
+    // 更早的消息被编辑后，检查点的计算余额随之变化，调整差额也重新计算
+    let record = db.get_message_record(1, TEST_CHAT_ID).await?.unwrap();
+    let parsed = parser
+        .parse("#支付宝 #12月 #2024年\n#入账 250.00元\n#总额 200.00元")
+        .unwrap();
+    calculator
+        .recalculate_edited_message(TEST_CHAT_ID, &record, &parsed)
+        .await?;
+    let adjustments = db.get_balance_adjustments(TEST_CHAT_ID).await?;
+    assert_eq!(adjustments[0].old_balance, Money::from_yuan(240));
+    assert_eq!(adjustments[0].difference, -Money::from_yuan(90));
+
+    // 把手动总额改成与交易一致后，调整记录被移除
+    let record = db.get_message_record(3, TEST_CHAT_ID).await?.unwrap();
+    let parsed = parser
+        .parse("#支付宝 #01月 #2025年\n#入账 5.00元\n#总额 155.00元")
+        .unwrap();
+    calculator
+        .recalculate_edited_message(TEST_CHAT_ID, &record, &parsed)
+        .await?;
+    let adjustments = db.get_balance_adjustments(TEST_CHAT_ID).await?;
+    assert_eq!(adjustments.len(), 1);
+    assert_eq!(adjustments[0].message_id, Some(2));
+
+    // 账本核对仍然一致
+    let result = calculator
+        .reconcile_wallet(TEST_CHAT_ID, "支付宝", false)
+        .await?;
+    assert!(result.is_consistent());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_multiple_line_items_in_one_message() -> Result<()> {
+    let db = create_test_db().await?;
+    let parser = MessageParser::new();
+    let calculator = BalanceCalculator::new(db.clone());
+
+    let text = "#支付宝 #12月 #2024年\n#出账 午饭 30.00元\n晚饭：50元\n#入账 红包 20.00元";
+    let parsed = parser.parse(text).unwrap();
+    assert_eq!(parsed.items.len(), 3);
+    assert_eq!(parsed.items[0].transaction_type, "出账");
+    assert_eq!(parsed.items[0].amount, Money::from_yuan(30));
+    assert_eq!(parsed.items[0].note.as_deref(), Some("午饭"));
+    // 没有交易标签的行沿用上一行的类型
+    assert_eq!(parsed.items[1].transaction_type, "出账");
+    assert_eq!(parsed.items[1].note.as_deref(), Some("晚饭"));
+    assert_eq!(parsed.items[2].transaction_type, "入账");
+    assert_eq!(parsed.delta(), -Money::from_yuan(60));
+
+    // 标题和交易写在同一行时没有备注
+    let parsed = parser
+        .parse("#支付宝 #12月 #2024年 #出账 100元\n#总额 900.00元")
+        .unwrap();
+    assert_eq!(parsed.items.len(), 1);
+    assert_eq!(parsed.items[0].note, None);
+    assert_eq!(parsed.total_amount, Some(Money::from_yuan(900)));
+
+    db.get_or_create_wallet(TEST_CHAT_ID, "支付宝").await?;
+    db.update_wallet_balance(TEST_CHAT_ID, "支付宝", Money::from_yuan(100))
+        .await?;
+    let balance = process_wallet_message(&db, TEST_CHAT_ID, 1, text).await?;
+    assert_eq!(balance, Money::from_yuan(40));
+
+    let transactions = db.get_message_transactions(1, TEST_CHAT_ID).await?;
+    assert_eq!(transactions.len(), 3);
+    assert_eq!(transactions[1].amount, Money::from_yuan(50));
+    assert_eq!(transactions[1].note.as_deref(), Some("晚饭"));
+
+    // 编辑后明细数量变化，交易记录随之重建
+    let record = db.get_message_record(1, TEST_CHAT_ID).await?.unwrap();
+    let parsed = parser
+        .parse("#支付宝 #12月 #2024年\n#出账 午饭 30.00元\n#总额 40.00元")
+        .unwrap();
+    let update = calculator
+        .recalculate_edited_message(TEST_CHAT_ID, &record, &parsed)
+        .await?;
+    assert_eq!(update.new_balance, Money::from_yuan(70));
+    assert_eq!(db.get_message_transactions(1, TEST_CHAT_ID).await?.len(), 1);
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(70)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_transfer_between_wallets() -> Result<()> {
+    let db = create_test_db().await?;
+    let parser = MessageParser::new();
+    let calculator = BalanceCalculator::new(db.clone());
+
+    let text = "#转账 #支付宝 → #银行卡 #12月 #2024年\n500.00元 还信用卡";
+    assert!(parser.is_transfer_message(text));
+    assert!(!parser.is_wallet_message(text));
+    let transfer = parser.parse_transfer(text).unwrap();
+    assert_eq!(transfer.from_wallet, "支付宝");
+    assert_eq!(transfer.to_wallet, "银行卡");
+    assert_eq!(transfer.amount, Money::from_yuan(500));
+    assert_eq!(transfer.month, "12月");
+    assert_eq!(transfer.note.as_deref(), Some("还信用卡"));
+
+    // 箭头可以省略或写成 -> / 到
+    for text in [
+        "#转账 #支付宝 #银行卡 #12月 #2024年 100元",
+        "#转账 #支付宝->#银行卡 #12月 #2024年 100元",
+        "#转账 #支付宝 到 #银行卡 #12月 #2024年 100元",
+    ] {
+        let transfer = parser.parse_transfer(text).unwrap();
+        assert_eq!(transfer.from_wallet, "支付宝", "{text}");
+        assert_eq!(transfer.to_wallet, "银行卡", "{text}");
+        assert_eq!(transfer.note, None, "{text}");
+    }
+    assert!(parser
+        .parse_transfer("#转账 #支付宝 → #支付宝 #12月 #2024年 100元")
+        .is_none());
+    assert!(parser
+        .parse_transfer("#转账 #12月 #2024年 #支付宝 100元")
+        .is_none());
+
+    process_wallet_message(
+        &db,
+        TEST_CHAT_ID,
+        1,
+        "#支付宝 #12月 #2024年\n#入账 800.00元",
+    )
+    .await?;
+    let update = calculator
+        .process_transfer(TEST_CHAT_ID, 2, &transfer)
+        .await?;
+    assert_eq!(update.from.old_balance, Money::from_yuan(800));
+    assert_eq!(update.from.new_balance, Money::from_yuan(300));
+    assert_eq!(update.to.old_balance, Money::ZERO);
+    assert_eq!(update.to.new_balance, Money::from_yuan(500));
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(300)
+    );
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "银行卡").await?,
+        Money::from_yuan(500)
+    );
+    assert!(db.is_message_processed(2, TEST_CHAT_ID).await?);
+
+    // 两行交易互相链接
+    let rows = db.get_message_transactions(2, TEST_CHAT_ID).await?;
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].transaction_type, "转出");
+    assert_eq!(rows[1].transaction_type, "转入");
+    assert_eq!(rows[0].linked_transaction_id, rows[1].id);
+    assert_eq!(rows[1].linked_transaction_id, rows[0].id);
+    assert_eq!(rows[1].note.as_deref(), Some("还信用卡"));
+
+    // 两侧的账本都能重放出一致的余额
+    for result in calculator.reconcile_chat(TEST_CHAT_ID, false).await? {
+        assert!(result.is_consistent(), "{result:?}");
+    }
+
+    // 转出钱包之后的消息照常计算
+    let balance = process_wallet_message(
+        &db,
+        TEST_CHAT_ID,
+        3,
+        "#银行卡 #12月 #2024年\n#出账 120.00元",
+    )
+    .await?;
+    assert_eq!(balance, Money::from_yuan(380));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_categories_are_parsed_and_grouped() -> Result<()> {
+    let db = create_test_db().await?;
+    let parser = MessageParser::new();
+
+    let text = "#支付宝 #12月 #2024年 #餐饮\n#出账 午饭 30.00元\n#出账 打车 20.00元 #交通\n#入账 报销 15.00元";
+    let parsed = parser.parse(text).unwrap();
+    let categories: Vec<_> = parsed
+        .items
+        .iter()
+        .map(|item| item.category.as_deref())
+        .collect();
+    // 标题行的分类是默认值，明细行自己的分类优先
+    assert_eq!(categories, [Some("餐饮"), Some("交通"), Some("餐饮")]);
+    assert_eq!(parsed.items[1].note.as_deref(), Some("打车"));
+
+    // 钱包、时间、交易类型标签不会被当成分类
+    let parsed = parser.parse("#微信 #01月 #2025年\n#支出 10元").unwrap();
+    assert_eq!(parsed.items[0].category, None);
+
+    process_wallet_message(&db, TEST_CHAT_ID, 1, text).await?;
+    process_wallet_message(
+        &db,
+        TEST_CHAT_ID,
+        2,
+        "#微信 #11月 #2024年\n#出账 火锅 #餐饮 100.00元\n#出账 杂项 5.00元",
+    )
+    .await?;
+    let transfer = parser
+        .parse_transfer("#转账 #支付宝 → #微信 #12月 #2024年 50元")
+        .unwrap();
+    BalanceCalculator::new(db.clone())
+        .process_transfer(TEST_CHAT_ID, 3, &transfer)
+        .await?;
+
+    let stored = db.get_message_transactions(1, TEST_CHAT_ID).await?;
+    assert_eq!(stored[1].category.as_deref(), Some("交通"));
+
+    let totals = db.get_category_totals(TEST_CHAT_ID, None, None).await?;
+    let summary: Vec<_> = totals
+        .iter()
+        .map(|t| (t.category.as_deref(), t.count, t.expense, t.income))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (Some("餐饮"), 3, Money::from_yuan(130), Money::from_yuan(15)),
+            (Some("交通"), 1, Money::from_yuan(20), Money::ZERO),
+            (None, 1, Money::from_yuan(5), Money::ZERO),
+        ]
+    );
+
+    let december = db
+        .get_category_totals(TEST_CHAT_ID, Some(12), Some(2024))
+        .await?;
+    assert_eq!(december.len(), 2);
+    assert_eq!(december[0].expense, Money::from_yuan(30));
+
+    let dining = db.get_category_transactions(TEST_CHAT_ID, "餐饮").await?;
+    assert_eq!(dining.len(), 3);
+    assert_eq!(dining[2].note.as_deref(), Some("火锅"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_wallet_summaries_for_balance_commands() -> Result<()> {
+    let db = create_test_db().await?;
+    assert!(db.get_wallet_summaries(TEST_CHAT_ID).await?.is_empty());
+
+    process_wallet_message(
+        &db,
+        TEST_CHAT_ID,
+        1,
+        "#支付宝 #12月 #2024年\n#入账 100.00元\n#出账 20.00元",
+    )
+    .await?;
+    process_wallet_message(&db, TEST_CHAT_ID, 2, "#微信 #12月 #2024年\n#入账 8.50元").await?;
+    process_wallet_message(&db, TEST_CHAT_ID + 1, 1, "#银行卡 #12月 #2024年\n#入账 1元").await?;
+
+    let wallets = db.get_wallet_summaries(TEST_CHAT_ID).await?;
+    let listed: Vec<_> = wallets
+        .iter()
+        .map(|w| (w.name.as_str(), w.balance, w.transaction_count))
+        .collect();
+    assert_eq!(
+        listed,
+        [
+            ("微信", Money::from_cents(850), 1),
+            ("支付宝", Money::from_yuan(80), 2),
+        ]
+    );
+    assert!(wallets.iter().all(|w| w.updated_at.is_some()));
+
+    let alipay = db
+        .get_wallet_summary(TEST_CHAT_ID, "支付宝")
+        .await?
+        .unwrap();
+    assert_eq!(
+        alipay.balance,
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?
+    );
+    assert!(db
+        .get_wallet_summary(TEST_CHAT_ID, "银行卡")
+        .await?
+        .is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_paginated_transaction_history() -> Result<()> {
+    use walletbot::database::models::TransactionFilter;
+    use walletbot::utils::Formatter;
+
+    let db = create_test_db().await?;
+    let chat_id = -1001234567890;
+    for i in 1..=12 {
+        let (month, kind) = if i <= 8 {
+            (12, "出账")
+        } else {
+            (11, "入账")
+        };
+        process_wallet_message(
+            &db,
+            chat_id,
+            i,
+            &format!("#支付宝 #{month}月 #2024年\n#{kind} {i}.00元"),
+        )
+        .await?;
+    }
+    process_wallet_message(&db, chat_id, 100, "#微信 #12月 #2024年\n#出账 1.00元").await?;
+
+    let filter = TransactionFilter {
+        wallet_name: Some("支付宝".to_string()),
+        ..Default::default()
+    };
+    let first = db.get_transactions_page(chat_id, &filter, 0, 5).await?;
+    assert_eq!(first.total, 12);
+    assert_eq!(first.page_count(), 3);
+    assert!(!first.has_previous() && first.has_next());
+    // 最新的交易在前
+    let ids: Vec<_> = first.transactions.iter().map(|t| t.message_id).collect();
+    assert_eq!(ids, [Some(12), Some(11), Some(10), Some(9), Some(8)]);
+
+    let last = db.get_transactions_page(chat_id, &filter, 2, 5).await?;
+    assert_eq!(last.transactions.len(), 2);
+    assert!(last.has_previous() && !last.has_next());
+
+    let filter = TransactionFilter {
+        wallet_name: Some("支付宝".to_string()),
+        month: Some(12),
+        year: Some(2024),
+        transaction_type: Some("出账".to_string()),
+        ..Default::default()
+    };
+    let december = db.get_transactions_page(chat_id, &filter, 0, 10).await?;
+    assert_eq!(december.total, 8);
+    assert!(december
+        .transactions
+        .iter()
+        .all(|t| t.transaction_type == "出账" && t.month == "12月"));
+
+    let text = Formatter::format_history(
+        &walletbot::database::models::ChatSettings::new(TEST_CHAT_ID),
+        "支付宝",
+        &december,
+    );
+    assert!(text.contains("第 1/1 页，共 8 笔"));
+    assert!(text.contains("https://t.me/c/1234567890/8"));
+    assert_eq!(Formatter::message_link(TEST_CHAT_ID, 1), None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_monthly_and_yearly_reports() -> Result<()> {
+    use walletbot::calculator::report::{ReportGenerator, ReportPeriod};
+
+    let db = create_test_db().await?;
+    let parser = MessageParser::new();
+    let messages = [
+        (1, "#支付宝 #11月 #2024年\n#入账 工资 1000.00元"),
+        (2, "#支付宝 #12月 #2024年 #餐饮\n#出账 午饭 30.00元"),
+        (3, "#支付宝 #12月 #2024年 #购物\n#出账 200.00元"),
+        (4, "#支付宝 #12月 #2024年\n#入账 红包 50.00元"),
+    ];
+    for (id, text) in messages {
+        process_wallet_message(&db, TEST_CHAT_ID, id, text).await?;
+    }
+    let transfer = parser
+        .parse_transfer("#转账 #支付宝 → #银行卡 #12月 #2024年 100元")
+        .unwrap();
+    BalanceCalculator::new(db.clone())
+        .process_transfer(TEST_CHAT_ID, 5, &transfer)
+        .await?;
+    // 手动总额比计算结果少 10 元
+    process_wallet_message(
+        &db,
+        TEST_CHAT_ID,
+        6,
+        "#支付宝 #12月 #2024年\n#出账 10.00元\n#总额 700.00元",
+    )
+    .await?;
+    process_wallet_message(&db, TEST_CHAT_ID, 7, "#支付宝 #01月 #2025年\n#出账 5.00元").await?;
+
+    let generator = ReportGenerator::new(db.clone());
+    let december = ReportPeriod::month(2024, 12);
+    let report = generator
+        .wallet_report(TEST_CHAT_ID, "支付宝", december)
+        .await?;
+    assert_eq!(report.opening_balance, Money::from_yuan(1000));
+    assert_eq!(report.closing_balance, Money::from_yuan(700));
+    assert_eq!(report.income, Money::from_yuan(50));
+    assert_eq!(report.expense, Money::from_yuan(240));
+    assert_eq!(report.transfer_out, Money::from_yuan(100));
+    assert_eq!(report.net_change(), -Money::from_yuan(300));
+    assert_eq!(report.unexplained(), -Money::from_yuan(10));
+    assert_eq!(report.transaction_count, 5);
+    assert_eq!(report.largest[0].transaction.amount, Money::from_yuan(200));
+    let categories: Vec<_> = report
+        .categories
+        .iter()
+        .map(|c| (c.category.as_deref(), c.expense, c.income))
+        .collect();
+    assert_eq!(
+        categories,
+        [
+            (Some("购物"), Money::from_yuan(200), Money::ZERO),
+            (Some("餐饮"), Money::from_yuan(30), Money::ZERO),
+            (None, Money::from_yuan(10), Money::from_yuan(50)),
+        ]
+    );
+
+    let chat = generator.chat_report(TEST_CHAT_ID, december).await?;
+    assert_eq!(chat.wallets.len(), 2);
+    assert_eq!(chat.total.opening_balance, Money::from_yuan(1000));
+    assert_eq!(chat.total.closing_balance, Money::from_yuan(800));
+    assert_eq!(chat.total.transfer_in, chat.total.transfer_out);
+    let text = walletbot::utils::Formatter::format_chat_report(
+        &walletbot::database::models::ChatSettings::new(TEST_CHAT_ID),
+        &chat,
+    );
+    assert!(text.contains("📊 全部钱包 2024年12月 报表"));
+    assert!(text.contains("• 银行卡：0.00元 → 100.00元"));
+
+    let year = generator
+        .wallet_report(TEST_CHAT_ID, "支付宝", ReportPeriod::year(2024))
+        .await?;
+    assert_eq!(year.opening_balance, Money::ZERO);
+    assert_eq!(year.closing_balance, Money::from_yuan(700));
+    assert_eq!(year.income, Money::from_yuan(1050));
+
+    // 期内没有交易时期初、期末都是此前的余额
+    let quiet = generator
+        .wallet_report(TEST_CHAT_ID, "支付宝", ReportPeriod::month(2025, 2))
+        .await?;
+    assert_eq!(quiet.opening_balance, Money::from_yuan(695));
+    assert_eq!(quiet.closing_balance, Money::from_yuan(695));
+    assert_eq!(quiet.transaction_count, 0);
+
+    // 晚记录的更早月份的交易计入该月，之后各月的期初、期末随之变化
+    process_wallet_message(&db, TEST_CHAT_ID, 8, "#微信 #12月 #2024年\n#入账 100.00元").await?;
+    process_wallet_message(&db, TEST_CHAT_ID, 9, "#微信 #12月 #2024年\n#出账 30.00元").await?;
+    process_wallet_message(&db, TEST_CHAT_ID, 10, "#微信 #11月 #2024年\n#入账 50.00元").await?;
+    let november = generator
+        .wallet_report(TEST_CHAT_ID, "微信", ReportPeriod::month(2024, 11))
+        .await?;
+    assert_eq!(november.opening_balance, Money::ZERO);
+    assert_eq!(november.closing_balance, Money::from_yuan(50));
+    let december = generator
+        .wallet_report(TEST_CHAT_ID, "微信", december)
+        .await?;
+    assert_eq!(december.opening_balance, Money::from_yuan(50));
+    assert_eq!(december.closing_balance, Money::from_yuan(120));
+    assert_eq!(december.unexplained(), Money::ZERO);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_monthly_summary_subscriptions() -> Result<()> {
+    use chrono::TimeZone;
+    use walletbot::bot::scheduler::due_period;
+    use walletbot::calculator::report::ReportPeriod;
+    use walletbot::database::models::SummarySubscription;
+
+    let db = create_test_db().await?;
+    assert!(db.get_summary_subscription(TEST_CHAT_ID).await?.is_none());
+
+    let mut subscription = SummarySubscription::new(TEST_CHAT_ID);
+    subscription.day_of_month = 3;
+    subscription.hour = 20;
+    db.save_summary_subscription(&subscription).await?;
+    let mut disabled = SummarySubscription::new(TEST_CHAT_ID + 1);
+    disabled.enabled = false;
+    db.save_summary_subscription(&disabled).await?;
+    assert_eq!(
+        db.get_enabled_summary_subscriptions().await?,
+        vec![subscription.clone()]
+    );
+
+    // 到期时间之前不发送，之后总结上一个月，一月总结上一年的十二月
+    let early = Utc.with_ymd_and_hms(2025, 3, 3, 19, 59, 0).unwrap();
+    assert_eq!(due_period(&subscription, early), None);
+    let due = Utc.with_ymd_and_hms(2025, 3, 3, 20, 0, 0).unwrap();
+    assert_eq!(
+        due_period(&subscription, due),
+        Some(ReportPeriod::month(2025, 2))
+    );
+    let january = Utc.with_ymd_and_hms(2025, 1, 15, 0, 0, 0).unwrap();
+    assert_eq!(
+        due_period(&subscription, january),
+        Some(ReportPeriod::month(2024, 12))
+    );
+    assert_eq!(due_period(&disabled, january), None);
+
+    // 同一个月只会被登记一次，发送失败释放后可以重新登记
+    assert!(db.claim_summary_period(TEST_CHAT_ID, 2025, 2).await?);
+    assert!(!db.claim_summary_period(TEST_CHAT_ID, 2025, 2).await?);
+    db.release_summary_period(TEST_CHAT_ID, 2025, 2).await?;
+    assert!(db.claim_summary_period(TEST_CHAT_ID, 2025, 2).await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_summary_scheduler_with_mock_bot() -> Result<()> {
+    use chrono::TimeZone;
+    use std::time::Duration;
+    use walletbot::bot::permissions::ChatAllowlist;
+    use walletbot::bot::scheduler::SummaryScheduler;
+    use walletbot::database::models::SummarySubscription;
+
+    const OTHER_CHAT: i64 = -100777;
+    const DROPPED_CHAT: i64 = -100888;
+
+    let db = create_test_db().await?;
+    let bot = MockBotApi::new();
+    for (chat_id, wallet) in [
+        (TEST_CHAT_ID, "支付宝"),
+        (OTHER_CHAT, "微信"),
+        (DROPPED_CHAT, "银行卡"),
+    ] {
+        process_wallet_message(
+            &db,
+            chat_id,
+            1,
+            &format!("#{wallet} #2月 #2025年\n#入账 100.00元"),
+        )
+        .await?;
+        db.save_summary_subscription(&SummarySubscription::new(chat_id))
+            .await?;
+    }
+
+    // 已从允许列表中移除的聊天不再收到总结
+    let scheduler =
+        SummaryScheduler::new(bot.clone(), db.clone(), Duration::from_secs(60)).with_allowlist(
+            ChatAllowlist::new(vec![TEST_CHAT_ID, OTHER_CHAT], Vec::new(), false),
+        );
+    let now = Utc.with_ymd_and_hms(2025, 3, 1, 9, 0, 0).unwrap();
+    assert_eq!(scheduler.post_due_summaries(now).await?, 2);
+
+    // 每个聊天的总结只发送到它自己，不包含其他聊天的数据
+    let sent = bot.get_sent_messages().await;
+    assert_eq!(sent.len(), 2);
+    for (chat_id, wallet, other) in [
+        (TEST_CHAT_ID, "支付宝", "微信"),
+        (OTHER_CHAT, "微信", "支付宝"),
+    ] {
+        let summary = sent
+            .iter()
+            .find(|m| m.chat_id == ChatId(chat_id))
+            .expect("summary for chat");
+        assert!(summary.text.contains("🗓️ 2025年2月 月度总结"));
+        assert!(summary.text.contains(wallet));
+        assert!(!summary.text.contains(other));
+        assert!(!summary.text.contains("银行卡"));
+    }
+
+    // 同一个月不会重复发送
+    assert_eq!(scheduler.post_due_summaries(now).await?, 0);
+
+    // 发送失败时下次检查重试
+    bot.clear_all().await;
+    bot.set_should_fail(true).await;
+    let april = Utc.with_ymd_and_hms(2025, 4, 1, 9, 0, 0).unwrap();
+    assert_eq!(scheduler.post_due_summaries(april).await?, 0);
+    bot.set_should_fail(false).await;
+    assert_eq!(scheduler.post_due_summaries(april).await?, 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_csv_export_of_transactions_and_balances() -> Result<()> {
+    use walletbot::export::{CsvExporter, ExportKind, ExportQuery};
+
+    let db = create_test_db().await?;
+    let chat_id = -1001234567890;
+    process_wallet_message(
+        &db,
+        chat_id,
+        1,
+        "#支付宝 #11月 #2024年\n#入账 工资 1000.00元",
+    )
+    .await?;
+    process_wallet_message(
+        &db,
+        chat_id,
+        2,
+        "#支付宝 #12月 #2024年 #餐饮\n#出账 午饭, 饮料 30.00元",
+    )
+    .await?;
+    process_wallet_message(&db, chat_id, 3, "#微信 #12月 #2024年\n#入账 50.00元").await?;
+
+    let exporter = CsvExporter::new(db.clone());
+    let all = exporter
+        .export(chat_id, &ExportQuery::default(), ExportKind::Transactions)
+        .await?;
+    assert_eq!(all.rows, 3);
+    assert_eq!(all.file_name, "transactions.csv");
+    let lines: Vec<&str> = all.content.lines().collect();
+    assert_eq!(
+        lines[0],
+        "id,created_at,wallet,type,amount,category,note,month,year,message_id,message_link"
+    );
+    assert!(
+        lines[1].contains(",支付宝,入账,1000.00,,工资,11月,2024年,1,https://t.me/c/1234567890/1")
+    );
+    assert!(lines[2].contains(",支付宝,出账,30.00,餐饮,\"午饭, 饮料\",12月,2024年,2,"));
+
+    let query = ExportQuery::parse_args(["支付宝", "12月", "2024年"])?;
+    let december = exporter.transactions(chat_id, &query).await?;
+    assert_eq!(december.rows, 1);
+    assert_eq!(december.file_name, "transactions_支付宝_2024-12.csv");
+
+    let balances = exporter.balances(chat_id, &query).await?;
+    assert_eq!(balances.rows, 1);
+    assert_eq!(
+        balances
+            .content
+            .lines()
+            .nth(1)
+            .unwrap()
+            .rsplit_once(',')
+            .unwrap()
+            .0,
+        "支付宝,2024年12月,1000.00,970.00,1"
+    );
+
+    let current = exporter.balances(chat_id, &ExportQuery::default()).await?;
+    assert_eq!(current.rows, 2);
+    assert!(current.content.contains("微信,全部,0.00,50.00,1,"));
+
+    let missing = ExportQuery::parse_args(["银行卡"])?;
+    assert!(exporter.balances(chat_id, &missing).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_import_historical_transactions() -> Result<()> {
+    use walletbot::export::{CsvExporter, ExportQuery};
+    use walletbot::import::{ImportFormat, Importer};
+
+    let db = create_test_db().await?;
+    process_wallet_message(
+        &db,
+        TEST_CHAT_ID,
+        1,
+        "#支付宝 #12月 #2024年\n#入账 100.00元",
+    )
+    .await?;
+
+    let csv = "\u{feff}钱包,类型,金额,月份,年份,分类,备注\r\n\
+               支付宝,入账,1000,11,2024,,工资\r\n\
+               支付宝,出账,30.00元,11月,2024年,#餐饮,\"午饭, 饮料\"\r\n\
+               支付宝,出账,30.00元,11月,2024年,#餐饮,\"午饭, 饮料\"\r\n\
+               银行卡,入账,500,10,2024,,\r\n";
+    let importer = Importer::new(db.clone());
+
+    let preview = importer
+        .import(TEST_CHAT_ID, csv, ImportFormat::Csv, true)
+        .await?;
+    assert_eq!(
+        (preview.rows, preview.imported, preview.duplicates),
+        (4, 4, 0)
+    );
+    assert!(!db.wallet_exists(TEST_CHAT_ID, "银行卡").await?);
+
+    let report = importer
+        .import(TEST_CHAT_ID, csv, ImportFormat::Csv, false)
+        .await?;
+    assert_eq!(report.imported, 4);
+    let wallets: Vec<_> = report
+        .wallets
+        .iter()
+        .map(|w| (w.name.as_str(), w.created, w.transactions, w.balance))
+        .collect();
+    assert_eq!(
+        wallets,
+        [
+            ("支付宝", false, 3, Some(Money::from_yuan(1040))),
+            ("银行卡", true, 1, Some(Money::from_yuan(500))),
+        ]
+    );
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(1040)
+    );
+
+    // 重复导入同一个文件不会产生新的交易
+    let again = importer
+        .import(TEST_CHAT_ID, csv, ImportFormat::Csv, false)
+        .await?;
+    assert_eq!((again.imported, again.duplicates), (0, 4));
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(1040)
+    );
+
+    // 导入的历史交易按时间排在已有消息之前，报表和导出都能看到
+    let exported = CsvExporter::new(db.clone())
+        .transactions(TEST_CHAT_ID, &ExportQuery::parse_args(["支付宝"])?)
+        .await?;
+    assert_eq!(exported.rows, 4);
+    assert!(exported
+        .content
+        .lines()
+        .nth(1)
+        .unwrap()
+        .contains("入账,1000.00,,工资,11月"));
+
+    let json = r#"[
+        {"wallet": "微信", "type": "收入", "amount": 12.5, "month": 9, "year": 2024,
+         "created_at": "2024-09-15 08:30:00", "key": "wx-1"},
+        {"wallet": "微信", "type": "支出", "amount": "2.5", "month": "9", "year": "2024", "key": "wx-2"}
+    ]"#;
+    let report = importer
+        .import(TEST_CHAT_ID, json, ImportFormat::Json, false)
+        .await?;
+    assert_eq!(report.imported, 2);
+    assert_eq!(report.wallets[0].balance, Some(Money::from_yuan(10)));
+    let again = importer
+        .import(TEST_CHAT_ID, json, ImportFormat::Json, false)
+        .await?;
+    assert_eq!(again.duplicates, 2);
+
+    // 有任何一行无效时整个文件都不导入
+    let invalid = "wallet,type,amount,month,year\n支付宝,出账,1,1,2025\n支付宝,出账,-1,13,2025\n";
+    let rejected = importer
+        .import(TEST_CHAT_ID, invalid, ImportFormat::Csv, false)
+        .await?;
+    assert!(rejected.is_rejected());
+    assert_eq!(rejected.errors[0].line, 3);
+    assert_eq!(rejected.errors[0].message, "金额无效：-1");
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(1040)
+    );
+    let text = walletbot::utils::Formatter::format_import_report(
+        &walletbot::database::models::ChatSettings::new(TEST_CHAT_ID),
+        &rejected,
+    );
+    assert!(text.contains("• 第 3 行：金额无效：-1"));
+
+    // 导入只加上导入交易的影响，已有的偏差不会被悄悄修正，而是在报告中列出
+    process_wallet_message(&db, TEST_CHAT_ID, 50, "#现金 #12月 #2024年\n#入账 50.00元").await?;
+    db.update_wallet_balance(TEST_CHAT_ID, "现金", Money::from_yuan(80))
+        .await?;
+    let csv = "wallet,type,amount,month,year\n现金,入账,20,11,2024\n";
+    let report = importer
+        .import(TEST_CHAT_ID, csv, ImportFormat::Csv, false)
+        .await?;
+    assert_eq!(report.wallets[0].drift, -Money::from_yuan(30));
+    assert_eq!(report.wallets[0].balance, Some(Money::from_yuan(100)));
+    let text = walletbot::utils::Formatter::format_import_report(
+        &walletbot::database::models::ChatSettings::new(TEST_CHAT_ID),
+        &report,
+    );
+    assert!(text.contains("⚠️ 导入前余额与账本相差 -30.00元"));
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "现金").await?,
+        Money::from_yuan(100)
+    );
+    // 导入的交易早于已有消息，该消息的余额快照随之移动
+    let record = db.get_message_record(50, TEST_CHAT_ID).await?.unwrap();
+    assert_eq!(record.original_balance, Some(Money::from_yuan(20)));
+    assert_eq!(record.new_balance, Some(Money::from_yuan(70)));
+    let drift = BalanceCalculator::new(db.clone())
+        .reconcile_wallet(TEST_CHAT_ID, "现金", false)
+        .await?;
+    assert_eq!(drift.drift(), -Money::from_yuan(30));
+    assert_eq!(drift.stale_snapshots, 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replay_telegram_desktop_export() -> Result<()> {
+    use walletbot::replay::{ChatExport, Replayer};
+
+    let db = create_test_db().await?;
+    let export = ChatExport::from_json(
+        r##"{
+            "name": "账本频道",
+            "type": "private_channel",
+            "id": 1234567890,
+            "messages": [
+                {"id": 1, "type": "service", "date": "2024-12-01T08:00:00", "action": "create_channel"},
+                {"id": 4, "type": "message", "date": "2024-12-03T10:00:00",
+                 "text": [{"type": "hashtag", "text": "#转账"}, " ", {"type": "hashtag", "text": "#支付宝"}, " → ", {"type": "hashtag", "text": "#银行卡"}, " #12月 #2024年\n100元"]},
+                {"id": 2, "type": "message", "date": "2024-12-01T09:00:00",
+                 "text": [{"type": "hashtag", "text": "#支付宝"}, " ", {"type": "hashtag", "text": "#12月"}, " ", {"type": "hashtag", "text": "#2024年"}, "\n#入账 1000.00元"]},
+                {"id": 3, "type": "message", "date": "2024-12-02T09:00:00", "text": "#支付宝 #12月 #2024年\n#出账 30.00元"},
+                {"id": 5, "type": "message", "date": "2024-12-04T09:00:00", "text": "今天天气不错"},
+                {"id": 6, "type": "message", "date": "2024-12-05T09:00:00", "text": "#转账 #支付宝 → #支付宝 #12月 #2024年\n10元"}
+            ]
+        }"##,
+    )?;
+    let chat_id = export.bot_chat_id();
+    assert_eq!(chat_id, -1001234567890);
+
+    // 机器人在线时已经处理过第 3 条消息
+    process_wallet_message(&db, chat_id, 3, "#支付宝 #12月 #2024年\n#出账 30.00元").await?;
+
+    let replayer = Replayer::new(db.clone());
+    let report = replayer.replay(chat_id, &export).await?;
+    assert_eq!(report.total, 6);
+    assert_eq!(report.parsed, 2);
+    assert_eq!(report.already_processed, 1);
+    assert_eq!(report.ignored, 2);
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].message_id, 6);
+    assert!(db.is_message_processed(2, chat_id).await?);
+
+    assert_eq!(
+        db.get_balance(chat_id, "支付宝").await?,
+        Money::from_yuan(870)
+    );
+    assert_eq!(
+        db.get_balance(chat_id, "银行卡").await?,
+        Money::from_yuan(100)
+    );
+
+    // 再次重放不会重复记账
+    let again = replayer.replay(chat_id, &export).await?;
+    assert_eq!(again.parsed, 0);
+    assert_eq!(again.already_processed, 3);
+    assert_eq!(
+        db.get_balance(chat_id, "支付宝").await?,
+        Money::from_yuan(870)
+    );
+
+    let text = walletbot::utils::Formatter::format_replay_report(&report);
+    assert!(text.contains("✅ 已记账：2"));
+    assert!(text.contains("• 消息 6：转账格式不正确"));
+
+    // 之后已有手动总额时拒绝补记，避免总额中已经包含的金额被再算一次
+    process_wallet_message(
+        &db,
+        chat_id,
+        20,
+        "#微信 #12月 #2024年\n#入账 50.00元\n#总额 500.00元",
+    )
+    .await?;
+    let export = ChatExport::from_json(
+        r##"{
+            "type": "private_channel",
+            "id": 1234567890,
+            "messages": [
+                {"id": 21, "type": "message", "date": "2024-12-06T09:00:00", "text": "#微信 #12月 #2024年\n#出账 20.00元"},
+                {"id": 22, "type": "message", "date": "2024-12-06T10:00:00", "text": "#银行卡 #12月 #2024年\n#入账 10.00元\n#总额 999.00元"}
+            ]
+        }"##,
+    )?;
+    let report = replayer.replay(chat_id, &export).await?;
+    assert_eq!(report.parsed, 0);
+    assert_eq!(report.failed.len(), 2);
+    assert!(report.failed[0]
+        .reason
+        .contains("之后已有手动总额（消息 20）"));
+    assert!(report.failed[1].reason.contains("补记会覆盖之后的余额"));
+    assert!(!db.is_message_processed(21, chat_id).await?);
+    assert_eq!(
+        db.get_balance(chat_id, "微信").await?,
+        Money::from_yuan(500)
+    );
+    assert_eq!(
+        db.get_balance(chat_id, "银行卡").await?,
+        Money::from_yuan(100)
+    );
+
+    // 记账 → /undo → 重放：撤销过的消息和带 #已撤销 标记的消息都不会重新记账
+    process_wallet_message(&db, chat_id, 30, "#微信 #12月 #2024年\n#出账 5.00元").await?;
+    BalanceCalculator::new(db.clone())
+        .undo_message(chat_id, 30)
+        .await?;
+    assert_eq!(
+        db.get_balance(chat_id, "微信").await?,
+        Money::from_yuan(500)
+    );
+    let export = ChatExport::from_json(
+        r##"{
+            "type": "private_channel",
+            "id": 1234567890,
+            "messages": [
+                {"id": 30, "type": "message", "date": "2024-12-07T09:00:00", "text": "#微信 #12月 #2024年\n#出账 5.00元\n#总额 495.00元"},
+                {"id": 31, "type": "message", "date": "2024-12-07T10:00:00", "text": "#微信 #12月 #2024年\n#出账 7.00元\n#已撤销"}
+            ]
+        }"##,
+    )?;
+    let report = replayer.replay(chat_id, &export).await?;
+    assert_eq!(report.parsed, 0);
+    assert_eq!(report.already_processed, 1);
+    assert_eq!(report.undone, 1);
+    assert!(report.failed.is_empty());
+    assert!(db.get_message_transactions(30, chat_id).await?.is_empty());
+    assert!(!db.is_message_processed(31, chat_id).await?);
+    assert_eq!(
+        db.get_balance(chat_id, "微信").await?,
+        Money::from_yuan(500)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_webhook_settings() -> Result<()> {
+    use walletbot::bot::dispatcher::webhook_options;
+    use walletbot::config::Settings;
+
+    let mut settings = Settings {
+        telegram_bot_token: "token".to_string(),
+        ..Settings::default()
+    };
+    settings.validate()?;
+    assert!(webhook_options(&settings)?.is_none());
+
+    settings.webhook_url = Some("https://bot.example.com/telegram".to_string());
+    settings.webhook_port = 8443;
+    settings.webhook_secret = Some("s3cret_token-1".to_string());
+    settings.validate()?;
+    let options = webhook_options(&settings)?.expect("webhook mode");
+    assert_eq!(options.url.path(), "/telegram");
+    assert_eq!(options.address.port(), 8443);
+    assert_eq!(options.secret_token.as_deref(), Some("s3cret_token-1"));
+
+    settings.webhook_secret = Some("not allowed!".to_string());
+    assert!(settings.validate().is_err());
+    settings.webhook_secret = None;
+    settings.webhook_url = Some("http://bot.example.com/telegram".to_string());
+    assert!(settings.validate().is_err());
+    settings.webhook_url = Some("not a url".to_string());
+    assert!(settings.validate().is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_message_handler_end_to_end_with_mock_bot() -> Result<()> {
+    let db = create_test_db().await?;
+    let handler = MessageHandler::new(db.clone());
+    let bot = MockBotApi::new();
+    let chat_id = ChatId(TEST_CHAT_ID);
+
+    let income = "#支付宝 #12月 #2024年\n#入账 200.00元";
+    let message = MockBotApi::create_mock_message(chat_id, MessageId(100), income);
+    handler.handle_message(&bot, &message).await?;
+
+    let edited = bot.get_edited_messages().await;
+    assert_eq!(edited.len(), 1);
+    assert_eq!(edited[0].message_id, MessageId(100));
+    assert_eq!(edited[0].text, format!("{income}\n#总额 200.00元"));
+    let sent = bot.get_sent_messages().await;
+    assert_eq!(sent.len(), 1);
+    assert!(sent[0].text.contains("✅ 交易已记录"));
+    assert!(sent[0].text.contains("200.00元"));
+
+    let expense = "#支付宝 #12月 #2024年\n#出账 50.00元";
+    let message = MockBotApi::create_mock_message(chat_id, MessageId(101), expense);
+    handler.handle_message(&bot, &message).await?;
+    assert_eq!(
+        bot.get_edited_messages().await[1].text,
+        format!("{expense}\n#总额 150.00元")
+    );
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(150)
+    );
+
+    // 同一条消息再次送达时只提示，不重复记账
+    bot.clear_all().await;
+    handler.handle_message(&bot, &message).await?;
+    assert!(bot.get_edited_messages().await.is_empty());
+    assert!(bot.get_sent_messages().await[0]
+        .text
+        .contains("已经被处理过"));
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(150)
+    );
+
+    // 编辑后的消息按新金额重新记账并改写 #总额
+    bot.clear_all().await;
+    let edited_text = "#支付宝 #12月 #2024年\n#出账 80.00元\n#总额 150.00元";
+    let message = MockBotApi::create_mock_message(chat_id, MessageId(101), edited_text);
+    handler.handle_edited_message(&bot, &message).await?;
+    assert_eq!(
+        bot.get_edited_messages().await[0].text,
+        "#支付宝 #12月 #2024年\n#出账 80.00元\n#总额 120.00元"
+    );
+    assert!(bot.get_sent_messages().await[0]
+        .text
+        .contains("150.00元 → 120.00元"));
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(120)
+    );
+
+    // 普通聊天消息不会触发任何 Telegram 请求
+    bot.clear_all().await;
+    let message = MockBotApi::create_mock_message(chat_id, MessageId(102), "今天天气不错");
+    handler.handle_message(&bot, &message).await?;
+    assert!(bot.get_edited_messages().await.is_empty());
+    assert!(bot.get_sent_messages().await.is_empty());
+
+    // Telegram 请求失败时错误返回给调用方
+    bot.set_should_fail(true).await;
+    let message =
+        MockBotApi::create_mock_message(chat_id, MessageId(103), "#微信 #12月 #2024年\n#入账 10元");
+    assert!(handler.handle_message(&bot, &message).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_commands_with_mock_bot() -> Result<()> {
+    use walletbot::bot::commands::Commands;
+    use walletbot::bot::dispatcher::Command;
+
+    let db = create_test_db().await?;
+    let commands = Commands::new(MessageHandler::new(db.clone()));
+    let bot = MockBotApi::new();
+    let chat_id = ChatId(TEST_CHAT_ID);
+
+    for id in 1..=12 {
+        process_wallet_message(
+            &db,
+            TEST_CHAT_ID,
+            id,
+            &format!("#支付宝 #12月 #2024年\n#入账 {id}元"),
+        )
+        .await?;
+    }
+
+    let message = MockBotApi::create_mock_message(chat_id, MessageId(200), "/balance");
+    commands
+        .handle_command(&bot, &message, Command::Balance(String::new()))
+        .await?;
+    let sent = bot.get_sent_messages().await;
+    assert!(sent[0].text.contains("支付宝"));
+    assert!(sent[0].text.contains("78.00"));
+
+    // 超过一页的历史记录带翻页按钮
+    bot.clear_all().await;
+    commands
+        .handle_command(&bot, &message, Command::History("支付宝".to_string()))
+        .await?;
+    let sent = bot.get_sent_messages().await;
+    assert!(sent[0].keyboard.is_some());
+
+    // 中文钱包名和分类较长时翻页按钮仍然可用
+    let other_chat = ChatId(54321);
+    let wallet = "家庭共同生活开销备用金账户";
+    for id in 1..=12 {
+        process_wallet_message(
+            &db,
+            other_chat.0,
+            300 + id,
+            &format!("#{wallet} #12月 #2024年\n#出账 {id}元 #日常餐饮外卖"),
+        )
+        .await?;
+    }
+    bot.clear_all().await;
+    let other_message = MockBotApi::create_mock_message(other_chat, MessageId(200), "/history");
+    commands
+        .handle_command(
+            &bot,
+            &other_message,
+            Command::History(format!("{wallet} 12月 2024年 出账 日常餐饮外卖")),
+        )
+        .await?;
+    let sent = bot.get_sent_messages().await;
+    assert!(sent[0].text.contains("第 1/2 页"));
+    let next = sent[0]
+        .keyboard
+        .as_ref()
+        .expect("paging buttons")
+        .inline_keyboard[0][0]
+        .clone();
+    let teloxide::types::InlineKeyboardButtonKind::CallbackData(data) = next.kind else {
+        panic!("unexpected button: {next:?}");
+    };
+    assert!(data.len() <= 64, "{data}");
+
+    let history_message =
+        MockBotApi::create_mock_message(other_chat, MessageId(201), &sent[0].text);
+    let press: teloxide::types::CallbackQuery = serde_json::from_value(serde_json::json!({
+        "id": "history-next",
+        "from": {"id": 12345, "is_bot": false, "first_name": "Test"},
+        "message": serde_json::to_value(&history_message)?,
+        "chat_instance": "1",
+        "data": data
+    }))?;
+    commands.handle_callback_query(&bot, &press).await?;
+    let edited = bot.get_edited_messages().await;
+    assert!(edited[0].text.contains("第 2/2 页"));
+    assert!(edited[0].text.contains(wallet));
+
+    bot.clear_all().await;
+    commands
+        .handle_command(&bot, &message, Command::Export(String::new()))
+        .await?;
+    let documents = bot.get_sent_documents().await;
+    assert_eq!(documents.len(), 2);
+    assert_eq!(documents[0].file_name, "transactions.csv");
+    assert!(documents[0].caption.contains("12 笔"));
+    assert!(String::from_utf8(documents[1].content.clone())?.contains("支付宝"));
+
+    // 附带 /import 说明的文件从 mock 下载后导入
+    bot.clear_all().await;
+    bot.add_file(
+        "import-file",
+        "钱包,类型,金额,月份,年份\n微信,入账,30,11月,2024年\n".as_bytes(),
+    )
+    .await;
+    let document: Message = serde_json::from_value(serde_json::json!({
+        "message_id": 201,
+        "date": 1733000000,
+        "chat": {"id": TEST_CHAT_ID, "type": "private", "first_name": "Test"},
+        "from": {"id": 12345, "is_bot": false, "first_name": "Test"},
+        "document": {
+            "file_id": "import-file",
+            "file_unique_id": "import-file-unique",
+            "file_size": 64,
+            "file_name": "history.csv"
+        },
+        "caption": "/import"
+    }))?;
+    commands.handle_import(&bot, &document, "").await?;
+    assert!(bot.get_sent_messages().await[0].text.contains("微信"));
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "微信").await?,
+        Money::from_yuan(30)
+    );
+
+    Ok(())
+}
+
+/// 分发更新时需要的机器人信息
+fn test_me() -> teloxide::types::Me {
+    teloxide::types::Me {
+        user: User {
+            id: UserId(42),
+            is_bot: true,
+            first_name: "WalletBot".to_string(),
+            last_name: None,
+            username: Some("wallet_test_bot".to_string()),
+            language_code: None,
+            is_premium: false,
+            added_to_attachment_menu: false,
+        },
+        can_join_groups: true,
+        can_read_all_group_messages: false,
+        supports_inline_queries: false,
+    }
+}
+
+#[tokio::test]
+async fn test_dispatcher_schema_with_mock_bot() -> Result<()> {
+    use teloxide::dptree;
+    use teloxide::types::{Update, UpdateKind};
+    use walletbot::bot::dispatcher::BotDispatcher;
+
+    let db = create_test_db().await?;
+    let schema = BotDispatcher::new(MessageHandler::new(db.clone())).schema::<MockBotApi>();
+    let bot = MockBotApi::new();
+    let me = test_me();
+    let update = |id: i32, text: &str| Update {
+        id,
+        kind: UpdateKind::Message(MockBotApi::create_mock_message(
+            ChatId(TEST_CHAT_ID),
+            MessageId(id),
+            text,
+        )),
+    };
+
+    let text = "#支付宝 #12月 #2024年\n#入账 88元";
+    let result = schema
+        .dispatch(dptree::deps![bot.clone(), me.clone(), update(1, text)])
+        .await;
+    assert!(result.is_break());
+    assert_eq!(
+        bot.get_edited_messages().await[0].text,
+        format!("{text}\n#总额 88.00元")
+    );
+
+    bot.clear_all().await;
+    let result = schema
+        .dispatch(dptree::deps![bot.clone(), me, update(2, "/wallets")])
+        .await;
+    assert!(result.is_break());
+    assert!(bot.get_edited_messages().await.is_empty());
+    assert!(bot.get_sent_messages().await[0].text.contains("支付宝"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_access_control_roles_and_policies() -> Result<()> {
+    use walletbot::bot::commands::Commands;
+    use walletbot::bot::dispatcher::Command;
+    use walletbot::database::models::Role;
+
+    const GROUP: ChatId = ChatId(-100123);
+    const GLOBAL_ADMIN: u64 = 1;
+    const GROUP_ADMIN: u64 = 2;
+    const MEMBER: u64 = 3;
+    const STRANGER: u64 = 4;
+
+    let db = create_test_db().await?;
+    let handler = MessageHandler::new(db.clone()).with_admins(vec![GLOBAL_ADMIN as i64]);
+    let commands = Commands::new(handler.clone());
+    let bot = MockBotApi::new();
+    bot.add_chat_admin(GROUP, UserId(GROUP_ADMIN)).await;
+    let access = handler.access();
+    let message = |user: u64, text: &str| {
+        MockBotApi::create_group_message(GROUP, MessageId(rand::random::<i32>().abs()), user, text)
+    };
+
+    // 默认开放：成员可以记账，但管理命令需要管理员
+    let msg = message(STRANGER, "/reprocess");
+    assert!(access.message_has_role(&bot, &msg, Role::Writer).await);
+    assert!(!access.message_has_role(&bot, &msg, Role::Admin).await);
+    assert!(
+        access
+            .message_has_role(&bot, &message(GROUP_ADMIN, "/reprocess"), Role::Admin)
+            .await
+    );
+    assert!(
+        access
+            .message_has_role(&bot, &message(GLOBAL_ADMIN, "/reprocess"), Role::Admin)
+            .await
+    );
+    assert_eq!(Command::Reprocess.required_role(), Role::Admin);
+    assert_eq!(
+        Command::Reconcile(String::new()).required_role(),
+        Role::Reader
+    );
+    assert_eq!(
+        Command::Reconcile("fix".to_string()).required_role(),
+        Role::Admin
+    );
+
+    commands
+        .handle_command(&bot, &msg, Command::Reprocess)
+        .await?;
+    assert!(bot.get_sent_messages().await[0]
+        .text
+        .contains("需要管理员权限"));
+
+    // 管理员限制聊天并授权成员
+    let admin = message(GROUP_ADMIN, "/access");
+    commands
+        .handle_command(&bot, &admin, Command::Access("restrict".to_string()))
+        .await?;
+    commands
+        .handle_command(&bot, &admin, Command::Access(format!("writer {MEMBER}")))
+        .await?;
+    assert!(db.get_access_policy(GROUP.0).await?.restricted);
+    assert_eq!(
+        db.get_chat_role(GROUP.0, MEMBER as i64).await?,
+        Some(Role::Writer)
+    );
+    let overview = bot.get_sent_messages().await.last().unwrap().text.clone();
+    assert!(overview.contains("受限"));
+    assert!(overview.contains(&format!("{MEMBER}：记账")));
+
+    // 授权成员记账，未授权的消息被静默忽略
+    bot.clear_all().await;
+    let text = "#支付宝 #12月 #2024年\n#入账 100元";
+    handler.handle_message(&bot, &message(MEMBER, text)).await?;
+    handler
+        .handle_message(&bot, &message(STRANGER, text))
+        .await?;
+    assert_eq!(bot.get_edited_messages().await.len(), 1);
+    assert_eq!(bot.get_sent_messages().await.len(), 1);
+    assert_eq!(
+        db.get_balance(GROUP.0, "支付宝").await?,
+        Money::from_yuan(100)
+    );
+
+    // 受限聊天中未授权的用户也不能查询
+    bot.clear_all().await;
+    commands
+        .handle_command(
+            &bot,
+            &message(STRANGER, "/balance"),
+            Command::Balance(String::new()),
+        )
+        .await?;
+    assert!(bot.get_sent_messages().await[0]
+        .text
+        .contains("需要只读权限"));
+
+    // 策略改为回复提示
+    bot.clear_all().await;
+    db.save_access_policy(&walletbot::database::models::AccessPolicy {
+        chat_id: GROUP.0,
+        restricted: true,
+        reject_unauthorized: true,
+    })
+    .await?;
+    handler
+        .handle_message(&bot, &message(STRANGER, text))
+        .await?;
+    assert!(bot.get_sent_messages().await[0]
+        .text
+        .contains("没有在这个聊天记账的权限"));
+    assert_eq!(
+        db.get_balance(GROUP.0, "支付宝").await?,
+        Money::from_yuan(100)
+    );
+
+    // 只读成员可以查询但不能记账
+    bot.clear_all().await;
+    commands
+        .handle_command(&bot, &admin, Command::Access(format!("reader {MEMBER}")))
+        .await?;
+    bot.clear_all().await;
+    handler.handle_message(&bot, &message(MEMBER, text)).await?;
+    commands
+        .handle_command(
+            &bot,
+            &message(MEMBER, "/balance"),
+            Command::Balance(String::new()),
+        )
+        .await?;
+    let sent = bot.get_sent_messages().await;
+    assert!(sent[0].text.contains("没有在这个聊天记账的权限"));
+    assert!(sent[1].text.contains("100.00"));
+
+    // 私聊中的用户是自己聊天的管理员
+    let private = MockBotApi::create_mock_message(ChatId(TEST_CHAT_ID), MessageId(1), "/access");
+    assert!(access.message_has_role(&bot, &private, Role::Admin).await);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_chat_allowlist_in_dispatcher() -> Result<()> {
+    use teloxide::dptree;
+    use teloxide::types::{Update, UpdateKind};
+    use walletbot::bot::dispatcher::BotDispatcher;
+    use walletbot::bot::permissions::ChatAllowlist;
+    use walletbot::config::Settings;
+
+    const ADMIN: i64 = 1;
+    const UNKNOWN_GROUP: ChatId = ChatId(-100999);
+
+    let settings = Settings {
+        allowed_chat_ids: vec![TEST_CHAT_ID],
+        target_channel_id: Some(-100555),
+        admin_user_ids: vec![ADMIN],
+        leave_unknown_chats: true,
+        ..Settings::default()
+    };
+    // TARGET_CHANNEL_ID 不会加入允许列表，只设置它时不限制聊天
+    assert_eq!(settings.chat_allowlist(), vec![TEST_CHAT_ID]);
+    assert!(Settings::default().chat_allowlist().is_empty());
+    let channel_only = Settings {
+        target_channel_id: Some(-100555),
+        ..Settings::default()
+    };
+    assert!(channel_only.chat_allowlist().is_empty());
+
+    let db = create_test_db().await?;
+    let schema = BotDispatcher::new(MessageHandler::new(db.clone()))
+        .with_allowlist(ChatAllowlist::from_settings(&settings))
+        .schema::<MockBotApi>();
+    let bot = MockBotApi::new();
+    let text = "#支付宝 #12月 #2024年\n#入账 50元";
+    let update = |message: Message| Update {
+        id: 1,
+        kind: UpdateKind::Message(message),
+    };
+
+    // 未允许的群组：不记账，退出并通知管理员一次
+    for id in 1..=2 {
+        let message = MockBotApi::create_group_message(UNKNOWN_GROUP, MessageId(id), 7, text);
+        let result = schema
+            .dispatch(dptree::deps![bot.clone(), test_me(), update(message)])
+            .await;
+        assert!(result.is_break());
+    }
+    assert!(db.get_wallet_summaries(UNKNOWN_GROUP.0).await?.is_empty());
+    assert_eq!(*bot.left_chats.lock().await, vec![UNKNOWN_GROUP]);
+    let sent = bot.get_sent_messages().await;
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].chat_id, ChatId(ADMIN));
+    assert!(sent[0].text.contains("-100999"));
+    assert!(sent[0].text.contains("已自动退出"));
+
+    // 允许的聊天照常记账
+    bot.clear_all().await;
+    let message = MockBotApi::create_mock_message(ChatId(TEST_CHAT_ID), MessageId(3), text);
+    let result = schema
+        .dispatch(dptree::deps![bot.clone(), test_me(), update(message)])
+        .await;
+    assert!(result.is_break());
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(50)
+    );
+
+    // 全局管理员的私聊始终允许
+    let message = MockBotApi::create_mock_message(ChatId(ADMIN), MessageId(4), text);
+    let result = schema
+        .dispatch(dptree::deps![bot.clone(), test_me(), update(message)])
+        .await;
+    assert!(result.is_break());
+    assert_eq!(db.get_balance(ADMIN, "支付宝").await?, Money::from_yuan(50));
+    assert_eq!(bot.left_chats.lock().await.len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_undo_voids_transactions_and_restores_balances() -> Result<()> {
+    let db = create_test_db().await?;
+    let handler = MessageHandler::new(db.clone());
+    let calculator = BalanceCalculator::new(db.clone());
+    let bot = MockBotApi::new();
+    let chat_id = ChatId(TEST_CHAT_ID);
+
+    let texts = [
+        "#支付宝 #12月 #2024年\n#入账 200.00元",
+        "#支付宝 #12月 #2024年\n#出账 30.00元",
+        "#支付宝 #12月 #2024年\n#出账 20.00元",
+    ];
+    for (id, text) in texts.iter().enumerate() {
+        process_wallet_message(&db, TEST_CHAT_ID, id as i64 + 1, text).await?;
+    }
+    let transfer = MessageParser::new()
+        .parse_transfer("#转账 #支付宝 → #银行卡 #12月 #2024年\n50.00元")
+        .unwrap();
+    calculator
+        .process_transfer(TEST_CHAT_ID, 4, &transfer)
+        .await?;
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(100)
+    );
+
+    // 回复第 2 条消息撤销，之后的余额快照和钱包余额一起恢复
+    let undo_reply = |message_id: i64, reply_id: i64, reply_text: &str| -> Message {
+        serde_json::from_value(serde_json::json!({
+            "message_id": message_id,
+            "date": Utc::now().timestamp(),
+            "chat": {"id": TEST_CHAT_ID, "type": "private", "first_name": "Test"},
+            "from": {"id": TEST_CHAT_ID, "is_bot": false, "first_name": "Test"},
+            "text": "/undo",
+            "reply_to_message": {
+                "message_id": reply_id,
+                "date": Utc::now().timestamp(),
+                "chat": {"id": TEST_CHAT_ID, "type": "private", "first_name": "Test"},
+                "from": {"id": TEST_CHAT_ID, "is_bot": false, "first_name": "Test"},
+                "text": reply_text
+            }
+        }))
+        .expect("valid reply message")
+    };
+    let command = undo_reply(100, 2, &format!("{}\n#总额 170.00元", texts[1]));
+    handler.undo_message(&bot, &command).await?;
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(130)
+    );
+    let edited = bot.get_edited_messages().await;
+    assert_eq!(edited.len(), 1);
+    assert_eq!(edited[0].message_id, MessageId(2));
+    assert_eq!(edited[0].text, format!("{}\n#已撤销", texts[1]));
+    let sent = bot.get_sent_messages().await;
+    assert!(sent[0].text.contains("↩️"));
+    assert!(sent[0].text.contains("出账 30.00元"));
+    assert!(sent[0].text.contains("支付宝：100.00元 → 130.00元"));
+
+    // 交易只是作废，不会从数据库中删除
+    let record = db.get_message_record(2, TEST_CHAT_ID).await?.unwrap();
+    assert!(record.is_voided());
+    assert!(db
+        .get_message_transactions(2, TEST_CHAT_ID)
+        .await?
+        .is_empty());
+    let record = db.get_message_record(3, TEST_CHAT_ID).await?.unwrap();
+    assert_eq!(record.original_balance, Some(Money::from_yuan(200)));
+    assert_eq!(record.new_balance, Some(Money::from_yuan(180)));
+    let filter = walletbot::database::models::TransactionFilter::default();
+    let page = db
+        .get_transactions_page(TEST_CHAT_ID, &filter, 0, 10)
+        .await?;
+    assert_eq!(page.total, 4);
+
+    // 同一条消息不能撤销两次，编辑被撤销的消息也不会重新记账
+    bot.clear_all().await;
+    handler.undo_message(&bot, &command).await?;
+    assert!(bot.get_sent_messages().await[0]
+        .text
+        .contains("已经撤销过了"));
+    let edit = MockBotApi::create_mock_message(chat_id, MessageId(2), texts[1]);
+    handler.handle_edited_message(&bot, &edit).await?;
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(130)
+    );
+
+    // 不回复时撤销最近的一条：转账两侧都恢复，但无法改写原消息
+    bot.clear_all().await;
+    let command = MockBotApi::create_mock_message(chat_id, MessageId(101), "/undo");
+    handler.undo_message(&bot, &command).await?;
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(180)
+    );
+    assert_eq!(db.get_balance(TEST_CHAT_ID, "银行卡").await?, Money::ZERO);
+    assert!(bot.get_edited_messages().await.is_empty());
+    assert!(bot.get_sent_messages().await[0].text.contains("请手动删除"));
+
+    for result in calculator.reconcile_chat(TEST_CHAT_ID, false).await? {
+        assert!(result.is_consistent(), "{result:?}");
+    }
+
+    // 回复没有记过账的消息
+    bot.clear_all().await;
+    handler
+        .undo_message(&bot, &undo_reply(102, 50, "你好"))
+        .await?;
+    assert!(bot.get_sent_messages().await[0].text.contains("没有记过账"));
+
+    // 撤销后的消息仍算处理过，再次收到时不会重新记账，只能用 /reprocess
+    assert!(db.is_message_processed(2, TEST_CHAT_ID).await?);
+    assert!(!db.is_message_recorded(2, TEST_CHAT_ID).await?);
+    bot.clear_all().await;
+    let resend = MockBotApi::create_mock_message(chat_id, MessageId(2), texts[1]);
+    handler.handle_message(&bot, &resend).await?;
+    assert!(bot.get_sent_messages().await[0]
+        .text
+        .contains("已经被处理过"));
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(180)
+    );
+    handler.reprocess_message(&bot, &resend).await?;
+    assert!(db.is_message_recorded(2, TEST_CHAT_ID).await?);
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(150)
+    );
+    assert_eq!(db.get_message_transactions(2, TEST_CHAT_ID).await?.len(), 1);
+    let page = db
+        .get_transactions_page(TEST_CHAT_ID, &filter, 0, 10)
+        .await?;
+    assert_eq!(page.total, 3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reprocess_resets_and_reapplies_message() -> Result<()> {
+    let db = create_test_db().await?;
+    let handler = MessageHandler::new(db.clone());
+    let bot = MockBotApi::new();
+    let chat_id = ChatId(TEST_CHAT_ID);
+
+    let income = "#支付宝 #12月 #2024年\n#入账 200.00元";
+    let expense = "#支付宝 #12月 #2024年\n#出账 30.00元";
+    for (id, text) in [(1, income), (2, expense)] {
+        let message = MockBotApi::create_mock_message(chat_id, MessageId(id), text);
+        handler.handle_message(&bot, &message).await?;
+    }
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(170)
+    );
+
+    // 已处理的消息会先撤销再重新记账，机器人追加的 #总额 不会被当成手动总额
+    bot.clear_all().await;
+    let processed = MockBotApi::create_mock_message(
+        chat_id,
+        MessageId(2),
+        &format!("{expense}\n#总额 170.00元"),
+    );
+    handler.reprocess_message(&bot, &processed).await?;
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(170)
+    );
+    assert!(bot.get_edited_messages().await.is_empty());
+    let sent = bot.get_sent_messages().await;
+    let diff = &sent.last().unwrap().text;
+    assert!(diff.contains("🔄 重新处理结果"));
+    assert!(diff.contains("之前：\n• 出账 30.00元"));
+    assert!(diff.contains("之后：\n• 出账 30.00元"));
+    assert!(diff.contains("余额没有变化"));
+    assert!(!db
+        .get_message_record(2, TEST_CHAT_ID)
+        .await?
+        .unwrap()
+        .is_voided());
+    assert_eq!(db.get_message_transactions(2, TEST_CHAT_ID).await?.len(), 1);
+
+    // 被 /undo 撤销的消息重新处理后追加为最新的一条，撤销标记被替换成新的总额
+    let undo: Message = serde_json::from_value(serde_json::json!({
+        "message_id": 100,
+        "date": Utc::now().timestamp(),
+        "chat": {"id": TEST_CHAT_ID, "type": "private", "first_name": "Test"},
+        "text": "/undo",
+        "reply_to_message": {
+            "message_id": 1,
+            "date": Utc::now().timestamp(),
+            "chat": {"id": TEST_CHAT_ID, "type": "private", "first_name": "Test"},
+            "text": format!("{income}\n#总额 200.00元")
+        }
+    }))?;
+    handler.undo_message(&bot, &undo).await?;
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(-30)
+    );
+
+    bot.clear_all().await;
+    let undone =
+        MockBotApi::create_mock_message(chat_id, MessageId(1), &format!("{income}\n#已撤销"));
+    handler.reprocess_message(&bot, &undone).await?;
+    assert_eq!(
+        db.get_balance(TEST_CHAT_ID, "支付宝").await?,
+        Money::from_yuan(170)
+    );
+    let edited = bot.get_edited_messages().await;
+    assert_eq!(edited[0].text, format!("{income}\n#总额 170.00元"));
+    let sent = bot.get_sent_messages().await;
+    let diff = &sent.last().unwrap().text;
+    assert!(diff.contains("之前：没有记账"));
+    assert!(diff.contains("之后：\n• 入账 200.00元"));
+    assert!(diff.contains("支付宝：-30.00元 → 170.00元"));
+
+    for result in BalanceCalculator::new(db.clone())
+        .reconcile_chat(TEST_CHAT_ID, false)
+        .await?
+    {
+        assert!(result.is_consistent(), "{result:?}");
+    }
+
+    // 不是记账格式的消息不会被撤销
+    bot.clear_all().await;
+    let chat = MockBotApi::create_mock_message(chat_id, MessageId(3), "你好");
+    handler.reprocess_message(&bot, &chat).await?;
+    assert!(bot.get_sent_messages().await[0]
+        .text
+        .contains("不是有效的记账消息"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_chat_output_modes() -> Result<()> {
+    use walletbot::bot::commands::Commands;
+    use walletbot::bot::dispatcher::Command;
+    use walletbot::database::models::{ChatSettings, OutputMode};
+
+    let db = create_test_db().await?;
+    let handler = MessageHandler::new(db.clone());
+    let bot = MockBotApi::new();
+    let chat_id = ChatId(TEST_CHAT_ID);
+    let text = |amount: u32| format!("#支付宝 #12月 #2024年\n#出账 {amount}元");
+
+    // 默认编辑原消息并发送确认
+    assert_eq!(
+        db.get_chat_settings(TEST_CHAT_ID).await?,
+        ChatSettings::new(TEST_CHAT_ID)
+    );
+    let set_mode = |chat_id: i64, output_mode: OutputMode| {
+        let db = db.clone();
+        async move {
+            db.save_chat_settings(&ChatSettings {
+                output_mode,
+                confirmation_ttl: 5,
+                ..ChatSettings::new(chat_id)
+            })
+            .await
+        }
+    };
+
+    // 只编辑：追加 #总额，不发送确认消息
+    set_mode(TEST_CHAT_ID, OutputMode::EditOnly).await?;
+    let message = MockBotApi::create_mock_message(chat_id, MessageId(1), &text(10));
+    handler.handle_message(&bot, &message).await?;
+    assert_eq!(bot.get_edited_messages().await.len(), 1);
+    assert!(bot.get_sent_messages().await.is_empty());
+
+    // 转账不改写原消息，结果和格式错误都回复原消息
+    let transfer = MockBotApi::create_mock_message(
+        chat_id,
+        MessageId(5),
+        "#转账 #支付宝 → #银行卡 #12月 #2024年\n5.00元",
+    );
+    handler.handle_message(&bot, &transfer).await?;
+    let invalid = MockBotApi::create_mock_message(
+        chat_id,
+        MessageId(6),
+        "#转账 #支付宝 → #支付宝 #12月 #2024年\n5.00元",
+    );
+    handler.handle_message(&bot, &invalid).await?;
+    let sent = bot.get_sent_messages().await;
+    assert_eq!(sent.len(), 2);
+    assert!(sent[0].text.contains("转账"));
+    assert_eq!(sent[0].reply_to_message_id, Some(MessageId(5)));
+    assert!(sent[1].text.contains("转账格式不正确"));
+    assert_eq!(sent[1].reply_to_message_id, Some(MessageId(6)));
+    assert_eq!(bot.get_edited_messages().await.len(), 1);
+
+    // 确认消息自动删除
+    bot.clear_all().await;
+    set_mode(TEST_CHAT_ID, OutputMode::TemporaryConfirm).await?;
+    let message = MockBotApi::create_mock_message(chat_id, MessageId(2), &text(20));
+    handler.handle_message(&bot, &message).await?;
+    assert!(bot.get_sent_messages().await[0].text.contains("交易已记录"));
+    let deleted = bot.get_deleted_messages().await;
+    assert_eq!(deleted.len(), 1);
+    assert_eq!(deleted[0].chat_id, chat_id);
+
+    // 不是频道时直接回复原消息
+    bot.clear_all().await;
+    set_mode(TEST_CHAT_ID, OutputMode::DiscussionReply).await?;
+    let message = MockBotApi::create_mock_message(chat_id, MessageId(3), &text(30));
+    handler.handle_message(&bot, &message).await?;
+    assert_eq!(
+        bot.get_sent_messages().await[0].reply_to_message_id,
+        Some(MessageId(3))
+    );
+
+    // 频道帖子的确认等到讨论组收到自动转发后回复在评论区
+    const CHANNEL_ID: i64 = -1001000000001;
+    const DISCUSSION_ID: i64 = -1002000000002;
+    set_mode(CHANNEL_ID, OutputMode::DiscussionReply).await?;
+    let channel = serde_json::json!({"id": CHANNEL_ID, "type": "channel", "title": "家庭账本"});
+    let post = |id: i32, text: &str| -> Message {
+        serde_json::from_value(serde_json::json!({
+            "message_id": id,
+            "date": Utc::now().timestamp(),
+            "chat": channel,
+            "text": text
+        }))
+        .expect("valid channel post")
+    };
+    let forward = |id: i32, post_id: i32, text: &str| -> Message {
+        serde_json::from_value(serde_json::json!({
+            "message_id": id,
+            "date": Utc::now().timestamp(),
+            "chat": {"id": DISCUSSION_ID, "type": "supergroup", "title": "家庭账本 讨论"},
+            "from": {"id": 777000, "is_bot": false, "first_name": "Telegram"},
+            "sender_chat": channel,
+            "is_automatic_forward": true,
+            "forward_from_chat": channel,
+            "forward_from_message_id": post_id,
+            "forward_date": Utc::now().timestamp(),
+            "text": text
+        }))
+        .expect("valid automatic forward")
+    };
+
+    bot.clear_all().await;
+    handler.handle_message(&bot, &post(10, &text(40))).await?;
+    assert_eq!(bot.get_edited_messages().await.len(), 1);
+    assert!(bot.get_sent_messages().await.is_empty());
+    handler
+        .handle_message(&bot, &forward(77, 10, &text(40)))
+        .await?;
+    let sent = bot.get_sent_messages().await;
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].chat_id, ChatId(DISCUSSION_ID));
+    assert_eq!(sent[0].reply_to_message_id, Some(MessageId(77)));
+    // 自动转发不会在讨论组中重复记账
+    assert!(db.get_wallet_summaries(DISCUSSION_ID).await?.is_empty());
+
+    // 自动转发先到时，帖子的确认直接回复
+    bot.clear_all().await;
+    handler
+        .handle_message(&bot, &forward(78, 11, &text(50)))
+        .await?;
+    handler.handle_message(&bot, &post(11, &text(50))).await?;
+    let sent = bot.get_sent_messages().await;
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].reply_to_message_id, Some(MessageId(78)));
+
+    // /output 查看和修改输出方式
+    let commands = Commands::new(handler.clone());
+    let command = MockBotApi::create_mock_message(chat_id, MessageId(100), "/output");
+    bot.clear_all().await;
+    commands
+        .handle_command(&bot, &command, Command::Output("temporary 60".to_string()))
+        .await?;
+    let settings = db.get_chat_settings(TEST_CHAT_ID).await?;
+    assert_eq!(settings.output_mode, OutputMode::TemporaryConfirm);
+    assert_eq!(settings.confirmation_ttl, 60);
+    assert!(bot.get_sent_messages().await[0]
+        .text
+        .contains("确认消息自动删除（60 秒后删除）"));
+
+    bot.clear_all().await;
+    for args in ["edit 60", "temporary 0", "silent"] {
+        commands
+            .handle_command(&bot, &command, Command::Output(args.to_string()))
+            .await?;
+    }
+    assert!(bot
+        .get_sent_messages()
+        .await
+        .iter()
+        .all(|sent| sent.text.starts_with("❌ 用法")));
+    assert_eq!(
+        db.get_chat_settings(TEST_CHAT_ID).await?.output_mode,
+        OutputMode::TemporaryConfirm
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_chat_settings_command() -> Result<()> {
+    use chrono::TimeZone;
+    use walletbot::bot::commands::Commands;
+    use walletbot::bot::dispatcher::Command;
+    use walletbot::bot::scheduler::due_period;
+    use walletbot::database::models::{ChatSettings, Language, SummarySubscription};
+
+    const GROUP_ID: i64 = -1003000000003;
+    const ADMIN: u64 = 1;
+    const MEMBER: u64 = 2;
+    let db = create_test_db().await?;
+    let handler = MessageHandler::new(db.clone());
+    let commands = Commands::new(handler.clone());
+    let bot = MockBotApi::new();
+    let chat_id = ChatId(GROUP_ID);
+    bot.add_chat_admin(chat_id, UserId(ADMIN)).await;
+
+    // /settings 发送当前设置和修改按钮
+    let command = MockBotApi::create_group_message(chat_id, MessageId(1), ADMIN, "/settings");
+    commands
+        .handle_command(&bot, &command, Command::Settings)
+        .await?;
+    let sent = bot.get_sent_messages().await;
+    assert!(sent[0].text.contains("💱 货币单位：元"));
+    assert!(sent[0].text.contains("🕒 时区：UTC"));
+    let keyboard = sent[0].keyboard.clone().expect("settings keyboard");
+    let data: Vec<String> = keyboard
+        .inline_keyboard
+        .iter()
+        .flatten()
+        .filter_map(|button| match &button.kind {
+            teloxide::types::InlineKeyboardButtonKind::CallbackData(data) => Some(data.clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        data,
+        [
+            "settings:output",
+            "settings:currency",
+            "settings:wallet",
+            "settings:tz-",
+            "settings:tz+",
+            "settings:lang"
+        ]
+    );
+
+    let settings_message =
+        MockBotApi::create_group_message(chat_id, MessageId(2), 0, "⚙️ 聊天设置");
+    let press = |user_id: u64, data: &str| -> teloxide::types::CallbackQuery {
+        serde_json::from_value(serde_json::json!({
+            "id": format!("{user_id}-{data}"),
+            "from": {"id": user_id, "is_bot": false, "first_name": "成员"},
+            "message": serde_json::to_value(&settings_message).unwrap(),
+            "chat_instance": "1",
+            "data": data
+        }))
+        .expect("valid callback query")
+    };
+
+    // 普通成员不能修改设置
+    bot.clear_all().await;
+    commands
+        .handle_callback_query(&bot, &press(MEMBER, "settings:currency"))
+        .await?;
+    assert_eq!(
+        db.get_chat_settings(GROUP_ID).await?,
+        ChatSettings::new(GROUP_ID)
+    );
+    assert!(bot.get_edited_messages().await.is_empty());
+    assert_eq!(bot.answered_callbacks.lock().await.len(), 1);
+
+    // 管理员切换货币、语言和时区，设置消息随之刷新
+    commands
+        .handle_callback_query(&bot, &press(ADMIN, "settings:currency"))
+        .await?;
+    commands
+        .handle_callback_query(&bot, &press(ADMIN, "settings:lang"))
+        .await?;
+    for _ in 0..8 {
+        commands
+            .handle_callback_query(&bot, &press(ADMIN, "settings:tz+"))
+            .await?;
+    }
+    let settings = db.get_chat_settings(GROUP_ID).await?;
+    assert_eq!(settings.currency, "¥");
+    assert_eq!(settings.language, Language::English);
+    assert_eq!(settings.utc_offset, 8);
+    let edited = bot.get_edited_messages().await;
+    assert_eq!(edited.len(), 10);
+    assert!(edited[9].text.contains("🕒 时区：UTC+8"));
+
+    // 还没有钱包时默认钱包不会变化，也不编辑消息
+    commands
+        .handle_callback_query(&bot, &press(ADMIN, "settings:wallet"))
+        .await?;
+    assert_eq!(bot.get_edited_messages().await.len(), 10);
+
+    // 确认消息使用英文，确认消息和 #总额 都使用聊天的货币单位
+    bot.clear_all().await;
+    let message = MockBotApi::create_group_message(
+        chat_id,
+        MessageId(10),
+        MEMBER,
+        "#支付宝 #12月 #2024年\n#入账 100元",
+    );
+    handler.handle_message(&bot, &message).await?;
+    assert!(bot.get_edited_messages().await[0]
+        .text
+        .ends_with("#总额 ¥100.00"));
+    assert_eq!(
+        bot.get_sent_messages().await[0].text,
+        "✅ Transaction recorded\n📊 Wallet: 支付宝\n💰 Balance: ¥100.00"
+    );
+
+    // 编辑带 ¥ 总额的消息时能识别并替换机器人追加的总额
+    bot.clear_all().await;
+    let edit = MockBotApi::create_group_message(
+        chat_id,
+        MessageId(10),
+        MEMBER,
+        "#支付宝 #12月 #2024年\n#入账 120元\n#总额 ¥100.00",
+    );
+    handler.handle_edited_message(&bot, &edit).await?;
+    assert_eq!(
+        bot.get_edited_messages().await[0].text,
+        "#支付宝 #12月 #2024年\n#入账 120元\n#总额 ¥120.00"
+    );
+    let edit = MockBotApi::create_group_message(
+        chat_id,
+        MessageId(10),
+        MEMBER,
+        "#支付宝 #12月 #2024年\n#入账 100元\n#总额 ¥120.00",
+    );
+    handler.handle_edited_message(&bot, &edit).await?;
+    assert_eq!(
+        db.get_balance(GROUP_ID, "支付宝").await?,
+        Money::from_yuan(100)
+    );
+
+    // 设置默认钱包后，没有写钱包的消息记到默认钱包
+    commands
+        .handle_callback_query(&bot, &press(ADMIN, "settings:wallet"))
+        .await?;
+    assert_eq!(
+        db.get_chat_settings(GROUP_ID)
+            .await?
+            .default_wallet
+            .as_deref(),
+        Some("支付宝")
+    );
+    bot.clear_all().await;
+    let message = MockBotApi::create_group_message(
+        chat_id,
+        MessageId(11),
+        MEMBER,
+        "#12月 #2024年\n#出账 30元",
+    );
+    handler.handle_message(&bot, &message).await?;
+    assert_eq!(
+        bot.get_edited_messages().await[0].text,
+        "#支付宝 #12月 #2024年\n#出账 30元\n#总额 ¥70.00"
+    );
+    let wallet = db.get_wallet_summary(GROUP_ID, "支付宝").await?.unwrap();
+    assert_eq!(wallet.balance, Money::from_cents(7000));
+
+    // /history 不写钱包时查看默认钱包
+    bot.clear_all().await;
+    commands
+        .handle_command(&bot, &command, Command::History(String::new()))
+        .await?;
+    let history = &bot.get_sent_messages().await[0].text;
+    assert!(history.contains("支付宝 transactions · all time"));
+    assert!(history.contains("• 2024-12 出账 ¥30.00"));
+
+    // 查询命令同样使用聊天的语言和货币单位
+    bot.clear_all().await;
+    for command_kind in [
+        Command::Balance(String::new()),
+        Command::Wallets,
+        Command::Report("12月 2024年".to_string()),
+        Command::Categories("12月 2024年".to_string()),
+        Command::Reconcile(String::new()),
+        Command::Adjustments,
+    ] {
+        commands
+            .handle_command(&bot, &command, command_kind)
+            .await?;
+    }
+    let sent = bot.get_sent_messages().await;
+    assert!(sent[0].text.starts_with("💰 Balances\n• 支付宝: ¥70.00"));
+    assert_eq!(
+        sent[1].text,
+        "👛 1 wallets\n• 支付宝: 2 transactions, balance ¥70.00"
+    );
+    assert!(sent[2].text.contains("📊 All wallets report for 2024-12"));
+    assert!(sent[2]
+        .text
+        .contains("🔒 Closing balance: ¥70.00 (2 transactions)"));
+    assert!(sent[2]
+        .text
+        .contains("• 支付宝: ¥0.00 → ¥70.00 (income ¥100.00, expense ¥30.00)"));
+    assert!(sent[3]
+        .text
+        .contains("• Uncategorized: expense ¥30.00, income ¥100.00 (2 transactions)"));
+    assert_eq!(
+        sent[4].text,
+        "🔍 Reconciliation\n✅ 支付宝: ¥70.00 (2 transactions, 0 manual totals)"
+    );
+    assert!(sent[5].text.starts_with("📒 No balance adjustments"));
+
+    // 权限、用法和格式错误的提示同样使用聊天的语言
+    bot.clear_all().await;
+    commands
+        .handle_command(&bot, &command, Command::Report("a b c".to_string()))
+        .await?;
+    commands
+        .handle_command(&bot, &command, Command::Categories("上个月".to_string()))
+        .await?;
+    let member_command =
+        MockBotApi::create_group_message(chat_id, MessageId(12), MEMBER, "/settings");
+    commands
+        .handle_command(&bot, &member_command, Command::Settings)
+        .await?;
+    let invalid = MockBotApi::create_group_message(
+        chat_id,
+        MessageId(13),
+        MEMBER,
+        "#支付宝 #12月 #2024年\n#出账 10.555元",
+    );
+    handler.handle_message(&bot, &invalid).await?;
+    let sent = bot.get_sent_messages().await;
+    assert!(sent[0]
+        .text
+        .starts_with("❌ Usage: /report [wallet] [month] [year]"));
+    assert!(sent[1].text.starts_with("❌ Unrecognised period"));
+    assert_eq!(sent[2].text, "⛔ This command requires the admin role");
+    assert!(sent[3].text.starts_with("❌ Invalid message format"));
+
+    // 月度总结按聊天的时区判断是否到期
+    let subscription = SummarySubscription {
+        day_of_month: 1,
+        hour: 4,
+        ..SummarySubscription::new(GROUP_ID)
+    };
+    // UTC 还是 12 月 31 日，UTC+8 已经是 1 月 1 日 4 点半，应当总结 12 月
+    let now = Utc.with_ymd_and_hms(2024, 12, 31, 20, 30, 0).unwrap();
+    let period = due_period(&subscription, now).unwrap();
+    assert_eq!((period.year, period.month), (2024, Some(11)));
+    let period = due_period(&subscription, settings.local_time(now)).unwrap();
+    assert_eq!((period.year, period.month), (2024, Some(12)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_large_transaction_confirmation() -> Result<()> {
+    use std::time::Duration;
+    use teloxide::dptree;
+    use teloxide::types::{CallbackQuery, Update, UpdateKind};
+    use walletbot::bot::commands::Commands;
+    use walletbot::bot::dispatcher::{BotDispatcher, Command};
+    use walletbot::database::models::AccessPolicy;
+
+    const GROUP_ID: i64 = -1004000000004;
+    const ADMIN: u64 = 1;
+    const MEMBER: u64 = 2;
+    const GUEST: u64 = 3;
+    let db = create_test_db().await?;
+    let handler = MessageHandler::new(db.clone());
+    let commands = Commands::new(handler.clone());
+    let schema = BotDispatcher::new(handler.clone()).schema::<MockBotApi>();
+    let bot = MockBotApi::new();
+    let me = test_me();
+    let chat_id = ChatId(GROUP_ID);
+    bot.add_chat_admin(chat_id, UserId(ADMIN)).await;
+    db.save_access_policy(&AccessPolicy {
+        chat_id: GROUP_ID,
+        restricted: true,
+        reject_unauthorized: false,
+    })
+    .await?;
+    db.set_chat_role(
+        GROUP_ID,
+        MEMBER as i64,
+        walletbot::database::models::Role::Writer,
+    )
+    .await?;
+
+    let message = |id: i32, amount: u32| {
+        MockBotApi::create_group_message(
+            chat_id,
+            MessageId(id),
+            MEMBER,
+            &format!("#支付宝 #12月 #2024年\n#出账 {amount}元"),
+        )
+    };
+    let balance = || async {
+        db.get_wallet_summary(GROUP_ID, "支付宝")
+            .await
+            .unwrap()
+            .map(|wallet| wallet.balance)
+    };
+    // 最近一条带按钮的提示，以及按下按钮的回调
+    let last_prompt = || async {
+        let sent = bot.get_sent_messages().await;
+        let prompt = sent
+            .iter()
+            .rev()
+            .find(|sent| sent.keyboard.is_some())
+            .expect("confirmation prompt")
+            .clone();
+        let data: Vec<String> = prompt
+            .keyboard
+            .unwrap()
+            .inline_keyboard
+            .iter()
+            .flatten()
+            .filter_map(|button| match &button.kind {
+                teloxide::types::InlineKeyboardButtonKind::CallbackData(data) => Some(data.clone()),
+                _ => None,
+            })
+            .collect();
+        (prompt.text, data)
+    };
+    let press = |user_id: u64, prompt: &str, data: &str| -> CallbackQuery {
+        let prompt = MockBotApi::create_group_message(chat_id, MessageId(900), 0, prompt);
+        serde_json::from_value(serde_json::json!({
+            "id": format!("{user_id}-{data}"),
+            "from": {"id": user_id, "is_bot": false, "first_name": "成员"},
+            "message": serde_json::to_value(&prompt).unwrap(),
+            "chat_instance": "1",
+            "data": data
+        }))
+        .expect("valid callback query")
+    };
+
+    // 管理员设置 1000 元的阈值
+    let command = MockBotApi::create_group_message(chat_id, MessageId(1), ADMIN, "/threshold");
+    commands
+        .handle_command(&bot, &command, Command::Threshold("1000元".to_string()))
+        .await?;
+    let settings = db.get_chat_settings(GROUP_ID).await?;
+    assert_eq!(settings.confirm_above, Some(Money::from_yuan(1000)));
+    assert_eq!(settings.confirm_ratio, None);
+    assert!(bot.get_sent_messages().await[0]
+        .text
+        .starts_with("🔔 大额确认：超过 1000.00元"));
+
+    // 阈值以内的交易直接记账
+    handler.handle_message(&bot, &message(10, 150)).await?;
+    assert_eq!(balance().await, Some(Money::from_yuan(-150)));
+
+    // 多写了一个 0：不记账，先发送确认按钮
+    bot.clear_all().await;
+    handler.handle_message(&bot, &message(11, 1500)).await?;
+    assert!(bot.get_edited_messages().await.is_empty());
+    assert_eq!(balance().await, Some(Money::from_yuan(-150)));
+    assert!(!db.is_message_processed(11, GROUP_ID).await?);
+    let (prompt, data) = last_prompt().await;
+    assert!(prompt.contains("💰 金额：1500.00元（超过 1000.00元）"));
+    assert!(prompt.contains("10 分钟内没有确认将不会记账"));
+    assert_eq!(data, ["pending:confirm:1", "pending:cancel:1"]);
+
+    // 没有记账权限的成员不能确认
+    let update = |query: CallbackQuery| Update {
+        id: 1,
+        kind: UpdateKind::CallbackQuery(query),
+    };
+    let result = schema
+        .dispatch(dptree::deps![
+            bot.clone(),
+            me.clone(),
+            update(press(GUEST, &prompt, &data[0]))
+        ])
+        .await;
+    assert!(result.is_break());
+    assert_eq!(balance().await, Some(Money::from_yuan(-150)));
+    assert!(bot.get_edited_messages().await.is_empty());
+
+    // 确认后按原消息记账并改写 #总额，按钮去掉
+    let result = schema
+        .dispatch(dptree::deps![
+            bot.clone(),
+            me.clone(),
+            update(press(MEMBER, &prompt, &data[0]))
+        ])
+        .await;
+    assert!(result.is_break());
+    assert_eq!(balance().await, Some(Money::from_yuan(-1650)));
+    let edited = bot.get_edited_messages().await;
+    assert_eq!(edited[0].message_id, MessageId(900));
+    assert!(edited[0].text.ends_with("✅ 成员 已确认。"));
+    assert_eq!(edited[1].message_id, MessageId(11));
+    assert!(edited[1].text.ends_with("#总额 -1650.00元"));
+    assert_eq!(bot.answered_callbacks.lock().await.len(), 2);
+
+    // 再次按下不会重复记账
+    handler
+        .handle_confirmation_callback(&bot, &press(MEMBER, &prompt, &data[0]))
+        .await?;
+    assert_eq!(balance().await, Some(Money::from_yuan(-1650)));
+    assert!(bot.get_edited_messages().await[2]
+        .text
+        .contains("确认已过期或已经处理过"));
+
+    // 取消后不记账
+    bot.clear_all().await;
+    handler.handle_message(&bot, &message(12, 2000)).await?;
+    let (prompt, data) = last_prompt().await;
+    handler
+        .handle_confirmation_callback(&bot, &press(MEMBER, &prompt, &data[1]))
+        .await?;
+    assert!(bot.get_edited_messages().await[0]
+        .text
+        .ends_with("❌ 成员 已取消，这笔交易没有记账。"));
+    assert_eq!(balance().await, Some(Money::from_yuan(-1650)));
+    assert!(!db.is_message_processed(12, GROUP_ID).await?);
+
+    // 把已记账的 150 元改成 1500 元：先询问，确认前保留编辑前的记账
+    let edit = |amount: u32| {
+        MockBotApi::create_group_message(
+            chat_id,
+            MessageId(10),
+            MEMBER,
+            &format!("#支付宝 #12月 #2024年\n#出账 {amount}元\n#总额 -150.00元"),
+        )
+    };
+    bot.clear_all().await;
+    handler.handle_edited_message(&bot, &edit(1500)).await?;
+    assert!(bot.get_edited_messages().await.is_empty());
+    let (prompt, data) = last_prompt().await;
+    assert!(prompt.contains("💰 金额：1500.00元（超过 1000.00元）"));
+    assert_eq!(balance().await, Some(Money::from_yuan(-1650)));
+    let transactions = db.get_message_transactions(10, GROUP_ID).await?;
+    assert_eq!(transactions[0].amount, Money::from_yuan(150));
+    handler
+        .handle_confirmation_callback(&bot, &press(MEMBER, &prompt, &data[1]))
+        .await?;
+    assert!(bot.get_edited_messages().await[0]
+        .text
+        .ends_with("❌ 成员 已取消，保留编辑前的记账。"));
+    assert_eq!(balance().await, Some(Money::from_yuan(-1650)));
+
+    // 确认后按编辑重新计算，之后的余额一起更新
+    bot.clear_all().await;
+    handler.handle_edited_message(&bot, &edit(1500)).await?;
+    let (prompt, data) = last_prompt().await;
+    handler
+        .handle_confirmation_callback(&bot, &press(MEMBER, &prompt, &data[0]))
+        .await?;
+    assert_eq!(balance().await, Some(Money::from_yuan(-3000)));
+    let edited = bot.get_edited_messages().await;
+    assert!(edited[0].text.ends_with("✅ 成员 已确认。"));
+    assert_eq!(edited[1].message_id, MessageId(10));
+    assert_eq!(
+        edited[1].text,
+        "#支付宝 #12月 #2024年\n#出账 1500元\n#总额 -1500.00元"
+    );
+    let transactions = db.get_message_transactions(10, GROUP_ID).await?;
+    assert_eq!(transactions.len(), 1);
+    assert_eq!(transactions[0].amount, Money::from_yuan(1500));
+
+    // 没有改动大额金额的编辑不再询问
+    bot.clear_all().await;
+    let note_edit = MockBotApi::create_group_message(
+        chat_id,
+        MessageId(10),
+        MEMBER,
+        "#支付宝 #12月 #2024年\n#出账 1500元 房租\n#总额 -1500.00元",
+    );
+    handler.handle_edited_message(&bot, &note_edit).await?;
+    assert!(bot
+        .get_sent_messages()
+        .await
+        .iter()
+        .all(|sent| sent.keyboard.is_none()));
+    assert_eq!(balance().await, Some(Money::from_yuan(-3000)));
+
+    // 按钱包平时金额的倍数判断：交易不足 5 笔时不判断
+    commands
+        .handle_command(&bot, &command, Command::Threshold("5x".to_string()))
+        .await?;
+    bot.clear_all().await;
+    for id in 13..=15 {
+        handler.handle_message(&bot, &message(id, 100)).await?;
+    }
+    assert!(bot
+        .get_sent_messages()
+        .await
+        .iter()
+        .all(|sent| sent.keyboard.is_none()));
+    assert_eq!(balance().await, Some(Money::from_yuan(-3300)));
+
+    // 最近 5 笔的中位数是 100 元，超过 500 元需要确认
+    handler.handle_message(&bot, &message(16, 600)).await?;
+    let (prompt, _) = last_prompt().await;
+    assert!(prompt.contains("600.00元（超过平时金额 100.00元 的 5 倍）"));
+    assert_eq!(balance().await, Some(Money::from_yuan(-3300)));
+
+    // 超时后确认不再记账
+    let expiring = MessageHandler::new(db.clone()).with_confirmation_timeout(Duration::ZERO);
+    bot.clear_all().await;
+    expiring.handle_message(&bot, &message(17, 700)).await?;
+    let (prompt, data) = last_prompt().await;
+    expiring
+        .handle_confirmation_callback(&bot, &press(MEMBER, &prompt, &data[0]))
+        .await?;
+    assert!(bot.get_edited_messages().await[0]
+        .text
+        .contains("确认已过期或已经处理过"));
+    assert_eq!(balance().await, Some(Money::from_yuan(-3300)));
+
+    Ok(())
+}